@@ -0,0 +1,12 @@
+fn main() {
+    // The gRPC code is generated with protox, so that protoc doesn't need to be installed
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo::rerun-if-changed=proto/multiping.proto");
+        let descriptors = protox::compile(["proto/multiping.proto"], ["proto"]).expect("Couldn't compile multiping.proto");
+        tonic_prost_build::configure()
+            .build_client(false)
+            .compile_fds(descriptors)
+            .expect("Couldn't generate the gRPC code");
+    }
+}
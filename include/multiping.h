@@ -0,0 +1,132 @@
+#ifndef MULTIPING_H
+#define MULTIPING_H
+
+/* Generated by cbindgen from src/ffi.rs. Don't edit it by hand */
+
+#include <stdbool.h>
+#include <stdint.h>
+
+// What an event is about
+typedef enum multiping_event_kind {
+  // A request was sent to the host
+  MULTIPING_EVENT_KIND_SENT,
+  // A reply came back, taking `latency_ms`
+  MULTIPING_EVENT_KIND_RECEIVED,
+  // Probing the host failed, as described by `message`
+  MULTIPING_EVENT_KIND_ERROR,
+  // An ICMP error (`icmp_type` and `icmp_code`) came back about a request
+  MULTIPING_EVENT_KIND_ICMP_ERROR,
+  // The host stopped replying
+  MULTIPING_EVENT_KIND_DOWN,
+  // The host replied again after being down
+  MULTIPING_EVENT_KIND_UP,
+  // The host moved to another state, given by `state` (along with `Down` or `Up` if it went down or came back)
+  MULTIPING_EVENT_KIND_STATE_CHANGED,
+} multiping_event_kind;
+
+// Whether a host is up, degraded or down
+typedef enum multiping_host_state {
+  MULTIPING_HOST_STATE_UP,
+  // Answering, but slowly or losing requests
+  MULTIPING_HOST_STATE_DEGRADED,
+  MULTIPING_HOST_STATE_DOWN,
+} multiping_host_state;
+
+// A running pinger and the statistics for its hosts. Opaque to C
+typedef struct multiping_pinger multiping_pinger;
+
+// Something that happened to a host, passed to the callback
+typedef struct multiping_event {
+  enum multiping_event_kind kind;
+  // The host's index, as returned by `multiping_add_host`
+  uint32_t host;
+  // NaN unless it's a reply
+  double latency_ms;
+  uint8_t icmp_type;
+  uint8_t icmp_code;
+  // What went wrong for errors, or else NULL. Only valid until the callback returns
+  const char *message;
+  // The host's state once the event has been taken in
+  enum multiping_host_state state;
+} multiping_event;
+
+// Called by `multiping_poll` for each event, with the user data given to `multiping_set_callback`
+typedef void (*multiping_callback)(const struct multiping_event *event, void *user_data);
+
+// A host's statistics. Times are NaN until the host has replied, and the loss until a request has been answered
+// or given up on
+typedef struct multiping_stats {
+  uint32_t sent;
+  uint32_t received;
+  double loss_percent;
+  double latest_ms;
+  double min_ms;
+  double average_ms;
+  double max_ms;
+  // Standard deviation of the round trip times
+  double jitter_ms;
+  enum multiping_host_state state;
+  // Whether `state` is down
+  bool down;
+  bool removed;
+} multiping_stats;
+
+// Starts a pinger with no hosts, which probes every `interval_seconds`, sending each request again up to `retries`
+// times within the interval if there's no reply. Returns NULL if it couldn't be started (e.g. ICMP sockets
+// aren't allowed), with errno set
+struct multiping_pinger *multiping_new(double interval_seconds,
+                                       uint32_t retries);
+
+// Stops pinging and frees the pinger. It waits for the threads to stop, which can take up to an interval,
+// so their sockets are closed once it returns
+//
+// # Safety
+// `pinger` has to be NULL or from `multiping_new`, and not used again afterwards
+void multiping_free(struct multiping_pinger *pinger);
+
+// Starts pinging a host, given as on the command line (e.g. "example.com" or "tcp:example.com:443"). Returns its
+// index, or -1 if it couldn't be resolved. A host with several probe types (e.g. "icmp+tcp:example.com:443") gets
+// an index for each, one after the other, and the first is returned. It's in the statistics after the next poll
+//
+// # Safety
+// `pinger` has to be from `multiping_new`, and `host` a nul-terminated string
+int64_t multiping_add_host(struct multiping_pinger *pinger,
+                           const char *host);
+
+// Stops pinging a host. Its index isn't reused. Indices that aren't in use are ignored
+//
+// # Safety
+// `pinger` has to be from `multiping_new`
+void multiping_remove_host(struct multiping_pinger *pinger, uint32_t host);
+
+// Sets the function called for each event by `multiping_poll` (or stops calling one, if it's NULL)
+//
+// # Safety
+// `pinger` has to be from `multiping_new`. `user_data` is passed to the callback as it is
+void multiping_set_callback(struct multiping_pinger *pinger,
+                            multiping_callback callback,
+                            void *user_data);
+
+// Waits up to `timeout_ms` for something to happen, then updates the statistics with everything that has,
+// calling the callback for each event. Returns how many updates there were, or -1 if the pinger has stopped
+//
+// # Safety
+// `pinger` has to be from `multiping_new`
+int multiping_poll(struct multiping_pinger *pinger,
+                   uint32_t timeout_ms);
+
+// How many hosts there are in the statistics, including removed ones
+//
+// # Safety
+// `pinger` has to be from `multiping_new`
+uint32_t multiping_host_count(const struct multiping_pinger *pinger);
+
+// Fills in `stats` for a host. Returns 0, or -1 if there's no such host
+//
+// # Safety
+// `pinger` has to be from `multiping_new`, and `stats` has to point to a `multiping_stats`
+int multiping_get_stats(const struct multiping_pinger *pinger,
+                        uint32_t host,
+                        struct multiping_stats *stats);
+
+#endif  /* MULTIPING_H */
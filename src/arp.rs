@@ -0,0 +1,142 @@
+//! ARP ping: asks who-has the target's address on the local network, and times how long the answer takes.
+//! This works even when the host is firewalling ICMP, but only on the same subnet
+//!
+//! Sending on a packet socket needs a link-layer address (sockaddr_ll) with the interface, the protocol and the
+//! broadcast hardware address in it. Neither socket2 nor nix can make one from its parts (nix's `LinkAddr` only comes
+//! from the kernel), so `link_sockaddr` fills one in itself, which is one of the few places in the crate that allows
+//! unsafe code. It only writes plain fields of a zeroed sockaddr_storage
+
+use std::io::{Error, ErrorKind, Read};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+use nix::ifaddrs::{InterfaceAddress, getifaddrs};
+use nix::net::if_::if_nametoindex;
+use socket2::{Domain, Protocol, SockAddr, SockAddrStorage, Socket, Type};
+
+use crate::helper::{self, HelperSocket};
+use crate::privileges::{self, SocketKind};
+use crate::probe::{Probe, ProbeReply};
+
+pub(crate) const ETH_P_ARP: u16 = 0x0806;
+const BROADCAST: [u8; 6] = [0xff; 6];
+
+/// The interface (and our addresses on it) that's used to reach a neighbour
+#[derive(Clone, Debug)]
+pub struct LocalInterface {
+    pub index: u32,
+    pub mac: [u8; 6],
+    pub ip: Ipv4Addr,
+}
+
+/// Finds the interface with a subnet containing `target`
+pub fn find_interface(target: Ipv4Addr) -> Result<LocalInterface, Error> {
+    let addrs: Vec<_> = getifaddrs().map_err(Error::from)?.collect();
+    for ifaddr in &addrs {
+        let Some(ip) = ifaddr.address.as_ref().and_then(|a| a.as_sockaddr_in()).map(|a| a.ip()) else { continue };
+        let Some(mask) = ifaddr.netmask.as_ref().and_then(|a| a.as_sockaddr_in()).map(|a| a.ip()) else { continue };
+        if ip.is_loopback() || ip.to_bits() & mask.to_bits() != target.to_bits() & mask.to_bits() {
+            continue;
+        }
+        let mac = interface_mac(&addrs, &ifaddr.interface_name)?;
+        let index = if_nametoindex(ifaddr.interface_name.as_str()).map_err(Error::from)?;
+        return Ok(LocalInterface { index, mac, ip });
+    }
+    Err(Error::new(ErrorKind::AddrNotAvailable, format!("{} is not on a local subnet", target)))
+}
+
+/// The MAC address of an interface, which getifaddrs lists as a separate link-layer address
+pub(crate) fn interface_mac(addrs: &[InterfaceAddress], name: &str) -> Result<[u8; 6], Error> {
+    addrs.iter()
+        .filter(|a| a.interface_name == name)
+        .find_map(|a| a.address.as_ref()?.as_link_addr()?.addr())
+        .ok_or(Error::from(ErrorKind::AddrNotAvailable))
+}
+
+/// Sends ARP requests for a target, and waits for the replies
+pub struct ArpProbe {
+    target: Ipv4Addr,
+    interface: LocalInterface,
+    socket: Socket,
+    sent_at: Instant,
+}
+
+impl ArpProbe {
+    pub fn new(target: Ipv4Addr) -> Result<ArpProbe, Error> {
+        let interface = find_interface(target)?;
+        let socket = Socket::new(Domain::from(libc::AF_PACKET), Type::DGRAM, Some(Protocol::from(ETH_P_ARP.to_be() as i32)))
+            .or_else(|e| helper::fallback(e, HelperSocket::PacketArp))
+            .map_err(|e| privileges::explain(e, SocketKind::Raw))?;
+        socket.bind(&link_sockaddr(interface.index, [0; 6]))?;
+        Ok(ArpProbe { target, interface, socket, sent_at: Instant::now() })
+    }
+}
+
+impl Probe for ArpProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        let request = construct_arp_request(&self.interface, self.target);
+        self.sent_at = Instant::now();
+        self.socket.send_to(&request, &link_sockaddr(self.interface.index, BROADCAST))?;
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let mut buf = [0u8; 64];
+        loop {
+            let remaining = timeout.saturating_sub(self.sent_at.elapsed());
+            if remaining.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+            let len = match (&self.socket).read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::TimedOut.into()),
+                Err(e) => return Err(e),
+            };
+            if is_reply_from(&buf[..len], self.target, self.interface.ip) {
+                return Ok(ProbeReply::new(self.sent_at.elapsed().as_micros() as u64));
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("ARP who-has {}", self.target)
+    }
+}
+
+/// Builds the ARP packet (without the Ethernet header, which the kernel adds)
+fn construct_arp_request(interface: &LocalInterface, target: Ipv4Addr) -> Vec<u8> {
+    let mut packet = vec![
+        0, 1, // Hardware type: Ethernet
+        0x08, 0x00, // Protocol type: IPv4
+        6, 4, // Hardware and protocol address lengths
+        0, 1, // Operation: request
+    ];
+    packet.extend_from_slice(&interface.mac);
+    packet.extend_from_slice(&interface.ip.octets());
+    packet.extend_from_slice(&[0; 6]); // Target hardware address isn't known yet
+    packet.extend_from_slice(&target.octets());
+    packet
+}
+
+/// Whether the packet is an ARP reply from `target` to `our_ip`
+fn is_reply_from(packet: &[u8], target: Ipv4Addr, our_ip: Ipv4Addr) -> bool {
+    packet.len() >= 28
+        && packet[6..8] == [0, 2]
+        && packet[14..18] == target.octets()
+        && packet[24..28] == our_ip.octets()
+}
+
+/// Builds a link-layer (sockaddr_ll) address for the interface
+#[allow(unsafe_code)]
+fn link_sockaddr(ifindex: u32, mac: [u8; 6]) -> SockAddr {
+    let mut storage = SockAddrStorage::zeroed();
+    // SAFETY: sockaddr_ll is smaller than sockaddr_storage, and every field is plain data
+    let addr = unsafe { storage.view_as::<libc::sockaddr_ll>() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = ETH_P_ARP.to_be();
+    addr.sll_ifindex = ifindex as i32;
+    addr.sll_halen = 6;
+    addr.sll_addr[..6].copy_from_slice(&mac);
+    // SAFETY: the storage holds an initialised sockaddr_ll of the given length
+    unsafe { SockAddr::new(storage, size_of::<libc::sockaddr_ll>() as libc::socklen_t) }
+}
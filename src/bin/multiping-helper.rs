@@ -0,0 +1,22 @@
+//! Creates the sockets multiping isn't allowed to, and passes them back over a Unix socket on stdin.
+//! Install it setuid root or with CAP_NET_RAW (e.g. `sudo setcap cap_net_raw+ep multiping-helper`),
+//! next to the multiping binary. It's started by multiping, not by hand, and only serves root and
+//! members of the `multiping` group
+
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::process::exit;
+
+fn main() {
+    let stdin = match std::io::stdin().as_fd().try_clone_to_owned() {
+        Ok(fd) => UnixStream::from(fd),
+        Err(e) => {
+            eprintln!("multiping-helper: {}", e);
+            exit(1);
+        }
+    };
+    if let Err(e) = multiping::helper::serve(stdin) {
+        eprintln!("multiping-helper: {} (it should be started by multiping)", e);
+        exit(1);
+    }
+}
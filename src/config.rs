@@ -0,0 +1,85 @@
+//! Config files, which list hosts to probe and settings to use. Each line is either a setting
+//! (e.g. `interval = 2` or `interval = 10ms`, named after the command line option) or a host, and `#` starts a comment:
+//!
+//! ```text
+//! interval = 2
+//! sla-loss = 1
+//! latency-warning = 100
+//! latency-warning = example.com=300
+//! note = example.com=rack 12, ISP A
+//! example.com
+//! tcp:example.com:443
+//! ```
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::HostInfo;
+use crate::schedule::TimeWindow;
+use crate::sla::Sla;
+use crate::thresholds::LatencyColours;
+use crate::time;
+
+/// Hosts and settings read from a config file. Settings that aren't in the file are None (or empty)
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub hosts: Vec<String>,
+    pub interval: Option<Duration>,
+    pub retries: Option<u32>,
+    pub windows: Vec<TimeWindow>,
+    pub pauses: Vec<TimeWindow>,
+    pub sla: Sla,
+    pub latency_colours: LatencyColours,
+    /// Notes for hosts, as the host and the note
+    pub notes: Vec<(String, String)>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, Error> {
+        Config::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Config, Error> {
+        let mut config = Config::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                if !config.hosts.iter().any(|h| h == line) {
+                    config.hosts.push(line.to_string());
+                }
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let invalid = |reason: String| Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, reason));
+            let number_value = || value.parse::<f64>().map_err(|_| invalid(format!("{} isn't a number", value)));
+            match key {
+                "interval" => config.interval = Some(time::parse_interval(value).map_err(|e| invalid(e.to_string()))?),
+                "retries" => config.retries = Some(value.parse().map_err(|_| invalid(format!("{} isn't a whole number", value)))?),
+                "window" => config.windows.push(value.parse().map_err(invalid)?),
+                "pause" => config.pauses.push(value.parse().map_err(invalid)?),
+                "sla-average" => config.sla.max_average_ms = Some(number_value()?),
+                "sla-loss" => config.sla.max_loss_percent = Some(number_value()?),
+                "sla-availability" => config.sla.min_availability_percent = Some(number_value()?),
+                "latency-warning" => config.latency_colours.warning.push(value.parse().map_err(invalid)?),
+                "latency-critical" => config.latency_colours.critical.push(value.parse().map_err(invalid)?),
+                "note" => match value.split_once('=') {
+                    Some((host, note)) if !host.trim().is_empty() => config.notes.push((host.trim().to_string(), note.trim().to_string())),
+                    _ => return Err(invalid(format!("{} should be a host and its note, e.g. example.com=rack 12", value))),
+                },
+                _ => return Err(invalid(format!("unknown setting {}", key))),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The note for a host, if there is one. Hosts are named as for the latency thresholds, and later notes win over
+    /// earlier ones
+    pub fn note_for(&self, host: &HostInfo) -> Option<String> {
+        self.notes.iter().rev().find(|(name, _)| host.is_named(name)).map(|(_, note)| note.clone())
+    }
+}
@@ -0,0 +1,110 @@
+//! Lining up the hosts' failures (outages and latency spikes) in time, to tell whether a problem is on this side
+//! (every host affected at once) or out on the network (only one host, or the path to it)
+
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use crate::HostInfo;
+use crate::stats::median;
+
+/// A reply counts as a latency spike if it took this many times the host's median, and at least `SPIKE_MIN_EXTRA` more
+const SPIKE_FACTOR: u64 = 3;
+const SPIKE_MIN_EXTRA: u64 = 20_000;
+
+/// Where a problem seems to be, going by which hosts it affected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Scope {
+    /// Every host at once, so it's most likely this machine, its network or its uplink
+    Local,
+    /// Some of the hosts, e.g. the ones behind a router or link they share
+    Shared,
+    /// Just one host, so it's that host or the path to it
+    Remote,
+}
+
+/// A period when one or more hosts were down or slow, with failures that overlapped in time
+#[derive(Debug, Clone)]
+pub struct Incident {
+    pub start: SystemTime,
+    pub end: Option<SystemTime>, // None if a host is still down
+    pub hosts: Vec<usize>, // The indices of the hosts affected
+    pub addresses: usize, // How many different addresses they're at
+    pub outage: bool, // Whether any host went down, rather than just being slow
+    pub scope: Scope,
+}
+
+/// Finds when hosts failed at the same time. Failures count as the same incident if they overlap, or are less than
+/// `slack` apart (usually the interval, as the hosts aren't all probed at the same moment). Hosts are told apart by
+/// their address, so that several probes of the same target only count once. Removed hosts are left out, and with
+/// fewer than two addresses there's nothing to compare, so there aren't any incidents. Oldest first
+pub fn incidents(hinfos: &[HostInfo], slack: Duration) -> Vec<Incident> {
+    let addresses: BTreeSet<IpAddr> = hinfos.iter().filter(|h| !h.removed).map(|h| h.host.ip()).collect();
+    if addresses.len() < 2 {
+        return Vec::new();
+    }
+    let mut failures = failures(hinfos);
+    failures.sort_by_key(|f| f.start);
+
+    let mut incidents: Vec<Incident> = Vec::new();
+    let mut incident_end: Option<SystemTime> = None;
+    for failure in failures {
+        let overlaps = incidents.last().is_some() && incident_end.is_none_or(|end| failure.start <= end + slack);
+        if !overlaps {
+            incidents.push(Incident { start: failure.start, end: Some(failure.start), hosts: Vec::new(), addresses: 0, outage: false, scope: Scope::Remote });
+            incident_end = Some(failure.start);
+        }
+        let incident = incidents.last_mut().unwrap();
+        if !incident.hosts.contains(&failure.host) {
+            incident.hosts.push(failure.host);
+        }
+        incident.outage |= failure.outage;
+        incident_end = incident_end.zip(failure.end).map(|(a, b)| a.max(b));
+        incident.end = incident_end;
+    }
+
+    for incident in &mut incidents {
+        incident.hosts.sort();
+        incident.addresses = incident.hosts.iter().map(|i| hinfos[*i].host.ip()).collect::<BTreeSet<_>>().len();
+        incident.scope = match incident.addresses {
+            1 => Scope::Remote,
+            n if n >= addresses.len() => Scope::Local,
+            _ => Scope::Shared,
+        };
+    }
+    incidents
+}
+
+/// The incident going on now, if a host is down
+pub fn ongoing(incidents: &[Incident]) -> Option<&Incident> {
+    incidents.last().filter(|i| i.end.is_none() && i.outage)
+}
+
+/// When a host was down or slow
+struct Failure {
+    host: usize,
+    start: SystemTime,
+    end: Option<SystemTime>,
+    outage: bool,
+}
+
+/// Every outage and latency spike of every host that hasn't been removed
+fn failures(hinfos: &[HostInfo]) -> Vec<Failure> {
+    let mut failures = Vec::new();
+    for (i, h) in hinfos.iter().enumerate().filter(|(_, h)| !h.removed) {
+        for outage in &h.outages {
+            failures.push(Failure { host: i, start: outage.start, end: outage.end, outage: true });
+        }
+        let Some(median) = median(&h.samples_vec()) else { continue };
+        let median = median as u64;
+        for (latency, time) in h.samples.iter().zip(&h.sample_times) {
+            if *latency >= median * SPIKE_FACTOR && *latency >= median + SPIKE_MIN_EXTRA {
+                // The request was sent one round trip before the reply arrived
+                let start = time.checked_sub(Duration::from_micros(*latency)).unwrap_or(*time);
+                failures.push(Failure { host: i, start, end: Some(*time), outage: false });
+            }
+        }
+    }
+    failures
+}
@@ -0,0 +1,88 @@
+//! Quick checks of this machine's own network connection, for when every host fails at once and the problem is
+//! probably on this side: whether an interface is up with a carrier, there's a default route, and DNS answers
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::InterfaceFlags;
+
+use crate::dns::{TYPE_A, build_query};
+use crate::srv::nameservers;
+
+/// How long to wait for each nameserver to answer
+const DNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Something wrong with this machine's connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Finding {
+    NoInterfaceUp,
+    NoCarrier(Vec<String>), // The interfaces that are up, but with nothing plugged in (or no Wi-Fi connection)
+    NoDefaultRoute,
+    DnsUnreachable(Vec<SocketAddr>), // The nameservers that didn't answer
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::NoInterfaceUp => write!(f, "no network interface is up"),
+            Finding::NoCarrier(interfaces) => write!(f, "no link on {} (is the cable plugged in, or the Wi-Fi connected?)", interfaces.join(", ")),
+            Finding::NoDefaultRoute => write!(f, "there's no default route"),
+            Finding::DnsUnreachable(servers) => {
+                let servers: Vec<String> = servers.iter().map(|s| s.ip().to_string()).collect();
+                write!(f, "DNS ({}) isn't answering", servers.join(", "))
+            },
+        }
+    }
+}
+
+/// Runs every check, which takes up to a couple of seconds for each nameserver if DNS isn't answering. Nothing found
+/// means the connection looks fine from here, so the problem is further out
+pub fn diagnose() -> Vec<Finding> {
+    let mut findings = Vec::new();
+    // Whether each interface (apart from loopback) is up, and whether it has a carrier
+    let mut interfaces: BTreeMap<String, (bool, bool)> = BTreeMap::new();
+    for ifaddr in getifaddrs().into_iter().flatten().filter(|i| !i.flags.contains(InterfaceFlags::IFF_LOOPBACK)) {
+        let up = ifaddr.flags.contains(InterfaceFlags::IFF_UP);
+        interfaces.insert(ifaddr.interface_name, (up, up && ifaddr.flags.contains(InterfaceFlags::IFF_RUNNING)));
+    }
+    if !interfaces.values().any(|(up, _)| *up) {
+        findings.push(Finding::NoInterfaceUp);
+    } else if !interfaces.values().any(|(_, carrier)| *carrier) {
+        findings.push(Finding::NoCarrier(interfaces.into_iter().filter(|(_, (up, _))| *up).map(|(name, _)| name).collect()));
+    }
+    #[cfg(target_os = "linux")]
+    if crate::gateway::default_gateways().is_err() {
+        findings.push(Finding::NoDefaultRoute);
+    }
+    let servers = nameservers();
+    if !servers.iter().any(|server| dns_answers(*server)) {
+        findings.push(Finding::DnsUnreachable(servers));
+    }
+    findings
+}
+
+/// Whether the nameserver answers a question about the root zone (whatever the answer is)
+fn dns_answers(server: SocketAddr) -> bool {
+    let ask = || -> std::io::Result<()> {
+        let socket = UdpSocket::bind(if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+        socket.set_read_timeout(Some(DNS_TIMEOUT))?;
+        socket.connect(server)?;
+        socket.send(&build_query(0x6d70, &[(".", TYPE_A)]))?;
+        socket.recv(&mut [0u8; 512])?;
+        Ok(())
+    };
+    ask().is_ok()
+}
+
+/// Describes the findings on one line
+pub fn summary(findings: &[Finding]) -> String {
+    if findings.is_empty() {
+        return "this machine's connection looks fine (an interface has a link, there's a default route and DNS answers), \
+            so the problem is probably further out".to_string();
+    }
+    let findings: Vec<String> = findings.iter().map(|f| f.to_string()).collect();
+    findings.join("; ")
+}
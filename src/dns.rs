@@ -0,0 +1,130 @@
+//! Just enough of the DNS message format to ask simple questions and read the answers
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+
+/// A resource record from the answer, authority or additional sections of a response
+#[derive(Debug, Clone)]
+pub struct DnsRecord {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+#[derive(Debug, Clone)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Other {
+        rtype: u16,
+    },
+}
+
+/// Build a query message asking each of the questions (name, type)
+pub fn build_query(id: u16, questions: &[(&str, u16)]) -> Vec<u8> {
+    let mut msg = Vec::new();
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&0x0100_u16.to_be_bytes()); // Recursion desired (ignored by mDNS)
+    msg.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+    msg.extend_from_slice(&[0, 0, 0, 0, 0, 0]); // No answer, authority or additional records
+    for (name, qtype) in questions {
+        for label in name.trim_end_matches('.').split('.').filter(|l| !l.is_empty()) {
+            let bytes = &label.as_bytes()[..label.len().min(63)];
+            msg.push(bytes.len() as u8);
+            msg.extend_from_slice(bytes);
+        }
+        msg.push(0);
+        msg.extend_from_slice(&qtype.to_be_bytes());
+        msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    msg
+}
+
+/// Parse a response, returning its ID and every record in it.
+/// Returns None if the message is malformed
+pub fn parse_response(buf: &[u8]) -> Option<(u16, Vec<DnsRecord>)> {
+    let id = u16::from_be_bytes(buf.get(0..2)?.try_into().ok()?);
+    let count = |i: usize| -> Option<usize> {
+        Some(u16::from_be_bytes(buf.get(i..i + 2)?.try_into().ok()?) as usize)
+    };
+    let questions = count(4)?;
+    let records = count(6)? + count(8)? + count(10)?;
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = read_name(buf, offset)?.1 + 4;
+    }
+
+    let mut parsed = Vec::with_capacity(records);
+    for _ in 0..records {
+        let (name, after_name) = read_name(buf, offset)?;
+        let fixed = buf.get(after_name..after_name + 10)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdlength = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let rdata_start = after_name + 10;
+        let rdata = buf.get(rdata_start..rdata_start + rdlength)?;
+
+        let data = match rtype {
+            TYPE_A if rdlength == 4 => RecordData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            TYPE_AAAA if rdlength == 16 => {
+                let octets: [u8; 16] = rdata.try_into().ok()?;
+                RecordData::Aaaa(Ipv6Addr::from(octets))
+            },
+            TYPE_PTR => RecordData::Ptr(read_name(buf, rdata_start)?.0),
+            TYPE_SRV if rdlength >= 7 => RecordData::Srv {
+                priority: u16::from_be_bytes([rdata[0], rdata[1]]),
+                weight: u16::from_be_bytes([rdata[2], rdata[3]]),
+                port: u16::from_be_bytes([rdata[4], rdata[5]]),
+                target: read_name(buf, rdata_start + 6)?.0,
+            },
+            _ => RecordData::Other { rtype },
+        };
+        parsed.push(DnsRecord { name, ttl, data });
+        offset = rdata_start + rdlength;
+    }
+
+    Some((id, parsed))
+}
+
+/// Read a (possibly compressed) name starting at `offset`.
+/// Returns the name and the offset just after it
+fn read_name(buf: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut pos = offset;
+    let mut end: Option<usize> = None;
+    let mut jumps = 0;
+    loop {
+        let len = *buf.get(pos)? as usize;
+        if len == 0 {
+            break;
+        } else if len & 0xc0 == 0xc0 {
+            // Compression pointer to somewhere earlier in the message
+            let target = ((len & 0x3f) << 8) | *buf.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            jumps += 1;
+            if jumps > 32 {
+                return None;
+            }
+            pos = target;
+        } else {
+            let label = buf.get(pos + 1..pos + 1 + len)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += 1 + len;
+        }
+    }
+    Some((labels.join("."), end.unwrap_or(pos + 1)))
+}
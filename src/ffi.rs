@@ -0,0 +1,295 @@
+//! C interface to the probing engine, for embedding multiping in C and C++ programs (see include/multiping.h).
+//! Updates are only handled (and callbacks only called) in `multiping_poll`, on the thread that calls it, so the
+//! C side doesn't have to deal with multiping's threads
+
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::io::Error;
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::schedule::Schedule;
+use crate::{HistoryLimit, HostInfo, HostOptions, HostState, PingOptions, Pinger, StateThresholds, StatusUpdate, start_pinging, update_host_info};
+
+/// A running pinger and the statistics for its hosts. Opaque to C
+pub struct MultipingPinger {
+    pinger: Pinger,
+    rx: Receiver<StatusUpdate>,
+    hinfos: Vec<HostInfo>,
+    callback: MultipingCallback,
+    user_data: *mut c_void,
+}
+
+/// What an event is about
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub enum MultipingEventKind {
+    /// A request was sent to the host
+    Sent,
+    /// A reply came back, taking `latency_ms`
+    Received,
+    /// Probing the host failed, as described by `message`
+    Error,
+    /// An ICMP error (`icmp_type` and `icmp_code`) came back about a request
+    IcmpError,
+    /// The host stopped replying
+    Down,
+    /// The host replied again after being down
+    Up,
+    /// The host moved to another state, given by `state` (along with `Down` or `Up` if it went down or came back)
+    StateChanged,
+}
+
+/// Whether a host is up, degraded or down
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub enum MultipingHostState {
+    Up,
+    /// Answering, but slowly or losing requests
+    Degraded,
+    Down,
+}
+
+impl From<HostState> for MultipingHostState {
+    fn from(state: HostState) -> MultipingHostState {
+        match state {
+            HostState::Up => MultipingHostState::Up,
+            HostState::Degraded => MultipingHostState::Degraded,
+            HostState::Down => MultipingHostState::Down,
+        }
+    }
+}
+
+/// Something that happened to a host, passed to the callback
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MultipingEvent {
+    pub kind: MultipingEventKind,
+    /// The host's index, as returned by `multiping_add_host`
+    pub host: u32,
+    /// NaN unless it's a reply
+    pub latency_ms: f64,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    /// What went wrong for errors, or else NULL. Only valid until the callback returns
+    pub message: *const c_char,
+    /// The host's state once the event has been taken in
+    pub state: MultipingHostState,
+}
+
+/// Called by `multiping_poll` for each event, with the user data given to `multiping_set_callback`
+pub type MultipingCallback = Option<extern "C" fn(event: *const MultipingEvent, user_data: *mut c_void)>;
+
+/// A host's statistics. Times are NaN until the host has replied, and the loss until a request has been answered
+/// or given up on
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MultipingStats {
+    pub sent: u32,
+    pub received: u32,
+    pub loss_percent: f64,
+    pub latest_ms: f64,
+    pub min_ms: f64,
+    pub average_ms: f64,
+    pub max_ms: f64,
+    /// Standard deviation of the round trip times
+    pub jitter_ms: f64,
+    pub state: MultipingHostState,
+    /// Whether `state` is down
+    pub down: bool,
+    pub removed: bool,
+}
+
+/// Starts a pinger with no hosts, which probes every `interval_seconds`, sending each request again up to `retries`
+/// times within the interval if there's no reply. Returns NULL if it couldn't be started (e.g. ICMP sockets
+/// aren't allowed), with errno set
+#[unsafe(no_mangle)]
+pub extern "C" fn multiping_new(interval_seconds: f64, retries: u32) -> *mut MultipingPinger {
+    // Too big to be a Duration, as well as NaN and anything up to 0, is invalid
+    let Some(interval) = Duration::try_from_secs_f64(interval_seconds).ok().filter(|i| !i.is_zero()) else {
+        set_errno(libc::EINVAL);
+        return ptr::null_mut();
+    };
+    let options = PingOptions {
+        interval,
+        retries,
+        schedule: Schedule::default(),
+        connected_sockets: false,
+        raw_sockets: false,
+        packet_log: false,
+        jitter: 0.0,
+        burst: 1,
+        shards: 1,
+        history: HistoryLimit::default(),
+        state_thresholds: StateThresholds::default(),
+    };
+    let (tx, rx) = mpsc::channel();
+    match start_pinging(&[], options, tx) {
+        Ok(pinger) => Box::into_raw(Box::new(MultipingPinger {
+            pinger, rx, hinfos: Vec::new(), callback: None, user_data: ptr::null_mut(),
+        })),
+        Err(e) => {
+            set_errno(e.raw_os_error().unwrap_or(libc::EIO));
+            ptr::null_mut()
+        },
+    }
+}
+
+/// Stops pinging and frees the pinger. It waits for the threads to stop, which can take up to an interval,
+/// so their sockets are closed once it returns
+///
+/// # Safety
+/// `pinger` has to be NULL or from `multiping_new`, and not used again afterwards
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn multiping_free(pinger: *mut MultipingPinger) {
+    if !pinger.is_null() {
+        let pinger = unsafe { Box::from_raw(pinger) };
+        pinger.pinger.shutdown();
+    }
+}
+
+/// Starts pinging a host, given as on the command line (e.g. "example.com" or "tcp:example.com:443"). Returns its
+/// index, or -1 if it couldn't be resolved. A host with several probe types (e.g. "icmp+tcp:example.com:443") gets
+/// an index for each, one after the other, and the first is returned. It's in the statistics after the next poll
+///
+/// # Safety
+/// `pinger` has to be from `multiping_new`, and `host` a nul-terminated string
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn multiping_add_host(pinger: *mut MultipingPinger, host: *const c_char) -> i64 {
+    let (Some(pinger), false) = (unsafe { pinger.as_mut() }, host.is_null()) else { return -1 };
+    let Ok(host) = unsafe { CStr::from_ptr(host) }.to_str() else { return -1 };
+    match HostInfo::new_group(host, HostOptions::default()) {
+        Ok(group) => group.into_iter().map(|h| pinger.pinger.add_host(h) as i64).min().unwrap_or(-1),
+        Err(_) => -1,
+    }
+}
+
+/// Stops pinging a host. Its index isn't reused. Indices that aren't in use are ignored
+///
+/// # Safety
+/// `pinger` has to be from `multiping_new`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn multiping_remove_host(pinger: *mut MultipingPinger, host: u32) {
+    if let Some(pinger) = unsafe { pinger.as_mut() } {
+        pinger.pinger.remove_host(host as usize);
+    }
+}
+
+/// Sets the function called for each event by `multiping_poll` (or stops calling one, if it's NULL)
+///
+/// # Safety
+/// `pinger` has to be from `multiping_new`. `user_data` is passed to the callback as it is
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn multiping_set_callback(pinger: *mut MultipingPinger, callback: MultipingCallback, user_data: *mut c_void) {
+    if let Some(pinger) = unsafe { pinger.as_mut() } {
+        pinger.callback = callback;
+        pinger.user_data = user_data;
+    }
+}
+
+/// Waits up to `timeout_ms` for something to happen, then updates the statistics with everything that has,
+/// calling the callback for each event. Returns how many updates there were, or -1 if the pinger has stopped
+///
+/// # Safety
+/// `pinger` has to be from `multiping_new`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn multiping_poll(pinger: *mut MultipingPinger, timeout_ms: u32) -> c_int {
+    let Some(pinger) = (unsafe { pinger.as_mut() }) else { return -1 };
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms as u64);
+    let mut handled = 0;
+    loop {
+        let update = if handled == 0 {
+            pinger.rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        } else {
+            pinger.rx.try_recv().map_err(|_| RecvTimeoutError::Timeout)
+        };
+        match update {
+            Ok(update) => {
+                pinger.handle(&update);
+                handled += 1;
+            },
+            Err(RecvTimeoutError::Timeout) => return handled,
+            Err(RecvTimeoutError::Disconnected) => return -1,
+        }
+    }
+}
+
+/// How many hosts there are in the statistics, including removed ones
+///
+/// # Safety
+/// `pinger` has to be from `multiping_new`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn multiping_host_count(pinger: *const MultipingPinger) -> u32 {
+    unsafe { pinger.as_ref() }.map_or(0, |pinger| pinger.hinfos.len() as u32)
+}
+
+/// Fills in `stats` for a host. Returns 0, or -1 if there's no such host
+///
+/// # Safety
+/// `pinger` has to be from `multiping_new`, and `stats` has to point to a `multiping_stats`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn multiping_get_stats(pinger: *const MultipingPinger, host: u32, stats: *mut MultipingStats) -> c_int {
+    let (Some(pinger), Some(stats)) = (unsafe { pinger.as_ref() }, unsafe { stats.as_mut() }) else { return -1 };
+    let Some(h) = pinger.hinfos.get(host as usize) else { return -1 };
+    let ms = |time: Option<u64>| time.map_or(f64::NAN, |t| t as f64 / 1000.0);
+    let replied = h.successful > 0;
+    *stats = MultipingStats {
+        sent: h.pings_sent,
+        received: h.successful,
+        loss_percent: h.loss_percent().unwrap_or(f64::NAN),
+        latest_ms: ms(h.latest_time),
+        min_ms: ms(h.min_time),
+        average_ms: if replied { h.average() as f64 } else { f64::NAN },
+        max_ms: ms(h.max_time),
+        jitter_ms: if replied { h.jitter() as f64 } else { f64::NAN },
+        state: h.state.into(),
+        down: h.state == HostState::Down,
+        removed: h.removed,
+    };
+    0
+}
+
+impl MultipingPinger {
+    /// Applies an update to the statistics, and tells the callback about it (and about the host changing state, if
+    /// it did)
+    fn handle(&mut self, update: &StatusUpdate) {
+        let i = update.host_index(&self.hinfos);
+        let change = update_host_info(update, &mut self.hinfos);
+        let (Some(callback), Some(i)) = (self.callback, i) else { return };
+        let state = self.hinfos.get(i).map_or(MultipingHostState::Up, |h| h.state.into());
+        let event = |kind| MultipingEvent {
+            kind, host: i as u32, latency_ms: f64::NAN, icmp_type: 0, icmp_code: 0, message: ptr::null(), state,
+        };
+        let message = match update {
+            StatusUpdate::Error(_, kind) => CString::new(Error::from(*kind).to_string()).ok(),
+            _ => None,
+        };
+        let happened = match update {
+            StatusUpdate::Sent(_) => Some(event(MultipingEventKind::Sent)),
+            StatusUpdate::Received(_, latency, _) => Some(MultipingEvent {
+                latency_ms: latency.map_or(f64::NAN, |l| l as f64 / 1000.0), ..event(MultipingEventKind::Received)
+            }),
+            StatusUpdate::Error(..) => Some(MultipingEvent {
+                message: message.as_ref().map_or(ptr::null(), |m| m.as_ptr()), ..event(MultipingEventKind::Error)
+            }),
+            StatusUpdate::IcmpError(_, report) => Some(MultipingEvent {
+                icmp_type: report.icmp_type, icmp_code: report.code, ..event(MultipingEventKind::IcmpError)
+            }),
+            _ => None,
+        };
+        if let Some(happened) = happened {
+            callback(&happened, self.user_data);
+        }
+        let Some(change) = change else { return };
+        if change.to == HostState::Down {
+            callback(&event(MultipingEventKind::Down), self.user_data);
+        } else if change.from == HostState::Down {
+            callback(&event(MultipingEventKind::Up), self.user_data);
+        }
+        callback(&event(MultipingEventKind::StateChanged), self.user_data);
+    }
+}
+
+fn set_errno(errno: c_int) {
+    nix::errno::Errno::set_raw(errno);
+}
@@ -0,0 +1,78 @@
+//! Finding the default gateways from the kernel's routing tables, so the first hop can be probed alongside the
+//! hosts, to see whether a problem starts there
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use nix::net::if_::if_nametoindex;
+
+use crate::HostInfo;
+
+/// A default route's next hop, and the interface it's reached through
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gateway {
+    pub address: SocketAddr, // With the interface as its scope ID, if it's an IPv6 link-local address
+    pub interface: String,
+}
+
+impl Gateway {
+    /// A host for probing the gateway. IPv6 link-local ones are named with their interface, e.g. "fe80::1%eth0"
+    pub fn host_info(&self) -> HostInfo {
+        let name = match self.address {
+            SocketAddr::V6(v6) if v6.scope_id() != 0 => format!("{}%{}", v6.ip(), self.interface),
+            _ => self.address.ip().to_string(),
+        };
+        HostInfo { host: self.address, ..HostInfo::from_address(&name, self.address.ip()) }
+    }
+}
+
+/// The next hops of the default routes, IPv4 ones first. Each address is only given once, even if several routes
+/// go through it. Errors with `NotFound` if there aren't any
+pub fn default_gateways() -> Result<Vec<Gateway>, Error> {
+    let mut routes = ipv4_gateways(&fs::read_to_string("/proc/net/route")?);
+    // IPv6 may be turned off, in which case there's no table
+    routes.extend(ipv6_gateways(&fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default()));
+    let mut gateways: Vec<Gateway> = Vec::new();
+    for route in routes {
+        if !gateways.iter().any(|g| g.address == route.address) {
+            gateways.push(route);
+        }
+    }
+    if gateways.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, "no default routes"));
+    }
+    Ok(gateways)
+}
+
+/// Reads /proc/net/route, where addresses are hex in the machine's byte order, and the first line is a heading
+fn ipv4_gateways(table: &str) -> Vec<Gateway> {
+    // The route's flags have RTF_GATEWAY set when it goes through a next hop
+    const RTF_GATEWAY: u32 = 0x2;
+    table.lines().skip(1).filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [interface, destination, gateway, flags, _, _, _, mask, ..] = fields.as_slice() else { return None };
+        let hex = |s: &str| u32::from_str_radix(s, 16).ok();
+        if hex(destination)? != 0 || hex(mask)? != 0 || hex(flags)? & RTF_GATEWAY == 0 {
+            return None;
+        }
+        let address = Ipv4Addr::from(hex(gateway)?.to_ne_bytes());
+        Some(Gateway { address: SocketAddr::new(address.into(), 0), interface: interface.to_string() })
+    }).collect()
+}
+
+/// Reads /proc/net/ipv6_route, where addresses are 32 hex digits, and there's no heading
+fn ipv6_gateways(table: &str) -> Vec<Gateway> {
+    table.lines().filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [destination, prefix_len, _, _, next_hop, .., interface] = fields.as_slice() else { return None };
+        let address = Ipv6Addr::from(u128::from_str_radix(next_hop, 16).ok()?);
+        let default = u128::from_str_radix(destination, 16).ok()? == 0 && *prefix_len == "00";
+        // Unreachable routes (like the one on lo) have no next hop
+        if !default || address.is_unspecified() || *interface == "lo" {
+            return None;
+        }
+        let scope_id = if address.is_unicast_link_local() { if_nametoindex(*interface).ok()? } else { 0 };
+        Some(Gateway { address: SocketAddr::V6(SocketAddrV6::new(address, 0, 0, scope_id)), interface: interface.to_string() })
+    }).collect()
+}
@@ -0,0 +1,124 @@
+//! gRPC server which streams the statistics of every host, so that multiping can be used
+//! as a probing sidecar. The service is defined in proto/multiping.proto
+
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tonic::transport::Server;
+
+use crate::HostInfo;
+
+#[allow(unreachable_pub)]
+pub mod proto {
+    tonic::include_proto!("multiping");
+}
+
+use proto::multiping_server::{Multiping, MultipingServer};
+use proto::{HostStats, StreamStatsRequest};
+
+/// How many updates a slow client can fall behind by before it misses some
+const BACKLOG: usize = 1024;
+
+/// Sends statistics to every client of the gRPC server
+#[derive(Clone)]
+pub struct StatsPublisher {
+    tx: broadcast::Sender<HostStats>,
+    latest: Arc<Mutex<Vec<HostStats>>>, // Sent to clients when they connect
+}
+
+impl StatsPublisher {
+    /// Sends a host's latest statistics to every client
+    pub fn publish(&self, index: usize, host: &HostInfo) {
+        let stats = host_stats(index, host);
+        let mut latest = self.latest.lock().unwrap();
+        if latest.len() <= index {
+            latest.resize(index + 1, HostStats::default());
+        }
+        latest[index] = stats.clone();
+        // It doesn't matter if there aren't any clients
+        let _ = self.tx.send(stats);
+    }
+}
+
+/// Starts the gRPC server on its own thread, with the statistics of `hosts` (the ones probing started with) for
+/// clients that connect before they're first published. If the server stops, `stopped` is called with why
+pub fn serve<F>(addr: SocketAddr, hosts: &[HostInfo], stopped: F) -> Result<StatsPublisher, Error>
+where F: FnOnce(Error) + Send + 'static {
+    let (tx, _) = broadcast::channel(BACKLOG);
+    let publisher = StatsPublisher { tx, latest: Arc::new(Mutex::new(Vec::new())) };
+    for (i, host) in hosts.iter().enumerate() {
+        publisher.publish(i, host);
+    }
+    let service = StatsService(publisher.clone());
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    // Bind before starting the thread, so that errors (like the address being in use) are returned
+    let listener = runtime.block_on(tokio::net::TcpListener::bind(addr))?;
+    thread::spawn(move || {
+        let incoming = tonic::transport::server::TcpIncoming::from(listener);
+        let result = runtime.block_on(Server::builder()
+            .add_service(MultipingServer::new(service))
+            .serve_with_incoming(incoming));
+        if let Err(e) = result {
+            stopped(Error::other(e));
+        }
+    });
+    Ok(publisher)
+}
+
+struct StatsService(StatsPublisher);
+
+#[tonic::async_trait]
+impl Multiping for StatsService {
+    type StreamStatsStream = ReceiverStream<Result<HostStats, Status>>;
+
+    async fn stream_stats(&self, _request: Request<StreamStatsRequest>) -> Result<Response<Self::StreamStatsStream>, Status> {
+        // Subscribe before taking the snapshot, so that no update is missed in between
+        let mut updates = self.0.tx.subscribe();
+        let snapshot = self.0.latest.lock().unwrap().clone();
+        let (tx, rx) = mpsc::channel(BACKLOG);
+        tokio::spawn(async move {
+            for stats in snapshot.into_iter().filter(|s| !s.host.is_empty()) {
+                if tx.send(Ok(stats)).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                match updates.recv().await {
+                    Ok(stats) => if tx.send(Ok(stats)).await.is_err() {
+                        return;
+                    },
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+fn host_stats(index: usize, host: &HostInfo) -> HostStats {
+    let ms = |micros: Option<u64>| micros.map(|m| m as f64 / 1000.0);
+    let replied = host.successful > 0;
+    HostStats {
+        index: index as u32,
+        host: host.host_str.clone(),
+        address: host.host.ip().to_string(),
+        probe: host.probe.name().to_string(),
+        sent: host.pings_sent,
+        received: host.successful,
+        latest_ms: ms(host.latest_time),
+        min_ms: ms(host.min_time),
+        average_ms: replied.then(|| host.average() as f64),
+        max_ms: ms(host.max_time),
+        jitter_ms: replied.then(|| host.jitter() as f64).filter(|j| !j.is_nan()),
+        loss_percent: host.loss_percent().unwrap_or(0.0),
+        mos: host.call_quality().map(|q| q.mos),
+        down: host.is_down(),
+        error: host.last_error.map(|e| e.to_string()).unwrap_or_default(),
+        removed: host.removed,
+    }
+}
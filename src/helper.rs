@@ -0,0 +1,229 @@
+//! Privilege separation: a small helper (the `multiping-helper` binary, installed setuid root or with
+//! CAP_NET_RAW) creates the sockets that need privileges and passes them back over a Unix socket,
+//! so the rest of multiping can run unprivileged. The helper only makes a fixed set of sockets,
+//! and doesn't do anything else with them
+//!
+//! The privilege model:
+//! * multiping starts the helper itself, with one end of a socket pair as its stdin. The helper never listens on
+//!   anything, so only the process that started it can ask it for sockets
+//! * The helper only serves root and members of the `multiping` group (`HELPER_GROUP`), which it checks against its
+//!   real user and groups (those of whoever started it, even when it's setuid). Anyone else gets EACCES, so
+//!   installing it doesn't give every user on the machine raw sockets
+//! * A raw or packet socket lets its owner send and receive any ICMP (or ARP) traffic, which is what CAP_NET_RAW
+//!   allows anyway. The helper doesn't bind, connect or filter them, and can't be asked for any other kind
+//! * Only the effective group is changed, and only while making a ping socket (see `create_ping_socket`). A setuid
+//!   helper keeps running as root until multiping closes the socket pair
+//!
+//! Taking ownership of a descriptor that arrived with SCM_RIGHTS needs `OwnedFd::from_raw_fd`, which is unsafe as
+//! the compiler can't know where the number came from. nix only hands them over as raw descriptors, so this is one of
+//! the few places in the crate that allows unsafe code (see `take_fd`)
+
+use std::io::{Error, ErrorKind, IoSlice, IoSliceMut, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use nix::sys::socket::{ControlMessage, ControlMessageOwned, MsgFlags, recvmsg, sendmsg};
+use nix::unistd::{Group, getgid, getuid};
+#[cfg(not(target_vendor = "apple"))]
+use nix::unistd::getgroups;
+#[cfg(target_os = "linux")]
+use nix::unistd::{Gid, getegid, setegid};
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// The name of the helper binary, which is looked for next to the main one
+pub const HELPER_NAME: &str = "multiping-helper";
+
+/// Only root and members of this group can get sockets from the helper
+pub const HELPER_GROUP: &str = "multiping";
+
+/// Whether whoever started the helper is allowed to use it. A setuid program's real user and groups are still
+/// those of the user who ran it
+fn caller_allowed() -> bool {
+    if getuid().is_root() {
+        return true;
+    }
+    let Ok(Some(group)) = Group::from_name(HELPER_GROUP) else { return false };
+    #[cfg(not(target_vendor = "apple"))]
+    if getgroups().is_ok_and(|groups| groups.contains(&group.gid)) {
+        return true;
+    }
+    getgid() == group.gid
+}
+
+/// The sockets the helper is willing to make
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HelperSocket {
+    Icmp4,
+    Icmp6,
+    RawIcmp6, // For NDP, and pinging with raw sockets
+    PacketArp, // For ARP
+    RawIcmp4, // For pinging with raw sockets
+}
+
+impl HelperSocket {
+    fn from_byte(byte: u8) -> Option<HelperSocket> {
+        match byte {
+            1 => Some(HelperSocket::Icmp4),
+            2 => Some(HelperSocket::Icmp6),
+            3 => Some(HelperSocket::RawIcmp6),
+            4 => Some(HelperSocket::PacketArp),
+            5 => Some(HelperSocket::RawIcmp4),
+            _ => None,
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            HelperSocket::Icmp4 => 1,
+            HelperSocket::Icmp6 => 2,
+            HelperSocket::RawIcmp6 => 3,
+            HelperSocket::PacketArp => 4,
+            HelperSocket::RawIcmp4 => 5,
+        }
+    }
+
+    /// Creates the socket in this process
+    pub fn create(self) -> Result<Socket, Error> {
+        match self {
+            HelperSocket::Icmp4 => create_ping_socket(Domain::IPV4, Protocol::ICMPV4),
+            HelperSocket::Icmp6 => create_ping_socket(Domain::IPV6, Protocol::ICMPV6),
+            HelperSocket::RawIcmp6 => Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6)),
+            HelperSocket::RawIcmp4 => Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4)),
+            #[cfg(target_os = "linux")]
+            HelperSocket::PacketArp => Socket::new(Domain::from(libc::AF_PACKET), Type::DGRAM, Some(Protocol::from(crate::arp::ETH_P_ARP.to_be() as i32))),
+            #[cfg(not(target_os = "linux"))]
+            HelperSocket::PacketArp => Err(ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+/// Ping sockets depend on the group rather than capabilities, so if our group isn't allowed to make them,
+/// the helper (if it's setuid root) switches to one that is while it makes the socket
+fn create_ping_socket(domain: Domain, protocol: Protocol) -> Result<Socket, Error> {
+    let error = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+        Ok(socket) => return Ok(socket),
+        Err(e) => e,
+    };
+    #[cfg(target_os = "linux")]
+    if let Some((low, high)) = crate::privileges::ping_group_range() && low <= high && error.raw_os_error() == Some(libc::EACCES) {
+        let original = getegid();
+        if setegid(Gid::from_raw(low)).is_ok() {
+            let result = Socket::new(domain, Type::DGRAM, Some(protocol));
+            setegid(original).map_err(Error::from)?;
+            return result;
+        }
+    }
+    Err(error)
+}
+
+/// Runs the helper's side: reads one byte per request from `stream`, and answers each with an
+/// error number (0 for success) and, if it worked, the socket. Returns when the other end closes.
+/// Callers that aren't root or in `HELPER_GROUP` get EACCES for everything
+pub fn serve(mut stream: UnixStream) -> Result<(), Error> {
+    let allowed = caller_allowed();
+    let mut request = [0u8; 1];
+    loop {
+        if stream.read(&mut request)? == 0 {
+            return Ok(());
+        }
+        let result = HelperSocket::from_byte(request[0])
+            .filter(|_| allowed)
+            .ok_or(if allowed { Error::from(ErrorKind::InvalidInput) } else { Error::from_raw_os_error(libc::EACCES) })
+            .and_then(HelperSocket::create);
+        match result {
+            Ok(socket) => {
+                let status = 0i32.to_ne_bytes();
+                let fds = [socket.as_raw_fd()];
+                sendmsg::<()>(stream.as_raw_fd(), &[IoSlice::new(&status)], &[ControlMessage::ScmRights(&fds)], MsgFlags::empty(), None)
+                    .map_err(Error::from)?;
+            },
+            Err(e) => stream.write_all(&e.raw_os_error().unwrap_or(libc::EINVAL).to_ne_bytes())?,
+        }
+    }
+}
+
+/// A running helper process
+pub struct Helper {
+    stream: UnixStream,
+    _child: Child,
+}
+
+impl Helper {
+    /// Starts the helper, which talks to us over its stdin
+    pub fn spawn(path: &Path) -> Result<Helper, Error> {
+        let (ours, theirs) = UnixStream::pair()?;
+        let child = Command::new(path)
+            .stdin(Stdio::from(OwnedFd::from(theirs)))
+            .stdout(Stdio::null())
+            .spawn()?;
+        Ok(Helper { stream: ours, _child: child })
+    }
+
+    /// Asks the helper for a socket
+    pub fn request(&mut self, kind: HelperSocket) -> Result<Socket, Error> {
+        self.stream.write_all(&[kind.to_byte()])?;
+        let mut status = [0u8; 4];
+        let mut cmsg_buffer = nix::cmsg_space!([RawFd; 1]);
+        let mut iov = [IoSliceMut::new(&mut status)];
+        let msg = recvmsg::<()>(self.stream.as_raw_fd(), &mut iov, Some(&mut cmsg_buffer), MsgFlags::empty())
+            .map_err(Error::from)?;
+        if msg.bytes < 4 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let fd = msg.cmsgs().map_err(Error::from)?.find_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+            _ => None,
+        });
+        let errno = i32::from_ne_bytes(status);
+        match fd {
+            Some(fd) if errno == 0 => Ok(Socket::from(take_fd(fd))),
+            _ => Err(Error::from_raw_os_error(if errno == 0 { libc::EPROTO } else { errno })),
+        }
+    }
+}
+
+/// Takes ownership of a file descriptor that came with SCM_RIGHTS
+#[allow(unsafe_code)]
+fn take_fd(fd: RawFd) -> OwnedFd {
+    // SAFETY: the kernel has just given us this descriptor, and nothing else refers to it
+    unsafe { OwnedFd::from_raw_fd(fd) }
+}
+
+/// Where the helper is, if one should be used. The helper's only started once it's needed
+static HELPER_PATH: OnceLock<PathBuf> = OnceLock::new();
+static HELPER: Mutex<Option<Helper>> = Mutex::new(None);
+
+/// Uses the helper at `path` for sockets that this process isn't allowed to create
+pub fn install(path: PathBuf) {
+    let _ = HELPER_PATH.set(path);
+}
+
+/// The helper next to the current executable, if there is one
+pub fn find_helper() -> Option<PathBuf> {
+    let path = std::env::current_exe().ok()?.with_file_name(HELPER_NAME);
+    path.is_file().then_some(path)
+}
+
+/// If creating a socket failed because of permissions and a helper is installed, asks the helper for
+/// the socket instead. If that fails too (or there's no helper), the original error is returned
+pub fn fallback(error: Error, kind: HelperSocket) -> Result<Socket, Error> {
+    let denied = error.kind() == ErrorKind::PermissionDenied || error.raw_os_error() == Some(libc::EPERM);
+    let Some(path) = HELPER_PATH.get().filter(|_| denied) else {
+        return Err(error);
+    };
+    let mut helper = HELPER.lock().unwrap();
+    if helper.is_none() {
+        match Helper::spawn(path) {
+            Ok(h) => *helper = Some(h),
+            Err(_) => return Err(error),
+        }
+    }
+    let result = helper.as_mut().unwrap().request(kind);
+    // If the helper's gone away (rather than not being allowed either), start it again next time
+    if let Err(e) = &result && !matches!(e.raw_os_error(), Some(libc::EACCES) | Some(libc::EPERM)) {
+        *helper = None;
+    }
+    result.map_err(|_| error)
+}
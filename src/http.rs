@@ -0,0 +1,80 @@
+//! HTTP probe: times how long a web server takes to start answering a GET request, from opening the connection to
+//! the status line of the response. Any status counts as an answer, as it's the server being reachable that's
+//! measured. Only plain HTTP is spoken (`tls:` times the handshake of an HTTPS server)
+
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+use crate::probe::{Probe, ProbeReply, connect_tcp};
+
+/// The longest status line that's read, which is far longer than any real one
+const MAX_STATUS_LINE: usize = 1024;
+
+/// Sends a GET request for a path on a new connection each time, and waits for the status line
+pub struct HttpProbe {
+    addr: SocketAddr,
+    host: String, // For the Host header, e.g. "example.com:8080"
+    path: String,
+    /// The local address to connect from, or None to let the kernel choose
+    pub source: Option<IpAddr>,
+}
+
+impl HttpProbe {
+    /// `address` is the host as it was given without the "http:" prefix, e.g. "example.com:8080/health".
+    /// Without a path, "/" is requested
+    pub fn new(addr: SocketAddr, address: &str) -> HttpProbe {
+        let (host, path) = match address.find('/') {
+            Some(i) => address.split_at(i),
+            None => (address, "/"),
+        };
+        HttpProbe { addr, host: host.to_string(), path: path.to_string(), source: None }
+    }
+}
+
+impl Probe for HttpProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let start = Instant::now();
+        let remaining = || Some(timeout.saturating_sub(start.elapsed())).filter(|r| !r.is_zero()).ok_or(Error::from(ErrorKind::TimedOut));
+        let mut stream = connect_tcp(self.addr, self.source, timeout)?;
+        stream.set_write_timeout(Some(remaining()?))?;
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: multiping/{}\r\nConnection: close\r\n\r\n",
+            self.path, self.host, env!("CARGO_PKG_VERSION"));
+        stream.write_all(request.as_bytes())?;
+        let mut response = Vec::new();
+        let mut buf = [0u8; 256];
+        while !response.contains(&b'\n') && response.len() < MAX_STATUS_LINE {
+            stream.set_read_timeout(Some(remaining()?))?;
+            match stream.read(&mut buf) {
+                Ok(0) => return Err(Error::new(ErrorKind::UnexpectedEof, "the server closed the connection without answering")),
+                Ok(len) => response.extend_from_slice(&buf[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::TimedOut.into()),
+                Err(e) => return Err(e),
+            }
+        }
+        let latency = start.elapsed().as_micros() as u64;
+        match status_code(&response) {
+            Some(_) => Ok(ProbeReply::new(latency)),
+            None => Err(Error::new(ErrorKind::InvalidData, "the answer wasn't an HTTP response")),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.source {
+            Some(source) => format!("HTTP GET http://{}{} at {} from {}", self.host, self.path, self.addr, source),
+            None => format!("HTTP GET http://{}{} at {}", self.host, self.path, self.addr),
+        }
+    }
+}
+
+/// The status code from the start of a response, e.g. 200 from "HTTP/1.1 200 OK"
+pub fn status_code(response: &[u8]) -> Option<u16> {
+    let line = response.split(|b| *b == b'\n').next()?;
+    let mut parts = std::str::from_utf8(line).ok()?.split_whitespace();
+    parts.next().filter(|version| version.starts_with("HTTP/"))?;
+    parts.next()?.parse().ok().filter(|code| (100..1000).contains(code))
+}
@@ -1,9 +1,16 @@
-/// Structs for ICMPv4 and ICMPv6
+//! Structs for ICMPv4 and ICMPv6
 
 // Sources: https://en.wikipedia.org/wiki/Internet_Control_Message_Protocol
 // and https://en.wikipedia.org/wiki/ICMPv6
 
-#[derive(Debug)]
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::ipv4::Ipv4Header;
+use crate::ipv6::Ipv6Header;
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ICMPv4Message {
     /// Type of control message, including the code
     pub icmpv4_type: ICMPv4Type, // on wire: two bytes (type: u8 and code: u8)
@@ -13,7 +20,8 @@ pub struct ICMPv4Message {
     pub icmpv4_data: Vec<u8>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ICMPv4Type {
     EchoReply { // #0
         identifier: u16,
@@ -22,6 +30,7 @@ pub enum ICMPv4Type {
     // #1 and #2 are unassigned & reserved
     DestinationUnreachable { // #3
         code: DestinationUnreachableCode,
+        /// Length of the original datagram in 32-bit words, if there are extensions after it (RFC 4884)
         length: u8,
         next_hop_mtu: u16,
         // The header data is unused
@@ -37,13 +46,21 @@ pub enum ICMPv4Type {
         identifier: u16,
         sequence_num: u16,
     },
-    RouterAdvertisement {}, // #9
+    RouterAdvertisement { // #9
+        address_count: u8, // The addresses and their preference levels are in the data
+        entry_size: u8, // In 32-bit words, which is 2 for an address and its preference
+        lifetime: u16, // Seconds
+    },
     RouterSolicitation {}, // #10
     TimeExceeded { // #11
-        code: TimeExceededCode
+        code: TimeExceededCode,
+        length: u8, // as for DestinationUnreachable
     },
     BadIPHeader { // #12
-        code: BadIPHeaderCode
+        code: BadIPHeaderCode,
+        /// Which byte of the original datagram's header is wrong, for `PointerIndicatesError`
+        pointer: u8,
+        length: u8, // as for DestinationUnreachable
     },
     Timestamp { // #13
         identifier: u16,
@@ -63,7 +80,8 @@ pub enum ICMPv4Type {
     // (except for Extended Echo Request/Reply)
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DestinationUnreachableCode {
     NetworkUnreachable, // #0
     HostUnreachable, // #1
@@ -83,7 +101,8 @@ pub enum DestinationUnreachableCode {
     PrecedenceCuttoffInEffect, // #15
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RedirectMsgCode {
     Network, // #0
     Host, // #1
@@ -91,125 +110,674 @@ pub enum RedirectMsgCode {
     ToSAndHost, // #3
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TimeExceededCode {
     ExpiredInTransit, // #0
     FragmentReassemblyTimeExceeded, // #1
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BadIPHeaderCode {
     PointerIndicatesError, // #0
     MissingRequiredOption, // #1
     BadLength, // #2
 }
 
-pub enum IntoICMPError {
-    UnknownType,
-    UnknownCode,
-    NotLongEnough,
-    OtherError,
+/// Why a message (or an IP header) couldn't be parsed. Offsets are from the start of the bytes being parsed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownType { icmp_type: u8 },
+    UnknownCode { icmp_type: u8, code: u8 },
+    /// The field at `offset` needs the message to be at least `required` bytes long, but it's only `actual`
+    NotLongEnough { offset: usize, required: usize, actual: usize },
+    /// The message was cut off at `actual` bytes because it didn't fit in the receive buffer
+    Truncated { actual: usize },
+    /// The field at `offset` has a value that can't be right
+    Invalid { offset: usize, reason: &'static str },
+    /// The checksum in the message isn't the one worked out from the rest of it
+    BadChecksum { checksum: u16, computed: u16 },
 }
 
-impl TryFrom<&[u8]> for ICMPv4Message {
-    type Error = IntoICMPError;
+/// The old name of `ParseError`, from before it had the details of what went wrong
+#[deprecated(note = "renamed to ParseError")]
+pub type IntoICMPError = ParseError;
 
-    fn try_from(msgbytes: &[u8]) -> Result<Self, Self::Error> {
-        let icmpv4_checksum = be_u16(msgbytes, 2);
-        let icmpv4_data = msgbytes[8..].to_vec();
-        match msgbytes[0] { // Match on the type
-            0 => Ok(ICMPv4Message {
-                icmpv4_type: ICMPv4Type::EchoReply {
-                    identifier: be_u16(msgbytes, 4),
-                    sequence_num: be_u16(msgbytes, 6)
-                },
-                icmpv4_checksum, icmpv4_data}),
+/// The extension structure that can follow the original datagram in an error message (RFC 4884), which
+/// routers use to add things like the MPLS labels of the packet that caused the error
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ICMPExtensions {
+    pub version: u8, // on wire: the top 4 bits of the first byte, followed by 12 reserved bits
+    pub checksum: u16,
+    pub objects: Vec<ICMPExtensionObject>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ICMPExtensionObject {
+    /// What the object is about, e.g. 1 for an MPLS label stack
+    pub class_num: u8,
+    /// The kind of object within its class
+    pub c_type: u8,
+    pub payload: Vec<u8>, // on wire: preceded by a u16 length that includes the 4 byte object header
+}
+
+/// An entry in the MPLS label stack of the packet that caused an error, which routers in MPLS networks add as an
+/// extension object (RFC 4950), so the label switched path it was on can be seen
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MplsLabel {
+    pub label: u32, // on wire: 20 bits
+    /// Experimental use bits, which are now the traffic class (3 bits)
+    pub exp: u8,
+    /// Whether this is the last entry in the stack
+    pub bottom_of_stack: bool,
+    pub ttl: u8,
+}
+
+/// The only version of the extension structure there is
+const EXTENSION_VERSION: u8 = 2;
+
+/// Class and type of the extension object with the MPLS label stack of the packet as it arrived
+const MPLS_CLASS: u8 = 1;
+const MPLS_INCOMING_STACK: u8 = 1;
+
+impl ICMPExtensions {
+    /// The MPLS label stack of the packet that caused the error, from the top down, if the router added it
+    pub fn mpls_labels(&self) -> Vec<MplsLabel> {
+        self.objects.iter().filter_map(ICMPExtensionObject::mpls_labels).flatten().collect()
+    }
+}
+
+impl ICMPExtensionObject {
+    /// The label stack entries in the object, or None if it isn't an MPLS label stack
+    pub fn mpls_labels(&self) -> Option<Vec<MplsLabel>> {
+        if self.class_num != MPLS_CLASS || self.c_type != MPLS_INCOMING_STACK {
+            return None;
+        }
+        Some(self.payload.chunks_exact(4).map(|entry| {
+            let entry = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            MplsLabel {
+                label: entry >> 12,
+                exp: (entry >> 9 & 0x7) as u8,
+                bottom_of_stack: entry & 0x100 != 0,
+                ttl: entry as u8,
+            }
+        }).collect())
+    }
+}
+
+/// e.g. "16005 (exp 0, TTL 254)"
+impl fmt::Display for MplsLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (exp {}, TTL {})", self.label, self.exp, self.ttl)
+    }
+}
+
+/// Where routers that don't fill in the length of the original datagram put the extensions
+const LEGACY_ORIGINAL_LENGTH: usize = 128;
+
+impl TryFrom<&[u8]> for ICMPExtensions {
+    type Error = ParseError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let header = be_bytes::<4>(bytes, 0)?;
+        let version = header[0] >> 4;
+        if version != EXTENSION_VERSION {
+            return Err(ParseError::Invalid { offset: 0, reason: "extension structure version isn't 2" });
+        }
+        let mut objects = Vec::new();
+        let mut start = 4;
+        while start < bytes.len() {
+            let length = be_u16(bytes, start)? as usize;
+            // The length includes the object's header, so anything less would never get to the next object
+            if length < 4 {
+                return Err(ParseError::Invalid { offset: start, reason: "extension object shorter than its header" });
+            }
+            let object = slice(bytes, start, length)?;
+            objects.push(ICMPExtensionObject {
+                class_num: object[2],
+                c_type: object[3],
+                payload: object[4..].to_vec(),
+            });
+            start += length;
+        }
+        Ok(ICMPExtensions { version, checksum: u16::from_be_bytes([header[2], header[3]]), objects })
+    }
+}
+
+/// Splits the data of an error message into the original datagram and the extension structure after it (if there
+/// is one), given how long the message says the original datagram is
+fn split_extensions(data: &[u8], original_length: Option<usize>) -> (&[u8], Option<&[u8]>) {
+    match original_length {
+        // Routers from before RFC 4884 always put the extensions after 128 bytes of the original datagram, so
+        // anything there with the right version and checksum is taken to be them
+        Some(0) => match data.split_at_checked(LEGACY_ORIGINAL_LENGTH) {
+            Some((original, extensions)) if extensions.len() >= 4 && extensions[0] >> 4 == EXTENSION_VERSION
+                && internet_checksum(extensions) == 0 => (original, Some(extensions)),
+            _ => (data, None),
+        },
+        Some(length) => match data.split_at_checked(length) {
+            Some((original, extensions)) if !extensions.is_empty() => (original, Some(extensions)),
+            // If it was cut off before the end of the original datagram, there's only the original datagram
+            _ => (data, None),
+        },
+        None => (data, None),
+    }
+}
+
+/// What can be told about the datagram an error message is about from the start of it, which is quoted in the message
+/// (at least its IP header and the first 8 bytes after it), so the error can be matched to the request that caused it
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OriginalDatagram {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    /// Protocol (or IPv6 next header) number, e.g. 1 for ICMP or 58 for ICMPv6
+    pub protocol: u8,
+    /// The ICMP type, if it was an ICMP message
+    pub icmp_type: Option<u8>,
+    /// The identifier and sequence number, if it was an ICMP request with them (e.g. an echo request)
+    pub identifier: Option<u16>,
+    pub sequence: Option<u16>,
+}
+
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_ICMPV6: u8 = 58;
+
+impl TryFrom<&[u8]> for OriginalDatagram {
+    type Error = ParseError;
+
+    /// Works out the IP version from the header
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let (source, destination, protocol, payload) = match byte(bytes, 0)? >> 4 {
+            4 => {
+                let (header, payload) = Ipv4Header::split(bytes)?;
+                // Only the first fragment starts with the ICMP header
+                let payload = if header.is_first_fragment() { payload } else { &[] };
+                (IpAddr::V4(header.source), IpAddr::V4(header.destination), header.protocol, payload)
+            },
+            6 => {
+                // The ICMPv6 header comes after any extension headers
+                let (header, payload) = Ipv6Header::split(bytes)?;
+                let payload = if header.is_first_fragment() { payload } else { &[] };
+                (IpAddr::V6(header.source), IpAddr::V6(header.destination), header.protocol, payload)
+            },
+            _ => return Err(ParseError::Invalid { offset: 0, reason: "not an IPv4 or IPv6 datagram" }),
+        };
+        let icmp_type = matches!((source, protocol), (IpAddr::V4(_), PROTOCOL_ICMP) | (IpAddr::V6(_), PROTOCOL_ICMPV6))
+            .then(|| payload.first().copied()).flatten();
+        // Echo and timestamp requests have the identifier and sequence number in the same place
+        let request = matches!((source, icmp_type), (IpAddr::V4(_), Some(8 | 13)) | (IpAddr::V6(_), Some(128)));
+        let (identifier, sequence) = if request {
+            (be_u16(payload, 4).ok(), be_u16(payload, 6).ok())
+        } else {
+            (None, None)
+        };
+        Ok(OriginalDatagram { source, destination, protocol, icmp_type, identifier, sequence })
+    }
+}
+
+/// e.g. "192.0.2.1 to 198.51.100.7, echo request, identifier 0x1234, sequence 5"
+impl fmt::Display for OriginalDatagram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} to {}", self.source, self.destination)?;
+        match (self.icmp_type, self.protocol) {
+            (Some(icmp_type), _) => write!(f, ", {}", type_name(icmp_type, self.source.is_ipv4()).unwrap_or("unknown ICMP message"))?,
+            (None, 6) => f.write_str(", TCP")?,
+            (None, 17) => f.write_str(", UDP")?,
+            (None, protocol) => write!(f, ", protocol {}", protocol)?,
+        }
+        if let Some(identifier) = self.identifier {
+            write!(f, ", identifier {:#06x}", identifier)?;
+        }
+        if let Some(sequence) = self.sequence {
+            write!(f, ", sequence {}", sequence)?;
+        }
+        Ok(())
+    }
+}
+
+impl ParseError {
+    /// `NotLongEnough`, for a field of `len` bytes at `offset` in `bytes`
+    pub(crate) fn not_long_enough(bytes: &[u8], offset: usize, len: usize) -> ParseError {
+        ParseError::NotLongEnough { offset, required: offset.saturating_add(len), actual: bytes.len() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownType { icmp_type } => write!(f, "unknown message type {}", icmp_type),
+            ParseError::UnknownCode { icmp_type, code } => write!(f, "unknown code {} for message type {}", code, icmp_type),
+            ParseError::NotLongEnough { offset, required, actual } =>
+                write!(f, "message too short: {} bytes, but the field at byte {} needs at least {}", actual, offset, required),
+            ParseError::Truncated { actual } => write!(f, "message truncated to {} bytes", actual),
+            ParseError::Invalid { offset, reason } => write!(f, "invalid message: {} (at byte {})", reason, offset),
+            ParseError::BadChecksum { checksum, computed } => write!(f, "bad checksum {:#06x} (should be {:#06x})", checksum, computed),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<ParseError> for std::io::Error {
+    fn from(e: ParseError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// A borrowed view of an ICMPv4 message (e.g. in a receive buffer), which reads the fields when they're asked for
+/// instead of copying the data out like `ICMPv4Message` does
+#[derive(Clone, Copy, Debug)]
+pub struct ICMPv4Packet<'a> {
+    bytes: &'a [u8],
+    /// Type, code, checksum and the rest of the header, which every message has
+    header: &'a [u8; 8],
+    data: &'a [u8],
+}
+
+impl<'a> ICMPv4Packet<'a> {
+    /// Only checks that there's a whole header. Fields after it are read with `be_u16`/`be_u32`/`byte`,
+    /// which check the length too, so a short message can't panic
+    pub fn new(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let (header, data) = bytes.split_first_chunk::<8>().ok_or(ParseError::not_long_enough(bytes, 0, 8))?;
+        Ok(ICMPv4Packet { bytes, header, data })
+    }
+
+    pub fn icmp_type(&self) -> u8 {
+        self.header[0]
+    }
+
+    pub fn code(&self) -> u8 {
+        self.header[1]
+    }
+
+    /// The code as one of the code enums, with this message's type in the error if it isn't known
+    fn code_as<T: TryFrom<u8, Error = ParseError>>(&self) -> Result<T, ParseError> {
+        T::try_from(self.code()).map_err(|_| ParseError::UnknownCode { icmp_type: self.icmp_type(), code: self.code() })
+    }
+
+    /// The checksum, as it is in the message
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes([self.header[2], self.header[3]])
+    }
+
+    /// Everything after the 8 byte header
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// The whole message
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// As for `ICMPv4Message::original_datagram`
+    pub fn original_datagram(&self) -> &'a [u8] {
+        split_extensions(self.data, self.message_type().ok().and_then(|t| t.original_length())).0
+    }
+
+    /// As for `ICMPv4Message::extensions`
+    pub fn extensions(&self) -> Result<Option<ICMPExtensions>, ParseError> {
+        split_extensions(self.data, self.message_type()?.original_length()).1.map(ICMPExtensions::try_from).transpose()
+    }
+
+    /// As for `ICMPv4Message::original`
+    pub fn original(&self) -> Option<OriginalDatagram> {
+        self.message_type().is_ok_and(|t| t.is_error()).then(|| OriginalDatagram::try_from(self.original_datagram()).ok()).flatten()
+    }
+
+    /// Parses the type, code and the fields that go with them
+    pub fn message_type(&self) -> Result<ICMPv4Type, ParseError> {
+        match self.icmp_type() {
+            0 => Ok(ICMPv4Type::EchoReply {
+                identifier: be_u16(self.bytes, 4)?,
+                sequence_num: be_u16(self.bytes, 6)?
+            }),
             3 => {
-                let code: DestinationUnreachableCode = msgbytes[1].try_into()?;
-                Ok(ICMPv4Message {
-                    icmpv4_type: ICMPv4Type::DestinationUnreachable {
-                        code,
-                        length: msgbytes[5],
-                        next_hop_mtu: be_u16(msgbytes, 6)
-                    }, icmpv4_checksum, icmpv4_data})
-            },
-            4 => Ok(ICMPv4Message {
-                icmpv4_type: ICMPv4Type::SourceQuench {},
-                icmpv4_checksum, icmpv4_data}),
+                let code: DestinationUnreachableCode = self.code_as()?;
+                Ok(ICMPv4Type::DestinationUnreachable {
+                    code,
+                    length: byte(self.bytes, 5)?,
+                    next_hop_mtu: be_u16(self.bytes, 6)?
+                })
+            },
+            4 => Ok(ICMPv4Type::SourceQuench {}),
             5 => {
-                let code: RedirectMsgCode = msgbytes[1].try_into()?;
-                Ok(ICMPv4Message {
-                    icmpv4_type: ICMPv4Type::RedirectMessage {
-                        code,
-                        address: be_u32(msgbytes, 4)
-                    },
-                    icmpv4_checksum, icmpv4_data})
-            },
-            6 => Ok(ICMPv4Message {
-                icmpv4_type: ICMPv4Type::SourceQuench {},
-                icmpv4_checksum, icmpv4_data}),
-            8 => Ok(ICMPv4Message {
-                icmpv4_type: ICMPv4Type::EchoRequest {
-                    identifier: be_u16(msgbytes, 4),
-                    sequence_num: be_u16(msgbytes, 6)
-                },
-                icmpv4_checksum, icmpv4_data}),
-            9 => Ok(ICMPv4Message {
-                icmpv4_type: ICMPv4Type::RouterAdvertisement {},
-                icmpv4_checksum, icmpv4_data}),
-            10 => Ok(ICMPv4Message {
-                icmpv4_type: ICMPv4Type::RouterSolicitation {},
-                icmpv4_checksum, icmpv4_data}),
+                let code: RedirectMsgCode = self.code_as()?;
+                Ok(ICMPv4Type::RedirectMessage {
+                    code,
+                    address: be_u32(self.bytes, 4)?
+                })
+            },
+            6 => Ok(ICMPv4Type::AlternateHostAddress {}),
+            8 => Ok(ICMPv4Type::EchoRequest {
+                identifier: be_u16(self.bytes, 4)?,
+                sequence_num: be_u16(self.bytes, 6)?
+            }),
+            9 => Ok(ICMPv4Type::RouterAdvertisement {
+                address_count: byte(self.bytes, 4)?,
+                entry_size: byte(self.bytes, 5)?,
+                lifetime: be_u16(self.bytes, 6)?
+            }),
+            10 => Ok(ICMPv4Type::RouterSolicitation {}),
             11 => {
-                let code: TimeExceededCode = match msgbytes[1] {
+                let code: TimeExceededCode = match self.code() {
                     0 => TimeExceededCode::ExpiredInTransit,
                     1 => TimeExceededCode::FragmentReassemblyTimeExceeded,
-                    _ => return Err(IntoICMPError::UnknownCode),
+                    _ => return Err(ParseError::UnknownCode { icmp_type: self.icmp_type(), code: self.code() }),
                 };
-                Ok(ICMPv4Message {
-                    icmpv4_type: ICMPv4Type::TimeExceeded {
-                        code,
-                    },
-                    icmpv4_checksum, icmpv4_data})
+                Ok(ICMPv4Type::TimeExceeded {
+                    code,
+                    length: byte(self.bytes, 5)?,
+                })
             },
             12 => {
-                let code: BadIPHeaderCode = match msgbytes[1] {
+                let code: BadIPHeaderCode = match self.code() {
                     0 => BadIPHeaderCode::PointerIndicatesError,
                     1 => BadIPHeaderCode::MissingRequiredOption,
                     2 => BadIPHeaderCode::BadLength,
-                    _ => return Err(IntoICMPError::UnknownCode),
+                    _ => return Err(ParseError::UnknownCode { icmp_type: self.icmp_type(), code: self.code() }),
                 };
-                Ok(ICMPv4Message {
-                    icmpv4_type: ICMPv4Type::BadIPHeader {
-                        code,
-                    },
-                    icmpv4_checksum, icmpv4_data})
-            },
-            13 => Ok(ICMPv4Message {
-                icmpv4_type: ICMPv4Type::Timestamp {
-                    identifier: be_u16(msgbytes, 4),
-                    sequence_num: be_u16(msgbytes, 6),
-                    ts_originate: be_u32(msgbytes, 8),
-                    ts_receive: be_u32(msgbytes, 12),
-                    ts_transmit:  be_u32(msgbytes, 16)
-                },
-                icmpv4_checksum, icmpv4_data}),
-            14 => Ok(ICMPv4Message {
-                icmpv4_type: ICMPv4Type::TimestampReply {
-                    identifier: be_u16(msgbytes, 4),
-                    sequence_num: be_u16(msgbytes, 6),
-                    ts_originate: be_u32(msgbytes, 8),
-                    ts_receive: be_u32(msgbytes, 12),
-                    ts_transmit:  be_u32(msgbytes, 16)
+                Ok(ICMPv4Type::BadIPHeader {
+                    code,
+                    pointer: byte(self.bytes, 4)?,
+                    length: byte(self.bytes, 5)?,
+                })
+            },
+            13 => Ok(ICMPv4Type::Timestamp {
+                identifier: be_u16(self.bytes, 4)?,
+                sequence_num: be_u16(self.bytes, 6)?,
+                ts_originate: be_u32(self.bytes, 8)?,
+                ts_receive: be_u32(self.bytes, 12)?,
+                ts_transmit:  be_u32(self.bytes, 16)?
+            }),
+            14 => Ok(ICMPv4Type::TimestampReply {
+                identifier: be_u16(self.bytes, 4)?,
+                sequence_num: be_u16(self.bytes, 6)?,
+                ts_originate: be_u32(self.bytes, 8)?,
+                ts_receive: be_u32(self.bytes, 12)?,
+                ts_transmit:  be_u32(self.bytes, 16)?
+            }),
+            _ => Err(ParseError::UnknownType { icmp_type: self.icmp_type() })
+        }
+    }
+}
+
+impl TryFrom<ICMPv4Packet<'_>> for ICMPv4Message {
+    type Error = ParseError;
+
+    fn try_from(packet: ICMPv4Packet<'_>) -> Result<Self, Self::Error> {
+        Ok(ICMPv4Message {
+            icmpv4_type: packet.message_type()?,
+            icmpv4_checksum: packet.checksum(),
+            icmpv4_data: packet.data().to_vec(),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for ICMPv4Message {
+    type Error = ParseError;
+
+    fn try_from(msgbytes: &[u8]) -> Result<Self, Self::Error> {
+        ICMPv4Packet::new(msgbytes)?.try_into()
+    }
+}
+
+impl ICMPv4Type {
+    /// The type and code numbers, as they are on the wire
+    pub fn type_code(&self) -> (u8, u8) {
+        match self {
+            ICMPv4Type::EchoReply { .. } => (0, 0),
+            ICMPv4Type::DestinationUnreachable { code, .. } => (3, *code as u8),
+            ICMPv4Type::SourceQuench {} => (4, 0),
+            ICMPv4Type::RedirectMessage { code, .. } => (5, *code as u8),
+            ICMPv4Type::AlternateHostAddress {} => (6, 0),
+            ICMPv4Type::EchoRequest { .. } => (8, 0),
+            ICMPv4Type::RouterAdvertisement { .. } => (9, 0),
+            ICMPv4Type::RouterSolicitation {} => (10, 0),
+            ICMPv4Type::TimeExceeded { code, .. } => (11, *code as u8),
+            ICMPv4Type::BadIPHeader { code, .. } => (12, *code as u8),
+            ICMPv4Type::Timestamp { .. } => (13, 0),
+            ICMPv4Type::TimestampReply { .. } => (14, 0),
+        }
+    }
+
+    /// The fields that only this type of message has, as names and values
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            ICMPv4Type::EchoReply { identifier, sequence_num } | ICMPv4Type::EchoRequest { identifier, sequence_num } => {
+                vec![("identifier", format!("{:#06x}", identifier)), ("sequence", sequence_num.to_string())]
+            },
+            ICMPv4Type::DestinationUnreachable { code, length, next_hop_mtu } => {
+                let mut fields = error_fields(code, *length as usize * 4);
+                if *next_hop_mtu > 0 {
+                    fields.push(("next-hop MTU", next_hop_mtu.to_string()));
+                }
+                fields
+            },
+            ICMPv4Type::RedirectMessage { code, address } => {
+                vec![("reason", format!("{:?}", code)), ("gateway", Ipv4Addr::from(*address).to_string())]
+            },
+            ICMPv4Type::RouterAdvertisement { address_count, lifetime, .. } => {
+                vec![("addresses", address_count.to_string()), ("lifetime", format!("{} s", lifetime))]
+            },
+            ICMPv4Type::TimeExceeded { code, length } => error_fields(code, *length as usize * 4),
+            ICMPv4Type::BadIPHeader { code, pointer, length } => {
+                let mut fields = error_fields(code, *length as usize * 4);
+                if matches!(code, BadIPHeaderCode::PointerIndicatesError) {
+                    fields.push(("pointer", pointer.to_string()));
+                }
+                fields
+            },
+            ICMPv4Type::Timestamp { identifier, sequence_num, ts_originate, ts_receive, ts_transmit }
+                | ICMPv4Type::TimestampReply { identifier, sequence_num, ts_originate, ts_receive, ts_transmit } => vec![
+                    ("identifier", format!("{:#06x}", identifier)),
+                    ("sequence", sequence_num.to_string()),
+                    ("originate", format_timestamp(*ts_originate)),
+                    ("receive", format_timestamp(*ts_receive)),
+                    ("transmit", format_timestamp(*ts_transmit)),
+                ],
+            ICMPv4Type::SourceQuench {} | ICMPv4Type::AlternateHostAddress {}
+                | ICMPv4Type::RouterSolicitation {} => Vec::new(),
+        }
+    }
+
+    /// How many bytes of the data are the original datagram, for the error messages that can have extensions
+    /// after it (RFC 4884). `Some(0)` means the sender didn't say
+    pub fn original_length(&self) -> Option<usize> {
+        match self {
+            ICMPv4Type::DestinationUnreachable { length, .. } | ICMPv4Type::TimeExceeded { length, .. }
+                | ICMPv4Type::BadIPHeader { length, .. } => Some(*length as usize * 4),
+            _ => None,
+        }
+    }
+
+    /// Whether it's an error message, which has the start of the datagram it's about as its data
+    pub fn is_error(&self) -> bool {
+        matches!(self, ICMPv4Type::DestinationUnreachable { .. } | ICMPv4Type::SourceQuench {} | ICMPv4Type::RedirectMessage { .. }
+            | ICMPv4Type::TimeExceeded { .. } | ICMPv4Type::BadIPHeader { .. })
+    }
+
+    /// The 4 bytes after the checksum, as they are on the wire
+    pub fn rest_of_header(&self) -> [u8; 4] {
+        match self {
+            ICMPv4Type::EchoReply { identifier, sequence_num } | ICMPv4Type::EchoRequest { identifier, sequence_num }
+                | ICMPv4Type::Timestamp { identifier, sequence_num, .. }
+                | ICMPv4Type::TimestampReply { identifier, sequence_num, .. } => concat_u16(*identifier, *sequence_num),
+            ICMPv4Type::DestinationUnreachable { length, next_hop_mtu, .. } => {
+                let mtu = next_hop_mtu.to_be_bytes();
+                [0, *length, mtu[0], mtu[1]]
+            },
+            ICMPv4Type::RedirectMessage { address, .. } => address.to_be_bytes(),
+            ICMPv4Type::TimeExceeded { length, .. } => [0, *length, 0, 0],
+            ICMPv4Type::BadIPHeader { pointer, length, .. } => [*pointer, *length, 0, 0],
+            ICMPv4Type::RouterAdvertisement { address_count, entry_size, lifetime } => {
+                let lifetime = lifetime.to_be_bytes();
+                [*address_count, *entry_size, lifetime[0], lifetime[1]]
+            },
+            ICMPv4Type::SourceQuench {} | ICMPv4Type::AlternateHostAddress {} | ICMPv4Type::RouterSolicitation {} => [0; 4],
+        }
+    }
+}
+
+impl ICMPv4Message {
+    /// Appends the message to `buf` as it would be sent, with the checksum worked out again (so `icmpv4_checksum`
+    /// isn't used). The fields of the type are written over the start of the data if they're in it
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        let (icmp_type, code) = self.icmpv4_type.type_code();
+        buf.extend_from_slice(&[icmp_type, code, 0, 0]);
+        buf.extend_from_slice(&self.icmpv4_type.rest_of_header());
+        match &self.icmpv4_type {
+            ICMPv4Type::Timestamp { ts_originate, ts_receive, ts_transmit, .. }
+                | ICMPv4Type::TimestampReply { ts_originate, ts_receive, ts_transmit, .. } => {
+                for timestamp in [ts_originate, ts_receive, ts_transmit] {
+                    buf.extend_from_slice(&timestamp.to_be_bytes());
+                }
+                buf.extend_from_slice(self.icmpv4_data.get(12..).unwrap_or_default());
+            },
+            _ => buf.extend_from_slice(&self.icmpv4_data),
+        }
+        let checksum = internet_checksum(&buf[start..]);
+        buf[start + 2..start + 4].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    /// The message as it would be sent, as for `write_to`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf);
+        buf
+    }
+
+    /// The data, without any extensions after the original datagram of an error message
+    pub fn original_datagram(&self) -> &[u8] {
+        split_extensions(&self.icmpv4_data, self.icmpv4_type.original_length()).0
+    }
+
+    /// Extra information that routers add after the original datagram of an error message (RFC 4884)
+    pub fn extensions(&self) -> Result<Option<ICMPExtensions>, ParseError> {
+        split_extensions(&self.icmpv4_data, self.icmpv4_type.original_length()).1.map(ICMPExtensions::try_from).transpose()
+    }
+
+    /// Where the datagram an error message is about was going, and which request it was if it was one of ours,
+    /// or None if it isn't an error or too little of the datagram came back
+    pub fn original(&self) -> Option<OriginalDatagram> {
+        self.icmpv4_type.is_error().then(|| OriginalDatagram::try_from(self.original_datagram()).ok()).flatten()
+    }
+
+    /// Every field on its own line, followed by a hex dump of the data and the extensions
+    pub fn dump(&self) -> String {
+        let (icmp_type, code) = self.icmpv4_type.type_code();
+        let extensions = self.extensions();
+        let mut s = dump_message(icmp_type, code, true, self.icmpv4_checksum,
+            &message_fields(self.icmpv4_type.fields(), self.original(), &extensions), self.original_datagram());
+        s.push_str(&dump_extensions(&extensions));
+        s
+    }
+}
+
+/// One line, e.g. "echo reply (type 0, code 0), identifier 0x1234, sequence 5, 56 bytes of data"
+impl fmt::Display for ICMPv4Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (icmp_type, code) = self.icmpv4_type.type_code();
+        let fields = message_fields(self.icmpv4_type.fields(), self.original(), &self.extensions());
+        format_message(f, icmp_type, code, true, &fields, self.original_datagram().len())
+    }
+}
+
+/// An ICMP timestamp, which is in milliseconds since midnight UTC unless the top bit is set
+fn format_timestamp(timestamp: u32) -> String {
+    if timestamp & 0x8000_0000 != 0 {
+        return format!("{:#010x} (not standard)", timestamp);
+    }
+    let (secs, millis) = (timestamp / 1000, timestamp % 1000);
+    format!("{:02}:{:02}:{:02}.{:03} UTC", secs / 3600, secs / 60 % 60, secs % 60, millis)
+}
+
+fn format_message(f: &mut fmt::Formatter<'_>, icmp_type: u8, code: u8, ipv4: bool, fields: &[(&str, String)], data_len: usize) -> fmt::Result {
+    write!(f, "{} (type {}, code {})", type_name(icmp_type, ipv4).unwrap_or("unknown"), icmp_type, code)?;
+    for (name, value) in fields {
+        write!(f, ", {} {}", name, value)?;
+    }
+    write!(f, ", {} bytes of data", data_len)
+}
+
+fn dump_message(icmp_type: u8, code: u8, ipv4: bool, checksum: u16, fields: &[(&str, String)], data: &[u8]) -> String {
+    let mut s = format!("{:<14}{} ({})\n", "type", icmp_type, type_name(icmp_type, ipv4).unwrap_or("unknown"));
+    s.push_str(&format!("{:<14}{}\n", "code", code));
+    s.push_str(&format!("{:<14}{:#06x}\n", "checksum", checksum));
+    for (name, value) in fields {
+        s.push_str(&format!("{:<14}{}\n", name, value));
+    }
+    s.push_str(&format!("data ({} bytes)\n", data.len()));
+    s.push_str(&hex_dump(data));
+    s
+}
+
+/// The reason for an error message, and how much of the data is the original datagram if the sender said
+fn error_fields(code: &impl fmt::Debug, original_length: usize) -> Vec<(&'static str, String)> {
+    let mut fields = vec![("reason", format!("{:?}", code))];
+    if original_length > 0 {
+        fields.push(("length", format!("{} bytes", original_length)));
+    }
+    fields
+}
+
+/// The fields of a message's type, what the original datagram was (for errors) and how many extension objects it has
+fn message_fields(mut fields: Vec<(&'static str, String)>, original: Option<OriginalDatagram>,
+    extensions: &Result<Option<ICMPExtensions>, ParseError>) -> Vec<(&'static str, String)> {
+    if let Some(original) = original {
+        fields.push(("original", original.to_string()));
+    }
+    match extensions {
+        Ok(Some(extensions)) => {
+            fields.push(("extensions", format!("{} objects", extensions.objects.len())));
+            let labels: Vec<String> = extensions.mpls_labels().iter().map(MplsLabel::to_string).collect();
+            if !labels.is_empty() {
+                fields.push(("MPLS labels", labels.join(", ")));
+            }
+        },
+        Err(e) => fields.push(("extensions", e.to_string())),
+        Ok(None) => {},
+    }
+    fields
+}
+
+/// Each extension object's class and type, followed by a hex dump of it
+fn dump_extensions(extensions: &Result<Option<ICMPExtensions>, ParseError>) -> String {
+    let mut s = String::new();
+    if let Ok(Some(extensions)) = extensions {
+        for object in &extensions.objects {
+            s.push_str(&format!("extension object (class {}, type {}, {} bytes)\n", object.class_num, object.c_type, object.payload.len()));
+            match object.mpls_labels() {
+                Some(labels) => for label in labels {
+                    s.push_str(&format!("{:<14}{}{}\n", "MPLS label", label, if label.bottom_of_stack { ", bottom of stack" } else { "" }));
                 },
-                icmpv4_checksum, icmpv4_data}),
-            _ => Err(IntoICMPError::UnknownType)
+                None => s.push_str(&hex_dump(&object.payload)),
+            }
         }
     }
+    s
+}
+
+/// Sixteen bytes per line, with the offset at the start and the printable ones at the end, like `hexdump -C`
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut s = String::new();
+    for (i, line) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let (first, second) = hex.split_at(hex.len().min(8));
+        let text: String = line.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect();
+        s.push_str(&format!("{:04x}  {:<23}  {:<23}  |{}|\n", i * 16, first.join(" "), second.join(" "), text));
+    }
+    s
 }
 
 impl TryFrom<u8> for DestinationUnreachableCode {
-    type Error = IntoICMPError;
+    type Error = ParseError;
     
     fn try_from(code: u8) -> Result<Self, Self::Error> {
         match code {
@@ -229,13 +797,13 @@ impl TryFrom<u8> for DestinationUnreachableCode {
             13 => Ok(DestinationUnreachableCode::CommAdministrativelyProhibited),
             14 => Ok(DestinationUnreachableCode::HostPrecedenceViolation),
             15 => Ok(DestinationUnreachableCode::PrecedenceCuttoffInEffect),
-            _ => Err(IntoICMPError::UnknownCode)
+            _ => Err(ParseError::UnknownCode { icmp_type: 3, code })
         }        
     }
 }
 
 impl TryFrom<u8> for RedirectMsgCode {
-    type Error = IntoICMPError;
+    type Error = ParseError;
     
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
@@ -243,71 +811,219 @@ impl TryFrom<u8> for RedirectMsgCode {
             1 => Ok(RedirectMsgCode::Host),
             2 => Ok(RedirectMsgCode::ToSAndNetwork),
             3 => Ok(RedirectMsgCode::ToSAndHost),
-            _ => Err(IntoICMPError::UnknownCode)
+            _ => Err(ParseError::UnknownCode { icmp_type: 5, code: value })
         }
     }
 }
 
 
-// TODO: write some tests for these (should be easy enough)
-/// Construct a big-endian u16 from 2 bytes
-fn be_u16(bytes: &[u8], start: usize) -> u16 {
-    u16::from_be_bytes(bytes[start..(start+2)].try_into().unwrap())
+/// Construct a big-endian u16 from 2 bytes, or `NotLongEnough` if the message stops before them
+fn be_u16(bytes: &[u8], start: usize) -> Result<u16, ParseError> {
+    Ok(u16::from_be_bytes(be_bytes(bytes, start)?))
+}
+/// Construct a big-endian u32 from four bytes, or `NotLongEnough` if the message stops before them
+fn be_u32(bytes: &[u8], start: usize) -> Result<u32, ParseError> {
+    Ok(u32::from_be_bytes(be_bytes(bytes, start)?))
+}
+/// `N` bytes from `start`, without panicking if there aren't enough
+fn be_bytes<const N: usize>(bytes: &[u8], start: usize) -> Result<[u8; N], ParseError> {
+    start.checked_add(N).and_then(|end| bytes.get(start..end))
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(ParseError::not_long_enough(bytes, start, N))
+}
+/// One byte, or `NotLongEnough` if the message stops before it
+fn byte(bytes: &[u8], index: usize) -> Result<u8, ParseError> {
+    bytes.get(index).copied().ok_or(ParseError::not_long_enough(bytes, index, 1))
 }
-/// Construct a big-endian u32 from four bytes
-fn be_u32(bytes: &[u8], start: usize) -> u32 {
-    u32::from_be_bytes(bytes[start..(start+4)].try_into().unwrap())
+/// `len` bytes from `start`, or `NotLongEnough` if the message stops before the end of them
+fn slice(bytes: &[u8], start: usize, len: usize) -> Result<&[u8], ParseError> {
+    start.checked_add(len).and_then(|end| bytes.get(start..end)).ok_or(ParseError::not_long_enough(bytes, start, len))
 }
 
-/// Construct an echo request message for ICMPv4
+/// The name of an ICMP (or ICMPv6, if `ipv4` is false) message type, for the ones that have one here
+pub fn type_name(icmp_type: u8, ipv4: bool) -> Option<&'static str> {
+    match (icmp_type, ipv4) {
+        (0, true) | (129, false) => Some("echo reply"),
+        (8, true) | (128, false) => Some("echo request"),
+        (3, true) | (1, false) => Some("destination unreachable"),
+        (2, false) => Some("packet too big"),
+        (11, true) | (3, false) => Some("time exceeded"),
+        (12, true) | (4, false) => Some("parameter problem"),
+        (5, true) => Some("redirect"),
+        (4, true) => Some("source quench"),
+        (6, true) => Some("alternate host address"),
+        (9, true) => Some("router advertisement"),
+        (10, true) => Some("router solicitation"),
+        (13, true) => Some("timestamp"),
+        (14, true) => Some("timestamp reply"),
+        (130, false) => Some("multicast listener query"),
+        (131, false) => Some("multicast listener report"),
+        (132, false) => Some("multicast listener done"),
+        (133, false) => Some("router solicitation"),
+        (134, false) => Some("router advertisement"),
+        (135, false) => Some("neighbor solicitation"),
+        (136, false) => Some("neighbor advertisement"),
+        (143, false) => Some("MLDv2 report"),
+        _ => None,
+    }
+}
+
+/// Construct an echo request message for ICMPv4, with the checksum filled in
 /// NOTE: identifier and sequence_num here use normal endianness for your platform
-#[allow(dead_code)]
 pub fn construct_echo_request_v4(identifier: u16, sequence_num: u16, extdata: &[u8]) -> Vec<u8> {
-    let msg_type: u8 = 8; // EchoRequest
-    let msg_code: u8 = 0;
-    // Note that the id and sequence number will be replaced when using a DGRAM socket (rather than RAW), which is currently what the program does
-    let be_id = identifier.to_be_bytes();
-    let be_seq = sequence_num.to_be_bytes();
-    let mut header = [msg_type, msg_code, 0, 0, be_id[0], be_id[1], be_seq[0], be_seq[1]];
-    populate_checksum(&mut header);
-    let mut message: Vec<u8> = header.to_vec();
-    message.append(&mut extdata.to_vec());
+    // Note that the id will be replaced when using a DGRAM socket (rather than RAW), which is what's used unless raw sockets are asked for
+    construct_v4(8, 0, concat_u16(identifier, sequence_num), extdata)
+}
+
+/// Construct a timestamp request (which is only for IPv4), with the checksum filled in.
+/// `originate` is the time it's sent, in milliseconds since midnight UTC
+pub fn construct_timestamp_request(identifier: u16, sequence_num: u16, originate: u32) -> Vec<u8> {
+    let mut message = vec![13, 0, 0, 0]; // Timestamp, code 0, checksum to be filled in
+    message.extend_from_slice(&identifier.to_be_bytes());
+    message.extend_from_slice(&sequence_num.to_be_bytes());
+    message.extend_from_slice(&originate.to_be_bytes());
+    message.extend_from_slice(&[0; 8]); // Receive and transmit times, which the host fills in
+    let checksum = internet_checksum(&message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+    message
+}
+
+/// Construct an echo reply message for ICMPv4, with the checksum filled in. `data` is the request's data, copied back
+pub fn construct_echo_reply_v4(identifier: u16, sequence_num: u16, data: &[u8]) -> Vec<u8> {
+    construct_v4(0, 0, concat_u16(identifier, sequence_num), data)
+}
+
+/// Construct a destination unreachable message for ICMPv4, with the checksum filled in. `original` is the start of
+/// the datagram that couldn't be delivered (its IP header and at least 8 bytes after it). The next-hop MTU is only
+/// meaningful for `FragmentationRequired`
+pub fn construct_destination_unreachable_v4(code: DestinationUnreachableCode, next_hop_mtu: u16, original: &[u8]) -> Vec<u8> {
+    construct_v4(3, code as u8, concat_u16(0, next_hop_mtu), original)
+}
+
+/// Construct a source quench message (which is deprecated) for ICMPv4, with the checksum filled in
+pub fn construct_source_quench(original: &[u8]) -> Vec<u8> {
+    construct_v4(4, 0, [0; 4], original)
+}
+
+/// Construct a redirect message for ICMPv4, telling the sender of `original` to use `gateway` instead
+pub fn construct_redirect(code: RedirectMsgCode, gateway: Ipv4Addr, original: &[u8]) -> Vec<u8> {
+    construct_v4(5, code as u8, gateway.octets(), original)
+}
+
+/// Construct a router advertisement for ICMPv4, with each address's preference level, which is valid for `lifetime` seconds
+pub fn construct_router_advertisement(lifetime: u16, addresses: &[(Ipv4Addr, i32)]) -> Vec<u8> {
+    let count = addresses.len().min(u8::MAX as usize);
+    let lifetime = lifetime.to_be_bytes();
+    // Each address takes two 32-bit words: the address and its preference
+    let header = [count as u8, 2, lifetime[0], lifetime[1]];
+    let data: Vec<u8> = addresses[..count].iter()
+        .flat_map(|(address, preference)| address.octets().into_iter().chain(preference.to_be_bytes()))
+        .collect();
+    construct_v4(9, 0, header, &data)
+}
+
+/// Construct a router solicitation for ICMPv4, with the checksum filled in
+pub fn construct_router_solicitation() -> Vec<u8> {
+    construct_v4(10, 0, [0; 4], &[])
+}
+
+/// Construct a time exceeded message for ICMPv4, with the checksum filled in. `original` is the start of the datagram
+/// that was dropped (its IP header and at least 8 bytes after it)
+pub fn construct_time_exceeded_v4(code: TimeExceededCode, original: &[u8]) -> Vec<u8> {
+    construct_v4(11, code as u8, [0; 4], original)
+}
+
+/// Construct a parameter problem (bad IP header) message for ICMPv4, with the checksum filled in.
+/// `pointer` is the offset of the byte in the original datagram's header that's wrong
+pub fn construct_bad_ip_header(code: BadIPHeaderCode, pointer: u8, original: &[u8]) -> Vec<u8> {
+    construct_v4(12, code as u8, [pointer, 0, 0, 0], original)
+}
+
+/// Construct a timestamp reply (which is only for IPv4), with the checksum filled in. The times are in milliseconds
+/// since midnight UTC: `originate` is copied from the request, and `receive` and `transmit` are when it was received
+/// and when the reply is sent
+pub fn construct_timestamp_reply(identifier: u16, sequence_num: u16, originate: u32, receive: u32, transmit: u32) -> Vec<u8> {
+    let times: Vec<u8> = [originate, receive, transmit].into_iter().flat_map(u32::to_be_bytes).collect();
+    construct_v4(14, 0, concat_u16(identifier, sequence_num), &times)
+}
+
+/// An ICMPv4 message with the given type, code, rest of the header and data, with the checksum filled in
+fn construct_v4(icmp_type: u8, code: u8, rest_of_header: [u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut message = vec![icmp_type, code, 0, 0];
+    message.extend_from_slice(&rest_of_header);
+    message.extend_from_slice(data);
+    let checksum = internet_checksum(&message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
     message
 }
 
-/// Populates the checksum in the header
-#[allow(dead_code)]
-pub fn populate_checksum(header: &mut [u8]) {
-    let mut total: u32 = 0;
-    for b in &mut *header {
-        total += *b as u32;
+/// Two u16s (like an identifier and sequence number) as they are in the rest of the header
+fn concat_u16(first: u16, second: u16) -> [u8; 4] {
+    let (first, second) = (first.to_be_bytes(), second.to_be_bytes());
+    [first[0], first[1], second[0], second[1]]
+}
+
+/// Populates the checksum of a message, which covers the whole of it
+pub fn populate_checksum(message: &mut [u8]) {
+    // Too short to have a checksum field, so there's nowhere to put it
+    if message.len() < 4 {
+        return;
+    }
+    message[2..4].fill(0);
+    let checksum = internet_checksum(message);
+    message[2..4].copy_from_slice(&checksum.to_be_bytes());
+}
+
+/// The Internet checksum (RFC 1071) of some data: the ones' complement of the ones' complement sum of its 16-bit words
+pub fn internet_checksum(data: &[u8]) -> u16 {
+    let mut total: u32 = data.chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while total > 0xffff {
+        total = (total & 0xffff) + (total >> 16);
     }
-    while total >= 0xffff {
-        total += total >> 16
+    !(total as u16)
+}
+
+/// The ICMPv6 checksum of a message from `source` to `destination`, which also covers a pseudo-header with the
+/// addresses, the message's length and the next header value (58) (RFC 8200, section 8.1)
+pub fn checksum_v6(source: Ipv6Addr, destination: Ipv6Addr, message: &[u8]) -> u16 {
+    let mut data = Vec::with_capacity(40 + message.len());
+    data.extend_from_slice(&source.octets());
+    data.extend_from_slice(&destination.octets());
+    data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    data.extend_from_slice(&[0, 0, 0, 58]);
+    data.extend_from_slice(message);
+    // The checksum field counts as 0
+    if let Some(field) = data.get_mut(42..44) {
+        field.fill(0);
     }
-    let final_checksum: [u8; 2] = (!total as u16).to_be_bytes();
-    header[2] = final_checksum[0];
-    header[3] = final_checksum[1];
+    internet_checksum(&data)
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ICMPv6Message {
     pub icmpv6_type: ICMPv6Type, // encompasses the code field too
     pub checksum: u16,
     pub body: Vec<u8>
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ICMPv6Type {
     // Error messages
     DestinationUnreachable {
-        code: DestinationUnreachableV6Code
+        code: DestinationUnreachableV6Code,
+        /// Length of the original datagram in 64-bit words, if there are extensions after it (RFC 4884)
+        length: u8,
     }, // #1
     PacketTooBig {
         mtu: u32,
     }, // #2
     TimeExceeded {
-        code: TimeExceededCode // uses the same code enum as v4
+        code: TimeExceededCode, // uses the same code enum as v4
+        length: u8, // as for DestinationUnreachable
     }, // #3
     ParameterProblem {
         code: ParamProblemCode,
@@ -322,10 +1038,248 @@ pub enum ICMPv6Type {
         identifier: u16,
         sequence_num: u16,
     }, // #129
+    // Multicast Listener Discovery
+    MulticastListenerQuery {
+        max_response_delay: u16, // Milliseconds
+        multicast_address: Ipv6Addr, // Unspecified (::) for a general query
+        sources: Vec<Ipv6Addr>, // Only in MLDv2 queries
+    }, // #130
+    MulticastListenerReport {
+        multicast_address: Ipv6Addr,
+    }, // #131
+    MulticastListenerDone {
+        multicast_address: Ipv6Addr,
+    }, // #132
+    // Neighbor Discovery Protocol
+    RouterSolicitation {
+        options: Vec<NdpOption>,
+    }, // #133
+    RouterAdvertisement {
+        /// The hop limit hosts should use, or 0 if the router doesn't say
+        hop_limit: u8,
+        managed: bool, // Addresses are from DHCPv6
+        other_config: bool, // Other configuration is from DHCPv6
+        router_lifetime: u16, // Seconds the router can be used as the default router for, or 0 if it can't
+        reachable_time: u32, // Milliseconds
+        retrans_timer: u32, // Milliseconds
+        options: Vec<NdpOption>,
+    }, // #134
+    NeighborSolicitation {
+        target: Ipv6Addr,
+        options: Vec<NdpOption>,
+    }, // #135
+    NeighborAdvertisement {
+        router: bool,
+        solicited: bool,
+        override_entry: bool,
+        target: Ipv6Addr,
+        options: Vec<NdpOption>,
+    }, // #136
+    MulticastListenerReportV2 {
+        records: Vec<MulticastAddressRecord>,
+    }, // #143
     // More exist, but `multiping` doesn't need them
 }
 
-#[derive(Debug)]
+/// What an MLDv2 report says about one multicast address
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MulticastAddressRecord {
+    /// e.g. 1 for "mode is include" or 4 for "change to exclude mode"
+    pub record_type: u8,
+    pub multicast_address: Ipv6Addr,
+    pub sources: Vec<Ipv6Addr>,
+}
+
+/// How long MLDv1 messages are, which is where the fields of MLDv2 queries start
+const MLD_V1_LEN: usize = 24;
+/// Where the sources of MLDv2 queries start, after the MLDv1 fields and their own
+const MLD_V2_QUERY_LEN: usize = 28;
+
+/// `count` addresses one after the other from `start`
+fn ipv6_addresses(msgbytes: &[u8], start: usize, count: usize) -> Result<Vec<Ipv6Addr>, ParseError> {
+    (0..count).map(|i| Ok(Ipv6Addr::from(be_bytes::<16>(msgbytes, start + i * 16)?))).collect()
+}
+
+/// The records of an MLDv2 report, which each have a type, address, sources and auxiliary data (which is skipped)
+fn multicast_address_records(msgbytes: &[u8]) -> Result<Vec<MulticastAddressRecord>, ParseError> {
+    let mut records = Vec::new();
+    let mut start = 8;
+    for _ in 0..be_u16(msgbytes, 6)? {
+        let aux_len = byte(msgbytes, start + 1)? as usize * 4;
+        let source_count = be_u16(msgbytes, start + 2)? as usize;
+        records.push(MulticastAddressRecord {
+            record_type: byte(msgbytes, start)?,
+            multicast_address: Ipv6Addr::from(be_bytes::<16>(msgbytes, start + 4)?),
+            sources: ipv6_addresses(msgbytes, start + 20, source_count)?,
+        });
+        start += 20 + source_count * 16 + aux_len;
+    }
+    Ok(records)
+}
+
+impl MulticastAddressRecord {
+    /// Appends the record to `buf` as it is in an MLDv2 report, without any auxiliary data
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&[self.record_type, 0]);
+        buf.extend_from_slice(&(self.sources.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&self.multicast_address.octets());
+        self.sources.iter().for_each(|source| buf.extend_from_slice(&source.octets()));
+    }
+}
+
+/// e.g. "change to exclude mode ff02::fb" or "allow new sources ff3e::1234 from 2001:db8::1"
+impl fmt::Display for MulticastAddressRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.record_type {
+            1 => f.write_str("mode is include")?,
+            2 => f.write_str("mode is exclude")?,
+            3 => f.write_str("change to include mode")?,
+            4 => f.write_str("change to exclude mode")?,
+            5 => f.write_str("allow new sources")?,
+            6 => f.write_str("block old sources")?,
+            record_type => write!(f, "type {}", record_type)?,
+        }
+        write!(f, " {}", self.multicast_address)?;
+        if !self.sources.is_empty() {
+            write!(f, " from {}", self.sources.iter().map(Ipv6Addr::to_string).collect::<Vec<_>>().join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// An option at the end of a Neighbor Discovery message
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NdpOption {
+    SourceLinkLayerAddress(Vec<u8>), // #1
+    TargetLinkLayerAddress(Vec<u8>), // #2
+    PrefixInformation { // #3
+        prefix_length: u8,
+        on_link: bool,
+        autonomous: bool, // Can be used for stateless address autoconfiguration
+        valid_lifetime: u32, // Seconds
+        preferred_lifetime: u32, // Seconds
+        prefix: Ipv6Addr,
+    },
+    Mtu(u32), // #5
+    Other {
+        option_type: u8,
+        data: Vec<u8>, // everything after the type and length
+    },
+}
+
+/// Where the options start in each type of Neighbor Discovery message
+const ROUTER_SOLICITATION_OPTIONS: usize = 8;
+const ROUTER_ADVERTISEMENT_OPTIONS: usize = 16;
+const NEIGHBOR_OPTIONS: usize = 24;
+
+/// Splits up the options from `start` to the end of a Neighbor Discovery message
+fn ndp_options(msgbytes: &[u8], start: usize) -> Result<Vec<NdpOption>, ParseError> {
+    let mut options = Vec::new();
+    let mut start = start;
+    if start > msgbytes.len() {
+        return Err(ParseError::not_long_enough(msgbytes, start, 0));
+    }
+    while start < msgbytes.len() {
+        // The length is in units of 8 bytes, including the type and length, so 0 is never valid
+        let len = byte(msgbytes, start + 1)? as usize * 8;
+        if len == 0 {
+            return Err(ParseError::Invalid { offset: start + 1, reason: "option length is 0" });
+        }
+        let option = slice(msgbytes, start, len)?;
+        let data = &option[2..];
+        // The fields are read from the option, so one that's too short for its type can't run into the next one
+        if option[0] == 3 && len < 32 {
+            return Err(ParseError::Invalid { offset: start + 1, reason: "prefix information option shorter than 32 bytes" });
+        }
+        options.push(match option[0] {
+            // Padded to a multiple of 8 bytes, which for Ethernet leaves just the 6 byte MAC address
+            1 => NdpOption::SourceLinkLayerAddress(data.to_vec()),
+            2 => NdpOption::TargetLinkLayerAddress(data.to_vec()),
+            3 => {
+                let flags = byte(option, 3)?;
+                NdpOption::PrefixInformation {
+                    prefix_length: byte(option, 2)?,
+                    on_link: flags & 0x80 != 0,
+                    autonomous: flags & 0x40 != 0,
+                    valid_lifetime: be_u32(option, 4)?,
+                    preferred_lifetime: be_u32(option, 8)?,
+                    prefix: Ipv6Addr::from(be_bytes::<16>(option, 16)?),
+                }
+            },
+            5 => NdpOption::Mtu(be_u32(option, 4)?),
+            option_type => NdpOption::Other { option_type, data: data.to_vec() },
+        });
+        start += len;
+    }
+    Ok(options)
+}
+
+impl NdpOption {
+    /// Appends the option to `buf` as it is on the wire, padded to a multiple of 8 bytes
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        match self {
+            NdpOption::SourceLinkLayerAddress(address) => {
+                buf.extend_from_slice(&[1, 0]);
+                buf.extend_from_slice(address);
+            },
+            NdpOption::TargetLinkLayerAddress(address) => {
+                buf.extend_from_slice(&[2, 0]);
+                buf.extend_from_slice(address);
+            },
+            NdpOption::PrefixInformation { prefix_length, on_link, autonomous, valid_lifetime, preferred_lifetime, prefix } => {
+                buf.extend_from_slice(&[3, 0, *prefix_length, (*on_link as u8) << 7 | (*autonomous as u8) << 6]);
+                buf.extend_from_slice(&valid_lifetime.to_be_bytes());
+                buf.extend_from_slice(&preferred_lifetime.to_be_bytes());
+                buf.extend_from_slice(&[0; 4]); // Reserved
+                buf.extend_from_slice(&prefix.octets());
+            },
+            NdpOption::Mtu(mtu) => {
+                buf.extend_from_slice(&[5, 0, 0, 0]);
+                buf.extend_from_slice(&mtu.to_be_bytes());
+            },
+            NdpOption::Other { option_type, data } => {
+                buf.extend_from_slice(&[*option_type, 0]);
+                buf.extend_from_slice(data);
+            },
+        }
+        let len = (buf.len() - start).div_ceil(8);
+        buf.resize(start + len * 8, 0);
+        buf[start + 1] = len.min(u8::MAX as usize) as u8;
+    }
+}
+
+/// e.g. "source link-layer address 52:54:00:12:34:56" or "MTU 1500"
+impl fmt::Display for NdpOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NdpOption::SourceLinkLayerAddress(address) => write!(f, "source link-layer address {}", format_link_layer(address)),
+            NdpOption::TargetLinkLayerAddress(address) => write!(f, "target link-layer address {}", format_link_layer(address)),
+            NdpOption::PrefixInformation { prefix_length, on_link, autonomous, valid_lifetime, preferred_lifetime, prefix } => {
+                write!(f, "prefix {}/{}", prefix, prefix_length)?;
+                if *on_link {
+                    f.write_str(", on-link")?;
+                }
+                if *autonomous {
+                    f.write_str(", autonomous")?;
+                }
+                write!(f, ", valid for {} s, preferred for {} s", valid_lifetime, preferred_lifetime)
+            },
+            NdpOption::Mtu(mtu) => write!(f, "MTU {}", mtu),
+            NdpOption::Other { option_type, data } => write!(f, "type {} ({} bytes)", option_type, data.len()),
+        }
+    }
+}
+
+/// Bytes separated by colons, like a MAC address
+fn format_link_layer(address: &[u8]) -> String {
+    address.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DestinationUnreachableV6Code {
     NoRouteToDestination, // #0
     CommAdministrativelyProhibited, // #1
@@ -337,70 +1291,404 @@ pub enum DestinationUnreachableV6Code {
     ErrorInSourceRoutingHeader, // #7
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ParamProblemCode {
     ErroneousHeaderField,
     UnrecognisedNextHeaderType,
     UnrecognisedIPv6Option,
 }
 
-impl TryFrom<&[u8]> for ICMPv6Message {
-    type Error = IntoICMPError;
+/// A borrowed view of an ICMPv6 message, like `ICMPv4Packet`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ICMPv6Packet<'a> {
+    bytes: &'a [u8],
+    header: &'a [u8; 8],
+    body: &'a [u8],
+}
 
-    fn try_from(msgbytes: &[u8]) -> Result<Self, Self::Error> {
-        let checksum = be_u16(msgbytes, 2);
-        let body = msgbytes[8..].to_vec();
-        match msgbytes[0] {
+impl<'a> ICMPv6Packet<'a> {
+    /// Only checks that there's a whole header, as for `ICMPv4Packet::new`
+    pub fn new(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let (header, body) = bytes.split_first_chunk::<8>().ok_or(ParseError::not_long_enough(bytes, 0, 8))?;
+        Ok(ICMPv6Packet { bytes, header, body })
+    }
+
+    pub fn icmp_type(&self) -> u8 {
+        self.header[0]
+    }
+
+    pub fn code(&self) -> u8 {
+        self.header[1]
+    }
+
+    /// The code as one of the code enums, with this message's type in the error if it isn't known
+    fn code_as<T: TryFrom<u8, Error = ParseError>>(&self) -> Result<T, ParseError> {
+        T::try_from(self.code()).map_err(|_| ParseError::UnknownCode { icmp_type: self.icmp_type(), code: self.code() })
+    }
+
+    /// The checksum, as it is in the message (sockets that fill it in don't check it on the way in either)
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes([self.header[2], self.header[3]])
+    }
+
+    /// Everything after the 8 byte header
+    pub fn body(&self) -> &'a [u8] {
+        self.body
+    }
+
+    /// The whole message
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// As for `ICMPv6Message::original_datagram`
+    pub fn original_datagram(&self) -> &'a [u8] {
+        split_extensions(self.body, self.message_type().ok().and_then(|t| t.original_length())).0
+    }
+
+    /// As for `ICMPv6Message::extensions`
+    pub fn extensions(&self) -> Result<Option<ICMPExtensions>, ParseError> {
+        split_extensions(self.body, self.message_type()?.original_length()).1.map(ICMPExtensions::try_from).transpose()
+    }
+
+    /// As for `ICMPv6Message::original`
+    pub fn original(&self) -> Option<OriginalDatagram> {
+        self.message_type().is_ok_and(|t| t.is_error()).then(|| OriginalDatagram::try_from(self.original_datagram()).ok()).flatten()
+    }
+
+    /// Parses the type, code and the fields that go with them
+    pub fn message_type(&self) -> Result<ICMPv6Type, ParseError> {
+        match self.icmp_type() {
             1 => { // DestinationUnreachable
-                let code = msgbytes[1].try_into()?;
-                Ok(ICMPv6Message {
-                    icmpv6_type: ICMPv6Type::DestinationUnreachable {
-                        code
-                    },
-                    checksum, body})
+                let code = self.code_as()?;
+                Ok(ICMPv6Type::DestinationUnreachable {
+                    code,
+                    length: byte(self.bytes, 4)?,
+                })
             },
             2 => { // PacketTooBig
-                Ok(ICMPv6Message {
-                    icmpv6_type: ICMPv6Type::PacketTooBig {
-                        mtu: be_u32(msgbytes, 4)
-                    },
-                    checksum, body})
+                Ok(ICMPv6Type::PacketTooBig {
+                    mtu: be_u32(self.bytes, 4)?
+                })
             }
             3 => { // TimeExceeded
-                let code = msgbytes[1].try_into()?;
-                Ok(ICMPv6Message {
-                    icmpv6_type: ICMPv6Type::TimeExceeded { code },
-                    checksum, body})
+                let code = self.code_as()?;
+                Ok(ICMPv6Type::TimeExceeded { code, length: byte(self.bytes, 4)? })
             },
             4 => { // ParameterProblem
-                let code = msgbytes[1].try_into()?;
-                Ok(ICMPv6Message {
-                    icmpv6_type: ICMPv6Type::ParameterProblem { code, ptr: be_u32(msgbytes, 4)},
-                    checksum, body})
+                let code = self.code_as()?;
+                Ok(ICMPv6Type::ParameterProblem { code, ptr: be_u32(self.bytes, 4)?})
             },
             128 => { // Echo Request
-                Ok(ICMPv6Message {
-                    icmpv6_type: ICMPv6Type::EchoRequest { 
-                        identifier: be_u16(msgbytes, 4),
-                        sequence_num: be_u16(msgbytes, 6)
-                    },
-                    checksum, body})
+                Ok(ICMPv6Type::EchoRequest {
+                    identifier: be_u16(self.bytes, 4)?,
+                    sequence_num: be_u16(self.bytes, 6)?
+                })
             }
             129 => { // Echo Reply
-                Ok(ICMPv6Message {
-                    icmpv6_type: ICMPv6Type::EchoReply { 
-                        identifier: be_u16(msgbytes, 4),
-                        sequence_num: be_u16(msgbytes, 6)
-                    },
-                    checksum, body})
+                Ok(ICMPv6Type::EchoReply {
+                    identifier: be_u16(self.bytes, 4)?,
+                    sequence_num: be_u16(self.bytes, 6)?
+                })
+            }
+            130 => { // Multicast Listener Query, which is MLDv2 if it's long enough for the extra fields
+                let sources = if self.bytes.len() >= MLD_V2_QUERY_LEN {
+                    ipv6_addresses(self.bytes, MLD_V2_QUERY_LEN, be_u16(self.bytes, 26)? as usize)?
+                } else if self.bytes.len() > MLD_V1_LEN {
+                    // Between the two lengths it's an MLDv2 query that was cut short, not an MLDv1 one (RFC 3810 section 8.1)
+                    return Err(ParseError::not_long_enough(self.bytes, MLD_V1_LEN, MLD_V2_QUERY_LEN - MLD_V1_LEN));
+                } else {
+                    Vec::new()
+                };
+                Ok(ICMPv6Type::MulticastListenerQuery {
+                    max_response_delay: be_u16(self.bytes, 4)?,
+                    multicast_address: Ipv6Addr::from(be_bytes::<16>(self.bytes, 8)?),
+                    sources
+                })
+            }
+            131 => { // Multicast Listener Report
+                Ok(ICMPv6Type::MulticastListenerReport {
+                    multicast_address: Ipv6Addr::from(be_bytes::<16>(self.bytes, 8)?)
+                })
             }
-            _ => Err(IntoICMPError::UnknownType),
+            132 => { // Multicast Listener Done
+                Ok(ICMPv6Type::MulticastListenerDone {
+                    multicast_address: Ipv6Addr::from(be_bytes::<16>(self.bytes, 8)?)
+                })
+            }
+            133 => { // Router Solicitation
+                Ok(ICMPv6Type::RouterSolicitation {
+                    options: ndp_options(self.bytes, ROUTER_SOLICITATION_OPTIONS)?
+                })
+            }
+            134 => { // Router Advertisement
+                let flags = byte(self.bytes, 5)?;
+                Ok(ICMPv6Type::RouterAdvertisement {
+                    hop_limit: byte(self.bytes, 4)?,
+                    managed: flags & 0x80 != 0,
+                    other_config: flags & 0x40 != 0,
+                    router_lifetime: be_u16(self.bytes, 6)?,
+                    reachable_time: be_u32(self.bytes, 8)?,
+                    retrans_timer: be_u32(self.bytes, 12)?,
+                    options: ndp_options(self.bytes, ROUTER_ADVERTISEMENT_OPTIONS)?
+                })
+            }
+            135 => { // Neighbor Solicitation
+                Ok(ICMPv6Type::NeighborSolicitation {
+                    target: ndp_target(self.bytes)?,
+                    options: ndp_options(self.bytes, NEIGHBOR_OPTIONS)?
+                })
+            }
+            136 => { // Neighbor Advertisement
+                let flags = byte(self.bytes, 4)?;
+                Ok(ICMPv6Type::NeighborAdvertisement {
+                    router: flags & 0x80 != 0,
+                    solicited: flags & 0x40 != 0,
+                    override_entry: flags & 0x20 != 0,
+                    target: ndp_target(self.bytes)?,
+                    options: ndp_options(self.bytes, NEIGHBOR_OPTIONS)?
+                })
+            }
+            143 => { // MLDv2 Report
+                Ok(ICMPv6Type::MulticastListenerReportV2 {
+                    records: multicast_address_records(self.bytes)?
+                })
+            }
+            _ => Err(ParseError::UnknownType { icmp_type: self.icmp_type() }),
         }
     }
 }
 
+impl TryFrom<ICMPv6Packet<'_>> for ICMPv6Message {
+    type Error = ParseError;
+
+    fn try_from(packet: ICMPv6Packet<'_>) -> Result<Self, Self::Error> {
+        Ok(ICMPv6Message {
+            icmpv6_type: packet.message_type()?,
+            checksum: packet.checksum(),
+            body: packet.body().to_vec(),
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for ICMPv6Message {
+    type Error = ParseError;
+
+    fn try_from(msgbytes: &[u8]) -> Result<Self, Self::Error> {
+        ICMPv6Packet::new(msgbytes)?.try_into()
+    }
+}
+
+impl ICMPv6Type {
+    /// The type and code numbers, as they are on the wire
+    pub fn type_code(&self) -> (u8, u8) {
+        match self {
+            ICMPv6Type::DestinationUnreachable { code, .. } => (1, *code as u8),
+            ICMPv6Type::PacketTooBig { .. } => (2, 0),
+            ICMPv6Type::TimeExceeded { code, .. } => (3, *code as u8),
+            ICMPv6Type::ParameterProblem { code, .. } => (4, *code as u8),
+            ICMPv6Type::EchoRequest { .. } => (128, 0),
+            ICMPv6Type::EchoReply { .. } => (129, 0),
+            ICMPv6Type::MulticastListenerQuery { .. } => (130, 0),
+            ICMPv6Type::MulticastListenerReport { .. } => (131, 0),
+            ICMPv6Type::MulticastListenerDone { .. } => (132, 0),
+            ICMPv6Type::RouterSolicitation { .. } => (133, 0),
+            ICMPv6Type::RouterAdvertisement { .. } => (134, 0),
+            ICMPv6Type::NeighborSolicitation { .. } => (135, 0),
+            ICMPv6Type::NeighborAdvertisement { .. } => (136, 0),
+            ICMPv6Type::MulticastListenerReportV2 { .. } => (143, 0),
+        }
+    }
+
+    /// The fields that only this type of message has, as names and values
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        match self {
+            ICMPv6Type::DestinationUnreachable { code, length } => error_fields(code, *length as usize * 8),
+            ICMPv6Type::PacketTooBig { mtu } => vec![("MTU", mtu.to_string())],
+            ICMPv6Type::TimeExceeded { code, length } => error_fields(code, *length as usize * 8),
+            ICMPv6Type::ParameterProblem { code, ptr } => vec![("reason", format!("{:?}", code)), ("pointer", ptr.to_string())],
+            ICMPv6Type::EchoRequest { identifier, sequence_num } | ICMPv6Type::EchoReply { identifier, sequence_num } => {
+                vec![("identifier", format!("{:#06x}", identifier)), ("sequence", sequence_num.to_string())]
+            },
+            ICMPv6Type::MulticastListenerQuery { max_response_delay, multicast_address, sources } => {
+                let group = if multicast_address.is_unspecified() { "all".to_string() } else { multicast_address.to_string() };
+                let mut fields = vec![("max delay", format!("{} ms", max_response_delay)), ("group", group)];
+                if !sources.is_empty() {
+                    fields.push(("sources", sources.iter().map(Ipv6Addr::to_string).collect::<Vec<_>>().join(", ")));
+                }
+                fields
+            },
+            ICMPv6Type::MulticastListenerReport { multicast_address } | ICMPv6Type::MulticastListenerDone { multicast_address } => {
+                vec![("group", multicast_address.to_string())]
+            },
+            ICMPv6Type::MulticastListenerReportV2 { records } => records.iter().map(|record| ("record", record.to_string())).collect(),
+            ICMPv6Type::RouterSolicitation { options } => option_fields(Vec::new(), options),
+            ICMPv6Type::RouterAdvertisement { hop_limit, managed, other_config, router_lifetime, reachable_time, retrans_timer, options } => {
+                let fields = vec![
+                    ("hop limit", hop_limit.to_string()),
+                    ("flags", flag_names(&[(*managed, "managed"), (*other_config, "other")])),
+                    ("lifetime", format!("{} s", router_lifetime)),
+                    ("reachable", format!("{} ms", reachable_time)),
+                    ("retransmit", format!("{} ms", retrans_timer)),
+                ];
+                option_fields(fields, options)
+            },
+            ICMPv6Type::NeighborSolicitation { target, options } => option_fields(vec![("target", target.to_string())], options),
+            ICMPv6Type::NeighborAdvertisement { router, solicited, override_entry, target, options } => {
+                let flags = flag_names(&[(*router, "router"), (*solicited, "solicited"), (*override_entry, "override")]);
+                option_fields(vec![("target", target.to_string()), ("flags", flags)], options)
+            },
+        }
+    }
+
+    /// As for `ICMPv4Type::original_length`
+    pub fn original_length(&self) -> Option<usize> {
+        match self {
+            ICMPv6Type::DestinationUnreachable { length, .. } | ICMPv6Type::TimeExceeded { length, .. } => Some(*length as usize * 8),
+            _ => None,
+        }
+    }
+
+    /// Whether it's an error message (types 0 to 127), which has the start of the datagram it's about as its body
+    pub fn is_error(&self) -> bool {
+        matches!(self, ICMPv6Type::DestinationUnreachable { .. } | ICMPv6Type::PacketTooBig { .. }
+            | ICMPv6Type::TimeExceeded { .. } | ICMPv6Type::ParameterProblem { .. })
+    }
+
+    /// As for `ICMPv4Type::rest_of_header`
+    pub fn rest_of_header(&self) -> [u8; 4] {
+        match self {
+            ICMPv6Type::DestinationUnreachable { length, .. } | ICMPv6Type::TimeExceeded { length, .. } => [*length, 0, 0, 0],
+            ICMPv6Type::PacketTooBig { mtu } => mtu.to_be_bytes(),
+            ICMPv6Type::ParameterProblem { ptr, .. } => ptr.to_be_bytes(),
+            ICMPv6Type::EchoRequest { identifier, sequence_num } | ICMPv6Type::EchoReply { identifier, sequence_num } => {
+                concat_u16(*identifier, *sequence_num)
+            },
+            ICMPv6Type::MulticastListenerQuery { max_response_delay, .. } => concat_u16(*max_response_delay, 0),
+            ICMPv6Type::RouterAdvertisement { hop_limit, managed, other_config, router_lifetime, .. } => {
+                let lifetime = router_lifetime.to_be_bytes();
+                [*hop_limit, (*managed as u8) << 7 | (*other_config as u8) << 6, lifetime[0], lifetime[1]]
+            },
+            ICMPv6Type::NeighborAdvertisement { router, solicited, override_entry, .. } => {
+                [(*router as u8) << 7 | (*solicited as u8) << 6 | (*override_entry as u8) << 5, 0, 0, 0]
+            },
+            ICMPv6Type::MulticastListenerReportV2 { records } => concat_u16(0, records.len() as u16),
+            ICMPv6Type::MulticastListenerReport { .. } | ICMPv6Type::MulticastListenerDone { .. }
+                | ICMPv6Type::RouterSolicitation { .. } | ICMPv6Type::NeighborSolicitation { .. } => [0; 4],
+        }
+    }
+}
+
+impl ICMPv6Message {
+    /// Appends the message to `buf` as it would be sent, with the checksum left as 0 for the kernel to fill in.
+    /// Messages whose fields go on after the header (e.g. Neighbor Discovery and MLD) have them written in place of
+    /// the start of the body, and the parts of it that aren't parsed (like MLDv2 auxiliary data) are left out
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        let (icmp_type, code) = self.icmpv6_type.type_code();
+        buf.extend_from_slice(&[icmp_type, code, 0, 0]);
+        buf.extend_from_slice(&self.icmpv6_type.rest_of_header());
+        match &self.icmpv6_type {
+            ICMPv6Type::MulticastListenerQuery { multicast_address, sources, .. } => {
+                buf.extend_from_slice(&multicast_address.octets());
+                // MLDv1 queries stop after the address
+                if !sources.is_empty() || self.body.len() >= MLD_V2_QUERY_LEN - 8 {
+                    // The suppress flag, robustness variable and query interval are kept as they were
+                    buf.extend_from_slice(self.body.get(16..18).unwrap_or(&[0, 0]));
+                    buf.extend_from_slice(&(sources.len() as u16).to_be_bytes());
+                    sources.iter().for_each(|source| buf.extend_from_slice(&source.octets()));
+                }
+            },
+            ICMPv6Type::MulticastListenerReport { multicast_address } | ICMPv6Type::MulticastListenerDone { multicast_address } => {
+                buf.extend_from_slice(&multicast_address.octets());
+                buf.extend_from_slice(self.body.get(16..).unwrap_or_default());
+            },
+            ICMPv6Type::MulticastListenerReportV2 { records } => records.iter().for_each(|record| record.write_to(buf)),
+            ICMPv6Type::RouterSolicitation { options } => options.iter().for_each(|option| option.write_to(buf)),
+            ICMPv6Type::RouterAdvertisement { reachable_time, retrans_timer, options, .. } => {
+                buf.extend_from_slice(&reachable_time.to_be_bytes());
+                buf.extend_from_slice(&retrans_timer.to_be_bytes());
+                options.iter().for_each(|option| option.write_to(buf));
+            },
+            ICMPv6Type::NeighborSolicitation { target, options } | ICMPv6Type::NeighborAdvertisement { target, options, .. } => {
+                buf.extend_from_slice(&target.octets());
+                options.iter().for_each(|option| option.write_to(buf));
+            },
+            _ => buf.extend_from_slice(&self.body),
+        }
+    }
+
+    /// The message as it would be sent, as for `write_to`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf);
+        buf
+    }
+
+    /// The message with the checksum filled in, for when it's sent from `source` to `destination` without the kernel
+    /// doing it (e.g. a packet capture)
+    pub fn to_bytes_with_checksum(&self, source: Ipv6Addr, destination: Ipv6Addr) -> Vec<u8> {
+        let mut message = self.to_bytes();
+        let checksum = checksum_v6(source, destination, &message);
+        message[2..4].copy_from_slice(&checksum.to_be_bytes());
+        message
+    }
+
+    /// The body, without any extensions after the original datagram of an error message
+    pub fn original_datagram(&self) -> &[u8] {
+        split_extensions(&self.body, self.icmpv6_type.original_length()).0
+    }
+
+    /// Extra information that routers add after the original datagram of an error message (RFC 4884)
+    pub fn extensions(&self) -> Result<Option<ICMPExtensions>, ParseError> {
+        split_extensions(&self.body, self.icmpv6_type.original_length()).1.map(ICMPExtensions::try_from).transpose()
+    }
+
+    /// Where the datagram an error message is about was going, and which request it was if it was one of ours,
+    /// or None if it isn't an error or too little of the datagram came back
+    pub fn original(&self) -> Option<OriginalDatagram> {
+        self.icmpv6_type.is_error().then(|| OriginalDatagram::try_from(self.original_datagram()).ok()).flatten()
+    }
+
+    /// Every field on its own line, followed by a hex dump of the body and the extensions
+    pub fn dump(&self) -> String {
+        let (icmp_type, code) = self.icmpv6_type.type_code();
+        let extensions = self.extensions();
+        let mut s = dump_message(icmp_type, code, false, self.checksum,
+            &message_fields(self.icmpv6_type.fields(), self.original(), &extensions), self.original_datagram());
+        s.push_str(&dump_extensions(&extensions));
+        s
+    }
+}
+
+/// One line, e.g. "echo reply (type 129, code 0), identifier 0x1234, sequence 5, 56 bytes of data"
+impl fmt::Display for ICMPv6Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (icmp_type, code) = self.icmpv6_type.type_code();
+        let fields = message_fields(self.icmpv6_type.fields(), self.original(), &self.extensions());
+        format_message(f, icmp_type, code, false, &fields, self.original_datagram().len())
+    }
+}
+
+/// The names of the flags that are set, or "none"
+fn flag_names(flags: &[(bool, &str)]) -> String {
+    let names: Vec<&str> = flags.iter().filter(|(set, _)| *set).map(|(_, name)| *name).collect();
+    if names.is_empty() { "none".to_string() } else { names.join(" ") }
+}
+
+/// The fields of a Neighbor Discovery message, followed by its options
+fn option_fields(mut fields: Vec<(&'static str, String)>, options: &[NdpOption]) -> Vec<(&'static str, String)> {
+    fields.extend(options.iter().map(|option| ("option", option.to_string())));
+    fields
+}
+
+/// The target address of a Neighbor Solicitation/Advertisement, which follows the 4 reserved/flag bytes
+fn ndp_target(msgbytes: &[u8]) -> Result<Ipv6Addr, ParseError> {
+    Ok(Ipv6Addr::from(be_bytes::<16>(msgbytes, 8)?))
+}
+
 impl TryFrom<u8> for DestinationUnreachableV6Code {
-    type Error = IntoICMPError;
+    type Error = ParseError;
     
     fn try_from(code: u8) -> Result<Self, Self::Error> {
         match code {
@@ -412,32 +1700,32 @@ impl TryFrom<u8> for DestinationUnreachableV6Code {
             5 => Ok(DestinationUnreachableV6Code::SourceAddressFailedIngressEgressPolicy),
             6 => Ok(DestinationUnreachableV6Code::RejectRouteToDestination),
             7 => Ok(DestinationUnreachableV6Code::ErrorInSourceRoutingHeader),
-            _ => Err(IntoICMPError::UnknownCode)
+            _ => Err(ParseError::UnknownCode { icmp_type: 1, code })
         }
     }
 }
 
 impl TryFrom<u8> for TimeExceededCode {
-    type Error = IntoICMPError;
+    type Error = ParseError;
     
     fn try_from(code: u8) -> Result<Self, Self::Error> {
         match code {
             0 => Ok(TimeExceededCode::ExpiredInTransit),
             1 => Ok(TimeExceededCode::FragmentReassemblyTimeExceeded),
-            _ => Err(IntoICMPError::UnknownCode)
+            _ => Err(ParseError::UnknownCode { icmp_type: 11, code })
         }
     }
 }
 
 impl TryFrom<u8> for ParamProblemCode {
-    type Error = IntoICMPError;
+    type Error = ParseError;
     
     fn try_from(code: u8) -> Result<Self, Self::Error> {
         match code {
             0 => Ok(ParamProblemCode::ErroneousHeaderField),
             1 => Ok(ParamProblemCode::UnrecognisedNextHeaderType),
             2 => Ok(ParamProblemCode::UnrecognisedIPv6Option),
-            _ => Err(IntoICMPError::UnknownCode)
+            _ => Err(ParseError::UnknownCode { icmp_type: 4, code })
         }
     }
 }
@@ -447,7 +1735,7 @@ impl TryFrom<u8> for ParamProblemCode {
 pub fn construct_echo_request_v6(identifier: u16, sequence_num: u16, extdata: &[u8]) -> Vec<u8> {
     let msg_type: u8 = 128; // EchoRequest
     let msg_code: u8 = 0;
-    // Note that the id and sequence number will be replaced when using a DGRAM socket (rather than RAW), which is currently what the program does
+    // Note that the id will be replaced when using a DGRAM socket (rather than RAW), which is what's used unless raw sockets are asked for
     let be_id = identifier.to_be_bytes();
     let be_seq = sequence_num.to_be_bytes();
     let /*mut*/ header = [msg_type, msg_code, 0, 0, be_id[0], be_id[1], be_seq[0], be_seq[1]];
@@ -456,3 +1744,577 @@ pub fn construct_echo_request_v6(identifier: u16, sequence_num: u16, extdata: &[
     message.append(&mut extdata.to_vec());
     message
 }
+
+/// The most of the original datagram that's put in an ICMPv6 error, so that the error fits in the minimum IPv6 MTU
+/// (1280 bytes, less 40 for the IPv6 header and 8 for the ICMPv6 one)
+pub const MAX_V6_ERROR_DATA: usize = 1232;
+
+/// Construct an echo reply message for ICMPv6. `data` is the request's data, copied back.
+/// The checksum is left as 0 for the kernel to fill in
+pub fn construct_echo_reply_v6(identifier: u16, sequence_num: u16, data: &[u8]) -> Vec<u8> {
+    construct_v6(129, 0, concat_u16(identifier, sequence_num), data)
+}
+
+/// Construct a destination unreachable message for ICMPv6, containing as much of `original` (the datagram that
+/// couldn't be delivered) as fits. The checksum is left as 0 for the kernel to fill in
+pub fn construct_destination_unreachable_v6(code: DestinationUnreachableV6Code, original: &[u8]) -> Vec<u8> {
+    construct_v6(1, code as u8, [0; 4], &original[..original.len().min(MAX_V6_ERROR_DATA)])
+}
+
+/// Construct a packet too big message, saying that `original` didn't fit in the next link's MTU.
+/// The checksum is left as 0 for the kernel to fill in
+pub fn construct_packet_too_big(mtu: u32, original: &[u8]) -> Vec<u8> {
+    construct_v6(2, 0, mtu.to_be_bytes(), &original[..original.len().min(MAX_V6_ERROR_DATA)])
+}
+
+/// Construct a time exceeded message for ICMPv6, containing as much of `original` (the datagram that was dropped)
+/// as fits. The checksum is left as 0 for the kernel to fill in
+pub fn construct_time_exceeded_v6(code: TimeExceededCode, original: &[u8]) -> Vec<u8> {
+    construct_v6(3, code as u8, [0; 4], &original[..original.len().min(MAX_V6_ERROR_DATA)])
+}
+
+/// Construct a parameter problem message for ICMPv6. `pointer` is the offset of the byte in `original` that's wrong.
+/// The checksum is left as 0 for the kernel to fill in
+pub fn construct_parameter_problem_v6(code: ParamProblemCode, pointer: u32, original: &[u8]) -> Vec<u8> {
+    construct_v6(4, code as u8, pointer.to_be_bytes(), &original[..original.len().min(MAX_V6_ERROR_DATA)])
+}
+
+/// Construct a Neighbor Advertisement for `target`, including our link-layer address as an option if it's given
+/// (which it should be when answering a solicitation sent to a multicast address).
+/// The checksum is left as 0 for the kernel to fill in
+pub fn construct_neighbor_advertisement(target: Ipv6Addr, router: bool, solicited: bool, override_entry: bool, target_mac: Option<[u8; 6]>) -> Vec<u8> {
+    let flags = (router as u8) << 7 | (solicited as u8) << 6 | (override_entry as u8) << 5;
+    let mut data = target.octets().to_vec();
+    if let Some(mac) = target_mac {
+        // Target Link-Layer Address option: type 2, length 1 (in units of 8 bytes)
+        data.extend_from_slice(&[2, 1]);
+        data.extend_from_slice(&mac);
+    }
+    construct_v6(136, 0, [flags, 0, 0, 0], &data)
+}
+
+/// An ICMPv6 message with the given type, code, rest of the header and data. The checksum covers a pseudo-header
+/// with the addresses, so it's left as 0 for the kernel to fill in
+fn construct_v6(icmp_type: u8, code: u8, rest_of_header: [u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut message = vec![icmp_type, code, 0, 0];
+    message.extend_from_slice(&rest_of_header);
+    message.extend_from_slice(data);
+    message
+}
+
+/// Construct a Neighbor Solicitation message asking for the link-layer address of `target`,
+/// including our own link-layer address as an option (which is required when it's sent to a multicast address).
+/// The checksum is left as 0 for the kernel to fill in, which it does for raw ICMPv6 sockets
+pub fn construct_neighbor_solicitation(target: Ipv6Addr, source_mac: [u8; 6]) -> Vec<u8> {
+    let mut message: Vec<u8> = vec![135, 0, 0, 0, 0, 0, 0, 0];
+    message.extend_from_slice(&target.octets());
+    // Source Link-Layer Address option: type 1, length 1 (in units of 8 bytes)
+    message.extend_from_slice(&[1, 1]);
+    message.extend_from_slice(&source_mac);
+    message
+}
+
+/// Which version of ICMP a message is, as ICMPv4 and ICMPv6 type numbers overlap
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    /// The version of ICMP used to ping an address
+    pub fn of(address: IpAddr) -> Family {
+        if address.is_ipv4() { Family::V4 } else { Family::V6 }
+    }
+}
+
+/// An ICMPv4 or ICMPv6 message, for code that treats them the same way
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ICMPMessage {
+    V4(ICMPv4Message),
+    V6(ICMPv6Message),
+}
+
+impl TryFrom<(&[u8], Family)> for ICMPMessage {
+    type Error = ParseError;
+
+    fn try_from((msgbytes, family): (&[u8], Family)) -> Result<Self, Self::Error> {
+        Ok(match family {
+            Family::V4 => ICMPMessage::V4(msgbytes.try_into()?),
+            Family::V6 => ICMPMessage::V6(msgbytes.try_into()?),
+        })
+    }
+}
+
+impl ICMPMessage {
+    pub fn family(&self) -> Family {
+        match self {
+            ICMPMessage::V4(_) => Family::V4,
+            ICMPMessage::V6(_) => Family::V6,
+        }
+    }
+
+    /// The type and code numbers, as they are on the wire
+    pub fn type_code(&self) -> (u8, u8) {
+        match self {
+            ICMPMessage::V4(message) => message.icmpv4_type.type_code(),
+            ICMPMessage::V6(message) => message.icmpv6_type.type_code(),
+        }
+    }
+
+    pub fn checksum(&self) -> u16 {
+        match self {
+            ICMPMessage::V4(message) => message.icmpv4_checksum,
+            ICMPMessage::V6(message) => message.checksum,
+        }
+    }
+
+    /// The identifier and sequence number of an echo request or reply
+    pub fn echo_id_seq(&self) -> Option<(u16, u16)> {
+        match self {
+            ICMPMessage::V4(ICMPv4Message { icmpv4_type: ICMPv4Type::EchoRequest { identifier, sequence_num }
+                | ICMPv4Type::EchoReply { identifier, sequence_num }, .. }) => Some((*identifier, *sequence_num)),
+            ICMPMessage::V6(ICMPv6Message { icmpv6_type: ICMPv6Type::EchoRequest { identifier, sequence_num }
+                | ICMPv6Type::EchoReply { identifier, sequence_num }, .. }) => Some((*identifier, *sequence_num)),
+            _ => None,
+        }
+    }
+
+    pub fn is_echo_reply(&self) -> bool {
+        matches!(self, ICMPMessage::V4(ICMPv4Message { icmpv4_type: ICMPv4Type::EchoReply { .. }, .. })
+            | ICMPMessage::V6(ICMPv6Message { icmpv6_type: ICMPv6Type::EchoReply { .. }, .. }))
+    }
+
+    /// Whether it's an error message, about the datagram in `original()`
+    pub fn is_error(&self) -> bool {
+        match self {
+            ICMPMessage::V4(message) => message.icmpv4_type.is_error(),
+            ICMPMessage::V6(message) => message.icmpv6_type.is_error(),
+        }
+    }
+
+    /// Everything after the 8 byte header
+    pub fn payload(&self) -> &[u8] {
+        match self {
+            ICMPMessage::V4(message) => &message.icmpv4_data,
+            ICMPMessage::V6(message) => &message.body,
+        }
+    }
+
+    pub fn original(&self) -> Option<OriginalDatagram> {
+        match self {
+            ICMPMessage::V4(message) => message.original(),
+            ICMPMessage::V6(message) => message.original(),
+        }
+    }
+
+    /// Every field on its own line, followed by a hex dump of the data and the extensions
+    pub fn dump(&self) -> String {
+        match self {
+            ICMPMessage::V4(message) => message.dump(),
+            ICMPMessage::V6(message) => message.dump(),
+        }
+    }
+
+    /// As for `ICMPv4Message::write_to` and `ICMPv6Message::write_to`
+    pub fn write_to(&self, buf: &mut Vec<u8>) {
+        match self {
+            ICMPMessage::V4(message) => message.write_to(buf),
+            ICMPMessage::V6(message) => message.write_to(buf),
+        }
+    }
+
+    /// The message as it would be sent. ICMPv6 messages have a checksum of 0, for the kernel to fill in
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.write_to(&mut buf);
+        buf
+    }
+}
+
+impl fmt::Display for ICMPMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ICMPMessage::V4(message) => message.fmt(f),
+            ICMPMessage::V6(message) => message.fmt(f),
+        }
+    }
+}
+
+/// A borrowed view of an ICMPv4 or ICMPv6 message, like `ICMPMessage` but without copying the data out,
+/// for the receive path
+#[derive(Clone, Copy, Debug)]
+pub enum ICMPPacket<'a> {
+    V4(ICMPv4Packet<'a>),
+    V6(ICMPv6Packet<'a>),
+}
+
+impl<'a> TryFrom<(&'a [u8], Family)> for ICMPPacket<'a> {
+    type Error = ParseError;
+
+    fn try_from((msgbytes, family): (&'a [u8], Family)) -> Result<Self, Self::Error> {
+        Ok(match family {
+            Family::V4 => ICMPPacket::V4(ICMPv4Packet::new(msgbytes)?),
+            Family::V6 => ICMPPacket::V6(ICMPv6Packet::new(msgbytes)?),
+        })
+    }
+}
+
+impl<'a> ICMPPacket<'a> {
+    pub fn family(&self) -> Family {
+        match self {
+            ICMPPacket::V4(_) => Family::V4,
+            ICMPPacket::V6(_) => Family::V6,
+        }
+    }
+
+    pub fn icmp_type(&self) -> u8 {
+        match self {
+            ICMPPacket::V4(packet) => packet.icmp_type(),
+            ICMPPacket::V6(packet) => packet.icmp_type(),
+        }
+    }
+
+    pub fn checksum(&self) -> u16 {
+        match self {
+            ICMPPacket::V4(packet) => packet.checksum(),
+            ICMPPacket::V6(packet) => packet.checksum(),
+        }
+    }
+
+    /// The identifier and sequence number of an echo request or reply, read straight from the header
+    pub fn echo_id_seq(&self) -> Option<(u16, u16)> {
+        let (header, echo) = match self {
+            ICMPPacket::V4(packet) => (packet.header, matches!(packet.icmp_type(), 0 | 8)),
+            ICMPPacket::V6(packet) => (packet.header, matches!(packet.icmp_type(), 128 | 129)),
+        };
+        echo.then(|| (u16::from_be_bytes([header[4], header[5]]), u16::from_be_bytes([header[6], header[7]])))
+    }
+
+    pub fn is_echo_reply(&self) -> bool {
+        matches!((self.family(), self.icmp_type()), (Family::V4, 0) | (Family::V6, 129))
+    }
+
+    /// Whether it's an error message, about the datagram in `original()`
+    pub fn is_error(&self) -> bool {
+        match self {
+            ICMPPacket::V4(packet) => packet.message_type().is_ok_and(|t| t.is_error()),
+            ICMPPacket::V6(packet) => packet.message_type().is_ok_and(|t| t.is_error()),
+        }
+    }
+
+    /// Everything after the 8 byte header
+    pub fn payload(&self) -> &'a [u8] {
+        match self {
+            ICMPPacket::V4(packet) => packet.data(),
+            ICMPPacket::V6(packet) => packet.body(),
+        }
+    }
+
+    pub fn original(&self) -> Option<OriginalDatagram> {
+        match self {
+            ICMPPacket::V4(packet) => packet.original(),
+            ICMPPacket::V6(packet) => packet.original(),
+        }
+    }
+}
+
+impl TryFrom<ICMPPacket<'_>> for ICMPMessage {
+    type Error = ParseError;
+
+    fn try_from(packet: ICMPPacket<'_>) -> Result<Self, Self::Error> {
+        Ok(match packet {
+            ICMPPacket::V4(packet) => ICMPMessage::V4(packet.try_into()?),
+            ICMPPacket::V6(packet) => ICMPMessage::V6(packet.try_into()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: Ipv6Addr = Ipv6Addr::new(0xfe80, 0, 0, 0, 0x5054, 0xff, 0xfe12, 0x3456);
+    const MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+    /// The start of an IPv4 echo request from 192.0.2.1 to 198.51.100.7, as it's quoted in an error message
+    fn original_v4() -> Vec<u8> {
+        let mut datagram = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, PROTOCOL_ICMP, 0, 0, 192, 0, 2, 1, 198, 51, 100, 7];
+        datagram.extend(construct_echo_request_v4(0x1234, 5, &[]));
+        datagram
+    }
+
+    /// As for `original_v4`, from 2001:db8::1 to 2001:db8::7
+    fn original_v6() -> Vec<u8> {
+        let mut datagram = vec![0x60, 0, 0, 0, 0, 8, PROTOCOL_ICMPV6, 64];
+        datagram.extend(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+        datagram.extend(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7).octets());
+        datagram.extend(construct_echo_request_v6(0x1234, 5, &[]));
+        datagram
+    }
+
+    fn parse_v4(bytes: &[u8]) -> ICMPv4Message {
+        ICMPv4Message::try_from(bytes).unwrap()
+    }
+
+    fn parse_v6(bytes: &[u8]) -> ICMPv6Message {
+        ICMPv6Message::try_from(bytes).unwrap()
+    }
+
+    #[test]
+    fn v4_constructors_parse_back() {
+        let original = original_v4();
+        let echo = parse_v4(&construct_echo_reply_v4(0x1234, 5, b"data"));
+        assert_eq!(echo.icmpv4_type, ICMPv4Type::EchoReply { identifier: 0x1234, sequence_num: 5 });
+        assert_eq!(echo.icmpv4_data, b"data");
+        assert_eq!(parse_v4(&construct_echo_request_v4(0x1234, 5, &[])).icmpv4_type,
+            ICMPv4Type::EchoRequest { identifier: 0x1234, sequence_num: 5 });
+        assert_eq!(parse_v4(&construct_timestamp_request(1, 2, 3)).icmpv4_type,
+            ICMPv4Type::Timestamp { identifier: 1, sequence_num: 2, ts_originate: 3, ts_receive: 0, ts_transmit: 0 });
+        assert_eq!(parse_v4(&construct_timestamp_reply(1, 2, 3, 4, 5)).icmpv4_type,
+            ICMPv4Type::TimestampReply { identifier: 1, sequence_num: 2, ts_originate: 3, ts_receive: 4, ts_transmit: 5 });
+        let unreachable = parse_v4(&construct_destination_unreachable_v4(DestinationUnreachableCode::FragmentationRequired, 1400, &original));
+        assert_eq!(unreachable.icmpv4_type,
+            ICMPv4Type::DestinationUnreachable { code: DestinationUnreachableCode::FragmentationRequired, length: 0, next_hop_mtu: 1400 });
+        let quoted = unreachable.original().unwrap();
+        assert_eq!((quoted.destination, quoted.identifier, quoted.sequence), ("198.51.100.7".parse().unwrap(), Some(0x1234), Some(5)));
+        assert_eq!(parse_v4(&construct_source_quench(&original)).icmpv4_type, ICMPv4Type::SourceQuench {});
+        assert_eq!(parse_v4(&construct_redirect(RedirectMsgCode::Host, Ipv4Addr::new(192, 0, 2, 254), &original)).icmpv4_type,
+            ICMPv4Type::RedirectMessage { code: RedirectMsgCode::Host, address: 0xc00002fe });
+        let advertisement = parse_v4(&construct_router_advertisement(1800, &[(Ipv4Addr::new(192, 0, 2, 254), 0)]));
+        assert_eq!(advertisement.icmpv4_type, ICMPv4Type::RouterAdvertisement { address_count: 1, entry_size: 2, lifetime: 1800 });
+        assert_eq!(advertisement.icmpv4_data, [192, 0, 2, 254, 0, 0, 0, 0]);
+        assert_eq!(parse_v4(&construct_router_solicitation()).icmpv4_type, ICMPv4Type::RouterSolicitation {});
+        assert_eq!(parse_v4(&construct_time_exceeded_v4(TimeExceededCode::ExpiredInTransit, &original)).icmpv4_type,
+            ICMPv4Type::TimeExceeded { code: TimeExceededCode::ExpiredInTransit, length: 0 });
+        assert_eq!(parse_v4(&construct_bad_ip_header(BadIPHeaderCode::PointerIndicatesError, 8, &original)).icmpv4_type,
+            ICMPv4Type::BadIPHeader { code: BadIPHeaderCode::PointerIndicatesError, pointer: 8, length: 0 });
+    }
+
+    #[test]
+    fn v4_constructors_fill_in_checksum() {
+        for message in [construct_echo_request_v4(0x1234, 5, b"odd"), construct_timestamp_request(1, 2, 3),
+            construct_redirect(RedirectMsgCode::Network, Ipv4Addr::new(192, 0, 2, 254), &original_v4())] {
+            assert_eq!(internet_checksum(&message), 0, "{:02x?}", message);
+        }
+    }
+
+    #[test]
+    fn v6_constructors_parse_back() {
+        let original = original_v6();
+        let echo = parse_v6(&construct_echo_reply_v6(0x1234, 5, b"data"));
+        assert_eq!(echo.icmpv6_type, ICMPv6Type::EchoReply { identifier: 0x1234, sequence_num: 5 });
+        assert_eq!(echo.body, b"data");
+        assert_eq!(parse_v6(&construct_echo_request_v6(0x1234, 5, &[])).icmpv6_type,
+            ICMPv6Type::EchoRequest { identifier: 0x1234, sequence_num: 5 });
+        let unreachable = parse_v6(&construct_destination_unreachable_v6(DestinationUnreachableV6Code::PortUnreachable, &original));
+        assert_eq!(unreachable.icmpv6_type,
+            ICMPv6Type::DestinationUnreachable { code: DestinationUnreachableV6Code::PortUnreachable, length: 0 });
+        assert_eq!(unreachable.original().unwrap().sequence, Some(5));
+        assert_eq!(parse_v6(&construct_packet_too_big(1280, &original)).icmpv6_type, ICMPv6Type::PacketTooBig { mtu: 1280 });
+        assert_eq!(parse_v6(&construct_time_exceeded_v6(TimeExceededCode::FragmentReassemblyTimeExceeded, &original)).icmpv6_type,
+            ICMPv6Type::TimeExceeded { code: TimeExceededCode::FragmentReassemblyTimeExceeded, length: 0 });
+        assert_eq!(parse_v6(&construct_parameter_problem_v6(ParamProblemCode::UnrecognisedNextHeaderType, 6, &original)).icmpv6_type,
+            ICMPv6Type::ParameterProblem { code: ParamProblemCode::UnrecognisedNextHeaderType, ptr: 6 });
+        assert_eq!(parse_v6(&construct_neighbor_solicitation(TARGET, MAC)).icmpv6_type,
+            ICMPv6Type::NeighborSolicitation { target: TARGET, options: vec![NdpOption::SourceLinkLayerAddress(MAC.to_vec())] });
+        assert_eq!(parse_v6(&construct_neighbor_advertisement(TARGET, false, true, true, Some(MAC))).icmpv6_type,
+            ICMPv6Type::NeighborAdvertisement { router: false, solicited: true, override_entry: true, target: TARGET,
+                options: vec![NdpOption::TargetLinkLayerAddress(MAC.to_vec())] });
+    }
+
+    #[test]
+    fn v6_errors_fit_in_minimum_mtu() {
+        let message = construct_packet_too_big(1280, &[0; 2000]);
+        assert_eq!(message.len(), 8 + MAX_V6_ERROR_DATA);
+    }
+
+    /// Checks that every prefix of `message` fails to parse (rather than panicking), except the lengths in `complete`,
+    /// which are where a whole message could end (e.g. before its options)
+    fn assert_prefixes_rejected<T: fmt::Debug>(message: &[u8], complete: &[usize], parse: impl Fn(&[u8]) -> Result<T, ParseError>) {
+        for len in 0..message.len() {
+            let result = parse(&message[..len]);
+            assert!(complete.contains(&len) || result.is_err(), "{} of {} bytes parsed as {:?}", len, message.len(), result);
+        }
+        parse(message).unwrap();
+    }
+
+    /// Parses an ICMPv4 message and everything that's worked out from it when it's shown
+    fn describe_v4(bytes: &[u8]) -> Result<String, ParseError> {
+        let message = ICMPv4Message::try_from(bytes)?;
+        Ok(format!("{}\n{}", message, message.dump()))
+    }
+
+    fn describe_v6(bytes: &[u8]) -> Result<String, ParseError> {
+        let message = ICMPv6Message::try_from(bytes)?;
+        Ok(format!("{}\n{}", message, message.dump()))
+    }
+
+    /// An extension structure with the MPLS label 16005, as a router would add after the original datagram
+    fn mpls_extensions() -> Vec<u8> {
+        let mut extensions = vec![EXTENSION_VERSION << 4, 0, 0, 0, 0, 8, MPLS_CLASS, MPLS_INCOMING_STACK];
+        extensions.extend((16005u32 << 12 | 0x100 | 254).to_be_bytes());
+        populate_checksum(&mut extensions);
+        extensions
+    }
+
+    /// An error message's data with the extensions where routers from before RFC 4884 put them
+    fn with_legacy_extensions(mut original: Vec<u8>) -> Vec<u8> {
+        original.resize(LEGACY_ORIGINAL_LENGTH, 0);
+        original.extend(mpls_extensions());
+        original
+    }
+
+    #[test]
+    fn short_echo_messages_are_rejected() {
+        let message = construct_echo_request_v4(0x1234, 5, b"data");
+        assert_prefixes_rejected(&message, &[8, 9, 10, 11], describe_v4);
+        assert_prefixes_rejected(&construct_timestamp_reply(1, 2, 3, 4, 5), &[], describe_v4);
+        let message = construct_echo_reply_v6(0x1234, 5, b"data");
+        assert_prefixes_rejected(&message, &[8, 9, 10, 11], describe_v6);
+    }
+
+    #[test]
+    fn short_ndp_messages_are_rejected() {
+        assert_prefixes_rejected(&construct_neighbor_solicitation(TARGET, MAC), &[NEIGHBOR_OPTIONS], describe_v6);
+        let message = construct_neighbor_advertisement(TARGET, true, true, false, Some(MAC));
+        assert_prefixes_rejected(&message, &[NEIGHBOR_OPTIONS], describe_v6);
+        let advertisement = ICMPv6Message {
+            icmpv6_type: ICMPv6Type::RouterAdvertisement {
+                hop_limit: 64, managed: false, other_config: true, router_lifetime: 1800, reachable_time: 0, retrans_timer: 0,
+                options: vec![
+                    NdpOption::SourceLinkLayerAddress(MAC.to_vec()),
+                    NdpOption::Mtu(1500),
+                    NdpOption::PrefixInformation {
+                        prefix_length: 64, on_link: true, autonomous: true, valid_lifetime: 86400, preferred_lifetime: 14400,
+                        prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                    },
+                ],
+            },
+            checksum: 0,
+            body: Vec::new(),
+        };
+        assert_prefixes_rejected(&advertisement.to_bytes(), &[16, 24, 32], describe_v6);
+    }
+
+    #[test]
+    fn short_mld_messages_are_rejected() {
+        let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+        let sources = vec![Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2)];
+        let message = |icmpv6_type| ICMPv6Message { icmpv6_type, checksum: 0, body: Vec::new() }.to_bytes();
+        let query = message(ICMPv6Type::MulticastListenerQuery { max_response_delay: 10000, multicast_address: group, sources: sources.clone() });
+        // Without the MLDv2 fields it's a whole MLDv1 query
+        assert_prefixes_rejected(&query, &[MLD_V1_LEN], describe_v6);
+        assert_prefixes_rejected(&message(ICMPv6Type::MulticastListenerDone { multicast_address: group }), &[], describe_v6);
+        let records = vec![
+            MulticastAddressRecord { record_type: 4, multicast_address: group, sources: Vec::new() },
+            MulticastAddressRecord { record_type: 5, multicast_address: group, sources },
+        ];
+        assert_prefixes_rejected(&message(ICMPv6Type::MulticastListenerReportV2 { records }), &[], describe_v6);
+    }
+
+    #[test]
+    fn short_extensions_are_rejected() {
+        assert_prefixes_rejected(&mpls_extensions(), &[4], |bytes| ICMPExtensions::try_from(bytes));
+        // Cut off anywhere, the message either has no extensions or its data is too short for the ones it has
+        let extensions = |message: Result<Option<ICMPExtensions>, ParseError>, bytes: &[u8]| {
+            message?.ok_or(ParseError::not_long_enough(bytes, 8 + LEGACY_ORIGINAL_LENGTH, 4))
+        };
+        let message = construct_time_exceeded_v4(TimeExceededCode::ExpiredInTransit, &with_legacy_extensions(original_v4()));
+        assert_prefixes_rejected(&message, &[], |bytes| {
+            describe_v4(bytes)?;
+            extensions(ICMPv4Message::try_from(bytes)?.extensions(), bytes)
+        });
+        let message = construct_time_exceeded_v6(TimeExceededCode::ExpiredInTransit, &with_legacy_extensions(original_v6()));
+        assert_prefixes_rejected(&message, &[], |bytes| {
+            describe_v6(bytes)?;
+            extensions(ICMPv6Message::try_from(bytes)?.extensions(), bytes)
+        });
+    }
+
+    #[test]
+    fn short_original_datagrams_are_rejected() {
+        // Only the IP header has to be there, as not every router quotes the 8 bytes after it
+        let complete: Vec<usize> = (20..28).collect();
+        assert_prefixes_rejected(&original_v4(), &complete, |bytes| OriginalDatagram::try_from(bytes));
+        let complete: Vec<usize> = (40..48).collect();
+        assert_prefixes_rejected(&original_v6(), &complete, |bytes| OriginalDatagram::try_from(bytes));
+    }
+
+    /// Checks that a message is written back out exactly as it was parsed
+    fn assert_round_trip(bytes: &[u8], family: Family) {
+        let message = ICMPMessage::try_from((bytes, family)).unwrap();
+        assert_eq!(message.to_bytes(), bytes, "{}", message);
+        assert_eq!(ICMPMessage::try_from((&message.to_bytes()[..], family)).unwrap(), message);
+    }
+
+    #[test]
+    fn v4_messages_round_trip() {
+        let original = original_v4();
+        for message in [
+            construct_echo_reply_v4(0x1234, 5, b"data"),
+            construct_destination_unreachable_v4(DestinationUnreachableCode::FragmentationRequired, 1400, &original),
+            construct_source_quench(&original),
+            construct_redirect(RedirectMsgCode::ToSAndHost, Ipv4Addr::new(192, 0, 2, 254), &original),
+            construct_v4(6, 0, [0; 4], &[]), // Alternate host address
+            construct_echo_request_v4(0x1234, 5, b"data"),
+            construct_router_advertisement(1800, &[(Ipv4Addr::new(192, 0, 2, 254), 0), (Ipv4Addr::new(192, 0, 2, 253), -1)]),
+            construct_router_solicitation(),
+            construct_time_exceeded_v4(TimeExceededCode::ExpiredInTransit, &with_legacy_extensions(original.clone())),
+            construct_bad_ip_header(BadIPHeaderCode::BadLength, 2, &original),
+            construct_timestamp_request(1, 2, 3),
+            construct_timestamp_reply(1, 2, 3, 4, 5),
+        ] {
+            assert_round_trip(&message, Family::V4);
+        }
+    }
+
+    #[test]
+    fn v6_messages_round_trip() {
+        let original = original_v6();
+        let group = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+        let message = |icmpv6_type| ICMPv6Message { icmpv6_type, checksum: 0, body: Vec::new() }.to_bytes();
+        let options = vec![NdpOption::SourceLinkLayerAddress(MAC.to_vec()), NdpOption::Other { option_type: 14, data: vec![1; 6] }];
+        for message in [
+            construct_destination_unreachable_v6(DestinationUnreachableV6Code::AddressUnreachable, &original),
+            construct_packet_too_big(1280, &original),
+            construct_time_exceeded_v6(TimeExceededCode::ExpiredInTransit, &with_legacy_extensions(original.clone())),
+            construct_parameter_problem_v6(ParamProblemCode::ErroneousHeaderField, 4, &original),
+            construct_echo_request_v6(0x1234, 5, b"data"),
+            construct_echo_reply_v6(0x1234, 5, b"data"),
+            message(ICMPv6Type::MulticastListenerQuery { max_response_delay: 10000, multicast_address: Ipv6Addr::UNSPECIFIED, sources: Vec::new() }),
+            message(ICMPv6Type::MulticastListenerQuery { max_response_delay: 1000, multicast_address: group, sources: vec![TARGET] }),
+            message(ICMPv6Type::MulticastListenerReport { multicast_address: group }),
+            message(ICMPv6Type::MulticastListenerDone { multicast_address: group }),
+            message(ICMPv6Type::RouterSolicitation { options: options.clone() }),
+            message(ICMPv6Type::RouterAdvertisement {
+                hop_limit: 64, managed: true, other_config: false, router_lifetime: 0, reachable_time: 30000, retrans_timer: 1000,
+                options: vec![NdpOption::Mtu(1500), NdpOption::PrefixInformation {
+                    prefix_length: 64, on_link: true, autonomous: false, valid_lifetime: u32::MAX, preferred_lifetime: u32::MAX,
+                    prefix: Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0),
+                }],
+            }),
+            construct_neighbor_solicitation(TARGET, MAC),
+            message(ICMPv6Type::NeighborSolicitation { target: TARGET, options }),
+            construct_neighbor_advertisement(TARGET, true, false, true, None),
+            construct_neighbor_advertisement(TARGET, false, true, true, Some(MAC)),
+            message(ICMPv6Type::MulticastListenerReportV2 { records: vec![
+                MulticastAddressRecord { record_type: 2, multicast_address: group, sources: Vec::new() },
+                MulticastAddressRecord { record_type: 6, multicast_address: group, sources: vec![TARGET, Ipv6Addr::LOCALHOST] },
+            ] }),
+        ] {
+            assert_round_trip(&message, Family::V6);
+        }
+    }
+
+    #[test]
+    fn extensions_survive_round_trip() {
+        let message = construct_time_exceeded_v4(TimeExceededCode::ExpiredInTransit, &with_legacy_extensions(original_v4()));
+        let message = parse_v4(&parse_v4(&message).to_bytes());
+        let labels = message.extensions().unwrap().unwrap().mpls_labels();
+        assert_eq!(labels.iter().map(|l| (l.label, l.bottom_of_stack, l.ttl)).collect::<Vec<_>>(), [(16005, true, 254)]);
+        assert_eq!(message.original().unwrap().sequence, Some(5));
+    }
+}
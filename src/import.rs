@@ -0,0 +1,557 @@
+//! Reading hosts to probe from files that already list them: the hosts file (/etc/hosts), OpenSSH client
+//! configs (~/.ssh/config) and Ansible inventories, so that the machines someone usually connects to can be monitored
+//! without listing them
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// How deep Includes in SSH configs can go, as for OpenSSH
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// The most hosts a pattern in an inventory can expand to, so that a mistyped range (like `[1:1000000000]`)
+/// is an error rather than using up all the memory
+pub const MAX_RANGE_HOSTS: usize = 10_000;
+
+/// The name of every host in a hosts file, apart from loopback, multicast and unspecified addresses (such as
+/// localhost and ip6-allnodes). Only the first name on each line is used, as the aliases after it are the same host
+pub fn hosts_file(text: &str) -> Vec<String> {
+    let mut hosts = Vec::new();
+    for line in text.lines() {
+        let mut fields = line.split('#').next().unwrap_or("").split_whitespace();
+        let (Some(address), Some(name)) = (fields.next(), fields.next()) else { continue };
+        let Ok(address) = address.split('%').next().unwrap_or(address).parse::<IpAddr>() else { continue };
+        if address.is_loopback() || address.is_multicast() || address.is_unspecified() {
+            continue;
+        }
+        push_new(&mut hosts, name.to_string());
+    }
+    hosts
+}
+
+/// The hosts in an SSH config and the files it includes. Each name on a `Host` line that isn't a pattern (with `*`
+/// or `?`) or negated (with `!`) is a host, which is probed at its `HostName` if it has one
+pub fn ssh_config(path: &Path) -> Result<Vec<String>, Error> {
+    let mut hosts = Vec::new();
+    read_ssh_config(path, 0, &mut hosts)?;
+    Ok(hosts)
+}
+
+fn read_ssh_config(path: &Path, depth: usize, hosts: &mut Vec<String>) -> Result<(), Error> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} is included too deeply", path.display())));
+    }
+    let text = fs::read_to_string(path)?;
+    // The names from the Host line of the block being read, and the HostName they were given in it
+    let mut names: Vec<String> = Vec::new();
+    let mut host_name: Option<String> = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Keywords are separated from their arguments by spaces or an equals sign
+        let (keyword, arguments) = line.split_once(|c: char| c.is_whitespace() || c == '=').unwrap_or((line, ""));
+        let arguments = arguments.trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim();
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" | "match" => {
+                finish_block(&mut names, host_name.take(), hosts);
+                if keyword.eq_ignore_ascii_case("host") {
+                    names = arguments.split_whitespace()
+                        .filter(|name| !name.starts_with('!') && !name.contains(['*', '?']))
+                        .map(|name| name.trim_matches('"').to_string())
+                        .collect();
+                }
+            },
+            "hostname" if host_name.is_none() => host_name = Some(arguments.trim_matches('"').to_string()),
+            "include" => for pattern in arguments.split_whitespace() {
+                for included in include_paths(path, pattern) {
+                    read_ssh_config(&included, depth + 1, hosts)?;
+                }
+            },
+            _ => {},
+        }
+    }
+    finish_block(&mut names, host_name, hosts);
+    Ok(())
+}
+
+/// Adds the hosts from a block, at its HostName (which can refer to the name with `%h`) if it has one
+fn finish_block(names: &mut Vec<String>, host_name: Option<String>, hosts: &mut Vec<String>) {
+    for name in names.drain(..) {
+        let host = host_name.as_ref().map_or(name.clone(), |h| h.replace("%h", &name).replace("%%", "%"));
+        push_new(hosts, host);
+    }
+}
+
+/// The files an Include refers to. As for OpenSSH, relative paths are in ~/.ssh, and the file name can be a pattern
+fn include_paths(from: &Path, pattern: &str) -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from).unwrap_or_default();
+    let path = match pattern.strip_prefix("~/") {
+        Some(rest) => home.join(rest),
+        None if Path::new(pattern).is_absolute() => PathBuf::from(pattern),
+        // ~/.ssh/config is the usual file, but a config given some other way has its includes next to it
+        None => from.parent().map_or(home.join(".ssh"), Path::to_path_buf).join(pattern),
+    };
+    if !pattern.contains(['*', '?']) {
+        return if path.is_file() { vec![path] } else { Vec::new() };
+    }
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else { return Vec::new() };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir).into_iter().flatten().flatten()
+        .filter(|entry| entry.file_name().to_str().is_some_and(|n| matches_pattern(name, n)))
+        .map(|entry| entry.path())
+        .filter(|p| p.is_file())
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Whether a name matches a pattern where `*` is any number of characters and `?` is any one character
+pub fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // Where the last * was in the pattern, and where in the name it's matching up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            },
+            Some(c) if *c == '?' || c.eq_ignore_ascii_case(&name[n]) => {
+                p += 1;
+                n += 1;
+            },
+            _ => match star {
+                // The * matches one more character, and the rest of the pattern starts after that
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Whether a host matches the patterns: any of the ones that aren't negated (or any host if they're all negated),
+/// and none of the ones that start with `!`
+pub fn is_selected(host: &str, patterns: &[String]) -> bool {
+    let (excludes, includes): (Vec<&str>, Vec<&str>) = patterns.iter().map(String::as_str).partition(|p| p.starts_with('!'));
+    (includes.is_empty() || includes.iter().any(|p| matches_pattern(p, host)))
+        && !excludes.iter().any(|p| matches_pattern(&p[1..], host))
+}
+
+/// A group of hosts from an Ansible inventory
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InventoryGroup {
+    pub name: String,
+    /// What to probe for each host: its `ansible_host` if it has one (wherever in the inventory that was given, as it
+    /// is for Ansible), or else its name
+    pub hosts: Vec<String>,
+    /// The names of the groups in this one
+    pub children: Vec<String>,
+}
+
+/// The groups of an Ansible inventory, in the order they first appear. Hosts that aren't in a group are in
+/// "ungrouped", as they are for Ansible
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Inventory {
+    pub groups: Vec<InventoryGroup>,
+}
+
+impl Inventory {
+    /// Reads an INI or YAML inventory. It's taken to be YAML if the file name ends with .yml or .yaml, or it
+    /// starts with `key:` (as INI inventories start with a host or a [group])
+    pub fn load(path: &Path) -> Result<Inventory, Error> {
+        let text = fs::read_to_string(path)?;
+        let yaml_name = path.extension().is_some_and(|e| e == "yml" || e == "yaml");
+        let first = text.lines().map(str::trim).find(|l| !l.is_empty() && !l.starts_with(['#', ';']));
+        if yaml_name || first.is_some_and(|l| l == "---" || (l.ends_with(':') && !l.starts_with('['))) {
+            Inventory::parse_yaml(&text)
+        } else {
+            Inventory::parse_ini(&text)
+        }
+    }
+
+    /// Parses an INI inventory: hosts under `[group]` headings, and groups under `[group:children]` ones.
+    /// Host ranges like `www[01:50].example.com` are expanded, and `[group:vars]` sections are skipped
+    pub fn parse_ini(text: &str) -> Result<Inventory, Error> {
+        let mut inventory = Inventory::default();
+        // The group being read, and whether the lines are its children (or else its hosts, or None for its vars)
+        let mut section = Some(("ungrouped".to_string(), false));
+        let mut addresses: Vec<(String, String)> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            let invalid = |reason: String| Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, reason));
+            if let Some(heading) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match heading.split_once(':') {
+                    None => Some((heading.to_string(), false)),
+                    Some((group, "children")) => Some((group.to_string(), true)),
+                    Some((_, "vars")) => None,
+                    Some((_, kind)) => return Err(invalid(format!("unknown section type {}", kind))),
+                };
+                if let Some((name, _)) = &section {
+                    inventory.group_mut(name);
+                }
+                continue;
+            }
+            let Some((group, children)) = &section else { continue };
+            let mut fields = line.split_whitespace();
+            let name = fields.next().unwrap_or_default();
+            if *children {
+                inventory.group_mut(name);
+                push_new(&mut inventory.group_mut(group).children, name.to_string());
+                continue;
+            }
+            let address = fields.find_map(|f| f.strip_prefix("ansible_host=")).map(|a| a.trim_matches(['"', '\'']));
+            for host in expand_range(name).map_err(invalid)? {
+                if let Some(address) = address {
+                    addresses.push((host.clone(), address.to_string()));
+                }
+                push_new(&mut inventory.group_mut(group).hosts, host);
+            }
+        }
+        inventory.use_addresses(&addresses);
+        Ok(inventory)
+    }
+
+    /// Parses a YAML inventory, which nests groups (from `all`) with `hosts`, `children` and `vars` keys. Only the
+    /// block style that inventories are written in is understood, not the whole of YAML
+    pub fn parse_yaml(text: &str) -> Result<Inventory, Error> {
+        let mut inventory = Inventory::default();
+        // The keys the line being read is nested in, with their indentation
+        let mut path: Vec<(usize, String)> = Vec::new();
+        let mut addresses: Vec<(String, String)> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let content = line.split(" #").next().unwrap_or("").trim_end();
+            let indent = content.len() - content.trim_start().len();
+            let content = content.trim_start();
+            if content.is_empty() || content.starts_with('#') || content == "---" || content == "..." {
+                continue;
+            }
+            let invalid = |reason: &str| Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, reason));
+            // Colons are only separators when they're followed by a space or the end of the line, as in host ranges
+            let (key, value) = content.split_once(": ").or_else(|| content.strip_suffix(':').map(|key| (key, "")))
+                .ok_or_else(|| invalid("expected key: value"))?;
+            let key = key.trim().trim_matches(['"', '\'']).to_string();
+            let value = value.trim().trim_matches(['"', '\'']);
+            while path.last().is_some_and(|(i, _)| *i >= indent) {
+                path.pop();
+            }
+            let keys: Vec<&str> = path.iter().map(|(_, k)| k.as_str()).collect();
+            // Groups are at the top, or under a group's children, so they alternate with the keys under them
+            let under_group_key = keys.len().is_multiple_of(2);
+            match keys.as_slice() {
+                [] => {
+                    inventory.group_mut(&key);
+                },
+                [.., group, "children"] if under_group_key => {
+                    inventory.group_mut(&key);
+                    push_new(&mut inventory.group_mut(group).children, key.clone());
+                },
+                [.., group, "hosts"] if under_group_key => {
+                    for host in expand_range(&key).map_err(|e| invalid(&e))? {
+                        push_new(&mut inventory.group_mut(group).hosts, host);
+                    }
+                },
+                [.., "hosts", host] if !under_group_key && key == "ansible_host" && !value.is_empty() => {
+                    addresses.push((host.to_string(), value.to_string()));
+                },
+                _ => {},
+            }
+            path.push((indent, key));
+        }
+        inventory.use_addresses(&addresses);
+        // "all" is only the top of the tree, rather than a group of its own
+        if let Some(all) = inventory.groups.iter().position(|g| g.name == "all") {
+            let all = inventory.groups.remove(all);
+            match inventory.groups.iter_mut().find(|g| g.name == "ungrouped") {
+                Some(ungrouped) => ungrouped.hosts.extend(all.hosts),
+                None if all.hosts.is_empty() => {},
+                None => inventory.groups.insert(0, InventoryGroup { name: "ungrouped".to_string(), hosts: all.hosts, children: Vec::new() }),
+            }
+        }
+        Ok(inventory)
+    }
+
+    /// Probes the hosts that were given an `ansible_host` at that address in every group they're in, rather than
+    /// only the one it was given in. If there's more than one, the last is used, as it is for Ansible
+    fn use_addresses(&mut self, addresses: &[(String, String)]) {
+        for group in &mut self.groups {
+            let mut hosts = Vec::new();
+            for host in group.hosts.drain(..) {
+                let address = addresses.iter().rev().find(|(name, _)| *name == host).map(|(_, address)| address);
+                push_new(&mut hosts, address.cloned().unwrap_or(host));
+            }
+            group.hosts = hosts;
+        }
+    }
+
+    fn group_mut(&mut self, name: &str) -> &mut InventoryGroup {
+        let i = match self.groups.iter().position(|g| g.name == name) {
+            Some(i) => i,
+            None => {
+                self.groups.push(InventoryGroup { name: name.to_string(), ..InventoryGroup::default() });
+                self.groups.len() - 1
+            },
+        };
+        &mut self.groups[i]
+    }
+
+    /// The hosts in the groups named (or every group, if none are or one is "all"), with the group each one is listed in, in the
+    /// order of the groups. Hosts in a group's children count as being in it. A host that's in several groups is
+    /// only given once, in the first of them
+    pub fn hosts(&self, groups: &[String]) -> Result<Vec<(String, String)>, Error> {
+        let mut selected: Vec<&str> = Vec::new();
+        let mut stack: Vec<&str> = if groups.is_empty() || groups.iter().any(|g| g == "all") {
+            self.groups.iter().map(|g| g.name.as_str()).rev().collect()
+        } else {
+            groups.iter().map(String::as_str).rev().collect()
+        };
+        while let Some(name) = stack.pop() {
+            let group = self.groups.iter().find(|g| g.name == name)
+                .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("there's no group called {}", name)))?;
+            if !selected.contains(&name) {
+                selected.push(name);
+                stack.extend(group.children.iter().map(String::as_str).rev());
+            }
+        }
+        let mut hosts: Vec<(String, String)> = Vec::new();
+        for group in self.groups.iter().filter(|g| selected.contains(&g.name.as_str())) {
+            for host in &group.hosts {
+                if !hosts.iter().any(|(h, _)| h == host) {
+                    hosts.push((host.clone(), group.name.clone()));
+                }
+            }
+        }
+        Ok(hosts)
+    }
+}
+
+/// Expands a host pattern with a range in it, like `www[01:50].example.com` (keeping the leading zeros),
+/// `db-[a:f]` or `node[0:10:2]` (with a stride). Ones without a range are as they are. It's an error for a pattern
+/// to expand to more than `MAX_RANGE_HOSTS` hosts
+fn expand_range(pattern: &str) -> Result<Vec<String>, String> {
+    let (Some(start), Some(end)) = (pattern.find('['), pattern.find(']')) else { return Ok(vec![pattern.to_string()]) };
+    let invalid = || format!("{} isn't a valid host range", pattern);
+    let (prefix, range, suffix) = (&pattern[..start], pattern.get(start + 1..end).ok_or_else(invalid)?, &pattern[end + 1..]);
+    let mut parts = range.split(':');
+    let (Some(first), Some(last)) = (parts.next(), parts.next()) else { return Err(invalid()) };
+    let stride: usize = parts.next().map_or(Ok(1), |s| s.parse()).map_err(|_| invalid())?;
+    if stride == 0 {
+        return Err(invalid());
+    }
+    let too_many = || format!("{} expands to more than {} hosts", pattern, MAX_RANGE_HOSTS);
+    let values: Vec<String> = match (first.parse::<u64>(), last.parse::<u64>()) {
+        (Ok(a), Ok(b)) if a <= b && (b - a) / stride as u64 >= MAX_RANGE_HOSTS as u64 => return Err(too_many()),
+        (Ok(a), Ok(b)) if a <= b => (a..=b).step_by(stride).map(|n| format!("{:0width$}", n, width = first.len())).collect(),
+        _ => match (first.as_bytes(), last.as_bytes()) {
+            (&[a], &[b]) if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() && a <= b => {
+                (a..=b).step_by(stride).map(|c| char::from(c).to_string()).collect()
+            },
+            _ => return Err(invalid()),
+        },
+    };
+    // There can be more ranges after this one
+    let mut hosts = Vec::new();
+    for value in values {
+        hosts.extend(expand_range(&format!("{}{}{}", prefix, value, suffix))?);
+        if hosts.len() > MAX_RANGE_HOSTS {
+            return Err(too_many());
+        }
+    }
+    Ok(hosts)
+}
+
+fn push_new(hosts: &mut Vec<String>, host: String) {
+    if !hosts.contains(&host) {
+        hosts.push(host);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group<'a>(inventory: &'a Inventory, name: &str) -> &'a InventoryGroup {
+        inventory.groups.iter().find(|g| g.name == name).unwrap()
+    }
+
+    /// A directory of its own under the temporary directory, for SSH configs
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("multiping-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn ranges_expand() {
+        assert_eq!(expand_range("web.example.com").unwrap(), ["web.example.com"]);
+        assert_eq!(expand_range("www[01:03].example.com").unwrap(), ["www01.example.com", "www02.example.com", "www03.example.com"]);
+        assert_eq!(expand_range("db-[a:c]").unwrap(), ["db-a", "db-b", "db-c"]);
+        assert_eq!(expand_range("node[0:10:5]").unwrap(), ["node0", "node5", "node10"]);
+        assert_eq!(expand_range("r[1:2]n[a:b]").unwrap(), ["r1na", "r1nb", "r2na", "r2nb"]);
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected() {
+        for pattern in ["web[3:1]", "web[1]", "web[1:2:0]", "web[a:1]", "web[1:x]", "web]1:2["] {
+            assert!(expand_range(pattern).is_err(), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn ranges_are_limited() {
+        assert_eq!(expand_range(&format!("h[1:{}]", MAX_RANGE_HOSTS)).unwrap().len(), MAX_RANGE_HOSTS);
+        assert!(expand_range(&format!("h[1:{}]", MAX_RANGE_HOSTS + 1)).is_err());
+        assert!(expand_range("h[0:18446744073709551615]").is_err());
+        // Each range is small enough, but not all of them together
+        assert!(expand_range("h[0:999][0:999]").is_err());
+    }
+
+    #[test]
+    fn ini_inventories_parse() {
+        let inventory = Inventory::parse_ini("
+            # Not in a group
+            bastion.example.com
+
+            [web]
+            www[1:2].example.com
+            api.example.com ansible_host=192.0.2.10 ansible_user=deploy
+
+            [db]
+            db1.example.com ansible_host='192.0.2.20'
+
+            [web:vars]
+            http_port=80
+
+            [prod:children]
+            web
+            db
+        ").unwrap();
+        let names: Vec<&str> = inventory.groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["ungrouped", "web", "db", "prod"]);
+        assert_eq!(group(&inventory, "ungrouped").hosts, ["bastion.example.com"]);
+        assert_eq!(group(&inventory, "web").hosts, ["www1.example.com", "www2.example.com", "192.0.2.10"]);
+        assert_eq!(group(&inventory, "db").hosts, ["192.0.2.20"]);
+        assert_eq!(group(&inventory, "prod").children, ["web", "db"]);
+        let prod = inventory.hosts(&["prod".to_string()]).unwrap();
+        assert_eq!(prod.iter().map(|(h, _)| h.as_str()).collect::<Vec<_>>(), ["www1.example.com", "www2.example.com", "192.0.2.10", "192.0.2.20"]);
+        assert!(inventory.hosts(&["staging".to_string()]).is_err());
+        assert!(Inventory::parse_ini("[web:other]\nwww").is_err());
+    }
+
+    #[test]
+    fn yaml_inventories_parse() {
+        let inventory = Inventory::parse_yaml("
+---
+all:
+  hosts:
+    bastion.example.com:
+  children:
+    web:
+      hosts:
+        www[1:2].example.com:
+        api.example.com:
+          ansible_host: 192.0.2.10  # The load balancer
+      vars:
+        http_port: 80
+    db:
+      hosts:
+        db1.example.com:
+          ansible_host: \"192.0.2.20\"
+").unwrap();
+        let names: Vec<&str> = inventory.groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, ["ungrouped", "web", "db"]);
+        assert_eq!(group(&inventory, "ungrouped").hosts, ["bastion.example.com"]);
+        assert_eq!(group(&inventory, "web").hosts, ["www1.example.com", "www2.example.com", "192.0.2.10"]);
+        assert_eq!(group(&inventory, "db").hosts, ["192.0.2.20"]);
+        assert!(Inventory::parse_yaml("all:\n  hosts\n").is_err());
+    }
+
+    #[test]
+    fn ansible_host_applies_in_every_group() {
+        let ini = Inventory::parse_ini("[web]\napi.example.com\n[lb]\napi.example.com ansible_host=192.0.2.10\n").unwrap();
+        assert_eq!(group(&ini, "web").hosts, ["192.0.2.10"]);
+        assert_eq!(group(&ini, "lb").hosts, ["192.0.2.10"]);
+        let yaml = Inventory::parse_yaml("
+all:
+  hosts:
+    api.example.com:
+      ansible_host: 192.0.2.10
+  children:
+    web:
+      hosts:
+        api.example.com:
+").unwrap();
+        assert_eq!(group(&yaml, "web").hosts, ["192.0.2.10"]);
+        assert_eq!(yaml.hosts(&[]).unwrap(), [("192.0.2.10".to_string(), "ungrouped".to_string())]);
+    }
+
+    #[test]
+    fn patterns_match() {
+        assert!(matches_pattern("web*", "web01"));
+        assert!(matches_pattern("web*", "web"));
+        assert!(matches_pattern("*.example.com", "db.EXAMPLE.com"));
+        assert!(matches_pattern("db?", "db1"));
+        assert!(!matches_pattern("db?", "db10"));
+        assert!(matches_pattern("*a*b*", "xxaxxbxx"));
+        assert!(!matches_pattern("*a*b", "xxbxxa"));
+        assert!(matches_pattern("**", ""));
+        assert!(!matches_pattern("web", "web01"));
+        assert!(is_selected("web01", &["web*".to_string(), "!web02".to_string()]));
+        assert!(!is_selected("web02", &["web*".to_string(), "!web02".to_string()]));
+        assert!(is_selected("db1", &["!web*".to_string()]));
+        assert!(is_selected("anything", &[]));
+    }
+
+    #[test]
+    fn hosts_files_parse() {
+        let hosts = hosts_file("
+127.0.0.1 localhost
+::1 localhost ip6-localhost
+ff02::1 ip6-allnodes
+192.0.2.1 router router.lan # The gateway
+fe80::1%eth0 link-local
+192.0.2.1 router
+");
+        assert_eq!(hosts, ["router", "link-local"]);
+    }
+
+    #[test]
+    fn ssh_configs_parse() {
+        let dir = temp_dir("ssh-config");
+        fs::create_dir(dir.join("conf.d")).unwrap();
+        fs::write(dir.join("config"), "
+# Comment
+Host web1 web2 *.internal !bastion
+    HostName %h.example.com
+    User deploy
+
+Host=db
+  HostName = 192.0.2.20
+
+Match host other
+  HostName ignored.example.com
+
+Include conf.d/*.conf
+").unwrap();
+        fs::write(dir.join("conf.d/a.conf"), "Host \"quoted\"\n").unwrap();
+        fs::write(dir.join("conf.d/b.conf"), "Host plain\n").unwrap();
+        fs::write(dir.join("conf.d/skipped.txt"), "Host skipped\n").unwrap();
+        let hosts = ssh_config(&dir.join("config")).unwrap();
+        assert_eq!(hosts, ["web1.example.com", "web2.example.com", "192.0.2.20", "quoted", "plain"]);
+        // An include of itself goes on until it's too deep
+        fs::write(dir.join("loop"), "Include loop\n").unwrap();
+        assert!(ssh_config(&dir.join("loop")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//! Minimal IPv4 header parser, for raw sockets (which give the IP header with each packet) and the datagrams
+//! quoted in ICMP errors
+
+use std::net::Ipv4Addr;
+
+use crate::icmp::ParseError;
+
+/// The length of a header without options
+pub const MIN_HEADER_LEN: usize = 20;
+
+/// The fixed part of an IPv4 header, and its options
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ipv4Header {
+    /// Length of the header including its options, in bytes (on wire: IHL, in 32-bit words)
+    pub header_len: usize,
+    /// Differentiated services code point and explicit congestion notification bits (formerly the type of service)
+    pub tos: u8,
+    /// Length of the whole datagram, including the header
+    pub total_len: u16,
+    pub identification: u16,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    /// Where the fragment goes in the datagram, in 8-byte units
+    pub fragment_offset: u16,
+    pub ttl: u8,
+    /// e.g. 1 for ICMP, 6 for TCP or 17 for UDP
+    pub protocol: u8,
+    pub checksum: u16,
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    /// Everything between the fixed part and the payload, which `options()` splits up
+    pub options: Vec<u8>,
+}
+
+/// An option from the header, e.g. Record Route (type 7)
+#[derive(Clone, Copy, Debug)]
+pub struct Ipv4Option<'a> {
+    /// On wire: the copied flag, class and number
+    pub kind: u8,
+    /// Everything after the type and length
+    pub data: &'a [u8],
+}
+
+/// Option types that are only one byte long
+const END_OF_OPTIONS: u8 = 0;
+const NO_OPERATION: u8 = 1;
+
+impl TryFrom<&[u8]> for Ipv4Header {
+    type Error = ParseError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let (fixed, _) = bytes.split_first_chunk::<MIN_HEADER_LEN>()
+            .ok_or(ParseError::not_long_enough(bytes, 0, MIN_HEADER_LEN))?;
+        if fixed[0] >> 4 != 4 {
+            return Err(ParseError::Invalid { offset: 0, reason: "IP version isn't 4" });
+        }
+        let header_len = (fixed[0] & 0x0f) as usize * 4;
+        if header_len < MIN_HEADER_LEN {
+            return Err(ParseError::Invalid { offset: 0, reason: "header length is less than 20 bytes" });
+        }
+        let options = bytes.get(MIN_HEADER_LEN..header_len)
+            .ok_or(ParseError::not_long_enough(bytes, MIN_HEADER_LEN, header_len - MIN_HEADER_LEN))?;
+        let flags_offset = u16::from_be_bytes([fixed[6], fixed[7]]);
+        Ok(Ipv4Header {
+            header_len,
+            tos: fixed[1],
+            total_len: u16::from_be_bytes([fixed[2], fixed[3]]),
+            identification: u16::from_be_bytes([fixed[4], fixed[5]]),
+            dont_fragment: flags_offset & 0x4000 != 0,
+            more_fragments: flags_offset & 0x2000 != 0,
+            fragment_offset: flags_offset & 0x1fff,
+            ttl: fixed[8],
+            protocol: fixed[9],
+            checksum: u16::from_be_bytes([fixed[10], fixed[11]]),
+            source: Ipv4Addr::new(fixed[12], fixed[13], fixed[14], fixed[15]),
+            destination: Ipv4Addr::new(fixed[16], fixed[17], fixed[18], fixed[19]),
+            options: options.to_vec(),
+        })
+    }
+}
+
+impl Ipv4Header {
+    /// Parses the header at the start of `packet`, and returns it with the rest of the datagram. Anything after the
+    /// total length (e.g. Ethernet padding) isn't part of the payload, but a datagram that's been cut short still is
+    pub fn split(packet: &[u8]) -> Result<(Ipv4Header, &[u8]), ParseError> {
+        let header = Ipv4Header::try_from(packet)?;
+        let end = (header.total_len as usize).clamp(header.header_len, packet.len());
+        let payload = packet.get(header.header_len..end).unwrap_or_default();
+        Ok((header, payload))
+    }
+
+    /// Whether the payload starts at the start of the datagram's payload (e.g. with the ICMP header),
+    /// which only the first fragment does
+    pub fn is_first_fragment(&self) -> bool {
+        self.fragment_offset == 0
+    }
+
+    /// The options, up to the end of the list
+    pub fn options(&self) -> Vec<Ipv4Option<'_>> {
+        let mut options = Vec::new();
+        let mut rest = &self.options[..];
+        while let Some((&kind, after)) = rest.split_first() {
+            match kind {
+                END_OF_OPTIONS => break,
+                NO_OPERATION => rest = after,
+                _ => {
+                    // The length includes the type and length bytes, so a bad one is the end of what can be read
+                    let Some(len) = after.first().map(|l| *l as usize).filter(|l| *l >= 2 && *l <= rest.len()) else { break };
+                    options.push(Ipv4Option { kind, data: rest.get(2..len).unwrap_or_default() });
+                    rest = rest.get(len..).unwrap_or_default();
+                },
+            }
+        }
+        options
+    }
+}
@@ -0,0 +1,101 @@
+//! Minimal IPv6 header parser, which walks the extension headers to find where the payload really starts, for
+//! the datagrams quoted in ICMPv6 errors and packets captured with their IPv6 header
+
+use std::net::Ipv6Addr;
+
+use crate::icmp::ParseError;
+
+/// The length of the fixed header, which extension headers come after
+pub const HEADER_LEN: usize = 40;
+
+/// Next header values for the extension headers that can be walked over
+const HOP_BY_HOP: u8 = 0;
+const ROUTING: u8 = 43;
+const FRAGMENT: u8 = 44;
+const AUTHENTICATION: u8 = 51;
+const DESTINATION_OPTIONS: u8 = 60;
+const MOBILITY: u8 = 135;
+const HOST_IDENTITY: u8 = 139;
+const SHIM6: u8 = 140;
+
+/// The fixed IPv6 header, and what was found by walking the extension headers after it
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ipv6Header {
+    pub traffic_class: u8,
+    pub flow_label: u32, // on wire: 20 bits
+    /// Length of everything after the fixed header, including the extension headers
+    pub payload_len: u16,
+    /// The header after the fixed one, which may be an extension header
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub source: Ipv6Addr,
+    pub destination: Ipv6Addr,
+    /// The types of the extension headers, in order
+    pub extension_headers: Vec<u8>,
+    /// What the payload after the extension headers is, e.g. 58 for ICMPv6. This is an extension header's type
+    /// if it couldn't be walked over (e.g. ESP, which is encrypted) or the packet ended in the middle of them
+    pub protocol: u8,
+    /// Where the fragment goes in the datagram in 8-byte units, if there's a fragment header
+    pub fragment_offset: Option<u16>,
+}
+
+impl Ipv6Header {
+    /// Parses the header at the start of `packet`, walks the extension headers and returns it with the payload
+    /// after them. As for IPv4, anything after the payload length isn't part of it, but a cut short payload still is
+    pub fn split(packet: &[u8]) -> Result<(Ipv6Header, &[u8]), ParseError> {
+        let (fixed, _) = packet.split_first_chunk::<HEADER_LEN>().ok_or(ParseError::not_long_enough(packet, 0, HEADER_LEN))?;
+        if fixed[0] >> 4 != 6 {
+            return Err(ParseError::Invalid { offset: 0, reason: "IP version isn't 6" });
+        }
+        let payload_len = u16::from_be_bytes([fixed[4], fixed[5]]);
+        let end = (HEADER_LEN + payload_len as usize).min(packet.len());
+        let mut payload = packet.get(HEADER_LEN..end).unwrap_or_default();
+        let mut protocol = fixed[6];
+        let mut extension_headers = Vec::new();
+        let mut fragment_offset = None;
+        // Every extension header that can be walked over starts with the next header, and (apart from the
+        // fragment header, which is always 8 bytes) its length
+        while let Some(len) = extension_len(protocol, payload) && let Some(next) = payload.get(..len).and_then(|h| h.first()) {
+            if protocol == FRAGMENT {
+                fragment_offset = payload.get(2..4).map(|f| u16::from_be_bytes([f[0], f[1]]) >> 3);
+            }
+            extension_headers.push(protocol);
+            protocol = *next;
+            payload = payload.get(len..).unwrap_or_default();
+        }
+        let traffic_flow = u32::from_be_bytes([fixed[0], fixed[1], fixed[2], fixed[3]]);
+        let header = Ipv6Header {
+            traffic_class: (traffic_flow >> 20) as u8,
+            flow_label: traffic_flow & 0x000f_ffff,
+            payload_len,
+            next_header: fixed[6],
+            hop_limit: fixed[7],
+            source: Ipv6Addr::from(<[u8; 16]>::try_from(&fixed[8..24]).unwrap_or_default()),
+            destination: Ipv6Addr::from(<[u8; 16]>::try_from(&fixed[24..40]).unwrap_or_default()),
+            extension_headers,
+            protocol,
+            fragment_offset,
+        };
+        Ok((header, payload))
+    }
+
+    /// Whether the payload starts at the start of the datagram's payload (e.g. with the ICMPv6 header),
+    /// which only the first fragment (or an unfragmented datagram) does
+    pub fn is_first_fragment(&self) -> bool {
+        self.fragment_offset.is_none_or(|offset| offset == 0)
+    }
+}
+
+/// How long the extension header of type `next_header` at the start of `payload` is, or None if it isn't one that
+/// can be walked over
+fn extension_len(next_header: u8, payload: &[u8]) -> Option<usize> {
+    match next_header {
+        FRAGMENT => Some(8),
+        // In 4-byte units, not counting the first 2
+        AUTHENTICATION => payload.get(1).map(|len| (*len as usize + 2) * 4),
+        // In 8-byte units, not counting the first 8 bytes
+        HOP_BY_HOP | ROUTING | DESTINATION_OPTIONS | MOBILITY | HOST_IDENTITY | SHIM6 => payload.get(1).map(|len| (*len as usize + 1) * 8),
+        _ => None,
+    }
+}
@@ -0,0 +1,247 @@
+//! Just enough JSON writing for the machine-readable outputs (and reading for what other copies of multiping
+//! write), without any dependencies
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::SystemTime;
+
+use crate::{HostInfo, HostState};
+use crate::time::format_rfc3339;
+
+/// Builds a JSON object one field at a time, e.g.
+/// `JsonObject::new().string("host", "example.com").number("rtt_ms", 1.5).finish()`
+#[derive(Clone, Debug, Default)]
+pub struct JsonObject {
+    text: String,
+}
+
+impl JsonObject {
+    pub fn new() -> JsonObject {
+        JsonObject { text: String::new() }
+    }
+
+    fn key(&mut self, key: &str) {
+        self.text.push(if self.text.is_empty() { '{' } else { ',' });
+        self.text.push_str(&quote(key));
+        self.text.push(':');
+    }
+
+    pub fn string(mut self, key: &str, value: &str) -> JsonObject {
+        self.key(key);
+        self.text.push_str(&quote(value));
+        self
+    }
+
+    /// Adds a number. NaN and infinity aren't allowed in JSON, so they're written as null
+    pub fn number(mut self, key: &str, value: f64) -> JsonObject {
+        self.key(key);
+        if value.is_finite() {
+            let _ = write!(self.text, "{}", value);
+        } else {
+            self.text.push_str("null");
+        }
+        self
+    }
+
+    pub fn integer(mut self, key: &str, value: i64) -> JsonObject {
+        self.key(key);
+        let _ = write!(self.text, "{}", value);
+        self
+    }
+
+    pub fn boolean(mut self, key: &str, value: bool) -> JsonObject {
+        self.key(key);
+        self.text.push_str(if value { "true" } else { "false" });
+        self
+    }
+
+    /// Adds a value that's already JSON, such as another object or an array
+    pub fn raw(mut self, key: &str, json: &str) -> JsonObject {
+        self.key(key);
+        self.text.push_str(json);
+        self
+    }
+
+    pub fn finish(mut self) -> String {
+        if self.text.is_empty() {
+            self.text.push('{');
+        }
+        self.text.push('}');
+        self.text
+    }
+}
+
+/// Writes a string as a JSON string literal, with quotes
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            },
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Reads the top level fields of a JSON object, such as an NDJSON event from another multiping. Strings are unescaped,
+/// and any other value (a number, true, null, an object...) is left as its JSON text. None if it isn't an object
+pub fn read_object(text: &str) -> Option<HashMap<String, String>> {
+    let mut chars = text.trim().chars().peekable();
+    let mut fields = HashMap::new();
+    if chars.next()? != '{' {
+        return None;
+    }
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Some(fields);
+    }
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next()? != '"' {
+            return None;
+        }
+        let key = read_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_whitespace(&mut chars);
+        let value = if chars.next_if_eq(&'"').is_some() { read_string(&mut chars)? } else { read_raw(&mut chars)? };
+        fields.insert(key, value);
+        skip_whitespace(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => return Some(fields),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+/// Reads the rest of a string literal, after its opening quote
+fn read_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(s),
+            '\\' => match chars.next()? {
+                'n' => s.push('\n'),
+                'r' => s.push('\r'),
+                't' => s.push('\t'),
+                'b' => s.push('\u{8}'),
+                'f' => s.push('\u{c}'),
+                'u' => {
+                    let hex: String = (0..4).map(|_| chars.next()).collect::<Option<_>>()?;
+                    s.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?).unwrap_or(char::REPLACEMENT_CHARACTER));
+                },
+                c => s.push(c), // \", \\ and \/
+            },
+            c => s.push(c),
+        }
+    }
+}
+
+/// Reads a value that isn't a string, up to the comma or brace after it
+fn read_raw(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut raw = String::new();
+    let mut depth = 0;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '{' | '[' => depth += 1,
+            '}' | ']' | ',' if depth == 0 => break,
+            '}' | ']' => depth -= 1,
+            // Strings inside objects and arrays can have brackets and commas in them
+            '"' => {
+                chars.next();
+                raw.push_str(&quote(&read_string(chars)?));
+                continue;
+            },
+            _ => {},
+        }
+        raw.push(c);
+        chars.next();
+    }
+    (depth == 0).then(|| raw.trim_end().to_string())
+}
+
+/// The statistics of a host, as sent by the WebSocket feed and the web dashboard.
+/// Times are in milliseconds, and are null if there haven't been any replies
+pub fn host_stats(index: usize, host: &HostInfo) -> JsonObject {
+    let ms = |micros: Option<u64>| micros.map_or(f64::NAN, |m| m as f64 / 1000.0);
+    let replied = host.successful > 0;
+    let loss = host.loss_percent().unwrap_or(0.0);
+    let json = JsonObject::new()
+        .integer("index", index as i64)
+        .string("host", &host.host_str)
+        .string("address", &host.host.ip().to_string())
+        .string("probe", host.probe.name())
+        .raw("source", &host.source.map_or("null".to_string(), |s| quote(&s.to_string())))
+        .raw("note", &host.note.as_deref().map_or("null".to_string(), quote))
+        .raw("group", &host.group.as_deref().map_or("null".to_string(), quote))
+        .integer("sent", host.pings_sent as i64)
+        .integer("received", host.successful as i64)
+        .number("latest_ms", ms(host.latest_time))
+        .number("min_ms", ms(host.min_time))
+        .number("average_ms", if replied { host.average() as f64 } else { f64::NAN })
+        .number("max_ms", ms(host.max_time))
+        .number("jitter_ms", if replied { host.jitter() as f64 } else { f64::NAN })
+        .number("loss_percent", loss)
+        .number("mos", host.call_quality().map_or(f64::NAN, |q| q.mos))
+        .raw("icmp_errors", &JsonObject::new()
+            .integer("unreachable", host.icmp_errors.unreachable as i64)
+            .integer("time_exceeded", host.icmp_errors.time_exceeded as i64)
+            .integer("other", host.icmp_errors.other as i64)
+            .finish())
+        .integer("clock_anomalies", host.clock_anomalies as i64)
+        .integer("corrupted", host.corrupted as i64)
+        .raw("bursts", &JsonObject::new()
+            .integer("complete", host.bursts.complete as i64)
+            .integer("partial", host.bursts.partial as i64)
+            .integer("lost", host.bursts.lost as i64)
+            .finish())
+        .raw("malformed", &JsonObject::new()
+            .integer("bad_checksum", host.malformed.bad_checksum as i64)
+            .integer("short", host.malformed.short as i64)
+            .integer("unknown_type", host.malformed.unknown_type as i64)
+            .finish())
+        .boolean("down", host.state == HostState::Down)
+        .string("state", host.state.name())
+        .boolean("rate_limited", host.rate_limited())
+        .boolean("removed", host.removed)
+        .boolean("stale", host.stale);
+    match host.last_error {
+        Some(error) => json.string("error", &error.to_string()),
+        None => json.raw("error", "null"),
+    }
+}
+
+/// Every host's statistics at `time`, along with its outages, e.g. to keep as evidence during an incident.
+/// Outages that haven't ended have a null end
+pub fn snapshot(hinfos: &[HostInfo], time: SystemTime) -> String {
+    let hosts: Vec<String> = hinfos.iter().enumerate().map(|(i, h)| {
+        let outages: Vec<String> = h.outages.iter().map(|o| JsonObject::new()
+            .string("start", &format_rfc3339(o.start))
+            .raw("end", &o.end.map_or("null".to_string(), |end| quote(&format_rfc3339(end))))
+            .number("duration_s", o.duration().as_secs_f64())
+            .finish()).collect();
+        host_stats(i, h).raw("outages", &format!("[{}]", outages.join(","))).finish()
+    }).collect();
+    JsonObject::new()
+        .string("time", &format_rfc3339(time))
+        .raw("hosts", &format!("[{}]", hosts.join(",")))
+        .finish()
+}
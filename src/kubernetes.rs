@@ -0,0 +1,45 @@
+//! Finding the nodes of a Kubernetes cluster to ping (`--kube-nodes`), through the API server of the current
+//! context in the kubeconfig (or of the cluster multiping is running in, with its service account)
+
+use std::io::Error;
+use std::net::IpAddr;
+
+use k8s_openapi::api::core::v1::Node;
+use kube::api::ListParams;
+use kube::{Api, Client};
+use tokio::runtime::Runtime;
+
+/// A node of the cluster, and the addresses it can be reached at inside it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClusterNode {
+    pub name: String,
+    pub internal_ips: Vec<IpAddr>,
+}
+
+/// A connection to the API server, which can list the nodes as often as needed
+pub struct Cluster {
+    runtime: Runtime,
+    client: Client,
+}
+
+impl Cluster {
+    /// Connects using the kubeconfig (from $KUBECONFIG or ~/.kube/config), or the in-cluster service account
+    pub fn connect() -> Result<Cluster, Error> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let client = runtime.block_on(Client::try_default()).map_err(Error::other)?;
+        Ok(Cluster { runtime, client })
+    }
+
+    /// Every node in the cluster, with its InternalIP addresses (there can be one of each IP version)
+    pub fn nodes(&self) -> Result<Vec<ClusterNode>, Error> {
+        let api: Api<Node> = Api::all(self.client.clone());
+        let list = self.runtime.block_on(api.list(&ListParams::default())).map_err(Error::other)?;
+        Ok(list.items.into_iter().map(|node| ClusterNode {
+            name: node.metadata.name.unwrap_or_default(),
+            internal_ips: node.status.and_then(|s| s.addresses).unwrap_or_default().into_iter()
+                .filter(|a| a.type_ == "InternalIP")
+                .filter_map(|a| a.address.parse().ok())
+                .collect(),
+        }).collect())
+    }
+}
@@ -1,18 +1,102 @@
-use std::net::SocketAddr;
-use std::io::{Error, Read, ErrorKind};
-use std::time::SystemTime;
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::fmt;
+use std::io::{Error, ErrorKind, IoSliceMut};
+use std::os::fd::AsRawFd;
+use std::time::{Duration, Instant, SystemTime};
 use std::net::ToSocketAddrs;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use nix::errno::Errno;
+use nix::sys::socket::{MsgFlags, SockaddrStorage, recvmsg};
 use socket2::{Domain, Protocol, Socket, Type};
 
 use crate::icmp::*;
+use crate::ipv4::Ipv4Header;
+use crate::helper::HelperSocket;
+use crate::privileges::SocketKind;
+use crate::probe::{ConnectedIcmpProbe, split_port};
+use crate::schedule::Schedule;
+use crate::shutdown::CancelToken;
+use crate::stats::{PendingRequests, SequenceLoss, WindowedAverage};
+pub use crate::probe::{Probe, ProbeDetails, ProbeReply, ProbeType};
+pub use crate::state::{HostState, StateChange, StateThresholds};
 
+#[cfg(target_os = "linux")]
+pub mod arp;
+pub mod config;
+pub mod correlation;
+pub mod diagnose;
+pub mod dns;
+#[cfg(target_os = "linux")]
+pub mod gateway;
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)]
+pub mod ffi;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod helper;
+pub mod http;
 pub mod icmp;
+pub mod import;
+pub mod ipv4;
+pub mod ipv6;
+pub mod json;
+#[cfg(feature = "kube")]
+pub mod kubernetes;
+pub mod logfile;
+pub mod mdns;
+pub mod mqtt;
+#[cfg(target_os = "linux")]
+pub mod mtu;
+pub mod nagios;
+#[cfg(target_os = "linux")]
+pub mod ndp;
+pub mod ntp;
+pub mod owd;
+pub mod privileges;
+pub mod probe;
+#[cfg(feature = "quic")]
+pub mod quic;
+#[cfg(feature = "python")]
+#[allow(unsafe_code)]
+pub mod python;
+pub mod remote;
+pub mod schedule;
+pub mod shutdown;
+pub mod sla;
+pub mod sources;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod srv;
+pub mod state;
+pub mod stats;
+#[cfg(unix)]
+pub mod systemd;
+pub mod thresholds;
+pub mod timestamp;
+pub mod time;
+pub mod web;
+pub mod websocket;
+pub mod zabbix;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HostInfo {
     // Times in this struct are in microseconds? (unless I change them to a Duration)
     pub host_str: String, // Original user input, used for display
+    pub note: Option<String>, // Free-form note from the config file to help tell hosts apart, e.g. "rack 12"
+    pub group: Option<String>, // The group the host is in (e.g. in an Ansible inventory), which it's shown under
+    pub pinned: bool, // Always shown at the top of the table, e.g. the default gateway as a reference
     pub host: SocketAddr,
+    pub source: Option<IpAddr>, // The local address requests are sent from, if one was chosen (see `sources`)
     pub pings_sent: u32,
     pub latest_time: Option<u64>,
     pub sum_times: u64,
@@ -20,39 +104,425 @@ pub struct HostInfo {
     pub min_time: Option<u64>,
     pub max_time: Option<u64>,
     pub successful: u32,
+    #[cfg_attr(feature = "serde", serde(with = "serialize::option_error_kind"))]
     pub last_error: Option<ErrorKind>,
+    pub samples: VecDeque<u64>, // Every latency received (up to the history limit), in the order they arrived
+    pub sample_times: VecDeque<SystemTime>, // When each of the samples arrived
+    pub history: HistoryLimit, // How many samples are kept
+    pub probe: ProbeType,
+    pub details: Option<ProbeDetails>, // From the latest reply, for probes that report more than the latency
+    pub retries: u32, // Requests sent again because there was no reply to the first one in time
+    pub sub_row: bool, // Another probe of the same target as the host before it, so shown underneath it
+    pub unanswered: u32, // Requests sent since the last reply
+    pub first_unanswered: Option<SystemTime>, // When the first of those was sent
+    pub outages: Vec<Outage>, // Every time the host went down, oldest first
+    pub first_sent: Option<SystemTime>, // When the first request was sent
+    pub last_reply: Option<SystemTime>, // When the latest reply arrived
+    pub removed: bool, // No longer being probed, and not shown
+    pub stale: bool, // An address the "all:" name it came from doesn't resolve to any more. It's still probed
+    pub paused: bool, // Not being probed at the moment, as it's outside the schedule or probing was paused
+    pub latency_averages: WindowedAverage, // Round trip time in milliseconds over the last 1, 5 and 15 minutes
+    pub loss_averages: WindowedAverage, // Percentage of requests lost over the last 1, 5 and 15 minutes
+    pub icmp_errors: IcmpErrorCounts, // ICMP errors (like destination unreachable) about the requests, which are lost too
+    pub previous_address: Option<SocketAddr>, // What was probed before the name last resolved to a different address
+    pub sequence_loss: SequenceLoss, // Which requests were answered, for the packet loss
+    pub clock_anomalies: u32, // Replies whose round trip time is unknown, as the clock changed while they were on their way
+    pub corrupted: u32, // Replies whose data wasn't what was sent, which aren't counted as valid ones
+    pub malformed: MalformedCounts, // Datagrams from the host that couldn't be parsed (only on the shared sockets)
+    pub burst: Option<Burst>, // The burst being sent, when requests are sent in bursts
+    pub bursts: BurstCounts, // How the bursts before it went
+    pub state: HostState, // Up, degraded or down, which only changes once it's clearly over (or back under) a threshold
+    pub state_thresholds: StateThresholds, // When the state changes
+    pub replied_in_a_row: u32, // Replies since the last request that went unanswered, for coming back up
 }
 
+/// How much latency history a host keeps (for the histogram and comparisons). Once there's too much of it,
+/// the oldest samples are dropped as new ones arrive, so that memory use stays bounded when running for a long time
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HistoryLimit {
+    pub max_samples: Option<usize>,
+    pub max_age: Option<Duration>,
+}
+
+/// A period of time during which a host didn't reply
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Outage {
+    pub start: SystemTime, // When the first unanswered request was sent
+    pub end: Option<SystemTime>, // When the host came back up, or None if it's still down
+}
+
+/// An ICMP error about one of the requests sent on a shared socket, e.g. a router saying the host is unreachable
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IcmpErrorReport {
+    /// Where the request was going
+    pub to: SocketAddr,
+    /// Who sent the error (a router on the way, or this machine), if the kernel says
+    pub from: Option<IpAddr>,
+    /// The sequence number of the request, if enough of it came back with the error
+    pub sequence: Option<u16>,
+    pub icmp_type: u8,
+    pub code: u8,
+}
+
+impl IcmpErrorReport {
+    pub fn kind(&self) -> IcmpErrorKind {
+        match (self.icmp_type, self.to.is_ipv4()) {
+            (3, true) | (1, false) => IcmpErrorKind::Unreachable,
+            (11, true) | (3, false) => IcmpErrorKind::TimeExceeded,
+            _ => IcmpErrorKind::Other,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IcmpErrorKind {
+    Unreachable, // Destination unreachable, which is usually a firewall or a missing route
+    TimeExceeded, // The TTL (or hop limit) ran out, which is usually a routing loop
+    Other, // e.g. packet too big or parameter problem
+}
+
+/// How many of each kind of ICMP error there have been for a host
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IcmpErrorCounts {
+    pub unreachable: u32,
+    pub time_exceeded: u32,
+    pub other: u32,
+}
+
+impl IcmpErrorCounts {
+    pub fn add(&mut self, kind: IcmpErrorKind) {
+        match kind {
+            IcmpErrorKind::Unreachable => self.unreachable += 1,
+            IcmpErrorKind::TimeExceeded => self.time_exceeded += 1,
+            IcmpErrorKind::Other => self.other += 1,
+        }
+    }
+    
+    pub fn total(&self) -> u32 {
+        self.unreachable + self.time_exceeded + self.other
+    }
+}
+
+/// Requests sent back to back each interval, with `PingOptions::burst`
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Burst {
+    pub size: u32,
+    pub sent: u32, // So far
+    pub answered: u32, // Replies that arrived before the next burst was due
+    /// The sequence number of its first request. Replies with earlier ones are late replies to the burst before,
+    /// so they don't count towards this one
+    pub first_sequence: u16,
+}
+
+impl Burst {
+    /// Whether the next request sent is part of this burst, rather than the first of a new one
+    pub fn sending(&self) -> bool {
+        self.sent > 0 && self.sent < self.size
+    }
+}
+
+/// How many bursts had every request answered, some lost, or all lost. Losing whole bursts points to outages,
+/// while losing some of each burst points to random loss (e.g. from congestion)
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BurstCounts {
+    pub complete: u32,
+    pub partial: u32,
+    pub lost: u32,
+}
+
+impl BurstCounts {
+    pub fn add(&mut self, burst: Burst) {
+        match burst.answered {
+            0 => self.lost += 1,
+            answered if answered >= burst.sent => self.complete += 1,
+            _ => self.partial += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.complete + self.partial + self.lost
+    }
+}
+
+/// Why a datagram received on a shared socket couldn't be used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MalformedKind {
+    BadChecksum, // Only checked for ICMPv4, as the ICMPv6 checksum needs our address, and the kernel checks it anyway
+    Short, // Too short for the ICMP header, or an echo reply too short for the timestamp
+    UnknownType, // An ICMP type (or code) that isn't known
+}
+
+impl MalformedKind {
+    /// The name used in the output, e.g. "bad_checksum"
+    pub fn name(&self) -> &'static str {
+        match self {
+            MalformedKind::BadChecksum => "bad_checksum",
+            MalformedKind::Short => "short",
+            MalformedKind::UnknownType => "unknown_type",
+        }
+    }
+}
+
+impl From<ParseError> for MalformedKind {
+    fn from(e: ParseError) -> Self {
+        match e {
+            ParseError::BadChecksum { .. } => MalformedKind::BadChecksum,
+            ParseError::UnknownType { .. } | ParseError::UnknownCode { .. } => MalformedKind::UnknownType,
+            // Invalid fields only come from parsing further than echo replies need
+            ParseError::NotLongEnough { .. } | ParseError::Truncated { .. } | ParseError::Invalid { .. } => MalformedKind::Short,
+        }
+    }
+}
+
+/// How many of each kind of malformed datagram there have been from a host
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MalformedCounts {
+    pub bad_checksum: u32,
+    pub short: u32,
+    pub unknown_type: u32,
+}
+
+impl MalformedCounts {
+    pub fn add(&mut self, kind: MalformedKind) {
+        match kind {
+            MalformedKind::BadChecksum => self.bad_checksum += 1,
+            MalformedKind::Short => self.short += 1,
+            MalformedKind::UnknownType => self.unknown_type += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.bad_checksum + self.short + self.unknown_type
+    }
+}
+
+impl Outage {
+    /// How long the outage lasted, or has lasted so far
+    pub fn duration(&self) -> Duration {
+        let end = self.end.unwrap_or_else(SystemTime::now);
+        end.duration_since(self.start).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
 pub struct HostOptions {
     pub ip_version: Option<u8>,
 }
 
+/// Hosts starting with this are probed at every address their name resolves to, rather than just the first
+pub const ALL_ADDRESSES_PREFIX: &str = "all:";
+
+/// How many names `HostInfo::new_groups` looks up at the same time
+pub const RESOLVER_THREADS: usize = 16;
+
+/// The HostInfos for a host given with `HostInfo::new_group`, or why it couldn't be resolved
+pub type HostGroup = Result<Vec<HostInfo>, Error>;
+
+/// Options for `start_pinging`, which apply to every host
+#[derive(Clone, Debug)]
+pub struct PingOptions {
+    pub interval: Duration,
+    /// How many times a request is sent again within an interval if there's no reply.
+    /// The retries are spread evenly over the interval
+    pub retries: u32,
+    /// When to probe. Outside of it the hosts are left alone, and reported as paused
+    pub schedule: Schedule,
+    /// Give each ICMP host its own connected socket (and thread), so the kernel sorts out which replies
+    /// are whose. This uses a file descriptor per host, so it isn't a good idea with huge numbers of hosts.
+    /// Only read by `start_pinging`, so changing it later has no effect
+    pub connected_sockets: bool,
+    /// Use raw ICMP sockets (which need CAP_NET_RAW, or the helper) instead of unprivileged ones. The kernel doesn't
+    /// rewrite the identifier of requests sent on them, so each host gets its own, and replies are matched up by it
+    /// rather than by who they came from (so they're still counted if NAT changes the address).
+    /// Only read by `start_pinging`, so changing it later has no effect
+    pub raw_sockets: bool,
+    /// Report every request sent and reply received with `StatusUpdate::Packet`, for a verbose log
+    pub packet_log: bool,
+    /// How much of the interval (from 0 to 1) each request can be delayed by at random, so that probing doesn't
+    /// line up with other periodic traffic (or other copies of multiping). With retries, it's a share of the time
+    /// between them instead, so that every retry still fits in the interval
+    pub jitter: f32,
+    /// How many requests are sent to each host back to back every interval. Over 1, each burst is reported with
+    /// `StatusUpdate::Burst`, and it counts as one request towards the host being down
+    pub burst: u32,
+    /// How many sets of shared ICMP sockets the hosts are spread over, each with its own threads to send the
+    /// requests and receive the replies, so that one thread doesn't hold up tens of thousands of hosts.
+    /// 0 counts as 1. Only read by `start_pinging`, so changing it later has no effect
+    pub shards: usize,
+    /// How much latency history is kept for each host added to the `Pinger` (with `add_host` or `add_probe`).
+    /// Hosts given to `start_pinging` keep the limit they have
+    pub history: HistoryLimit,
+    /// When each host added to the `Pinger` changes state, as for `history`
+    pub state_thresholds: StateThresholds,
+}
+
+impl PingOptions {
+    /// How long to wait for a reply before retrying (or giving up)
+    pub fn retry_gap(&self) -> Duration {
+        self.interval / (self.retries + 1)
+    }
+
+    /// How long probes that wait for each reply before sending the next request wait for one. The requests
+    /// in a burst share the time
+    pub fn reply_timeout(&self) -> Duration {
+        self.retry_gap() / self.burst.max(1)
+    }
+
+    /// A random delay for a request, up to the jitter's share of the time between retries (which is the interval
+    /// if there aren't any)
+    pub fn random_delay(&self) -> Duration {
+        if self.jitter <= 0.0 {
+            return Duration::ZERO;
+        }
+        self.retry_gap().mul_f64(self.jitter.min(1.0) as f64 * random_fraction())
+    }
+}
+
+/// A random number from 0 up to (but not including) 1. Every `RandomState` has different keys, so hashing
+/// nothing with a new one is random enough for spreading out requests, without another dependency
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
 impl HostInfo {
     /// Creates a new HostInfo struct for the specified host. Host can be an IP address or domain name
     pub fn new(host: &str, options: HostOptions) -> Result<HostInfo, Error> {
-        let possible_hosts = (host, 0).to_socket_addrs()?;
+        let (probe, address) = ProbeType::parse(host);
+        // Probes without ports ignore one if it's given, so that "icmp+tcp:example.com:22" works
+        let (address, port) = split_port(address)?;
+        let port = probe.default_port().map_or(0, |default| port.unwrap_or(default));
+        let possible_hosts = (address, port).to_socket_addrs()?;
         let mut chosen_host: Option<SocketAddr> = None;
         
         for h in possible_hosts {
-            // I guess we found one
+            // Take the first address that matches the requested IP version
             match options.ip_version {
                 None => chosen_host = Some(h),
                 Some(4) => if h.is_ipv4() {chosen_host = Some(h)},
                 Some(6) => if h.is_ipv6() {chosen_host = Some(h)},
-                Some(v) => {
-                    eprintln!("Invalid IP version {}", v);
-                    return Err(Error::from(ErrorKind::InvalidInput));
-                }
+                Some(v) => return Err(Error::new(ErrorKind::InvalidInput, format!("invalid IP version {}", v))),
             }
-            break;
+            if chosen_host.is_some() {
+                break;
+            }
+        }
+        if let Some(v) = probe.ip_version() && options.ip_version != Some(v) {
+            // Look for an address the probe can use, regardless of the IP version asked for
+            return HostInfo::new(host, HostOptions { ip_version: Some(v) });
+        }
+        match chosen_host {
+            // The whole address is kept, as IPv6 link-local addresses need their scope ID
+            Some(h) => Ok(HostInfo { probe, host: h, ..HostInfo::from_address(host, h.ip()) }),
+            None => Err(Error::from(ErrorKind::NotFound)),
+        }
+    }
+    
+    /// Like `new`, but the host can have a prefix naming several probe types (e.g. "icmp+tcp:example.com:443"),
+    /// in which case there's a HostInfo for each of them. All but the first are sub-rows.
+    /// An SRV record name (e.g. "srv:_xmpp-server._tcp.example.com") gives a TCP probe for each target and port it
+    /// lists, in a group named after it. Targets that can't be resolved are left out, unless none of them can be.
+    /// A name with the "all:" prefix gives a HostInfo for each address it resolves to (see `new_all`), in a group too
+    pub fn new_group(host: &str, options: HostOptions) -> Result<Vec<HostInfo>, Error> {
+        if let Some(rest) = host.strip_prefix(ALL_ADDRESSES_PREFIX) {
+            return Ok(HostInfo::new_all(rest, options)?.into_iter().map(|h| HostInfo { group: Some(host.to_string()), ..h }).collect());
+        }
+        if let Some(name) = srv::record_name(host) {
+            let (resolved, failed): (Vec<_>, Vec<_>) = srv::lookup(name)?.into_iter()
+                .map(|t| HostInfo::new(&format!("tcp:{}:{}", t.target, t.port), options))
+                .partition(Result::is_ok);
+            if resolved.is_empty() && let Some(Err(e)) = failed.into_iter().next() {
+                return Err(e);
+            }
+            return Ok(resolved.into_iter().flatten().map(|h| HostInfo { group: Some(host.to_string()), ..h }).collect());
+        }
+        let Some((probes, address)) = ProbeType::parse_multiple(host) else {
+            return Ok(vec![HostInfo::new(host, options)?]);
+        };
+        let mut hinfos = Vec::new();
+        for (i, probe) in probes.iter().enumerate() {
+            let hinfo = HostInfo::new(&format!("{}:{}", probe.name(), address), HostOptions { ip_version: options.ip_version })?;
+            hinfos.push(HostInfo { sub_row: i > 0, ..hinfo });
+        }
+        Ok(hinfos)
+    }
+    
+    /// Like `new`, but with a HostInfo for every address the name resolves to (of the IP version asked for), in the
+    /// order the resolver gives them. Each is named after its address, keeping the probe type and port of the host
+    pub fn new_all(host: &str, options: HostOptions) -> Result<Vec<HostInfo>, Error> {
+        let (probe, address) = ProbeType::parse(host);
+        let prefix = &host[..host.len() - address.len()];
+        let (address, port) = split_port(address)?;
+        let given_port = port.filter(|_| probe.default_port().is_some());
+        let port = probe.default_port().map_or(0, |default| port.unwrap_or(default));
+        let ip_version = probe.ip_version().or(options.ip_version);
+        if let Some(v) = ip_version && v != 4 && v != 6 {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("invalid IP version {}", v)));
         }
-        if chosen_host.is_none() {
+        let mut addresses: Vec<SocketAddr> = Vec::new();
+        for a in (address, port).to_socket_addrs()? {
+            // The resolver gives each address once for every socket type
+            if ip_version.is_none_or(|v| (v == 4) == a.is_ipv4()) && !addresses.contains(&a) {
+                addresses.push(a);
+            }
+        }
+        if addresses.is_empty() {
             return Err(Error::from(ErrorKind::NotFound));
         }
-        
-        Ok(HostInfo {
-            host_str: host.to_string(),
-            host: chosen_host.unwrap(),
+        Ok(addresses.into_iter().map(|a| {
+            let name = match given_port {
+                Some(_) => format!("{}{}", prefix, SocketAddr::new(a.ip(), a.port())),
+                None => format!("{}{}", prefix, a.ip()),
+            };
+            HostInfo { probe, host: a, ..HostInfo::from_address(&name, a.ip()) }
+        }).collect())
+    }
+    
+    /// `new_group` for each host, looking up to `RESOLVER_THREADS` names at the same time so that one slow (or
+    /// unanswered) lookup doesn't hold up the rest. The results are in the same order as the hosts, and `progress`
+    /// is called with how many have been looked up so far after each one
+    pub fn new_groups(hosts: &[String], options: HostOptions, progress: impl Fn(usize) + Sync) -> Vec<HostGroup> {
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<HostGroup>>> = Mutex::new(hosts.iter().map(|_| None).collect());
+        thread::scope(|scope| {
+            for _ in 0..RESOLVER_THREADS.min(hosts.len()) {
+                scope.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(host) = hosts.get(i) else { break };
+                        let result = HostInfo::new_group(host, options);
+                        // Reported while holding the lock, so the count only goes up
+                        if let Ok(mut results) = results.lock() {
+                            results[i] = Some(result);
+                            progress(done.fetch_add(1, Ordering::Relaxed) + 1);
+                        }
+                    }
+                });
+            }
+        });
+        results.into_inner().unwrap_or_else(|e| e.into_inner()).into_iter()
+            .map(|result| result.unwrap_or_else(|| Err(ErrorKind::Interrupted.into()))).collect()
+    }
+
+    /// Creates a new HostInfo struct for a host whose address is already known
+    pub fn from_address(host_str: &str, host: IpAddr) -> HostInfo {
+        HostInfo {
+            host_str: host_str.to_string(),
+            note: None,
+            group: None,
+            pinned: false,
+            host: SocketAddr::new(host, 0),
+            source: None,
             pings_sent: 0,
             latest_time: None,
             sum_times: 0,
@@ -61,7 +531,120 @@ impl HostInfo {
             max_time: None,
             successful: 0,
             last_error: None,
-        })
+            samples: VecDeque::new(),
+            sample_times: VecDeque::new(),
+            history: HistoryLimit::default(),
+            probe: ProbeType::Icmp,
+            details: None,
+            retries: 0,
+            sub_row: false,
+            unanswered: 0,
+            first_unanswered: None,
+            outages: Vec::new(),
+            first_sent: None,
+            last_reply: None,
+            removed: false,
+            stale: false,
+            paused: false,
+            latency_averages: WindowedAverage::default(),
+            loss_averages: WindowedAverage::default(),
+            icmp_errors: IcmpErrorCounts::default(),
+            previous_address: None,
+            sequence_loss: SequenceLoss::default(),
+            clock_anomalies: 0,
+            corrupted: 0,
+            malformed: MalformedCounts::default(),
+            burst: None,
+            bursts: BurstCounts::default(),
+            state: HostState::Up,
+            state_thresholds: StateThresholds::default(),
+            replied_in_a_row: 0,
+        }
+    }
+    
+    /// Whether the host is called `name`. Hosts can be named the way they were given, or by their name or address
+    /// without the probe type and port
+    pub fn is_named(&self, name: &str) -> bool {
+        name == self.host_str
+            || split_port(ProbeType::parse(&self.host_str).1).is_ok_and(|(host, _)| name == host)
+            || name == self.host.ip().to_string()
+    }
+    
+    /// The latency samples, oldest first, as one Vec for the functions in `stats`
+    pub fn samples_vec(&self) -> Vec<u64> {
+        self.samples.iter().copied().collect()
+    }
+    
+    /// Keeps a latency sample, dropping the oldest ones that are over the history limit
+    fn add_sample(&mut self, latency: u64, now: SystemTime) {
+        self.samples.push_back(latency);
+        self.sample_times.push_back(now);
+        let too_many = self.history.max_samples.map_or(0, |max| self.samples.len().saturating_sub(max));
+        let too_old = self.history.max_age.and_then(|age| now.checked_sub(age))
+            .map_or(0, |oldest| self.sample_times.partition_point(|t| *t < oldest));
+        let dropped = too_many.max(too_old);
+        if dropped > 0 {
+            self.samples.drain(..dropped);
+            self.sample_times.drain(..dropped);
+        }
+    }
+    
+    /// Clears the statistics, so they start again from now. The outage log and the current state
+    /// (whether it's down, its latest time and error, and when it last replied) are kept
+    pub fn reset_stats(&mut self) {
+        // The latest request could still be answered, so it counts towards the new statistics
+        self.pings_sent = self.unanswered.min(1);
+        self.first_sent = (self.pings_sent > 0).then(SystemTime::now);
+        self.sum_times = 0;
+        self.sum_squared_times_ms = 0.0;
+        self.min_time = None;
+        self.max_time = None;
+        self.successful = 0;
+        self.samples.clear();
+        self.sample_times.clear();
+        self.retries = 0;
+        self.latency_averages = WindowedAverage::default();
+        self.loss_averages = WindowedAverage::default();
+        self.icmp_errors = IcmpErrorCounts::default();
+        self.sequence_loss.reset();
+        self.clock_anomalies = 0;
+        self.corrupted = 0;
+        self.malformed = MalformedCounts::default();
+        self.bursts = BurstCounts::default();
+    }
+    
+    /// Counts a reply towards the burst being sent, if it's to one of the burst's requests (or retries). Replies
+    /// without a sequence number are to the latest request, as probes without them only have one out at a time
+    fn answered_burst(&mut self, sequence: Option<u16>) {
+        let next_sequence = self.sequence_loss.next_sequence();
+        if let Some(burst) = &mut self.burst
+            && sequence.is_none_or(|n| n.wrapping_sub(burst.first_sequence) < next_sequence.wrapping_sub(burst.first_sequence)) {
+            burst.answered = (burst.answered + 1).min(burst.sent);
+        }
+    }
+    
+    /// Percentage of requests lost, or None if none have been answered or given up on yet.
+    /// Requests that could still be answered aren't counted, so it doesn't go up while replies are on their way
+    pub fn loss_percent(&self) -> Option<f64> {
+        self.sequence_loss.loss_percent()
+    }
+    
+    /// Whether the host seems to be limiting how many requests it answers, going by the pattern of the loss. Its loss
+    /// is then more likely to be from probing too often than from a problem with the network
+    pub fn rate_limited(&self) -> bool {
+        self.sequence_loss.looks_rate_limited()
+    }
+    
+    /// Ends the outage the host is in the middle of, if it is
+    fn end_outage(&mut self) {
+        if let Some(outage) = self.outages.last_mut() && outage.end.is_none() {
+            outage.end = Some(SystemTime::now());
+        }
+    }
+    
+    /// Whether the host's state is down (see `StateThresholds`)
+    pub fn is_down(&self) -> bool {
+        self.state == HostState::Down
     }
     
     pub fn average(&self) -> f32 {
@@ -72,25 +655,162 @@ impl HostInfo {
     pub fn jitter(&self) -> f32 {
         f32::sqrt((self.sum_squared_times_ms as f32 / (self.successful as f32)) - f32::powi(self.average(), 2))
     }
+    
+    /// Whether the last minute's average latency is going up or down compared to the last five minutes,
+    /// or None if there haven't been any replies yet
+    pub fn latency_trend(&self) -> Option<stats::Trend> {
+        let [one, five, _] = self.latency_averages.averages()?;
+        Some(stats::trend(one, five))
+    }
+    
+    /// Estimated VoIP call quality, or None if there haven't been any replies yet.
+    /// The latency is halved, as the E-model uses one-way delay rather than the round trip
+    pub fn call_quality(&self) -> Option<stats::CallQuality> {
+        if self.successful == 0 || self.pings_sent == 0 {
+            return None;
+        }
+        let loss = self.loss_percent().unwrap_or(0.0);
+        let jitter = self.jitter();
+        let jitter = if jitter.is_nan() { 0.0 } else { jitter as f64 };
+        Some(stats::call_quality(self.average() as f64 / 2.0, jitter, loss))
+    }
+    
+    /// Health score from 0 to 100, or None if nothing's been sent yet. Hosts that haven't replied to anything
+    /// (apart from the latest request, which may still be on its way) score 0
+    pub fn health_score(&self, weights: stats::HealthWeights) -> Option<f64> {
+        if self.successful == 0 {
+            return (self.pings_sent > 1).then_some(0.0);
+        }
+        let loss = self.loss_percent().unwrap_or(0.0);
+        let jitter = self.jitter();
+        let jitter = if jitter.is_nan() { 0.0 } else { jitter as f64 };
+        Some(stats::health_score(self.average() as f64, jitter, loss, weights))
+    }
 }
 
 // Update for the messages passed from the worker threads
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StatusUpdate {
     Sent(usize),
-    Received(usize, u64),
-    Error(usize, ErrorKind),
+    Received(usize, Option<u64>, Option<u16>), // The latency (unless the clock changed), and the sequence number of the request if it has one
+    Corrupted(usize, Option<u16>), // A reply came back, but its data wasn't what was sent. Has the sequence number, as for `Received`
+    Error(usize, #[cfg_attr(feature = "serde", serde(with = "serialize::error_kind"))] ErrorKind),
+    Added(Box<HostInfo>), // A new host is being pinged, and will use the next index
+    Details(usize, ProbeDetails),
+    Retried(usize), // The request was sent again, as there was no reply yet
+    Removed(usize), // The host is no longer being probed. Its index isn't reused
+    Paused(usize), // Nothing was sent this interval, as it's outside the schedule or probing was paused
+    IcmpError(usize, IcmpErrorReport), // An ICMP error came back about a request (only on the shared sockets)
+    Burst(usize, u32), // A burst of this many requests is about to be sent (only with `PingOptions::burst` over 1)
+    Malformed(Option<usize>, MalformedKind), // A datagram that couldn't be parsed (only on the shared sockets). No host if it's not from one being probed
+    Resolved(usize, SocketAddr), // The host's name now resolves to a different address, which is probed from now on
+    Packet(Option<usize>, PacketEvent), // Only with `PingOptions::packet_log`. No host if it's not about one being probed
+    Reset, // Every host's statistics were cleared, with `Pinger::reset_stats`
+    Note(usize, Option<String>), // The host's note was changed, with `Pinger::set_notes`
+    Stale(usize, bool), // The host's address left (or came back into) the records of the name it came from, with `Pinger::set_stale`
+    Stopped, // Every thread has stopped, after `Pinger::shutdown`. Nothing is sent after it
+}
+
+/// A single packet, for the verbose log. Doesn't change any statistics
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PacketEvent {
+    /// A request is being sent. The sequence number is only known for probes that have them
+    Sent { sequence: Option<u16> },
+    /// A reply came back. The ICMP type, and the message as `ICMPMessage` shows it, are only known for replies on the
+    /// shared ICMP sockets
+    Reply { from: IpAddr, sequence: Option<u16>, icmp_type: Option<u8>, latency: Option<u64>, description: Option<String> },
+    /// An ICMP error came back instead of a reply
+    IcmpError(IcmpErrorReport),
+    /// Receiving on a shared socket failed
+    Error(String),
+}
+
+impl StatusUpdate {
+    /// The index of the host the update is about, if it's about one. For `Added`, that's the next index
+    pub fn host_index(&self, hinfos: &[HostInfo]) -> Option<usize> {
+        match self {
+            StatusUpdate::Added(_) => Some(hinfos.len()),
+            StatusUpdate::Sent(i) | StatusUpdate::Received(i, _, _) | StatusUpdate::Corrupted(i, _) | StatusUpdate::Error(i, _)
+                | StatusUpdate::Details(i, _) | StatusUpdate::Retried(i) | StatusUpdate::Removed(i) | StatusUpdate::Paused(i)
+                | StatusUpdate::IcmpError(i, _) | StatusUpdate::Resolved(i, _) | StatusUpdate::Burst(i, _)
+                | StatusUpdate::Note(i, _) | StatusUpdate::Stale(i, _) => Some(*i),
+            StatusUpdate::Packet(i, _) | StatusUpdate::Malformed(i, _) => *i,
+            StatusUpdate::Reset | StatusUpdate::Stopped => None,
+        }
+    }
+}
+
+/// Applies an update to the statistics. If it moved the host to another state, the change is returned.
+/// Outages start when a host goes down and end when it comes back up
+pub fn update_host_info(update: &StatusUpdate, hinfos: &mut Vec<HostInfo>) -> Option<StateChange> {
+    apply_update(update, hinfos);
+    let change = match update {
+        StatusUpdate::Sent(i) | StatusUpdate::Received(i, _, _) | StatusUpdate::Corrupted(i, _) | StatusUpdate::Error(i, _) => {
+            hinfos[*i].update_state(*i)
+        },
+        _ => None,
+    }?;
+    let h = &mut hinfos[change.host];
+    if change.to == HostState::Down {
+        h.outages.push(Outage { start: h.first_unanswered.unwrap_or_else(SystemTime::now), end: None });
+    } else if change.from == HostState::Down {
+        h.end_outage();
+    }
+    Some(change)
 }
 
-pub fn update_host_info(update: &StatusUpdate, hinfos: &mut [HostInfo]) {
+fn apply_update(update: &StatusUpdate, hinfos: &mut Vec<HostInfo>) {
     match update {
+        StatusUpdate::Added(hinfo) => {
+            hinfos.push(hinfo.as_ref().clone());
+        },
+        StatusUpdate::Sent(i) if hinfos[*i].burst.is_some_and(|b| b.sending()) => {
+            // Only the first request of a burst counts towards the host being down
+            let h = &mut hinfos[*i];
+            h.pings_sent += 1;
+            h.sequence_loss.sent(SystemTime::now());
+            if let Some(burst) = &mut h.burst {
+                burst.sent += 1;
+            }
+        },
         StatusUpdate::Sent(i) => {
-            hinfos[*i].pings_sent += 1;
+            let h = &mut hinfos[*i];
+            if let Some(burst) = &mut h.burst {
+                burst.sent += 1;
+            }
+            h.pings_sent += 1;
+            h.paused = false;
+            h.first_sent.get_or_insert_with(SystemTime::now);
+            if h.unanswered == 0 {
+                h.first_unanswered = Some(SystemTime::now());
+            } else {
+                // The last request wasn't answered in time
+                h.loss_averages.add(100.0, SystemTime::now());
+                h.replied_in_a_row = 0;
+            }
+            h.unanswered += 1;
+            h.sequence_loss.sent(SystemTime::now());
         },
-        StatusUpdate::Received(i, latency) => {
+        StatusUpdate::Received(i, latency, sequence) => {
+            hinfos[*i].answered_burst(*sequence);
+            hinfos[*i].unanswered = 0;
+            hinfos[*i].replied_in_a_row += 1;
+            hinfos[*i].sequence_loss.received(*sequence);
+            hinfos[*i].last_reply = Some(SystemTime::now());
+            hinfos[*i].loss_averages.add(0.0, SystemTime::now());
             hinfos[*i].last_error = None;
+            // The host replied, but the round trip time is meaningless, so it's left out of the statistics
+            let Some(latency) = latency else {
+                hinfos[*i].clock_anomalies += 1;
+                return;
+            };
+            let now = SystemTime::now();
+            hinfos[*i].latency_averages.add(*latency as f64 / 1000.0, now);
             hinfos[*i].successful += 1;
             hinfos[*i].latest_time = Some(*latency);
+            hinfos[*i].add_sample(*latency, now);
             hinfos[*i].sum_times += *latency;
             let latency_ms: f64 = *latency as f64 / 1000f64; 
             hinfos[*i].sum_squared_times_ms += (latency_ms) * (latency_ms);
@@ -110,98 +830,1023 @@ pub fn update_host_info(update: &StatusUpdate, hinfos: &mut [HostInfo]) {
                 hinfos[*i].max_time = Some(*latency);
             }
         },
+        StatusUpdate::Corrupted(i, sequence) => {
+            // The host is there, so the request isn't lost, but the reply doesn't count towards the times
+            let h = &mut hinfos[*i];
+            h.answered_burst(*sequence);
+            h.unanswered = 0;
+            h.replied_in_a_row += 1;
+            h.sequence_loss.received(*sequence);
+            h.last_reply = Some(SystemTime::now());
+            h.loss_averages.add(0.0, SystemTime::now());
+            h.last_error = None;
+            h.corrupted += 1;
+        },
         StatusUpdate::Error(i, errno) => {
             hinfos[*i].last_error = Some(*errno);
+            hinfos[*i].replied_in_a_row = 0;
+        },
+        StatusUpdate::Details(i, details) => {
+            hinfos[*i].details = Some(details.clone());
+        },
+        StatusUpdate::Retried(i) => {
+            hinfos[*i].retries += 1;
+            hinfos[*i].sequence_loss.retried();
+        },
+        StatusUpdate::IcmpError(i, report) => {
+            hinfos[*i].icmp_errors.add(report.kind());
+        },
+        StatusUpdate::Burst(i, size) => {
+            let h = &mut hinfos[*i];
+            if let Some(burst) = h.burst.filter(|b| b.sent > 0) {
+                h.bursts.add(burst);
+            }
+            h.burst = Some(Burst { size: *size, sent: 0, answered: 0, first_sequence: h.sequence_loss.next_sequence() });
+            h.sequence_loss.set_burst(*size);
+        },
+        StatusUpdate::Malformed(i, kind) => {
+            if let Some(i) = i {
+                hinfos[*i].malformed.add(*kind);
+            }
+        },
+        StatusUpdate::Paused(i) => {
+            hinfos[*i].paused = true;
+        },
+        StatusUpdate::Resolved(i, address) => {
+            hinfos[*i].previous_address = Some(std::mem::replace(&mut hinfos[*i].host, *address));
+        },
+        StatusUpdate::Removed(i) => {
+            hinfos[*i].removed = true;
+            // It isn't known whether the host came back, but it's not down any more as far as we're concerned
+            hinfos[*i].state = HostState::Up;
+            hinfos[*i].end_outage();
+        },
+        StatusUpdate::Packet(..) => {},
+        StatusUpdate::Reset => {
+            for h in hinfos.iter_mut() {
+                h.reset_stats();
+            }
+        },
+        StatusUpdate::Note(i, note) => {
+            hinfos[*i].note = note.clone();
+        },
+        StatusUpdate::Stale(i, stale) => {
+            hinfos[*i].stale = *stale;
+        },
+        StatusUpdate::Stopped => {},
+    }
+}
+
+/// The ICMP hosts (that haven't been removed) at each IP address, with their whole socket address (which can have an
+/// IPv6 scope ID), in the order they were added. Replies on the shared sockets are matched up with it, so that
+/// doesn't take longer with more hosts
+type AddressIndex = HashMap<IpAddr, Vec<(usize, SocketAddr)>>;
+
+/// Handle to the threads started by `start_pinging`, which can be used to add hosts while they run
+#[derive(Clone)]
+pub struct Pinger {
+    hosts: Arc<RwLock<Vec<HostInfo>>>,
+    /// Only changed while holding the lock on `hosts`, so the two agree
+    addresses: Arc<RwLock<AddressIndex>>,
+    tx: Sender<StatusUpdate>,
+    options: Arc<RwLock<PingOptions>>,
+    paused: Arc<AtomicBool>,
+    cancel: CancelToken,
+    /// The probing and listening threads, so that `shutdown` can wait for them to stop
+    workers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    connected_sockets: bool,
+    raw_sockets: bool,
+    shards: usize,
+}
+
+impl Pinger {
+    /// Starts pinging another host. It will be reported with the returned index,
+    /// after a `StatusUpdate::Added` for it
+    pub fn add_host(&self, hinfo: HostInfo) -> usize {
+        let options = self.options();
+        let hinfo = HostInfo { history: options.history, state_thresholds: options.state_thresholds, ..hinfo };
+        let mut hosts = self.hosts.write().unwrap();
+        hosts.push(hinfo.clone());
+        // Sent while holding the lock so that no other update for the host can come first
+        let _ = self.tx.send(StatusUpdate::Added(Box::new(hinfo.clone())));
+        let i = hosts.len() - 1;
+        self.index_address(i, &hinfo);
+        self.spawn_own_thread(i, hinfo);
+        i
+    }
+    
+    /// Starts a thread for the host if it doesn't use the shared ICMP sockets. The probe is made
+    /// from the host as it is at the time, so that it can be made again if its address changes
+    fn spawn_own_thread(&self, i: usize, hinfo: HostInfo) {
+        let pinger = self.clone();
+        if self.cancel.is_cancelled() {
+            return;
+        }
+        if hinfo.probe != ProbeType::Icmp {
+            spawn_probe_thread(i, self.clone(), move || probe::new_probe(&pinger.host(i)));
+        } else if self.connected_sockets || hinfo.source.is_some() {
+            spawn_probe_thread(i, self.clone(), move || Ok(Box::new(ConnectedIcmpProbe::new(pinger.host(i))?)));
+        }
+    }
+    
+    fn host(&self, i: usize) -> HostInfo {
+        self.hosts.read().unwrap()[i].clone()
+    }
+    
+    /// Resolves the name of every host again (apart from ones given as IP addresses, and custom probes),
+    /// and switches any that resolve to a different address over to it. Hosts that can't be resolved
+    /// any more carry on with the address they had
+    pub fn resolve_again(&self, options: HostOptions) {
+        let hosts = self.hosts.read().unwrap().clone();
+        for (i, h) in hosts.iter().enumerate() {
+            let Ok((name, _)) = split_port(ProbeType::parse(&h.host_str).1) else { continue };
+            if h.removed || h.probe == ProbeType::Custom || name.trim_matches(['[', ']']).parse::<IpAddr>().is_ok() {
+                continue;
+            }
+            if let Ok(resolved) = HostInfo::new(&h.host_str, HostOptions { ip_version: options.ip_version }) {
+                self.set_address(i, resolved.host);
+            }
+        }
+    }
+    
+    /// Probes a host at a different address from now on (and sends a `StatusUpdate::Resolved`), if it's changed
+    pub fn set_address(&self, i: usize, address: SocketAddr) {
+        let mut hosts = self.hosts.write().unwrap();
+        if let Some(h) = hosts.get_mut(i) && h.host != address {
+            self.unindex_address(i, h.host);
+            h.host = address;
+            self.index_address(i, h);
+            // Sent while holding the lock so that no update from the new address can come first
+            let _ = self.tx.send(StatusUpdate::Resolved(i, address));
+        }
+    }
+    
+    /// Sets each host's note to what `note` gives for it, sending a `StatusUpdate::Note` for every one that's changed
+    pub fn set_notes(&self, note: impl Fn(&HostInfo) -> Option<String>) {
+        let mut hosts = self.hosts.write().unwrap();
+        for (i, h) in hosts.iter_mut().enumerate() {
+            let note = note(h);
+            if h.note != note {
+                h.note = note.clone();
+                let _ = self.tx.send(StatusUpdate::Note(i, note));
+            }
+        }
+    }
+    
+    /// Marks a host as stale (or not any more), sending a `StatusUpdate::Stale` if it's changed
+    pub fn set_stale(&self, i: usize, stale: bool) {
+        let mut hosts = self.hosts.write().unwrap();
+        if let Some(h) = hosts.get_mut(i) && h.stale != stale {
+            h.stale = stale;
+            let _ = self.tx.send(StatusUpdate::Stale(i, stale));
+        }
+    }
+    
+    /// Shows an error for a host that didn't come from probing it (e.g. its name not resolving any more), by sending
+    /// a `StatusUpdate::Error`. Like any other error, it's cleared by the host's next reply
+    pub fn report_error(&self, i: usize, error: ErrorKind) {
+        let _ = self.tx.send(StatusUpdate::Error(i, error));
+    }
+    
+    /// Starts probing a host using a probe from outside of multiping.
+    /// The probe type of `hinfo` is set to `ProbeType::Custom`
+    pub fn add_probe(&self, hinfo: HostInfo, probe: Box<dyn Probe>) -> usize {
+        let mut hosts = self.hosts.write().unwrap();
+        let options = self.options();
+        let hinfo = HostInfo { probe: ProbeType::Custom, history: options.history, state_thresholds: options.state_thresholds, ..hinfo };
+        hosts.push(hinfo.clone());
+        let _ = self.tx.send(StatusUpdate::Added(Box::new(hinfo)));
+        let i = hosts.len() - 1;
+        let mut probe = Some(probe);
+        spawn_probe_thread(i, self.clone(), move || {
+            probe.take().ok_or(Error::from(ErrorKind::Unsupported))
+        });
+        i
+    }
+    
+    /// Stops pinging a host. Its index isn't reused, so the other hosts keep theirs
+    pub fn remove_host(&self, i: usize) {
+        let mut hosts = self.hosts.write().unwrap();
+        if let Some(h) = hosts.get_mut(i) && !h.removed {
+            h.removed = true;
+            self.unindex_address(i, h.host);
+            let _ = self.tx.send(StatusUpdate::Removed(i));
+        }
+    }
+    
+    /// Adds an ICMP host to the address index, unless it has its own socket. Call while holding the lock on `hosts`
+    fn index_address(&self, i: usize, hinfo: &HostInfo) {
+        if hinfo.probe != ProbeType::Icmp || hinfo.removed || hinfo.source.is_some() {
+            return;
+        }
+        let mut addresses = self.addresses.write().unwrap();
+        let at = addresses.entry(hinfo.host.ip()).or_default();
+        // Kept in order, so that the first host at an address gets its replies, as it did before
+        let position = at.partition_point(|(j, _)| *j < i);
+        at.insert(position, (i, hinfo.host));
+    }
+    
+    /// Takes a host off the address index. Call while holding the lock on `hosts`
+    fn unindex_address(&self, i: usize, address: SocketAddr) {
+        let mut addresses = self.addresses.write().unwrap();
+        if let Some(at) = addresses.get_mut(&address.ip()) {
+            at.retain(|(j, _)| *j != i);
+            if at.is_empty() {
+                addresses.remove(&address.ip());
+            }
+        }
+    }
+    
+    /// The first ICMP host in a shard at an IP address. With `exact`, the rest of the socket address
+    /// (i.e. an IPv6 scope ID) has to match too
+    fn icmp_host_at(&self, address: SocketAddr, exact: bool, shard: Shard) -> Option<usize> {
+        self.icmp_hosts_at(address, exact, shard).first().copied()
+    }
+    
+    /// Every ICMP host in a shard at an IP address (more than one if the same address was given twice, or two
+    /// names resolve to it), as for `icmp_host_at`
+    fn icmp_hosts_at(&self, address: SocketAddr, exact: bool, shard: Shard) -> Vec<usize> {
+        let addresses = self.addresses.read().unwrap();
+        addresses.get(&address.ip()).map_or_else(Vec::new, |hosts| {
+            hosts.iter().filter(|(i, a)| shard.has(*i) && (!exact || *a == address)).map(|(i, _)| *i).collect()
+        })
+    }
+    
+    fn is_removed(&self, i: usize) -> bool {
+        self.hosts.read().unwrap().get(i).is_none_or(|h| h.removed)
+    }
+    
+    /// The options currently being used
+    pub fn options(&self) -> PingOptions {
+        self.options.read().unwrap().clone()
+    }
+    
+    /// Changes the options for every host, which takes effect from the next interval
+    pub fn set_options(&self, options: PingOptions) {
+        *self.options.write().unwrap() = options;
+    }
+    
+    /// Clears the statistics of every host, so they start again from now. This goes through the updates
+    /// (as `StatusUpdate::Reset`), so everything keeping statistics from them clears them at the same point
+    pub fn reset_stats(&self) {
+        let _ = self.tx.send(StatusUpdate::Reset);
+    }
+    
+    /// Stops (or restarts) probing every host, without forgetting anything about them
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+    
+    /// Pauses probing if it's running, or resumes it if it's paused. Returns whether it's now paused
+    pub fn toggle_paused(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+    
+    /// Whether hosts should be probed now, rather than being left alone
+    fn should_probe(&self, options: &PingOptions) -> bool {
+        !self.paused.load(Ordering::Relaxed) && options.schedule.is_active(SystemTime::now())
+    }
+    
+    /// Stops probing for good: every thread is told to stop, and this waits until they all have (which can take up
+    /// to an interval, for a probe waiting for its replies), so their sockets are closed. `StatusUpdate::Stopped`
+    /// is then sent, after every update they sent, so that whatever is reading them knows it has all of them.
+    /// Hosts added afterwards aren't probed. Calling it again does nothing
+    pub fn shutdown(&self) {
+        if self.cancel.is_cancelled() {
+            return;
+        }
+        self.cancel.cancel();
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap_or_else(|e| e.into_inner()));
+        for worker in workers {
+            let _ = worker.join();
+        }
+        let _ = self.tx.send(StatusUpdate::Stopped);
+    }
+    
+    /// The token cancelled by `shutdown`, e.g. to stop other threads along with the probing ones
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+    
+    /// Starts a thread that `shutdown` waits for. Threads that have already finished (e.g. for removed hosts) are forgotten
+    fn spawn_worker(&self, f: impl FnOnce() + Send + 'static) {
+        let mut workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+        workers.retain(|w| !w.is_finished());
+        workers.push(thread::spawn(f));
+    }
+}
+
+/// Spawns the threads that ping the hosts every interval and listen for the replies.
+/// Progress is reported through `tx`, using the index of each host in `hinfos`
+pub fn start_pinging(hinfos: &[HostInfo], options: PingOptions, tx: Sender<StatusUpdate>) -> Result<Pinger, Error> {
+    let pinger = Pinger {
+        hosts: Arc::new(RwLock::new(hinfos.to_vec())),
+        addresses: Arc::new(RwLock::new(HashMap::new())),
+        tx,
+        connected_sockets: options.connected_sockets,
+        raw_sockets: options.raw_sockets,
+        shards: options.shards.max(1),
+        options: Arc::new(RwLock::new(options)),
+        paused: Arc::new(AtomicBool::new(false)),
+        cancel: CancelToken::new(),
+        workers: Arc::new(Mutex::new(Vec::new())),
+    };
+    for (i, h) in hinfos.iter().enumerate() {
+        pinger.index_address(i, h);
+        pinger.spawn_own_thread(i, h.clone());
+    }
+    if pinger.connected_sockets {
+        return Ok(pinger);
+    }
+    
+    for index in 0..pinger.shards {
+        start_shard(&pinger, Shard { index, count: pinger.shards })?;
+    }
+    Ok(pinger)
+}
+
+/// Which of the shared sockets (and the threads using them) look after a host
+#[derive(Clone, Copy, Debug)]
+struct Shard {
+    index: usize,
+    count: usize,
+}
+
+impl Shard {
+    /// Whether host `i` is in this shard. Hosts are dealt out in turn, so the shards stay even as they're added
+    fn has(&self, i: usize) -> bool {
+        i % self.count == self.index
+    }
+}
+
+/// How long the shared sockets wait for a reply before checking whether it's time to stop
+const STOP_CHECK_EVERY: Duration = Duration::from_millis(200);
+
+/// Opens a shard's shared ICMP sockets, and starts the threads that ping its hosts every interval and listen for
+/// the replies. Unprivileged sockets each get their own identifier from the kernel, which only gives them replies
+/// to their own requests. Raw sockets get every reply, so each shard ignores the ones for hosts in other shards
+fn start_shard(pinger: &Pinger, shard: Shard) -> Result<(), Error> {
+    let (txsocket4, txsocket6) = if pinger.raw_sockets {
+        (mkv4rawsocket()?, mkv6rawsocket()?)
+    } else {
+        (mkv4socket()?, mkv6socket()?)
+    };
+    // Without them, errors are only counted as lost requests
+    let _ = enable_icmp_errors(&txsocket4, true);
+    let _ = enable_icmp_errors(&txsocket6, false);
+    // The latest requests to each ICMP host in the shard (by the host's index), so that only the ones without
+    // replies are retried, and duplicate and very late replies aren't counted
+    let pending: Arc<Mutex<Vec<PendingRequests>>> = Arc::new(Mutex::new(Vec::new()));
+    
+    // Listening threads for each IP version. Both are started, as hosts can be added later
+    for rxsocket in [txsocket4.try_clone()?, txsocket6.try_clone()?] {
+        // Receiving gives up every so often, to see whether it's time to stop
+        rxsocket.set_read_timeout(Some(STOP_CHECK_EVERY))?;
+        let listen_pinger = pinger.clone();
+        let pending = pending.clone();
+        pinger.spawn_worker(move || listen_loop(&rxsocket, &listen_pinger, shard, &pending));
+    }
+    
+    // Sending thread (both IPv4 and IPv6)
+    let send_pinger = pinger.clone();
+    pinger.spawn_worker(move || {
+        let pinger = send_pinger;
+        // Sends a request to host `i` (or sends the latest one again), and reports it. The table stays locked until
+        // it's been reported, so that the listening thread can't report its reply first. A request that couldn't be
+        // sent is reported as an error instead, and isn't counted. Returns false if nobody is listening any more
+        let send_request = |i: usize, h: &HostInfo, retry: bool, packet_log: bool| {
+            let mut pending = pending.lock().unwrap();
+            let sequence = pending[i].next_sequence();
+            if let Err(e) = send_icmp(h, i, &txsocket4, &txsocket6, sequence, pinger.raw_sockets) {
+                drop(pending);
+                return pinger.tx.send(StatusUpdate::Error(i, e.kind())).is_ok();
+            }
+            if retry {
+                pending[i].retried();
+            } else {
+                pending[i].sent();
+            }
+            report_sending(&pinger.tx, if retry { StatusUpdate::Retried(i) } else { StatusUpdate::Sent(i) }, i, sequence, packet_log)
+        };
+        let mut ticker = Ticker::new();
+        loop {
+            let options = pinger.options();
+            let hosts = pinger.hosts.read().unwrap().clone();
+            // Other types of probe have their own threads (as do hosts probed from a chosen address), and other shards have the rest
+            let is_pinged = |i: usize, h: &HostInfo| shard.has(i) && h.probe == ProbeType::Icmp && h.source.is_none() && !h.removed;
+            if !pinger.should_probe(&options) {
+                for (i, _) in hosts.iter().enumerate().filter(|(i, h)| is_pinged(*i, h)) {
+                    if pinger.tx.send(StatusUpdate::Paused(i)).is_err() {
+                        return;
+                    }
+                }
+                if !ticker.wait(options.interval, &pinger.cancel) {
+                    return;
+                }
+                continue;
+            }
+            pending.lock().unwrap().resize_with(hosts.len(), PendingRequests::default);
+            // Each request is sent after its own random delay, so they go in order of it
+            let mut delayed: Vec<(Duration, usize, &HostInfo)> = hosts.iter().enumerate().filter(|(i, h)| is_pinged(*i, h))
+                .map(|(i, h)| (options.random_delay(), i, h))
+                .collect();
+            delayed.sort_by_key(|(delay, _, _)| *delay);
+            let delays: Vec<(Duration, usize)> = delayed.iter().map(|(delay, i, _)| (*delay, *i)).collect();
+            for (delay, i, h) in delayed {
+                if !pinger.cancel.sleep_until(ticker.due() + delay) {
+                    return;
+                }
+                if options.burst > 1 {
+                    pending.lock().unwrap()[i].set_burst(options.burst);
+                    if pinger.tx.send(StatusUpdate::Burst(i, options.burst)).is_err() {
+                        return;
+                    }
+                }
+                for _ in 0..options.burst.max(1) {
+                    // Nobody is listening for updates any more, so stop
+                    if !send_request(i, h, false, options.packet_log) {
+                        return;
+                    }
+                }
+            }
+            
+            // Each host's retries are timed from when its own request was sent. The delays are shorter than the
+            // time between retries, so every host's first retry comes before any host's second, and so on
+            for retry in 1..=options.retries {
+                for &(delay, i) in &delays {
+                    if !pinger.cancel.sleep_until(ticker.due() + delay + options.retry_gap() * retry) {
+                        return;
+                    }
+                    if !pending.lock().unwrap()[i].waiting() {
+                        continue;
+                    }
+                    if !send_request(i, &hosts[i], true, options.packet_log) {
+                        return;
+                    }
+                }
+            }
+            if !ticker.wait(options.interval, &pinger.cancel) {
+                return;
+            }
+        }
+    });
+    
+    Ok(())
+}
+
+/// Reports a request to host `i` that's been sent. `update` is `Sent` or `Retried`.
+/// Returns false if nobody is listening for updates any more
+fn report_sending(tx: &Sender<StatusUpdate>, update: StatusUpdate, i: usize, sequence: u16, packet_log: bool) -> bool {
+    tx.send(update).is_ok()
+        && (!packet_log || tx.send(StatusUpdate::Packet(Some(i), PacketEvent::Sent { sequence: Some(sequence) })).is_ok())
+}
+
+/// Sends an echo request to host `i`, using the socket for its IP version. On raw sockets, the request has the host's own identifier
+fn send_icmp(h: &HostInfo, i: usize, socket4: &Socket, socket6: &Socket, sequence: u16, raw: bool) -> Result<(), Error> {
+    let socket = if h.host.is_ipv4() { socket4 } else { socket6 };
+    if raw {
+        send_raw_ping(h, socket, host_identifier(i), sequence, DEFAULT_PAYLOAD_SIZE)
+    } else {
+        send_ping(h, socket, sequence, DEFAULT_PAYLOAD_SIZE)
+    }
+}
+
+/// The identifier used in requests to host `i` on raw sockets. They start from one based on the process ID,
+/// so that another copy of multiping running at the same time is unlikely to use the same ones
+fn host_identifier(i: usize) -> u16 {
+    (std::process::id() as u16).wrapping_mul(251).wrapping_add(i as u16)
+}
+
+/// The host that an identifier from `host_identifier` belongs to
+fn identified_host(identifier: u16) -> usize {
+    identifier.wrapping_sub(host_identifier(0)) as usize
+}
+
+/// Keeps rounds of probes on schedule: each one is due an interval after the last one was due (rather than after it
+/// finished), so that the time spent sending and oversleeping doesn't add up. If it falls more than an interval
+/// behind (e.g. the machine was suspended), it starts again from now instead of rushing to catch up
+pub(crate) struct Ticker {
+    due: Instant,
+}
+
+impl Ticker {
+    /// A ticker whose first round is due now
+    pub(crate) fn new() -> Ticker {
+        Ticker { due: Instant::now() }
+    }
+
+    /// When the current round started
+    pub(crate) fn due(&self) -> Instant {
+        self.due
+    }
+
+    /// Waits until the next round is due. The interval can change between rounds.
+    /// Returns false if `cancel` was cancelled first
+    pub(crate) fn wait(&mut self, interval: Duration, cancel: &CancelToken) -> bool {
+        self.due += interval;
+        let now = Instant::now();
+        if now.saturating_duration_since(self.due) > interval {
+            self.due = now;
         }
+        cancel.sleep_until(self.due)
     }
 }
 
-pub fn send_ping(host_info: &HostInfo, socket: &Socket) -> Result<(), Error> {
-    // Fill the buffer with the system time, then the numbers 0x10 to 0x37
-    // (this is to mimic the packets of the ping(8) command)
+/// Returns the sequence number to use, and moves on to the next one
+pub(crate) fn next_sequence(sequence: &mut u16) -> u16 {
+    let current = *sequence;
+    *sequence = sequence.wrapping_add(1);
+    current
+}
+
+/// Spawns a thread which probes a single host every interval, for probe types that
+/// wait for their own replies (rather than sharing the ICMP sockets).
+/// The probe is made with `make_probe`, which is tried again every interval until it works.
+/// The thread stops once the host is removed (or the pinger is shut down)
+fn spawn_probe_thread<F>(i: usize, pinger: Pinger, mut make_probe: F)
+where F: FnMut() -> Result<Box<dyn Probe>, Error> + Send + 'static {
+    pinger.clone().spawn_worker(move || {
+        let tx = &pinger.tx;
+        let mut probe: Option<Box<dyn Probe>> = None;
+        let mut address = pinger.hosts.read().unwrap()[i].host;
+        let mut ticker = Ticker::new();
+        loop {
+            let options = pinger.options();
+            if pinger.is_removed(i) {
+                return;
+            }
+            // The probe is made again if the host's name resolved to a different address
+            let current = pinger.hosts.read().unwrap()[i].host;
+            if current != address {
+                address = current;
+                probe = None;
+            }
+            if !pinger.should_probe(&options) {
+                if tx.send(StatusUpdate::Paused(i)).is_err() || !ticker.wait(options.interval, &pinger.cancel) {
+                    return;
+                }
+                continue;
+            }
+            if !pinger.cancel.sleep_until(ticker.due() + options.random_delay()) {
+                return;
+            }
+            if options.burst > 1 && tx.send(StatusUpdate::Burst(i, options.burst)).is_err() {
+                return;
+            }
+            // Each request in a burst is sent as soon as the one before it is answered (or times out)
+            for _ in 0..options.burst.max(1) {
+                if tx.send(StatusUpdate::Sent(i)).is_err() {
+                    return;
+                }
+            
+                let result = match &mut probe {
+                    Some(p) => Ok(p),
+                    None => make_probe().map(|p| probe.insert(p)),
+                }.and_then(|p| {
+                    let mut attempt = 0;
+                    loop {
+                        p.send()?;
+                        if options.packet_log && tx.send(StatusUpdate::Packet(Some(i), PacketEvent::Sent { sequence: p.sequence() })).is_err() {
+                            return Err(ErrorKind::BrokenPipe.into());
+                        }
+                        match p.match_reply(options.reply_timeout()) {
+                            Err(e) if e.kind() == ErrorKind::TimedOut && attempt < options.retries => {
+                                attempt += 1;
+                                if tx.send(StatusUpdate::Retried(i)).is_err() {
+                                    return Err(e);
+                                }
+                            },
+                            result => return result,
+                        }
+                    }
+                });
+                let mut updates = match &result {
+                    Ok(ProbeReply { latency, details: None }) => vec![StatusUpdate::Received(i, Some(*latency), None)],
+                    Ok(ProbeReply { latency, details: Some(details) }) => vec![StatusUpdate::Details(i, details.clone()), StatusUpdate::Received(i, Some(*latency), None)],
+                    // No reply counts as a lost ping, not an error
+                    Err(e) if e.kind() == ErrorKind::TimedOut => vec![],
+                    Err(e) if let Some(corrupted) = e.get_ref().and_then(|e| e.downcast_ref::<CorruptedReply>()) => {
+                        vec![StatusUpdate::Corrupted(i, corrupted.sequence)]
+                    },
+                    Err(e) => vec![StatusUpdate::Error(i, e.kind())],
+                };
+                if options.packet_log && let Ok(reply) = &result {
+                    let sequence = probe.as_ref().and_then(|p| p.sequence());
+                    updates.push(StatusUpdate::Packet(Some(i), PacketEvent::Reply { from: address.ip(), sequence, icmp_type: None, latency: Some(reply.latency), description: None }));
+                }
+                for u in updates {
+                    if tx.send(u).is_err() {
+                        return;
+                    }
+                }
+            }
+            if !ticker.wait(options.interval, &pinger.cancel) {
+                return;
+            }
+        }
+    });
+}
+
+/// Receives replies on the socket and reports which host they came from.
+/// Only the first reply to each request (or its retries) in `pending` is reported, so duplicates aren't counted
+fn listen_loop(socket: &Socket, pinger: &Pinger, shard: Shard, pending: &Mutex<Vec<PendingRequests>>) {
+    // Raw sockets get every datagram, so they're looked up in every shard and only reported by the host's own one
+    // (or the first one, if they aren't from a host). ICMP errors only go to the socket that sent the request
+    let every_shard = Shard { index: 0, count: 1 };
+    let lookup = if pinger.raw_sockets { every_shard } else { shard };
+    let ours = |found: Option<usize>| !pinger.raw_sockets || found.map_or(shard.index == 0, |i| shard.has(i));
+    loop {
+        let received = receive_reply(socket, pinger.options().packet_log);
+        if pinger.cancel.is_cancelled() {
+            return;
+        }
+        let options = pinger.options();
+        // The socket reports an error when there's an ICMP error waiting to be read
+        let icmp_errors = if received.is_err() { receive_icmp_errors(socket) } else { Vec::new() };
+        // Held while a reply is reported, so that it can't be reported before its request
+        let mut locked = None;
+        let updates = match received {
+            Err(_) if !icmp_errors.is_empty() => {
+                let mut updates = Vec::new();
+                for report in icmp_errors {
+                    let found = pinger.icmp_host_at(report.to, false, shard);
+                    if options.packet_log {
+                        updates.push(StatusUpdate::Packet(found, PacketEvent::IcmpError(report)));
+                    }
+                    if let Some(i) = found {
+                        updates.push(StatusUpdate::IcmpError(i, report));
+                    }
+                }
+                updates
+            },
+            Ok((reply, description)) => {
+                // Figure out which host the reply is from: by its identifier on raw sockets, or else by its address
+                let candidates: Vec<usize> = if pinger.raw_sockets {
+                    let hosts = pinger.hosts.read().unwrap();
+                    Some(identified_host(reply.identifier)).filter(|i| hosts.get(*i).is_some_and(|h| h.probe == ProbeType::Icmp && !h.removed))
+                        .into_iter().collect()
+                } else {
+                    pinger.icmp_hosts_at(reply.from, true, lookup)
+                };
+                if !ours(candidates.first().copied()) {
+                    continue;
+                }
+                // If several hosts are at the address, it goes to the first one with a request that it answers
+                // (and otherwise to the first one, as a duplicate)
+                let pending = locked.insert(pending.lock().unwrap());
+                let answered = candidates.iter().copied()
+                    .find(|i| pending.get_mut(*i).is_some_and(|requests| requests.answer(Some(reply.sequence))));
+                let found = answered.or(candidates.first().copied());
+                let event = PacketEvent::Reply { from: reply.from.ip(), sequence: Some(reply.sequence), icmp_type: Some(reply.icmp_type), latency: reply.latency, description };
+                let mut updates = Vec::new();
+                if options.packet_log {
+                    updates.push(StatusUpdate::Packet(found, event));
+                }
+                if let Some(i) = answered {
+                    if reply.is_intact(DEFAULT_PAYLOAD_SIZE) {
+                        updates.push(StatusUpdate::Received(i, reply.latency, Some(reply.sequence)));
+                    } else {
+                        updates.push(StatusUpdate::Corrupted(i, Some(reply.sequence)));
+                    }
+                }
+                updates
+            },
+            Err(ref e) if let Some(malformed) = e.get_ref().and_then(|e| e.downcast_ref::<MalformedReply>()) => {
+                let found = pinger.icmp_host_at(SocketAddr::new(malformed.from, 0), false, lookup);
+                if !ours(found) {
+                    continue;
+                }
+                let mut updates = Vec::new();
+                if options.packet_log {
+                    updates.push(StatusUpdate::Packet(found, PacketEvent::Error(e.to_string())));
+                }
+                updates.push(StatusUpdate::Malformed(found, malformed.kind));
+                updates
+            },
+            // The socket's read timeout, so that stopping is noticed
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => vec![],
+            Err(e) if options.packet_log => vec![StatusUpdate::Packet(None, PacketEvent::Error(e.to_string()))],
+            Err(_) => vec![],
+        };
+        if updates.into_iter().any(|u| pinger.tx.send(u).is_err()) {
+            return;
+        }
+        drop(locked);
+    }
+}
+
+/// Bytes of data in an echo request, not counting the ICMP header (the same as ping(8))
+pub const DEFAULT_PAYLOAD_SIZE: usize = 56;
+
+/// The smallest payload, which just fits the timestamp used to time the reply
+pub const MIN_PAYLOAD_SIZE: usize = 16;
+
+/// Sends an echo request with `payload_size` bytes of data (at least `MIN_PAYLOAD_SIZE`) on an unprivileged
+/// ICMP socket, which fills in the identifier and checksum itself
+pub fn send_ping(host_info: &HostInfo, socket: &Socket, sequence: u16, payload_size: usize) -> Result<(), Error> {
+    let identifier = if host_info.host.is_ipv4() { 0xbeef } else { 0xcafe };
+    socket.send_to(&echo_request(host_info.host.is_ipv4(), identifier, sequence, payload_size), &host_info.host.into())?;
+    Ok(())
+}
+
+/// Sends an echo request with the given identifier on a raw ICMP socket. The kernel works out
+/// the checksum of ICMPv6 messages, but ICMPv4 ones need it filled in
+pub fn send_raw_ping(host_info: &HostInfo, socket: &Socket, identifier: u16, sequence: u16, payload_size: usize) -> Result<(), Error> {
+    let mut buf = echo_request(host_info.host.is_ipv4(), identifier, sequence, payload_size);
+    if host_info.host.is_ipv4() {
+        buf[2..4].fill(0);
+        let checksum = internet_checksum(&buf);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    socket.send_to(&buf, &host_info.host.into())?;
+    Ok(())
+}
+
+/// Builds an echo request. The payload is the system time, then the numbers 0x10 to 0x37 (repeated if need be),
+/// to mimic the packets of the ping(8) command
+fn echo_request(ipv4: bool, identifier: u16, sequence: u16, payload_size: usize) -> Vec<u8> {
     let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
     let secs = time.as_secs();
     let micros = time.subsec_nanos() as u64 / 1000;
-    let mut buf: Vec<u8>;
-    if host_info.host.is_ipv4() {
-        buf = construct_echo_request_v4(0xbeef, 1, &secs.to_be_bytes());
-    } else if host_info.host.is_ipv6() {
-        buf = construct_echo_request_v6(0xcafe, 1, &secs.to_be_bytes());
+    let mut buf = if ipv4 {
+        construct_echo_request_v4(identifier, sequence, &secs.to_be_bytes())
     } else {
-        return Err(ErrorKind::AddrNotAvailable.into());
-    }
+        construct_echo_request_v6(identifier, sequence, &secs.to_be_bytes())
+    };
     buf.append(&mut micros.to_be_bytes().to_vec());
-    buf.append(&mut (0x10_u8..=0x37_u8).collect());
-    socket.send_to(&buf, &host_info.host.into())?;
+    let filler = payload_size.max(MIN_PAYLOAD_SIZE) - MIN_PAYLOAD_SIZE;
+    buf.extend(filler_pattern().take(filler));
+    buf
+}
+
+/// What comes after the timestamp in the payload of an echo request
+fn filler_pattern() -> impl Iterator<Item = u8> {
+    (0x10_u8..=0x37_u8).cycle()
+}
+
+/// The largest datagram that can be received
+const MAX_DATAGRAM: usize = 65535;
+
+thread_local! {
+    /// Each listening thread's receive buffer, which grows if a reply doesn't fit
+    static RECEIVE_BUFFER: RefCell<Vec<u8>> = RefCell::new(vec![0; 100]);
+}
+
+/// Grows this thread's receive buffer so that it holds replies with `payload_size` bytes of data (after the biggest
+/// IPv4 header and the ICMP header). Some sockets don't say how big a datagram that didn't fit really was, so its
+/// size can't be checked once it's been cut off
+pub(crate) fn reserve_receive_buffer(payload_size: usize) {
+    let needed = (60 + 8 + payload_size.max(MIN_PAYLOAD_SIZE)).min(MAX_DATAGRAM);
+    RECEIVE_BUFFER.with_borrow_mut(|buf| if buf.len() < needed {
+        buf.resize(needed, 0);
+    });
+}
+
+/// An echo reply received by `receive_ping`
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EchoReply {
+    pub from: SocketAddr,
+    /// Only meaningful on raw sockets, as unprivileged ones replace it with their own
+    pub identifier: u16,
+    pub sequence: u16,
+    pub icmp_type: u8,
+    /// Round trip time in microseconds, or None if the clock was changed while the request was on its way,
+    /// so that the timestamp in the reply makes no sense
+    pub latency: Option<u64>,
+    /// Bytes of data in the reply, including any that didn't fit in the receive buffer
+    pub payload_len: usize,
+    /// Whether the data after the timestamp (as far as it was received) is what `echo_request` put there
+    pub pattern_intact: bool,
+}
+
+impl EchoReply {
+    /// Whether the data is what was sent in a request with `payload_size` bytes of it
+    pub fn is_intact(&self, payload_size: usize) -> bool {
+        self.pattern_intact && self.payload_len == payload_size.max(MIN_PAYLOAD_SIZE)
+    }
+}
+
+/// The error returned by probes when a reply's data wasn't what was sent (e.g. it was corrupted on the way),
+/// which is counted on its own rather than as a valid reply
+#[derive(Debug)]
+pub struct CorruptedReply {
+    /// The sequence number of the request, if the probe has them
+    pub sequence: Option<u16>,
+}
+
+impl fmt::Display for CorruptedReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the reply's data wasn't what was sent")
+    }
+}
+
+impl std::error::Error for CorruptedReply {}
+
+/// The error returned by `receive_ping` for a datagram that couldn't be parsed, e.g. one mangled on the way
+#[derive(Debug)]
+pub struct MalformedReply {
+    pub from: IpAddr,
+    pub kind: MalformedKind,
+    pub cause: ParseError,
+}
+
+impl fmt::Display for MalformedReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed datagram from {}: {}", self.from, self.cause)
+    }
+}
+
+impl std::error::Error for MalformedReply {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.cause)
+    }
+}
+
+/// Round trip times longer than this are taken to mean that the clock jumped forwards while the request was on its way
+pub const MAX_PLAUSIBLE_LATENCY: Duration = Duration::from_secs(60);
+
+/// Waits for an echo reply on the socket, and returns who it came from and its round trip time
+/// (worked out from the timestamp `send_ping` put in it). Other ICMP messages are skipped, so errors come from
+/// the socket itself (e.g. `WouldBlock` if it has a timeout), apart from `InvalidData` for a reply too big for the
+/// buffer to hold its timestamp, or one containing a `MalformedReply` for a datagram that couldn't be parsed
+pub fn receive_ping(socket: &Socket) -> Result<EchoReply, Error> {
+    receive_reply(socket, false).map(|(reply, _)| reply)
+}
+
+/// As for `receive_ping`, along with the reply as it's shown in the verbose log if `describe` is set
+/// (e.g. "echo reply (type 0, code 0), identifier 0x1234, sequence 5, 56 bytes of data")
+fn receive_reply(socket: &Socket, describe: bool) -> Result<(EchoReply, Option<String>), Error> {
+    RECEIVE_BUFFER.with_borrow_mut(|rec_buf| loop {
+        // MSG_TRUNC makes some sockets say how big the datagram really was, and all of them flag it if it didn't fit
+        let mut iov = [IoSliceMut::new(rec_buf)];
+        let (len, addr, truncated) = match recvmsg::<SockaddrStorage>(socket.as_raw_fd(), &mut iov, None, MsgFlags::MSG_TRUNC) {
+            Ok(msg) => (msg.bytes, msg.address, msg.flags.contains(MsgFlags::MSG_TRUNC)),
+            Err(Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let used_bytes = len.min(rec_buf.len());
+        if truncated && rec_buf.len() < MAX_DATAGRAM {
+            // Big enough for the next one, if the real size is known
+            let new_len = len.clamp(rec_buf.len() * 2, MAX_DATAGRAM);
+            rec_buf.resize(new_len, 0);
+        }
+        let addr = match addr {
+            Some(a) if let Some(a4) = a.as_sockaddr_in() => SocketAddr::from(SocketAddrV4::from(*a4)),
+            Some(a) if let Some(a6) = a.as_sockaddr_in6() => SocketAddr::from(SocketAddrV6::from(*a6)),
+            _ => continue,
+        };
+        let mut packet = &rec_buf[..used_bytes];
+        // Raw IPv4 sockets give the IP header too. It starts with the version (4) in the top half of the first byte,
+        // which no ICMP type that's looked at here does, so it's skipped if it's there
+        // (only its own length is used, as some systems change the total length in it)
+        if addr.is_ipv4() && let Ok(header) = Ipv4Header::try_from(packet) {
+            packet = packet.get(header.header_len..).unwrap_or_default();
+        }
+        let (identifier, sequence, icmp_type, timestamp, payload) = match parse_echo_reply(packet, Family::of(addr.ip()), truncated) {
+            Ok(Some(reply)) => reply,
+            Ok(None) => continue,
+            Err(ParseError::Truncated { .. }) => return Err(Error::new(ErrorKind::InvalidData,
+                format!("reply from {} didn't fit in the {} byte receive buffer", addr.ip(), used_bytes))),
+            Err(cause) => return Err(Error::new(ErrorKind::InvalidData, MalformedReply { from: addr.ip(), kind: cause.into(), cause })),
+        };
+        // The clock can be stepped (by NTP, or when a VM is moved) while the request is on its way. If it went
+        // backwards, the timestamp is in the future, and if it went forwards, the time is implausibly long
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_micros();
+        let latency = now.checked_sub(timestamp)
+            .filter(|micros| *micros <= MAX_PLAUSIBLE_LATENCY.as_micros())
+            .map(|micros| micros as u64);
+        // Some sockets say how long the datagram really was, which is what the sender would have sent
+        let payload_len = payload.len() + (len - used_bytes);
+        let pattern_intact = payload.iter().skip(MIN_PAYLOAD_SIZE).copied().eq(filler_pattern().take(payload.len().saturating_sub(MIN_PAYLOAD_SIZE)));
+        // Only copied out of the buffer when it's going to be shown
+        let description = describe.then(|| ICMPMessage::try_from((packet, Family::of(addr.ip()))).ok().map(|m| m.to_string())).flatten();
+        return Ok((EchoReply { from: addr, identifier, sequence, icmp_type, latency, payload_len, pattern_intact }, description));
+    })
+}
+
+/// The identifier, sequence number, ICMP type, send time (in microseconds since the Unix epoch, from the payload)
+/// and payload of an echo reply
+type EchoReplyFields<'a> = (u16, u16, u8, u128, &'a [u8]);
+
+/// Picks out the fields of an echo reply, or None if it's another kind of ICMP message. If the packet was
+/// truncated before the end of the timestamp, that's `ParseError::Truncated`
+fn parse_echo_reply(packet: &[u8], family: Family, truncated: bool) -> Result<Option<EchoReplyFields<'_>>, ParseError> {
+    let truncated_to = |e| if truncated { ParseError::Truncated { actual: packet.len() } } else { e };
+    // Borrowed, so that nothing is allocated for each reply
+    let message = ICMPPacket::try_from((packet, family)).map_err(truncated_to)?;
+    let Some((identifier, sequence)) = message.echo_id_seq().filter(|_| message.is_echo_reply()) else {
+        return match type_name(message.icmp_type(), family == Family::V4) {
+            Some(_) => Ok(None),
+            None => Err(ParseError::UnknownType { icmp_type: message.icmp_type() }),
+        };
+    };
+    // A cut off message can't be checked
+    if family == Family::V4 && !truncated && internet_checksum(packet) != 0 {
+        let mut zeroed = packet.to_vec();
+        zeroed[2..4].fill(0);
+        return Err(ParseError::BadChecksum { checksum: message.checksum(), computed: internet_checksum(&zeroed) });
+    }
+    // The payload comes after the 8 byte header
+    let (timestamp, _) = message.payload().split_first_chunk::<16>()
+        .ok_or(truncated_to(ParseError::not_long_enough(packet, 8, 16)))?;
+    let (ts_seconds, ts_sub_micros) = timestamp.split_at(8);
+    let ts_seconds = u64::from_be_bytes(ts_seconds.try_into().unwrap_or_default());
+    let ts_sub_micros = u64::from_be_bytes(ts_sub_micros.try_into().unwrap_or_default());
+    Ok(Some((identifier, sequence, message.icmp_type(), ts_seconds as u128 * 1000000 + ts_sub_micros as u128, message.payload())))
+}
+
+/// Creates a raw ICMP socket (or gets one from the helper, if one's installed), which needs CAP_NET_RAW.
+/// If it isn't allowed, the error contains a `privileges::PermissionError` saying why
+pub fn mkv4rawsocket() -> Result<Socket, Error> {
+    Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))
+        .or_else(|e| helper::fallback(e, HelperSocket::RawIcmp4))
+        .map_err(|e| privileges::explain(e, SocketKind::Raw))
+}
+
+/// Creates a raw ICMPv6 socket (or gets one from the helper, if one's installed), which needs CAP_NET_RAW.
+/// If it isn't allowed, the error contains a `privileges::PermissionError` saying why
+pub fn mkv6rawsocket() -> Result<Socket, Error> {
+    Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))
+        .or_else(|e| helper::fallback(e, HelperSocket::RawIcmp6))
+        .map_err(|e| privileges::explain(e, SocketKind::Raw))
+}
+
+/// Asks the kernel to queue up ICMP errors about the requests sent on the socket, for `receive_icmp_errors`
+#[cfg(target_os = "linux")]
+fn enable_icmp_errors(socket: &Socket, ipv4: bool) -> Result<(), Error> {
+    use nix::sys::socket::{setsockopt, sockopt};
+    if ipv4 {
+        setsockopt(socket, sockopt::Ipv4RecvErr, &true)?;
+    } else {
+        setsockopt(socket, sockopt::Ipv6RecvErr, &true)?;
+    }
     Ok(())
 }
 
-pub fn receive_ping(mut socket: &Socket) -> Result<(SocketAddr, u64), Error> {
-    let mut rec_buf: [u8; 100] = [0; 100];
-    let addr = socket.peek_sender()?;
-    
-    // Try to parse the received bytes
-    if let Some(addr4) = addr.as_socket_ipv4() {
-        let used_bytes = socket.read(&mut rec_buf)?;
-        let maybe_message: Result<ICMPv4Message, IntoICMPError> = rec_buf[..used_bytes].try_into();
-        if let Ok(message) = maybe_message {
-            let ts_seconds = u64::from_be_bytes(message.icmpv4_data[0..8].try_into().unwrap());
-            let ts_sub_micros = u64::from_be_bytes(message.icmpv4_data[8..16].try_into().unwrap());
-            let ts_micros = (ts_seconds as u128 * 1000000) + ts_sub_micros as u128;
-            
-            let cur_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-            let cur_micros = cur_time.as_nanos() / 1000;
-            
-            let diff_micros = cur_micros - ts_micros;
-            
-            return Ok((SocketAddr::V4(addr4), diff_micros as u64));
-            
-        } else if let Err(e) = maybe_message {
-            print!("Error parsing response: ");
-            match e {
-                IntoICMPError::UnknownType => println!("unknown type"),
-                IntoICMPError::UnknownCode => println!("unknown code"),
-                IntoICMPError::NotLongEnough => println!("message not long enough"),
-                IntoICMPError::OtherError => println!("other error"),
-            }
-        }
-    } else if let Some(addr6) = addr.as_socket_ipv6() {
-        let used_bytes = socket.read(&mut rec_buf)?;
-        let maybe_message: Result<ICMPv6Message, IntoICMPError> = rec_buf[..used_bytes].try_into();
-        if let Ok(message) = maybe_message {
-            let ts_seconds = u64::from_be_bytes(message.body[0..8].try_into().unwrap());
-            let ts_sub_micros = u64::from_be_bytes(message.body[8..16].try_into().unwrap());
-            let ts_micros = (ts_seconds as u128 * 1000000) + ts_sub_micros as u128;
-            
-            let cur_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-            let cur_micros = cur_time.as_nanos() / 1000;
-            
-            let diff_micros = cur_micros - ts_micros;
-            
-            return Ok((SocketAddr::V6(addr6), diff_micros as u64));
-            
-        } else if let Err(e) = maybe_message {
-            print!("Error parsing response: ");
-            match e {
-                IntoICMPError::UnknownType => println!("unknown type"),
-                IntoICMPError::UnknownCode => println!("unknown code"),
-                IntoICMPError::NotLongEnough => println!("message not long enough"),
-                IntoICMPError::OtherError => println!("other error"),
-            }
+#[cfg(not(target_os = "linux"))]
+fn enable_icmp_errors(_socket: &Socket, _ipv4: bool) -> Result<(), Error> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// Reads every ICMP error waiting in the socket's error queue, without blocking. Errors that
+/// didn't come from ICMP (e.g. local ones, like the message being too big) are skipped
+#[cfg(target_os = "linux")]
+fn receive_icmp_errors(socket: &Socket) -> Vec<IcmpErrorReport> {
+    use nix::sys::socket::ControlMessageOwned;
+    let mut reports = Vec::new();
+    // Enough for the header of the request, which has the sequence number in it
+    let mut buf = [0u8; 8];
+    loop {
+        let mut cmsg_buffer = nix::cmsg_space!(libc::sock_extended_err, libc::sockaddr_in6);
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let msg = match recvmsg::<SockaddrStorage>(socket.as_raw_fd(), &mut iov, Some(&mut cmsg_buffer), MsgFlags::MSG_ERRQUEUE | MsgFlags::MSG_DONTWAIT) {
+            Ok(msg) => msg,
+            Err(Errno::EINTR) => continue,
+            Err(_) => return reports,
+        };
+        let to = match msg.address {
+            Some(a) if let Some(a4) = a.as_sockaddr_in() => SocketAddr::from(SocketAddrV4::from(*a4)),
+            Some(a) if let Some(a6) = a.as_sockaddr_in6() => SocketAddr::from(SocketAddrV6::from(*a6)),
+            _ => continue,
+        };
+        let whole_header = msg.bytes >= 8;
+        let Ok(mut cmsgs) = msg.cmsgs() else { continue };
+        let error = cmsgs.find_map(|cmsg| match cmsg {
+            ControlMessageOwned::Ipv4RecvErr(err, offender) if err.ee_origin == libc::SO_EE_ORIGIN_ICMP =>
+                Some((err, offender.map(|o| IpAddr::from(u32::from_be(o.sin_addr.s_addr).to_be_bytes())))),
+            ControlMessageOwned::Ipv6RecvErr(err, offender) if err.ee_origin == libc::SO_EE_ORIGIN_ICMP6 =>
+                Some((err, offender.map(|o| IpAddr::from(o.sin6_addr.s6_addr)))),
+            _ => None,
+        });
+        if let Some((err, from)) = error {
+            let sequence = whole_header.then(|| u16::from_be_bytes([buf[6], buf[7]]));
+            reports.push(IcmpErrorReport { to, from, sequence, icmp_type: err.ee_type, code: err.ee_code });
         }
     }
-    
-    Err(Error::from(ErrorKind::NotFound))
 }
 
+#[cfg(not(target_os = "linux"))]
+fn receive_icmp_errors(_socket: &Socket) -> Vec<IcmpErrorReport> {
+    Vec::new()
+}
+
+/// Creates an unprivileged ICMP socket (or gets one from the helper, if one's installed).
+/// If it isn't allowed, the error contains a `privileges::PermissionError` saying why
 pub fn mkv4socket() -> Result<Socket, Error> {
     let wildcard: SocketAddr = "0.0.0.0:0".parse().unwrap();
-    let socket = Socket::new(Domain::for_address(wildcard), Type::DGRAM, Some(Protocol::ICMPV4))?;
+    let socket = Socket::new(Domain::for_address(wildcard), Type::DGRAM, Some(Protocol::ICMPV4))
+        .or_else(|e| helper::fallback(e, HelperSocket::Icmp4))
+        .map_err(|e| privileges::explain(e, SocketKind::Ping))?;
     Ok(socket)
 }
 
+/// Creates an unprivileged ICMPv6 socket (or gets one from the helper, if one's installed).
+/// If it isn't allowed, the error contains a `privileges::PermissionError` saying why
 pub fn mkv6socket() -> Result<Socket, Error> {
     let wildcard: SocketAddr = "[::]:0".parse().unwrap();
-    let socket = Socket::new(Domain::for_address(wildcard), Type::DGRAM, Some(Protocol::ICMPV6))?;
+    let socket = Socket::new(Domain::for_address(wildcard), Type::DGRAM, Some(Protocol::ICMPV6))
+        .or_else(|e| helper::fallback(e, HelperSocket::Icmp6))
+        .map_err(|e| privileges::explain(e, SocketKind::Ping))?;
     Ok(socket)
 }
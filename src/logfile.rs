@@ -0,0 +1,181 @@
+//! Log files for each host that are rotated when they get too big or too old, so that multiping can run for weeks
+//! as a service without filling the disk. Rotated files are renamed to <name>.1, <name>.2 and so on (the oldest
+//! having the highest number), and can be compressed with gzip when the `gzip` feature is on
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// When log files are rotated, and what happens to the old ones
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    pub max_size: Option<u64>, // Bytes
+    pub every: Option<Duration>,
+    /// How many rotated files are kept, not counting the one being written to
+    pub keep: usize,
+    pub compress: bool,
+}
+
+/// A file that's appended to a line at a time, and rotated between lines. It can be closed in between, and is
+/// opened again when the next line is written
+pub struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    size: u64,
+    opened: SystemTime, // When it was started, for rotating it by age (which closing it in between doesn't change)
+    rotation: Rotation,
+}
+
+impl RotatingFile {
+    /// Opens the file at `path` to append to, creating it if it isn't there
+    pub fn open(path: PathBuf, rotation: Rotation) -> Result<RotatingFile, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, file: Some(file), size, opened: SystemTime::now(), rotation })
+    }
+
+    /// Writes a line (which shouldn't end with a newline), rotating the file first if it's due
+    pub fn write_line(&mut self, line: &str) -> Result<(), Error> {
+        let too_big = self.rotation.max_size.is_some_and(|max| self.size > 0 && self.size + line.len() as u64 + 1 > max);
+        let too_old = self.rotation.every.is_some_and(|every| self.opened.elapsed().unwrap_or_default() >= every);
+        if too_big || too_old {
+            self.rotate()?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+                // Something else could have changed it in the meantime
+                self.size = file.metadata()?.len();
+                self.file.insert(file)
+            },
+        };
+        writeln!(file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    /// Closes the file until the next line is written
+    pub fn close(&mut self) {
+        self.file = None;
+    }
+
+    /// Moves every rotated file up one, then the current one to .1, and starts a new one
+    fn rotate(&mut self) -> Result<(), Error> {
+        let extension = if self.rotation.compress { ".gz" } else { "" };
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}{}", self.path.display(), n, extension));
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            // The oldest one is overwritten
+            for n in (1..self.rotation.keep).rev() {
+                match fs::rename(rotated(n), rotated(n + 1)) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                    _ => {},
+                }
+            }
+            if self.rotation.compress {
+                compress(&self.path, &rotated(1))?;
+                fs::remove_file(&self.path)?;
+            } else {
+                fs::rename(&self.path, rotated(1))?;
+            }
+        }
+        self.file = Some(OpenOptions::new().create(true).append(true).open(&self.path)?);
+        self.size = 0;
+        self.opened = SystemTime::now();
+        Ok(())
+    }
+}
+
+/// Copies `from` to `to` with gzip compression
+#[cfg(feature = "gzip")]
+fn compress(from: &Path, to: &Path) -> Result<(), Error> {
+    let mut encoder = flate2::write::GzEncoder::new(File::create(to)?, flate2::Compression::default());
+    std::io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?.sync_all()
+}
+
+#[cfg(not(feature = "gzip"))]
+fn compress(_from: &Path, _to: &Path) -> Result<(), Error> {
+    Err(Error::new(ErrorKind::Unsupported, "multiping was built without the gzip feature"))
+}
+
+/// How many hosts' log files are kept open at once. Any more are closed (the least recently written first) and
+/// opened again when they're next written to, so that thousands of hosts don't run out of file descriptors
+pub const MAX_OPEN_LOGS: usize = 64;
+
+/// A log file for each host in a directory, opened when something is first logged about the host
+pub struct HostLogs {
+    dir: PathBuf,
+    rotation: Rotation,
+    files: HashMap<String, RotatingFile>,
+    open: VecDeque<String>, // The hosts whose files are open, least recently written first
+}
+
+impl HostLogs {
+    /// Creates the directory if it isn't there
+    pub fn new(dir: PathBuf, rotation: Rotation) -> Result<HostLogs, Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(HostLogs { dir, rotation, files: HashMap::new(), open: VecDeque::new() })
+    }
+
+    /// Appends a line to the host's log file
+    pub fn write_line(&mut self, host: &str, line: &str) -> Result<(), Error> {
+        match self.open.iter().position(|h| h == host) {
+            Some(position) => {
+                let host = self.open.remove(position).unwrap_or_default();
+                self.open.push_back(host);
+            },
+            None => {
+                if self.open.len() >= MAX_OPEN_LOGS && let Some(oldest) = self.open.pop_front()
+                    && let Some(file) = self.files.get_mut(&oldest) {
+                    file.close();
+                }
+                self.open.push_back(host.to_string());
+            },
+        }
+        if !self.files.contains_key(host) {
+            let file = RotatingFile::open(self.dir.join(file_name(host)), self.rotation.clone())?;
+            self.files.insert(host.to_string(), file);
+        }
+        self.files.get_mut(host).map_or(Ok(()), |file| file.write_line(line))
+    }
+}
+
+/// The name of a host's log file, e.g. "tcp_example.com_443.log" for tcp:example.com:443. Colons become underscores,
+/// and anything else that isn't a letter, digit, dot or dash is written as %XX (including underscores, and a dot at
+/// the start), so that every host gets a different name
+pub fn file_name(host: &str) -> String {
+    let mut name = String::new();
+    for (i, c) in host.char_indices() {
+        match c {
+            ':' => name.push('_'),
+            '.' if i == 0 => name.push_str("%2E"),
+            c if c.is_ascii_alphanumeric() || c == '.' || c == '-' => name.push(c),
+            c => {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    name.push_str(&format!("%{:02X}", byte));
+                }
+            },
+        }
+    }
+    format!("{}.log", name)
+}
+
+/// Parses a size in bytes, which can end with K, M or G (powers of 1024), e.g. "10M"
+pub fn parse_size(s: &str) -> Result<u64, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("{:?} should be a number of bytes, e.g. 500K or 10M", s));
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    let number: u64 = number.trim().parse().map_err(|_| invalid())?;
+    number.checked_mul(multiplier).filter(|size| *size > 0).ok_or_else(invalid)
+}
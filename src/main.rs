@@ -1,208 +1,2967 @@
 use console::{Term, style};
-use std::io::{Write, stdout};
-use std::{cmp::max, io::Error, process::exit};
-use clap::Parser;
-use std::time::Duration;
+use std::fs::{self, File};
+use std::io::{BufRead, Read, Write, stdin, stdout};
+use std::{cmp::{Reverse, max}, collections::{HashMap, VecDeque}, io::Error, panic, process::exit};
+use clap::{Parser, Subcommand, ValueEnum};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
+use std::time::{Duration, Instant, SystemTime};
 use std::sync::mpsc;
 use std::sync::mpsc::Receiver;
+#[cfg(feature = "count-allocations")]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "count-allocations")]
+use std::sync::atomic::AtomicU64;
 use std::thread;
+use std::path::{Path, PathBuf};
+use std::net::SocketAddr;
 
 use multiping::*;
+use multiping::config::Config;
+use multiping::icmp;
+use multiping::import::{self, Inventory};
+use multiping::ipv4::Ipv4Header;
+use multiping::ipv6::Ipv6Header;
+use multiping::json::{self, JsonObject};
+use multiping::logfile::{self, HostLogs, Rotation};
+use multiping::nagios::{self, Threshold};
+use multiping::owd;
+use multiping::remote;
+use multiping::schedule::{Schedule, TimeWindow};
+use multiping::sla::{Sla, SlaCheck};
+use multiping::thresholds::{HostThreshold, LatencyColours};
+use multiping::stats::{CallQuality, HealthWeights, Trend, log_histogram, mann_whitney_u, median, percentile};
+use multiping::websocket::Feed;
+use nix::sys::resource::{UsageWho, getrusage};
+use nix::sys::time::TimeValLike;
+use nix::sys::termios::{LocalFlags, SetArg, Termios, tcgetattr, tcsetattr};
 
-pub mod icmp;
+/// Every host replied at least once
+const EXIT_OK: i32 = 0;
+/// Some hosts never replied (or went over the --fail-fast loss threshold, or the `multiping assert` thresholds)
+const EXIT_UNANSWERED: i32 = 1;
+/// The command line was wrong, or something stopped multiping from working
+const EXIT_ERROR: i32 = 2;
 
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true,
+    after_help = "Exit status: 0 if every host replied at least once, 1 if some never replied \
+        (or went over the --fail-fast threshold), 2 for errors. \
+        --check uses the Nagios plugin exit codes instead")]
 struct Arguments {
-    /// Which hosts (IP addresses or domain names) to ping
+    /// Which hosts (IP addresses or domain names) to ping. Use - to read hosts from stdin, one per line.
+    /// A prefix probes them another way, e.g. tcp:example.com:22. Without a port, tcp:, tls: and quic: use 443,
+    /// http: uses 80 and udp: uses 33434
     hosts: Vec<String>,
     
-    /// How often the hosts should be pinged (in seconds)
-    #[arg(short = 'i', long, default_value_t = 1.0)]
-    interval: f32,
+    /// How often the hosts should be pinged, in seconds or ending with ms or us (e.g. 10ms).
+    /// Each round is sent when it's due, rather than after sleeping for the interval, so they don't drift
+    #[arg(short = 'i', long, value_name = "TIME", default_value = "1", value_parser = time::parse_interval, global = true)]
+    interval: Duration,
+    
+    /// Send this many requests to each host back to back every interval, and count how many bursts had every
+    /// request answered, some lost, or all lost. Losing whole bursts points to outages, and losing some of each to
+    /// random loss. Probes other than ICMP send each request once the one before is answered
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "retries", global = true)]
+    burst: u32,
+    
+    /// Delay each request by a random amount, up to this percentage of the interval, so that probing doesn't line up
+    /// with other periodic traffic, or with other copies of multiping running at the same time. With --retries,
+    /// it's a percentage of the time between retries instead
+    #[arg(long, value_name = "PERCENT", default_value_t = 0.0, global = true)]
+    interval_jitter: f32,
+    
+    /// How many times to send a request again within an interval if there's no reply yet,
+    /// before counting it as lost
+    #[arg(short = 'r', long, default_value_t = 0, global = true)]
+    retries: u32,
+    
+    /// How to show the results. The default is a table on a terminal, and a log of events otherwise
+    #[arg(short = 'o', long, value_enum)]
+    output: Option<Output>,
     
     /// Whether colours are used in the output
     #[arg(short = 'c', long)]
     colour: Option<bool>,
     
     /// If specified, forces a specific IP version to be used (valid options are 4 or 6)
-    #[arg(short = 'v', long)]
+    #[arg(short = 'v', long, global = true)]
     ip_version: Option<u8>,
+    
+    /// Only probe during this time of day (in local time), e.g. 09:00-17:00. Can be given more than once
+    #[arg(long, value_name = "HH:MM-HH:MM", global = true)]
+    window: Vec<TimeWindow>,
+    
+    /// Don't probe during this time of day (in local time), e.g. for a maintenance window. Can be given more than once
+    #[arg(long, value_name = "HH:MM-HH:MM", global = true)]
+    pause: Vec<TimeWindow>,
+    
+    /// Maximum average latency (in milliseconds) for the SLA report printed on exit
+    #[arg(long, value_name = "MS")]
+    sla_average: Option<f64>,
+    
+    /// Maximum packet loss (as a percentage) for the SLA report printed on exit
+    #[arg(long, value_name = "PERCENT")]
+    sla_loss: Option<f64>,
+    
+    /// Minimum availability (the percentage of time not spent in an outage) for the SLA report printed on exit
+    #[arg(long, value_name = "PERCENT")]
+    sla_availability: Option<f64>,
+    
+    /// Show latencies at or over this many milliseconds in yellow, either for every host or for one
+    /// (e.g. "example.com=300"). Can be given more than once
+    #[arg(long, value_name = "[HOST=]MS")]
+    latency_warning: Vec<HostThreshold>,
+    
+    /// Show latencies at or over this many milliseconds in red, either for every host or for one
+    /// (e.g. "example.com=500"). Can be given more than once
+    #[arg(long, value_name = "[HOST=]MS")]
+    latency_critical: Vec<HostThreshold>,
+    
+    /// Read hosts and settings from a file, which is reloaded on SIGHUP. Settings in the file
+    /// take precedence over the command line
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    
+    /// Exit as soon as any host's packet loss goes over this percentage
+    #[arg(long, value_name = "PERCENT")]
+    fail_fast: Option<f64>,
+    
+    /// Serve a gRPC API (with a StreamStats RPC) at this address, e.g. 127.0.0.1:50051
+    #[cfg(feature = "grpc")]
+    #[arg(long, value_name = "ADDRESS")]
+    grpc: Option<SocketAddr>,
+    
+    /// Stream each host's statistics as JSON over WebSocket at this address, e.g. 127.0.0.1:8081
+    #[arg(long, value_name = "ADDRESS")]
+    ws: Option<SocketAddr>,
+    
+    /// Serve a web dashboard at this address, e.g. 127.0.0.1:8080
+    #[arg(long, value_name = "ADDRESS")]
+    web: Option<SocketAddr>,
+    
+    /// Send statistics to this Zabbix server or proxy (with the trapper protocol), e.g. zabbix.example.com:10051
+    #[arg(long, value_name = "SERVER")]
+    zabbix: Option<String>,
+    
+    /// The host name in Zabbix that the items belong to. Defaults to this machine's host name
+    #[arg(long, value_name = "NAME")]
+    zabbix_host: Option<String>,
+    
+    /// Item key for each metric (rtt, min, max, jitter, loss, sent, received and up) of each host
+    #[arg(long, value_name = "TEMPLATE", default_value = zabbix::DEFAULT_KEY)]
+    zabbix_key: String,
+    
+    /// How often statistics are sent to Zabbix, in seconds or with a unit (as for --interval)
+    #[arg(long, value_name = "TIME", default_value = "60", value_parser = time::parse_interval)]
+    zabbix_interval: Duration,
+    
+    /// Publish statistics and up/down states to this MQTT broker, e.g. broker.example.com:1883
+    #[arg(long, value_name = "SERVER")]
+    mqtt: Option<String>,
+    
+    /// Start of the MQTT topics. Statistics go to <PREFIX>/<host>/stats (as JSON), "up" or "down"
+    /// to <PREFIX>/<host>/state, and "online" or "offline" to <PREFIX>/status
+    #[arg(long, value_name = "PREFIX", default_value = "multiping")]
+    mqtt_topic: String,
+    
+    /// User name to log in to the MQTT broker with
+    #[arg(long, value_name = "USERNAME")]
+    mqtt_username: Option<String>,
+    
+    /// Password to log in to the MQTT broker with. Anyone on this machine can see it in the process list, so
+    /// --mqtt-password-file or the MULTIPING_MQTT_PASSWORD environment variable are safer
+    #[arg(long, value_name = "PASSWORD")]
+    mqtt_password: Option<String>,
+    
+    /// Read the password to log in to the MQTT broker with from this file (without the newline at the end)
+    #[arg(long, value_name = "FILE", conflicts_with = "mqtt_password")]
+    mqtt_password_file: Option<PathBuf>,
+    
+    /// How often each host's statistics are published to MQTT, in seconds or with a unit (as for --interval).
+    /// States are published straight away
+    #[arg(long, value_name = "TIME", default_value = "10", value_parser = time::parse_interval)]
+    mqtt_interval: Duration,
+    
+    /// Run as a Nagios/Icinga plugin: ping every host --count times, print a status line with perfdata,
+    /// and exit with 0 (OK), 1 (WARNING), 2 (CRITICAL) or 3 (UNKNOWN)
+    #[arg(long)]
+    check: bool,
+    
+    /// How many pings to send to each host in --check mode
+    #[arg(short = 'n', long, default_value_t = 5)]
+    count: u32,
+    
+    /// Round trip average (in milliseconds) and packet loss that are a warning in --check mode
+    #[arg(long, value_name = "MS,PERCENT%", default_value = "200,20%")]
+    warning: Threshold,
+    
+    /// Round trip average (in milliseconds) and packet loss that are critical in --check mode
+    #[arg(long, value_name = "MS,PERCENT%", default_value = "500,60%")]
+    critical: Threshold,
+    
+    /// Ping every host once, print a one line result and exit with 0 if they all replied (or 1 if not),
+    /// e.g. for a container HEALTHCHECK or a shell conditional
+    #[arg(long, conflicts_with = "check")]
+    once: bool,
+    
+    /// Exit with 0 in --once mode if any of the hosts replied, rather than all of them
+    #[arg(long, requires = "once")]
+    any: bool,
+    
+    /// How long to wait for the replies in --once mode, in seconds or with a unit (as for --interval)
+    #[arg(long, value_name = "TIME", default_value = "2", value_parser = time::parse_interval, requires = "once")]
+    timeout: Duration,
+
+    /// Give each host its own connected ICMP socket, rather than sharing one socket between every host.
+    /// This needs a file descriptor and a thread per host
+    #[arg(long, global = true)]
+    connected_sockets: bool,
+    
+    /// Ping using raw sockets (which needs CAP_NET_RAW, or the helper), giving each host its own ICMP identifier.
+    /// Replies are matched to hosts by their identifier rather than their address, so they're still counted
+    /// if NAT changes where they come from
+    #[arg(long, global = true, conflicts_with = "connected_sockets")]
+    raw_sockets: bool,
+    
+    /// Also ping the default gateways (IPv4 and IPv6, from the routing table), in rows pinned to the top of the table,
+    /// to see straight away whether a problem starts at the first hop. Only on Linux
+    #[arg(long)]
+    with_gateway: bool,
+    
+    /// Also probe each host from every address this machine has of the same IP version (apart from loopback and
+    /// link-local ones), shown underneath it, to find out which uplink or tunnel a problem is on. Each gets its own
+    /// socket. Only ICMP, TCP, UDP and HTTP hosts given at the start are probed like this. `multiping sources` lists
+    /// the addresses
+    #[arg(long)]
+    from_every_source: bool,
+    
+    /// Also probe the hosts from this machine ([user@]host), by running multiping there over SSH, with its rows under
+    /// a heading for it. Can be given more than once, to compare several places. SSH has to be able to log in without
+    /// asking for a password (e.g. with a key), and multiping has to be installed there
+    #[arg(long, value_name = "[USER@]HOST")]
+    from: Vec<String>,
+    
+    /// The multiping to run on the machines given with --from. It's run by their shell, so it can have options
+    /// (e.g. "sudo multiping --raw-sockets")
+    #[arg(long, value_name = "PROGRAM", default_value = "multiping", requires = "from")]
+    remote_command: String,
+    
+    /// Spread the hosts over this many sets of shared ICMP sockets, each with its own threads to send the requests
+    /// and receive the replies, for when there are tens of thousands of hosts
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), global = true,
+        conflicts_with = "connected_sockets")]
+    shards: u32,
+    
+    /// A helper that creates the sockets multiping isn't allowed to (see multiping-helper), for root and
+    /// members of the multiping group. By default, multiping-helper next to this binary is used if it's there
+    #[arg(long, value_name = "PATH", global = true)]
+    helper: Option<PathBuf>,
+    
+    /// Run as a systemd service: notify systemd when ready or reloading, send watchdog pings,
+    /// and log events to the journal instead of showing the table
+    #[arg(long)]
+    systemd: bool,
+    
+    /// Look for devices on the local network using mDNS/DNS-SD, and ping them too
+    #[arg(long)]
+    discover_mdns: bool,
+    
+    /// Ping the nodes of the Kubernetes cluster in the kubeconfig's current context (or the cluster multiping is
+    /// running in) at their InternalIPs, listing them again every --kube-refresh seconds as nodes come and go
+    #[cfg(feature = "kube")]
+    #[arg(long)]
+    kube_nodes: bool,
+    
+    /// How often the Kubernetes nodes are listed again, in seconds or with a unit (as for --interval)
+    #[cfg(feature = "kube")]
+    #[arg(long, value_name = "TIME", default_value = "60", value_parser = time::parse_interval, requires = "kube_nodes")]
+    kube_refresh: Duration,
+    
+    /// Ping the hosts in a hosts file (/etc/hosts if no file is given), apart from loopback addresses
+    #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "/etc/hosts")]
+    from_hosts_file: Option<PathBuf>,
+    
+    /// Ping the hosts named on Host lines in an SSH config (~/.ssh/config if no file is given), at their HostName.
+    /// Patterns and negated names on Host lines are skipped
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    from_ssh_config: Option<Option<PathBuf>>,
+    
+    /// Ping the hosts in an Ansible inventory (INI or YAML), shown under a heading for their group. Hosts are probed
+    /// at their ansible_host if they have one
+    #[arg(long, value_name = "FILE")]
+    inventory: Option<PathBuf>,
+    
+    /// Only ping the hosts in this group of the --inventory (and the groups in it). Can be given more than once
+    #[arg(long, value_name = "GROUP", requires = "inventory")]
+    inventory_group: Vec<String>,
+    
+    /// Only import the hosts from --from-hosts-file, --from-ssh-config and --inventory that match one of these patterns
+    /// (with * and ?), and none of the ones starting with ! (e.g. "*.example.com" or "!backup*")
+    #[arg(long, value_name = "PATTERN")]
+    import_pattern: Vec<String>,
+    
+    /// Clear the statistics this often (in seconds or with a unit, as for --interval), so they only cover the recent
+    /// past. The table can also be cleared by pressing r. With --output log or ndjson, each host's statistics are
+    /// logged before they're cleared
+    #[arg(long, value_name = "TIME", value_parser = time::parse_interval)]
+    reset_every: Option<Duration>,
+    
+    /// Show the address being probed next to each host's name (and the one before it, if the name has resolved
+    /// to a different address since it started)
+    #[arg(long)]
+    show_address: bool,
+    
+    /// Resolve the hosts' names again this often (in seconds or with a unit, as for --interval), and switch to probing
+    /// the new address of any that have changed (e.g. when a DNS record is updated)
+    #[arg(long, value_name = "TIME", value_parser = time::parse_interval)]
+    resolve_every: Option<Duration>,
+    
+    /// How often SRV records given on the command line (e.g. "srv:_xmpp-server._tcp.example.com") are looked up again,
+    /// to start or stop probing targets as they're added to or removed from the record set. In seconds or with a unit
+    /// (as for --interval)
+    #[arg(long, value_name = "TIME", default_value = "60", value_parser = time::parse_interval)]
+    srv_refresh: Duration,
+    
+    /// How often names with the "all:" prefix (e.g. "all:example.com") are looked up again, to start probing the
+    /// addresses that have been added to their records, and mark the ones that have gone as stale. In seconds or
+    /// with a unit (as for --interval)
+    #[arg(long, value_name = "TIME", default_value = "60", value_parser = time::parse_interval)]
+    records_refresh: Duration,
+    
+    /// Show columns with the average latency and loss over the last 1, 5 and 15 minutes (weighted like the load average),
+    /// to tell short blips apart from things getting worse for longer
+    #[arg(long)]
+    averages: bool,
+    
+    /// Show a column with an arrow for which way each host's latency is going (comparing the last minute with the last five)
+    #[arg(long)]
+    trend: bool,
+    
+    /// Show a column counting the ICMP errors about each host's requests: destination unreachable, time exceeded and
+    /// anything else. These are usually from a firewall or router, unlike requests that just go unanswered
+    #[arg(long)]
+    icmp_errors: bool,
+    
+    /// Show a column with when each host last replied, either how long ago or the time of day (in UTC)
+    #[arg(long, value_name = "FORMAT")]
+    last_reply: Option<LastReply>,
+    
+    /// Show a column with each host's note from the config file (e.g. `note = example.com=rack 12`)
+    #[arg(long)]
+    notes: bool,
+    
+    /// Show a column with a health score from 0 to 100 for each host, worked out from its latency, jitter and loss
+    #[arg(long)]
+    health: bool,
+    
+    /// How much latency, jitter and loss count towards the health score, e.g. "1,1,2" (the default) counts loss
+    /// twice as much as the others
+    #[arg(long, value_name = "LATENCY,JITTER,LOSS", requires = "health")]
+    health_weights: Option<HealthWeights>,
+    
+    /// Highlight the host with the worst latency (the latest round trip time) or packet loss. Hosts that are down
+    /// count as the worst of all
+    #[arg(long, value_name = "BY")]
+    highlight_worst: Option<Worst>,
+    
+    /// Keep the host highlighted by --highlight-worst at the top of the table
+    #[arg(long, requires = "highlight_worst")]
+    pin_worst: bool,
+    
+    /// Show a row underneath the table that adds up every host: the requests sent and replies received, the overall
+    /// loss, the best, worst and average round trip times, and how many hosts are down or have errors
+    #[arg(long)]
+    summary: bool,
+    
+    /// Print a histogram of each host's round trip times on exit, with buckets that get wider as the times get longer
+    #[arg(long)]
+    histogram: bool,
+    
+    /// Keep at most this many round trip times for each host (for the histogram and comparisons), dropping the
+    /// oldest ones first, so that memory use stays bounded on small devices. By default, every one is kept
+    #[arg(long, value_name = "COUNT")]
+    history_samples: Option<usize>,
+    
+    /// Drop round trip times kept for each host once they're older than this, in seconds or with a unit (as for
+    /// --interval)
+    #[arg(long, value_name = "TIME", value_parser = time::parse_interval)]
+    history_age: Option<Duration>,
+    
+    /// How many requests in a row have to go unanswered for a host to be down (and for an outage to start)
+    #[arg(long, value_name = "COUNT", default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    down_after: u32,
+    
+    /// How many replies in a row a host that's down has to send to be up again
+    #[arg(long, value_name = "COUNT", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    up_after: u32,
+    
+    /// A host is degraded while its average round trip time over the last minute is over this many milliseconds
+    /// (until it's back under 80% of it)
+    #[arg(long, value_name = "MS", value_parser = parse_milliseconds)]
+    degraded_latency: Option<Duration>,
+    
+    /// A host is degraded while its packet loss over the last minute is over this percentage (until it's back under
+    /// 80% of it)
+    #[arg(long, value_name = "PERCENT")]
+    degraded_loss: Option<f64>,
+    
+    /// Log every request and reply, with its sequence number, round trip time, who it came from and its ICMP type.
+    /// They're shown underneath the table, or as extra events with --output log or ndjson. It has no -v, as that's
+    /// --ip-version
+    #[arg(long)]
+    verbose: bool,
+    
+    /// Start each line of the log (and the packets logged with --verbose) with the date and time, like ping -D,
+    /// in UTC (the default) or the local time zone. With --output ndjson, it's the time zone of each event's time
+    #[arg(short = 'D', long, value_name = "ZONE", num_args = 0..=1, require_equals = true, default_missing_value = "utc")]
+    timestamps: Option<TimeZone>,
+    
+    /// Write a log for each host to <DIR>/<host>.log, with every reply, timeout and error, whatever the output is
+    #[arg(long, value_name = "DIR")]
+    log_dir: Option<PathBuf>,
+    
+    /// Rotate each host's log file before it gets bigger than this, e.g. 10M (K, M and G are powers of 1024)
+    #[arg(long, value_name = "SIZE", value_parser = logfile::parse_size, requires = "log_dir")]
+    log_max_size: Option<u64>,
+    
+    /// Rotate each host's log file this often, in seconds or with a unit (as for --interval), e.g. 86400 for daily
+    #[arg(long, value_name = "TIME", value_parser = time::parse_interval, requires = "log_dir")]
+    log_rotate_every: Option<Duration>,
+    
+    /// How many rotated log files to keep for each host (named <host>.log.1, .2 and so on, oldest last)
+    #[arg(long, value_name = "COUNT", default_value_t = 5, requires = "log_dir")]
+    log_keep: usize,
+    
+    /// Compress rotated log files with gzip
+    #[cfg(feature = "gzip")]
+    #[arg(long, requires = "log_dir")]
+    log_compress: bool,
+    
+    /// Also write to this file while the output runs (e.g. the table), so there's a record of it afterwards.
+    /// It's appended to
+    #[arg(long, value_name = "FILE")]
+    tee: Option<PathBuf>,
+    
+    /// What --tee writes
+    #[arg(long, value_enum, default_value_t = TeeFormat::Snapshot, requires = "tee")]
+    tee_format: TeeFormat,
+    
+    /// How often --tee writes a snapshot of the statistics, in seconds or with a unit (as for --interval)
+    #[arg(long, value_name = "TIME", default_value = "60", value_parser = time::parse_interval, requires = "tee")]
+    tee_every: Duration,
+    
+    /// Where the statistics are saved on SIGUSR2 (or when pressing s), as multiping-<time>.json
+    #[arg(long, value_name = "DIR", default_value = ".")]
+    snapshot_dir: PathBuf,
+    
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// A table of statistics for each host, which is kept up to date
+    Table,
+    /// One line per event, such as a host going down or coming back up
+    Log,
+    /// One JSON object per line for every probe sent, reply received, timeout and error
+    Ndjson,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum TeeFormat {
+    /// Every host's statistics as plain text, every --tee-every seconds and when it finishes
+    Snapshot,
+    /// One JSON object per line for every event, as with --output ndjson
+    Ndjson,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LastReply {
+    /// How long ago, e.g. "12s ago"
+    Ago,
+    /// The time of day in UTC, e.g. "12:34:56"
+    Time,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TimeZone {
+    /// e.g. 2025-06-01T12:34:56.789Z
+    #[default]
+    Utc,
+    /// With the offset from UTC, e.g. 2025-06-01T14:34:56.789+02:00
+    Local,
+}
+
+impl TimeZone {
+    /// Formats a time as RFC 3339 in this time zone
+    fn format(self, time: SystemTime) -> String {
+        match self {
+            TimeZone::Utc => time::format_rfc3339(time),
+            TimeZone::Local => time::format_rfc3339_local(time),
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Worst {
+    Latency,
+    Loss,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Ping two hosts and report whether the difference in their latencies is statistically significant
+    Compare {
+        host_a: String,
+        host_b: String,
+        
+        /// How many pings to send to each host
+        #[arg(short = 'n', long, default_value_t = 20)]
+        count: u32,
+        
+        /// Significance level for the Mann-Whitney U test
+        #[arg(long, default_value_t = 0.05)]
+        alpha: f64,
+    },
+    /// Ping hosts with payloads of increasing size (like ping -g/-G) and report the statistics for
+    /// each size, to find size-dependent loss and MTU problems
+    Sweep {
+        #[arg(required = true)]
+        hosts: Vec<String>,
+        
+        /// Smallest payload size, in bytes
+        #[arg(short = 'g', long, default_value_t = MIN_PAYLOAD_SIZE)]
+        min_size: usize,
+        
+        /// Largest payload size, in bytes. The default fills a 1500 byte IPv4 packet
+        #[arg(short = 'G', long, default_value_t = 1472)]
+        max_size: usize,
+        
+        /// How much the payload size goes up by each time
+        #[arg(long, default_value_t = 64)]
+        step: usize,
+        
+        /// How many pings to send with each size
+        #[arg(short = 'n', long, default_value_t = 3)]
+        count: u32,
+    },
+    /// Find the path MTU to a host, by searching for the biggest ping that gets through without being fragmented.
+    /// Each size waits up to the interval for a reply
+    #[cfg(target_os = "linux")]
+    Mtu {
+        host: String,
+        
+        /// How many times to try each size before deciding it's too big
+        #[arg(short = 'n', long, default_value_t = 3)]
+        attempts: u32,
+    },
+    /// Decode an ICMP message given as hex bytes on standard input, and print every field. An IP header in front
+    /// of it is skipped. Messages are taken to be ICMPv4 unless there's an IPv6 header, or "-v 6" is given
+    Decode,
+    /// Answer one-way delay probes ("owd:" hosts) from multiping on other machines. For the delays
+    /// each way to mean anything, this machine's clock has to be synchronised with theirs
+    Reflect {
+        /// Address and port to listen on
+        #[arg(default_value_t = SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, owd::DEFAULT_PORT)))]
+        listen: SocketAddr,
+    },
+    /// Ping addresses on this machine (127.0.0.1 and up) as fast as possible for a while, and report how many
+    /// requests and replies went through, and the CPU time (and, if built with the count-allocations feature,
+    /// memory allocations) each request took.
+    /// Requests are sent back to back, rather than every interval. --shards and --raw-sockets are used
+    Bench {
+        /// How many addresses to ping
+        #[arg(short = 'n', long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..=BENCH_MAX_HOSTS))]
+        hosts: u32,
+        
+        /// How long to run for, in seconds or with a unit (as for --interval)
+        #[arg(short = 'd', long, value_name = "TIME", default_value = "10", value_parser = time::parse_interval)]
+        duration: Duration,
+        
+        /// Use probes that answer straight away without sending anything, to measure multiping's own overhead
+        #[arg(long)]
+        mock: bool,
+    },
+    /// List the addresses of this machine that --from-every-source probes from, and the interface each is on
+    Sources,
+    /// Ping hosts --count times and fail if any of them goes over the thresholds, e.g. to catch network regressions
+    /// in a CI pipeline. The result is printed as a JSON object, with why each host failed
+    #[command(group = clap::ArgGroup::new("thresholds").required(true).multiple(true))]
+    Assert {
+        #[arg(required = true)]
+        hosts: Vec<String>,
+        
+        /// How many pings to send to each host
+        #[arg(short = 'n', long, default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+        
+        /// Highest 95th percentile round trip time allowed, in milliseconds
+        #[arg(long, value_name = "MS", group = "thresholds")]
+        max_p95: Option<f64>,
+        
+        /// Highest packet loss allowed, in percent
+        #[arg(long, value_name = "PERCENT", group = "thresholds")]
+        max_loss: Option<f64>,
+    },
+}
+
+/// The environment variable the MQTT password can be given in, rather than on the command line
+const MQTT_PASSWORD_VAR: &str = "MULTIPING_MQTT_PASSWORD";
+
+/// The most addresses `multiping bench` can ping, which is all of 127.0.0.0/8 after 127.0.0.1
+const BENCH_MAX_HOSTS: i64 = (1 << 24) - 2;
+
+fn main() {
+    // Parse arguments
+    let args = Arguments::parse();
+    
+    if let Some(path) = args.helper.clone().or_else(helper::find_helper) {
+        helper::install(path);
+    }
+    
+    if !(0.0..=100.0).contains(&args.interval_jitter) {
+        eprintln!("--interval-jitter needs to be from 0 to 100 percent");
+        exit(EXIT_ERROR);
+    }
+    if args.history_samples == Some(0) {
+        eprintln!("--history-samples needs to be more than 0");
+        exit(EXIT_ERROR);
+    }
+    if args.degraded_loss.is_some_and(|loss| !(0.0..=100.0).contains(&loss)) {
+        eprintln!("--degraded-loss needs to be from 0 to 100 percent");
+        exit(EXIT_ERROR);
+    }
+    
+    if let Some(Command::Compare { host_a, host_b, count, alpha }) = &args.command {
+        if *alpha <= 0.0 || *alpha >= 1.0 {
+            eprintln!("--alpha needs to be between 0 and 1");
+            exit(EXIT_ERROR);
+        }
+        // Each side has to be a single probe, or it isn't clear what's being compared
+        let mut hinfos = Vec::new();
+        for (host, group) in [host_a, host_b].into_iter().zip(resolve_hosts(&[host_a.clone(), host_b.clone()], args.ip_version, true)) {
+            let [hinfo] = <[HostInfo; 1]>::try_from(group).unwrap_or_else(|group| {
+                eprintln!("{} gives {} hosts to probe, but compare needs just one on each side", host, group.len());
+                exit(EXIT_ERROR);
+            });
+            hinfos.push(hinfo);
+        }
+        compare(hinfos, *count, *alpha, ping_options(&args, None));
+        return;
+    }
+    
+    if let Some(Command::Sweep { hosts, min_size, max_size, step, count }) = &args.command {
+        if *min_size < MIN_PAYLOAD_SIZE || min_size > max_size || *step == 0 || *count == 0 {
+            eprintln!("The sizes need to go from at least {} bytes up to the maximum, in steps bigger than 0, with at least one ping each", MIN_PAYLOAD_SIZE);
+            exit(EXIT_ERROR);
+        }
+        let hinfos = resolve_hosts(hosts, args.ip_version, false).concat();
+        let sizes: Vec<usize> = (*min_size..=*max_size).step_by(*step).collect();
+        sweep(hinfos, &sizes, *count, args.interval);
+        return;
+    }
+    
+    if let Some(Command::Decode) = &args.command {
+        decode(args.ip_version == Some(6));
+        return;
+    }
+    
+    if let Some(Command::Sources) = &args.command {
+        for local in local_addresses_or_exit() {
+            println!("{}\t{}", local.address, local.interface);
+        }
+        return;
+    }
+    
+    if let Some(Command::Reflect { listen }) = &args.command {
+        if let Err(e) = owd::reflect(*listen) {
+            eprintln!("Couldn't reflect on {}: {}", listen, e);
+            exit(EXIT_ERROR);
+        }
+        return;
+    }
+    
+    #[cfg(target_os = "linux")]
+    if let Some(Command::Mtu { host, attempts }) = &args.command {
+        let hinfos = resolve_hosts(std::slice::from_ref(host), args.ip_version, true).concat();
+        path_mtu(hinfos, *attempts, args.interval);
+        return;
+    }
+    
+    if let Some(Command::Bench { hosts, duration, mock }) = &args.command {
+        if args.connected_sockets {
+            eprintln!("--connected-sockets can't be benchmarked");
+            exit(EXIT_ERROR);
+        }
+        bench(&args, *hosts, *duration, *mock);
+        return;
+    }
+    
+    // Loaded before the modes that exit once they're done, so that its options apply to them too
+    let config = args.config.as_ref().map(|path| match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            exit(EXIT_ERROR);
+        }
+    });
+    
+    if let Some(Command::Assert { hosts, count, max_p95, max_loss }) = &args.command {
+        let hinfos = resolve_hosts(hosts, args.ip_version, true).concat();
+        assert_thresholds(hinfos, *count, *max_p95, *max_loss, ping_options(&args, config.as_ref()));
+    }
+    
+    if args.check {
+        check(&args, config.as_ref());
+    }
+    if args.once {
+        once(&args, config.as_ref());
+    }
+
+    let imported = import_hosts(&args);
+    #[cfg(feature = "kube")]
+    let kube_nodes = args.kube_nodes;
+    #[cfg(not(feature = "kube"))]
+    let kube_nodes = false;
+    if args.hosts.is_empty() && !args.discover_mdns && !kube_nodes && !args.with_gateway && config.is_none() && imported.is_empty() {
+        eprintln!("You need to specify hosts on the command line.\nExample: multiping 127.0.0.1");
+        exit(EXIT_ERROR);
+    }
+    
+    let (tx, rx) = mpsc::channel::<StatusUpdate>();
+    let from_stdin = args.hosts.iter().any(|h| h == "-");
+    let cli_hosts: Vec<String> = args.hosts.iter().filter(|h| *h != "-").cloned()
+        .chain(imported.iter().map(|(h, _)| h.clone()))
+        .collect();
+    // Which inventory group each host from the command line is in, if any
+    let host_groups: Vec<Option<String>> = args.hosts.iter().filter(|h| *h != "-").map(|_| None)
+        .chain(imported.into_iter().map(|(_, group)| group))
+        .collect();
+    let config_host_strs = config.as_ref().map(|c| c.hosts.clone()).unwrap_or_default();
+    let groups = resolve_hosts(&[cli_hosts.clone(), config_host_strs.clone()].concat(), args.ip_version, false);
+    let groups = if args.from_every_source {
+        let local = local_addresses_or_exit();
+        groups.into_iter().map(|group| group.into_iter().flat_map(|h| sources::with_each_source(h, &local)).collect()).collect()
+    } else {
+        groups
+    };
+    
+    // Which indices each host from the config file has, so they can be removed when it's reloaded
+    let mut next_index = 0;
+    let mut config_hosts: Vec<(String, Vec<usize>)> = Vec::new();
+    // The SRV records and "all:" names from the command line, and the index of each of their targets or addresses
+    let mut srv_hosts: Vec<(String, Vec<(String, usize)>)> = Vec::new();
+    let mut all_address_hosts: Vec<(String, Vec<(String, usize)>)> = Vec::new();
+    for (i, group) in groups.iter().enumerate() {
+        let indices: Vec<usize> = (next_index..next_index + group.len()).collect();
+        let known = || group.iter().map(|h| h.host_str.clone()).zip(indices.clone()).collect();
+        if i < cli_hosts.len() && srv::record_name(&cli_hosts[i]).is_some() {
+            srv_hosts.push((cli_hosts[i].clone(), known()));
+        } else if i < cli_hosts.len() && cli_hosts[i].starts_with(ALL_ADDRESSES_PREFIX) {
+            all_address_hosts.push((cli_hosts[i].clone(), known()));
+        }
+        next_index += group.len();
+        // Ones that couldn't be resolved are left out, so that reloading the config tries them again
+        if i >= cli_hosts.len() && !group.is_empty() {
+            config_hosts.push((config_host_strs[i - cli_hosts.len()].clone(), indices));
+        }
+    }
+    let history = history_limit(&args);
+    let state_thresholds = state_thresholds(&args);
+    // Alongside the other machines' rows, the local ones get a heading too
+    check_remote_machines(&args);
+    let local_group = (!args.from.is_empty()).then(|| "From this machine".to_string());
+    let hinfos: Vec<HostInfo> = groups.into_iter().enumerate()
+        .flat_map(|(i, group)| {
+            let host_group = host_groups.get(i).cloned().flatten();
+            group.into_iter().map(move |h| (h, host_group.clone()))
+        })
+        .map(|(h, group)| HostInfo { history, state_thresholds, note: config.as_ref().and_then(|c| c.note_for(&h)), group: group.or(h.group.clone()).or_else(|| local_group.clone()), ..h })
+        // They're added at the end, so that the other hosts keep their indices, but they're shown first
+        .chain(default_gateways(&args).into_iter().map(|h| HostInfo { history, state_thresholds, pinned: true, group: Some("Default gateway".to_string()), ..h }))
+        .collect();
+    let sla = Arc::new(Mutex::new(sla_thresholds(&args, config.as_ref())));
+    let colours = Arc::new(Mutex::new(latency_colours(&args, config.as_ref())));
+    
+    // The table is only shown on a terminal. Otherwise (e.g. when running as a service), events are logged
+    let output = args.output.unwrap_or(if args.systemd || !Term::stdout().is_term() { Output::Log } else { Output::Table });
+    let notices = Notices::new(output == Output::Table);
+    
+    let pinger = match start_pinging(&hinfos, ping_options(&args, config.as_ref()), tx) {
+        Ok(p) => p,
+        Err(e) => {
+            eprintln!("Failed to start pinging: {}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    
+    for destination in &args.from {
+        if let Err(e) = remote::start(destination, &args.remote_command, &cli_hosts, args.interval, pinger.clone()) {
+            eprintln!("Failed to start probing from {}: {}", destination, e);
+            exit(EXIT_ERROR);
+        }
+    }
+    
+    if args.discover_mdns {
+        let pinger = pinger.clone();
+        thread::spawn(move || {
+            // Errors are ignored, as discovery is best-effort and the table is already on the screen
+            let _ = mdns::discover(Duration::from_secs(10), |name, addr| {
+                pinger.add_host(HostInfo::from_address(name, addr));
+            });
+        });
+    }
+    
+    #[cfg(feature = "kube")]
+    if args.kube_nodes {
+        let cluster = match kubernetes::Cluster::connect() {
+            Ok(cluster) => cluster,
+            Err(e) => {
+                eprintln!("Failed to connect to the Kubernetes API server: {}", e);
+                exit(EXIT_ERROR);
+            }
+        };
+        let (pinger, ip_version, every) = (pinger.clone(), args.ip_version, args.kube_refresh);
+        let (config, notices) = (config.clone(), notices.clone());
+        thread::spawn(move || watch_kube_nodes(cluster, pinger, ip_version, every, config, notices));
+    }
+    
+    // SIGUSR1 pauses and resumes probing, so that scripts can quiet multiping down for a while.
+    // SIGUSR2 saves the statistics (once the next update comes in, as they're kept by the output).
+    // SIGHUP reloads the config file, but is left alone if there isn't one
+    let signal_pinger = pinger.clone();
+    let signal_args = args.clone();
+    let signal_sla = sla.clone();
+    let signal_colours = colours.clone();
+    let signal_notices = notices.clone();
+    let snapshot_requested = Arc::new(AtomicBool::new(false));
+    let signal_snapshot = snapshot_requested.clone();
+    let signals = if args.config.is_some() { vec![SIGUSR1, SIGUSR2, SIGHUP] } else { vec![SIGUSR1, SIGUSR2] };
+    match Signals::new(signals) {
+        Ok(mut signals) => {
+            thread::spawn(move || {
+                for signal in signals.forever() {
+                    if signal == SIGUSR1 {
+                        signal_pinger.toggle_paused();
+                    } else if signal == SIGUSR2 {
+                        signal_snapshot.store(true, Ordering::Relaxed);
+                    } else if let Some(path) = &signal_args.config {
+                        if signal_args.systemd {
+                            let _ = systemd::notify_reloading();
+                        }
+                        if let Err(e) = reload_config(path, &signal_args, &signal_pinger, &mut config_hosts, &signal_sla, &signal_colours) {
+                            signal_notices.show(format!("Failed to reload {}: {}", path.display(), e));
+                        }
+                        if signal_args.systemd {
+                            let _ = systemd::notify("READY=1");
+                        }
+                    }
+                }
+            });
+        },
+        Err(e) => eprintln!("Couldn't listen for signals: {}", e),
+    }
+    
+    if let Some(every) = args.reset_every {
+        let pinger = pinger.clone();
+        thread::spawn(move || loop {
+            thread::sleep(every);
+            pinger.reset_stats();
+        });
+    }
+    
+    if let Some(every) = args.resolve_every {
+        let pinger = pinger.clone();
+        let ip_version = args.ip_version;
+        thread::spawn(move || loop {
+            thread::sleep(every);
+            pinger.resolve_again(HostOptions { ip_version });
+        });
+    }
+    
+    if !srv_hosts.is_empty() {
+        for (host, targets) in srv_hosts {
+            let (pinger, ip_version, every) = (pinger.clone(), args.ip_version, args.srv_refresh);
+            let notices = notices.clone();
+            thread::spawn(move || watch_srv_record(&host, targets, pinger, ip_version, every, notices));
+        }
+    }
+    
+    if !all_address_hosts.is_empty() {
+        for (host, addresses) in all_address_hosts {
+            let (pinger, ip_version, every) = (pinger.clone(), args.ip_version, args.records_refresh);
+            let notices = notices.clone();
+            thread::spawn(move || watch_all_addresses(&host, addresses, pinger, ip_version, every, notices));
+        }
+    }
+    
+    if from_stdin {
+        let ip_version = args.ip_version;
+        let pinger = pinger.clone();
+        thread::spawn(move || read_stdin_hosts(pinger, cli_hosts, ip_version));
+    }
+    
+    if args.systemd {
+        let _ = systemd::notify("READY=1");
+        if let Some(interval) = systemd::watchdog_interval() {
+            thread::spawn(move || loop {
+                let _ = systemd::notify("WATCHDOG=1");
+                thread::sleep(interval);
+            });
+        }
+    }
+    
+    #[cfg(feature = "grpc")]
+    let rx = match args.grpc {
+        Some(addr) => match grpc::serve(addr, &hinfos, {
+            let notices = notices.clone();
+            move |e| notices.show(format!("The gRPC server on {} stopped: {}", addr, e))
+        }) {
+            Ok(publisher) => tap_updates(rx, hinfos.clone(), move |i, h, _| publisher.publish(i, h)),
+            Err(e) => {
+                eprintln!("Failed to start the gRPC server on {}: {}", addr, e);
+                exit(EXIT_ERROR);
+            }
+        },
+        None => rx,
+    };
+    
+    // The WebSocket feed and the web dashboard share the same statistics
+    let rx = if args.ws.is_some() || args.web.is_some() {
+        let feed = Feed::new();
+        for (i, hinfo) in hinfos.iter().enumerate() {
+            feed.publish(i, hinfo);
+        }
+        if let Some(addr) = args.ws && let Err(e) = websocket::serve(addr, feed.clone()) {
+            eprintln!("Failed to start the WebSocket server on {}: {}", addr, e);
+            exit(EXIT_ERROR);
+        }
+        if let Some(addr) = args.web && let Err(e) = web::serve(addr, feed.clone()) {
+            eprintln!("Failed to start the web server on {}: {}", addr, e);
+            exit(EXIT_ERROR);
+        }
+        tap_updates(rx, hinfos.clone(), move |i, h, _| feed.publish(i, h))
+    } else {
+        rx
+    };
+    
+    let rx = match &args.zabbix {
+        Some(server) => {
+            let latest = Arc::new(Mutex::new(hinfos.clone()));
+            start_zabbix_sender(server.clone(), &args, latest.clone(), notices.clone());
+            tap_updates(rx, hinfos.clone(), move |i, h, _| {
+                let mut latest = latest.lock().unwrap();
+                if latest.len() <= i {
+                    latest.push(h.clone());
+                } else {
+                    latest[i] = h.clone();
+                }
+            })
+        },
+        None => rx,
+    };
+    
+    let rx = match &args.mqtt {
+        Some(server) => {
+            let password = match (&args.mqtt_password, &args.mqtt_password_file) {
+                (Some(password), _) => Some(password.clone()),
+                (None, Some(path)) => match fs::read_to_string(path) {
+                    Ok(password) => Some(password.trim_end_matches(['\r', '\n']).to_string()),
+                    Err(e) => {
+                        eprintln!("Failed to read {}: {}", path.display(), e);
+                        exit(EXIT_ERROR);
+                    }
+                },
+                (None, None) => std::env::var(MQTT_PASSWORD_VAR).ok(),
+            };
+            let publish = start_mqtt_publisher(server.clone(), &args, password, notices.clone());
+            let interval = args.mqtt_interval;
+            let prefix = args.mqtt_topic.clone();
+            let mut last_published: Vec<Option<Instant>> = Vec::new();
+            let mut state_published: Vec<bool> = Vec::new();
+            tap_updates(rx, hinfos.clone(), move |i, h, change| {
+                if last_published.len() <= i {
+                    last_published.resize(i + 1, None);
+                    state_published.resize(i + 1, false);
+                }
+                let topic = format!("{}/{}", prefix, mqtt::topic_level(&h.host_str));
+                // The state is retained, so anything subscribing later finds out straight away. It's first published
+                // once the host has replied (or gone down), and then whenever it changes
+                if change.is_some() || (!state_published[i] && (h.successful > 0 || h.state == HostState::Down)) {
+                    state_published[i] = true;
+                    let payload = h.state.name().to_string();
+                    let _ = publish.send(mqtt::Message { topic: format!("{}/state", topic), payload, retain: true });
+                }
+                if h.removed || last_published[i].is_none_or(|t| t.elapsed() >= interval) {
+                    last_published[i] = Some(Instant::now());
+                    let payload = json::host_stats(i, h).finish();
+                    let _ = publish.send(mqtt::Message { topic: format!("{}/stats", topic), payload, retain: false });
+                }
+            })
+        },
+        None => rx,
+    };
+    
+    let rx = match &args.log_dir {
+        Some(dir) => {
+            let rotation = Rotation {
+                max_size: args.log_max_size,
+                every: args.log_rotate_every,
+                keep: args.log_keep,
+                #[cfg(feature = "gzip")]
+                compress: args.log_compress,
+                #[cfg(not(feature = "gzip"))]
+                compress: false,
+            };
+            match HostLogs::new(dir.clone(), rotation) {
+                Ok(logs) => tap_host_logs(rx, hinfos.clone(), logs, args.timestamps.unwrap_or_default(), notices.clone()),
+                Err(e) => {
+                    eprintln!("Failed to create the log directory {}: {}", dir.display(), e);
+                    exit(EXIT_ERROR);
+                }
+            }
+        },
+        None => rx,
+    };
+    
+    let rx = match &args.tee {
+        Some(path) => {
+            match File::options().create(true).append(true).open(path) {
+                Ok(file) => tap_tee(rx, hinfos.clone(), file, args.tee_format, args.tee_every, args.timestamps.unwrap_or_default(), notices.clone()),
+                Err(e) => {
+                    eprintln!("Failed to open {}: {}", path.display(), e);
+                    exit(EXIT_ERROR);
+                }
+            }
+        },
+        None => rx,
+    };
+    
+    let snapshots = Snapshots { requested: snapshot_requested, dir: args.snapshot_dir.clone(), notices };
+    
+    let result = match output {
+        Output::Table => display_loop(rx, hinfos, args, sla, colours, pinger, snapshots),
+        Output::Log => log_loop(rx, hinfos, args, sla, pinger, snapshots),
+        Output::Ndjson => ndjson_loop(rx, hinfos, args, sla, pinger, snapshots),
+    };
+    match result {
+        Ok(code) => exit(code),
+        Err(e) => {
+            eprintln!("Error in display loop {}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// Checks that multiping can be run on every --from machine (at the same time, as SSH can take a while to connect),
+/// and exits if it can't on any of them
+fn check_remote_machines(args: &Arguments) {
+    let failures: Vec<String> = thread::scope(|scope| {
+        let checks: Vec<_> = args.from.iter()
+            .map(|destination| (destination, scope.spawn(|| remote::check(destination, &args.remote_command))))
+            .collect();
+        checks.into_iter().filter_map(|(destination, check)| match check.join() {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(format!("Couldn't run {} on {}: {}", args.remote_command, destination, e)),
+            Err(_) => Some(format!("Couldn't check {}", destination)),
+        }).collect()
+    });
+    if !failures.is_empty() {
+        for failure in failures {
+            eprintln!("{}", failure);
+        }
+        exit(EXIT_ERROR);
+    }
+}
+
+/// Calls `tap` with the latest statistics of the host each update is about (and the state it moved to, if it did),
+/// then passes the update on to the returned receiver. This lets other outputs see the statistics without slowing
+/// down the display
+fn tap_updates<F>(rx: Receiver<StatusUpdate>, mut hinfos: Vec<HostInfo>, mut tap: F) -> Receiver<StatusUpdate>
+where F: FnMut(usize, &HostInfo, Option<StateChange>) + Send + 'static {
+    let (tx, tapped_rx) = mpsc::channel();
+    thread::spawn(move || {
+        for update in rx {
+            let i = update.host_index(&hinfos);
+            let change = update_host_info(&update, &mut hinfos);
+            match (&update, i) {
+                (StatusUpdate::Reset, _) => {
+                    for (i, h) in hinfos.iter().enumerate() {
+                        tap(i, h, None);
+                    }
+                },
+                // Packets for the verbose log don't change the statistics
+                (StatusUpdate::Packet(..), _) | (_, None) => {},
+                (_, Some(i)) => tap(i, &hinfos[i], change),
+            }
+            if tx.send(update).is_err() {
+                return;
+            }
+        }
+    });
+    tapped_rx
+}
+
+/// Writes each update to the log file of the host it's about, then passes it on to the returned receiver.
+/// An error writing a log is shown with `notices`, but not again until it changes
+fn tap_host_logs(rx: Receiver<StatusUpdate>, mut hinfos: Vec<HostInfo>, mut logs: HostLogs, zone: TimeZone, notices: Notices) -> Receiver<StatusUpdate> {
+    let (tx, tapped_rx) = mpsc::channel();
+    thread::spawn(move || {
+        // Whether each host's last request is still waiting for a reply, so that timeouts can be logged
+        let mut waiting = vec![false; hinfos.len()];
+        let mut last_error = None;
+        for update in rx {
+            if let StatusUpdate::Added(_) = update {
+                waiting.push(false);
+            }
+            // The statistics are logged before they're cleared
+            let lines = match (&update, update.host_index(&hinfos)) {
+                (StatusUpdate::Reset, _) => hinfos.iter().filter(|h| !h.removed).map(|h| (h.host_str.clone(), format_reset(h))).collect(),
+                (StatusUpdate::Added(h), _) => vec![(h.host_str.clone(), "started probing".to_string())],
+                (update, Some(i)) => format_host_log(update, &hinfos[i], &mut waiting[i]).map(|line| (hinfos[i].host_str.clone(), line)).into_iter().collect(),
+                (_, None) => Vec::new(),
+            };
+            update_host_info(&update, &mut hinfos);
+            for (host, line) in lines {
+                let line = format!("{} {}", zone.format(SystemTime::now()), line);
+                match logs.write_line(&host, &line) {
+                    Ok(()) => last_error = None,
+                    Err(e) => {
+                        let message = format!("Failed to write the log for {}: {}", host, e);
+                        if last_error.as_ref() != Some(&message) {
+                            notices.show(message.clone());
+                            last_error = Some(message);
+                        }
+                    },
+                }
+            }
+            if tx.send(update).is_err() {
+                return;
+            }
+        }
+    });
+    tapped_rx
+}
+
+/// Writes a copy of the output to `file` for --tee, then passes each update on to the returned receiver: a snapshot
+/// of the statistics every `every` (and a last one when probing stops), or every event as NDJSON.
+/// An error writing is shown with `notices`, but not again until it changes
+fn tap_tee(rx: Receiver<StatusUpdate>, mut hinfos: Vec<HostInfo>, mut file: File, format: TeeFormat, every: Duration, zone: TimeZone,
+        notices: Notices) -> Receiver<StatusUpdate> {
+    let (tx, tapped_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut events = NdjsonEvents::new(zone, hinfos.len());
+        let mut last_snapshot = Instant::now();
+        let mut last_error = None;
+        for update in rx {
+            let stopped = matches!(update, StatusUpdate::Stopped);
+            let lines: Vec<String> = match format {
+                TeeFormat::Ndjson => events.apply(&update, &mut hinfos).into_iter().map(|e| e.finish()).collect(),
+                TeeFormat::Snapshot => {
+                    update_host_info(&update, &mut hinfos);
+                    if stopped || last_snapshot.elapsed() >= every {
+                        last_snapshot = Instant::now();
+                        format_snapshot(&hinfos, zone)
+                    } else {
+                        Vec::new()
+                    }
+                },
+            };
+            if !lines.is_empty() {
+                match lines.iter().try_for_each(|line| writeln!(file, "{}", line)) {
+                    Ok(()) => last_error = None,
+                    Err(e) => {
+                        let message = format!("Failed to write to the --tee file: {}", e);
+                        if last_error.as_ref() != Some(&message) {
+                            notices.show(message.clone());
+                            last_error = Some(message);
+                        }
+                    },
+                }
+            }
+            if tx.send(update).is_err() {
+                return;
+            }
+        }
+    });
+    tapped_rx
+}
+
+/// Saves every host's statistics (as `json::snapshot`) to a new file in `dir` when `requested` is set (by SIGUSR2 or
+/// pressing s). It's checked by the output loop, as that already has the statistics. Where the file went (or why it
+/// couldn't be saved) is shown with `notices`
+struct Snapshots {
+    requested: Arc<AtomicBool>,
+    dir: PathBuf,
+    notices: Notices,
+}
+
+impl Snapshots {
+    fn save_if_requested(&self, hinfos: &[HostInfo]) {
+        if !self.requested.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let now = SystemTime::now();
+        // Colons aren't allowed in file names everywhere the file might be copied to. The name has milliseconds,
+        // and in case two snapshots are still saved within one, the later one gets a number rather than replacing it
+        let stem = format!("multiping-{}", time::format_rfc3339(now).replace(':', ""));
+        let mut path = self.dir.join(format!("{}.json", stem));
+        let mut result = File::create_new(&path);
+        for n in 2.. {
+            match result {
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    path = self.dir.join(format!("{}-{}.json", stem, n));
+                    result = File::create_new(&path);
+                },
+                _ => break,
+            }
+        }
+        match result.and_then(|mut file| writeln!(file, "{}", json::snapshot(hinfos, now))) {
+            Ok(()) => self.notices.show(format!("Saved the statistics to {}", path.display())),
+            Err(e) => self.notices.show(format!("Failed to save the statistics to {}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// Messages from the threads working in the background, like failing to reach the MQTT broker. Printing them while
+/// the table is shown would draw over it (and they'd be gone with the next update), so the latest one is shown
+/// underneath the table for a while instead. With the other outputs, they're printed straight away
+#[derive(Clone)]
+struct Notices {
+    latest: Option<Arc<Mutex<Option<Notice>>>>, // None if they're printed instead
+}
+
+/// A message from the background, and when it came
+type Notice = (SystemTime, String);
+
+impl Notices {
+    fn new(table: bool) -> Notices {
+        Notices { latest: table.then(Arc::default) }
+    }
+
+    fn show(&self, message: String) {
+        match &self.latest {
+            Some(latest) => *latest.lock().unwrap() = Some((SystemTime::now(), message)),
+            None => eprintln!("{}", message),
+        }
+    }
+
+    /// The message to show under the table, if there's been one recently
+    fn latest(&self) -> Option<Notice> {
+        let latest = self.latest.as_ref()?.lock().unwrap().clone()?;
+        (latest.0.elapsed().unwrap_or_default() < NOTICE_SHOWN_FOR).then_some(latest)
+    }
+}
+
+/// A snapshot of every host's statistics for --tee: a line with the time, then a line for each host
+fn format_snapshot(hinfos: &[HostInfo], zone: TimeZone) -> Vec<String> {
+    let hosts = hinfos.iter().filter(|h| !h.removed).map(|h| format!("{}, {}", format_stats(h), h.state.name()));
+    [format!("{}:", zone.format(SystemTime::now()))].into_iter().chain(hosts).chain([String::new()]).collect()
+}
+
+/// The line in a host's log file for an update about it (from before the update), if it's logged. Requests aren't
+/// logged, but a request sent while the last one is still `waiting` for a reply means that one timed out
+/// (unless they're in the same burst)
+fn format_host_log(update: &StatusUpdate, h: &HostInfo, waiting: &mut bool) -> Option<String> {
+    let sequence = |sequence: &Option<u16>| sequence.map(|n| format!(" seq={}", n)).unwrap_or_default();
+    if let StatusUpdate::Received(..) | StatusUpdate::Corrupted(..) | StatusUpdate::Error(..) | StatusUpdate::IcmpError(..) = update {
+        *waiting = false;
+    }
+    match update {
+        StatusUpdate::Sent(_) if h.burst.is_some_and(|b| b.sending()) => None,
+        StatusUpdate::Sent(_) => std::mem::replace(waiting, true).then(|| "timeout".to_string()),
+        StatusUpdate::Received(_, Some(latency), n) => Some(format!("reply{} {:.2} ms", sequence(n), *latency as f64 / 1000.0)),
+        StatusUpdate::Received(_, None, n) => Some(format!("reply{}, time unknown as the clock changed", sequence(n))),
+        StatusUpdate::Corrupted(_, n) => Some(format!("reply{} with data that wasn't what was sent", sequence(n))),
+        StatusUpdate::Error(_, error) => Some(format!("error: {}", error)),
+        StatusUpdate::IcmpError(_, report) => {
+            let name = icmp::type_name(report.icmp_type, report.to.is_ipv4()).unwrap_or("unknown error");
+            let from = report.from.map(|from| format!(" from {}", from)).unwrap_or_default();
+            Some(format!("{}{} (type {}, code {}){}", name, sequence(&report.sequence), report.icmp_type, report.code, from))
+        },
+        StatusUpdate::Malformed(_, kind) => Some(format!("malformed datagram ({})", kind.name().replace('_', " "))),
+        StatusUpdate::Retried(_) => Some("no reply yet, sending again".to_string()),
+        StatusUpdate::Resolved(_, address) => Some(format!("now resolves to {}", address.ip())),
+        StatusUpdate::Details(_, details) => Some(format_details(details)),
+        StatusUpdate::Removed(_) => Some("stopped probing".to_string()),
+        StatusUpdate::Stale(_, true) => Some("the name doesn't resolve to this address any more".to_string()),
+        StatusUpdate::Stale(_, false) => Some("the name resolves to this address again".to_string()),
+        StatusUpdate::Burst(..) => h.burst.filter(|b| b.sent > 0).map(|b| format!("burst: {} of {} answered", b.answered, b.sent)),
+        StatusUpdate::Added(_) | StatusUpdate::Paused(_) | StatusUpdate::Packet(..) | StatusUpdate::Reset | StatusUpdate::Note(..)
+            | StatusUpdate::Stopped => None,
+    }
+}
+
+/// Sends the latest statistics of every host to Zabbix every `--zabbix-interval`.
+/// If sending fails, the error is shown and it's tried again next time
+fn start_zabbix_sender(server: String, args: &Arguments, latest: Arc<Mutex<Vec<HostInfo>>>, notices: Notices) {
+    let zabbix_host = args.zabbix_host.clone()
+        .or_else(|| nix::unistd::gethostname().ok()?.into_string().ok())
+        .unwrap_or_else(|| "multiping".to_string());
+    let template = args.zabbix_key.clone();
+    let interval = args.zabbix_interval;
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let items: Vec<zabbix::Item> = latest.lock().unwrap().iter()
+            .filter(|h| !h.removed)
+            .flat_map(|h| zabbix::host_items(&zabbix_host, &template, h))
+            .collect();
+        if let Err(e) = zabbix::send(&server, &items) {
+            notices.show(format!("Failed to send statistics to Zabbix at {}: {}", server, e));
+        }
+    });
+}
+
+/// Connects to the MQTT broker on its own thread, and publishes whatever's sent to the returned channel.
+/// If the connection fails or is lost, the error is shown and it tries again a few seconds later
+fn start_mqtt_publisher(server: String, args: &Arguments, password: Option<String>, notices: Notices) -> mpsc::Sender<mqtt::Message> {
+    let hostname = nix::unistd::gethostname().ok().and_then(|h| h.into_string().ok()).unwrap_or_default();
+    let status = format!("{}/status", args.mqtt_topic);
+    let options = mqtt::MqttOptions {
+        server,
+        client_id: format!("multiping-{}-{}", hostname, std::process::id()),
+        username: args.mqtt_username.clone(),
+        password,
+        keep_alive: Duration::from_secs(60),
+        will: Some(mqtt::Message { topic: status.clone(), payload: "offline".to_string(), retain: true }),
+    };
+    let (tx, rx) = mpsc::channel::<mqtt::Message>();
+    thread::spawn(move || loop {
+        let mut client = match mqtt::MqttClient::connect(&options) {
+            Ok(client) => client,
+            Err(e) => {
+                notices.show(format!("Failed to connect to the MQTT broker at {}: {}", options.server, e));
+                thread::sleep(Duration::from_secs(5));
+                continue;
+            }
+        };
+        let online = mqtt::Message { topic: status.clone(), payload: "online".to_string(), retain: true };
+        let mut result = client.publish(&online);
+        while result.is_ok() {
+            result = match rx.recv_timeout(options.keep_alive / 4) {
+                Ok(message) => client.publish(&message),
+                Err(mpsc::RecvTimeoutError::Timeout) => client.keep_alive(),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    client.disconnect();
+                    return;
+                },
+            };
+        }
+        if let Err(e) = result {
+            notices.show(format!("Lost the connection to the MQTT broker at {}: {}", options.server, e));
+        }
+    });
+    tx
+}
+
+/// The options from the command line, overridden by anything in the config file
+fn ping_options(args: &Arguments, config: Option<&Config>) -> PingOptions {
+    let windows = config.map(|c| c.windows.clone()).filter(|w| !w.is_empty()).unwrap_or(args.window.clone());
+    let pauses = config.map(|c| c.pauses.clone()).filter(|p| !p.is_empty()).unwrap_or(args.pause.clone());
+    PingOptions {
+        interval: config.and_then(|c| c.interval).unwrap_or(args.interval),
+        retries: config.and_then(|c| c.retries).unwrap_or(args.retries),
+        schedule: Schedule { windows, pauses },
+        connected_sockets: args.connected_sockets,
+        raw_sockets: args.raw_sockets,
+        packet_log: args.verbose,
+        jitter: args.interval_jitter / 100.0,
+        burst: args.burst,
+        shards: args.shards as usize,
+        history: history_limit(args),
+        state_thresholds: state_thresholds(args),
+    }
+}
+
+/// When hosts change state
+fn state_thresholds(args: &Arguments) -> StateThresholds {
+    StateThresholds {
+        down_after: args.down_after,
+        up_after: args.up_after,
+        degraded_latency: args.degraded_latency,
+        degraded_loss: args.degraded_loss,
+    }
+}
+
+/// How much latency history each host keeps
+fn history_limit(args: &Arguments) -> HistoryLimit {
+    HistoryLimit { max_samples: args.history_samples, max_age: args.history_age }
+}
+
+/// The SLA thresholds from the command line, overridden by anything in the config file
+fn sla_thresholds(args: &Arguments, config: Option<&Config>) -> Sla {
+    let from_config = config.map(|c| c.sla).unwrap_or_default();
+    Sla {
+        max_average_ms: from_config.max_average_ms.or(args.sla_average),
+        max_loss_percent: from_config.max_loss_percent.or(args.sla_loss),
+        min_availability_percent: from_config.min_availability_percent.or(args.sla_availability),
+    }
+}
+
+/// The latency thresholds from the command line. Any warning (or critical) thresholds in the config file
+/// replace the command line's
+fn latency_colours(args: &Arguments, config: Option<&Config>) -> LatencyColours {
+    let from_config = config.map(|c| c.latency_colours.clone()).unwrap_or_default();
+    LatencyColours {
+        warning: Some(from_config.warning).filter(|w| !w.is_empty()).unwrap_or(args.latency_warning.clone()),
+        critical: Some(from_config.critical).filter(|c| !c.is_empty()).unwrap_or(args.latency_critical.clone()),
+    }
+}
+
+/// Reloads the config file, adding and removing hosts and changing settings to match it.
+/// Hosts that are still in the file carry on with the statistics they already have
+fn reload_config(path: &Path, args: &Arguments, pinger: &Pinger, config_hosts: &mut Vec<(String, Vec<usize>)>,
+        sla: &Mutex<Sla>, colours: &Mutex<LatencyColours>) -> Result<(), Error> {
+    let config = Config::load(path)?;
+    config_hosts.retain(|(host, indices)| {
+        let keep = config.hosts.contains(host);
+        if !keep {
+            for i in indices {
+                pinger.remove_host(*i);
+            }
+        }
+        keep
+    });
+    for host in &config.hosts {
+        if config_hosts.iter().any(|(h, _)| h == host) {
+            continue;
+        }
+        // Hosts that can't be resolved are skipped, as the table is already on the screen
+        if let Ok(group) = HostInfo::new_group(host, HostOptions { ip_version: args.ip_version }) {
+            let indices = group.into_iter().map(|h| pinger.add_host(h)).collect();
+            config_hosts.push((host.clone(), indices));
+        }
+    }
+    pinger.set_notes(|h| config.note_for(h));
+    pinger.set_options(ping_options(args, Some(&config)));
+    *sla.lock().unwrap() = sla_thresholds(args, Some(&config));
+    *colours.lock().unwrap() = latency_colours(args, Some(&config));
+    Ok(())
+}
+
+/// Pings every node of the cluster at its InternalIP (of `ip_version`, if it's given), listing them again every
+/// `every` to add new nodes, remove ones that have gone and follow ones whose address has changed. If they can't
+/// be listed, the hosts are left as they are until next time, and why is shown with `notices`. New nodes get their
+/// notes from `config`
+#[cfg(feature = "kube")]
+fn watch_kube_nodes(cluster: kubernetes::Cluster, pinger: Pinger, ip_version: Option<u8>, every: Duration, config: Option<Config>, notices: Notices) {
+    let address = |node: &kubernetes::ClusterNode| node.internal_ips.iter().copied()
+        .find(|ip| ip_version.is_none_or(|v| (v == 4) == ip.is_ipv4()));
+    // The index of each node that's being pinged
+    let mut known: Vec<(String, usize)> = Vec::new();
+    loop {
+        match cluster.nodes() {
+            Ok(nodes) => {
+                known.retain(|(name, i)| {
+                    let keep = nodes.iter().any(|n| n.name == *name && address(n).is_some());
+                    if !keep {
+                        pinger.remove_host(*i);
+                    }
+                    keep
+                });
+                for node in &nodes {
+                    let Some(ip) = address(node) else { continue };
+                    match known.iter().find(|(name, _)| *name == node.name) {
+                        Some((_, i)) => pinger.set_address(*i, SocketAddr::new(ip, 0)),
+                        None => {
+                            let hinfo = HostInfo::from_address(&node.name, ip);
+                            let hinfo = HostInfo { note: config.as_ref().and_then(|c| c.note_for(&hinfo)), ..hinfo };
+                            known.push((node.name.clone(), pinger.add_host(hinfo)));
+                        },
+                    }
+                }
+            },
+            Err(e) => notices.show(format!("Failed to list the Kubernetes nodes: {}", e)),
+        }
+        thread::sleep(every);
+    }
+}
+
+/// Looks up an SRV record every `every`, starting to probe the targets that have been added to it, stopping probing
+/// the ones that have been removed, and following the addresses of the rest. The record set is left as it is if the
+/// lookup fails (and why is shown with `notices`). Targets still in the record whose names can't be resolved any more
+/// keep their rows, with the error shown, and new ones that can't be resolved yet are tried again next time
+fn watch_srv_record(host: &str, mut known: Vec<(String, usize)>, pinger: Pinger, ip_version: Option<u8>, every: Duration, notices: Notices) {
+    let Some(name) = srv::record_name(host) else { return };
+    loop {
+        thread::sleep(every);
+        let targets: Vec<String> = match srv::lookup(name) {
+            Ok(targets) => targets.iter().map(|t| format!("tcp:{}:{}", t.target, t.port)).collect(),
+            Err(e) => {
+                notices.show(format!("Failed to look up {}: {}", host, e));
+                continue;
+            },
+        };
+        known.retain(|(target, i)| {
+            let keep = targets.contains(target);
+            if !keep {
+                pinger.remove_host(*i);
+            }
+            keep
+        });
+        for target in targets {
+            let index = known.iter().find(|(t, _)| *t == target).map(|(_, i)| *i);
+            match (HostInfo::new(&target, HostOptions { ip_version }), index) {
+                (Ok(hinfo), Some(i)) => pinger.set_address(i, hinfo.host),
+                (Ok(hinfo), None) => known.push((target, pinger.add_host(HostInfo { group: Some(host.to_string()), ..hinfo }))),
+                (Err(e), Some(i)) => pinger.report_error(i, e.kind()),
+                (Err(_), None) => {},
+            }
+        }
+    }
+}
+
+/// Resolves a name with the "all:" prefix every `every`, starting to probe the addresses that have been added to its
+/// records, and marking the ones that have gone as stale (and not any more if they come back). Stale addresses are
+/// still probed, so that whatever's left there can be seen. Nothing changes if the name can't be resolved, and why is
+/// shown with `notices`
+fn watch_all_addresses(host: &str, mut known: Vec<(String, usize)>, pinger: Pinger, ip_version: Option<u8>, every: Duration, notices: Notices) {
+    loop {
+        thread::sleep(every);
+        let addresses = match HostInfo::new_group(host, HostOptions { ip_version }) {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                notices.show(format!("Failed to resolve {}: {}", host, e));
+                continue;
+            },
+        };
+        for (address, i) in &known {
+            pinger.set_stale(*i, !addresses.iter().any(|a| a.host_str == *address));
+        }
+        for address in addresses {
+            if !known.iter().any(|(a, _)| *a == address.host_str) {
+                known.push((address.host_str.clone(), pinger.add_host(address)));
+            }
+        }
+    }
+}
+
+/// An imported host, and the inventory group it's in
+type ImportedHost = (String, Option<String>);
+
+/// The hosts from --from-hosts-file, --from-ssh-config and --inventory that match --import-pattern.
+/// Exits if a file can't be read, or nothing in one matches
+fn import_hosts(args: &Arguments) -> Vec<ImportedHost> {
+    let ungrouped = |hosts: Vec<String>| hosts.into_iter().map(|h| (h, None)).collect();
+    let mut files: Vec<(PathBuf, Result<Vec<ImportedHost>, Error>)> = Vec::new();
+    if let Some(path) = &args.from_hosts_file {
+        files.push((path.clone(), fs::read_to_string(path).map(|text| ungrouped(import::hosts_file(&text)))));
+    }
+    if let Some(path) = &args.from_ssh_config {
+        let path = path.clone().unwrap_or_else(|| PathBuf::from(std::env::var_os("HOME").unwrap_or_default()).join(".ssh/config"));
+        files.push((path.clone(), import::ssh_config(&path).map(ungrouped)));
+    }
+    if let Some(path) = &args.inventory {
+        let hosts = Inventory::load(path).and_then(|inventory| inventory.hosts(&args.inventory_group));
+        files.push((path.clone(), hosts.map(|hosts| hosts.into_iter().map(|(h, group)| (h, Some(group))).collect())));
+    }
+    let mut hosts: Vec<ImportedHost> = Vec::new();
+    for (path, result) in files {
+        match result.map(|found| found.into_iter().filter(|(h, _)| import::is_selected(h, &args.import_pattern)).collect::<Vec<_>>()) {
+            Ok(found) if found.is_empty() => {
+                eprintln!("No hosts to import from {}", path.display());
+                exit(EXIT_ERROR);
+            },
+            // Hosts that are already being pinged aren't pinged again
+            Ok(found) => for (host, group) in found {
+                if !args.hosts.contains(&host) && !hosts.iter().any(|(h, _)| *h == host) {
+                    hosts.push((host, group));
+                }
+            },
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", path.display(), e);
+                exit(EXIT_ERROR);
+            },
+        }
+    }
+    hosts
+}
+
+/// Parse the provided hosts into HostInfos, looking several up at the same time. Each host gives a group of HostInfos,
+/// as it can have several probe types. Hosts that can't be resolved are reported and given an empty group, unless
+/// `all_needed` is set (e.g. for subcommands that need every host they're given), in which case multiping exits.
+/// It also exits if none of them can be resolved
+fn resolve_hosts(hosts: &[String], ip_version: Option<u8>, all_needed: bool) -> Vec<Vec<HostInfo>> {
+    let term = Term::stdout();
+    let results = HostInfo::new_groups(hosts, HostOptions { ip_version }, |done| {
+        // Progress is only shown on a terminal, so it doesn't clutter up logs
+        if term.is_term() {
+            let _ = term.clear_line();
+            print!("Resolving hosts ({}/{}).\r", done, hosts.len());
+            let _ = stdout().flush();
+        }
+    });
+    if term.is_term() {
+        let _ = term.clear_line();
+    }
+    
+    let mut failed = 0;
+    let hinfos: Vec<Vec<HostInfo>> = hosts.iter().zip(results).map(|(h, result)| result.unwrap_or_else(|e| {
+        eprintln!("Failed to parse/resolve {}: {}", h, e);
+        failed += 1;
+        Vec::new()
+    })).collect();
+    
+    if failed > 0 && (all_needed || failed == hosts.len()) {
+        exit(EXIT_ERROR);
+    }
+    if term.is_term() {
+        if failed > 0 {
+            println!("{} of {} hosts resolved", hosts.len() - failed, hosts.len());
+        } else {
+            println!("All hosts resolved");
+        }
+    }
+    hinfos
+}
+
+/// Reads hosts from stdin, one per line, and starts pinging each one as it arrives.
+/// Keeps going until stdin is closed, so hosts can be added while multiping is running
+fn read_stdin_hosts(pinger: Pinger, mut known: Vec<String>, ip_version: Option<u8>) {
+    for line in stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        let host = line.trim();
+        if host.is_empty() || host.starts_with('#') || known.iter().any(|k| k == host) {
+            continue;
+        }
+        // Hosts that can't be resolved are skipped, as the table is already on the screen
+        if let Ok(group) = HostInfo::new_group(host, HostOptions { ip_version }) {
+            known.push(host.to_string());
+            for hinfo in group {
+                pinger.add_host(hinfo);
+            }
+        }
+    }
+}
+
+/// Pings both hosts `count` times, then prints a report on which one is faster
+fn compare(hinfos: Vec<HostInfo>, count: u32, alpha: f64, options: PingOptions) {
+    let grace = options.interval.max(Duration::from_secs(1));
+    let hinfos = match ping_count(hinfos, count, options, grace) {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("Failed to start pinging: {}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    
+    let (a, b) = (&hinfos[0], &hinfos[1]);
+    let host_spaces = max(12, max(a.host_str.len(), b.host_str.len()));
+    println!("{:<host_spaces$}   {:>7}   {:>9}   {:>9}", "Host", "Replies", "Median", "Mean");
+    for h in [a, b] {
+        let replies = format!("{}/{}", h.successful, h.pings_sent);
+        println!("{:<host_spaces$}   {:>7}   {:>9}   {:>9}", h.host_str, replies,
+            format_ms(median(&h.samples_vec())), format_ms(mean(&h.samples_vec())));
+    }
+    
+    match mann_whitney_u(&a.samples_vec(), &b.samples_vec()) {
+        Some(result) => {
+            println!("Mann-Whitney U = {:.1}, z = {:.2}, p = {:.4}", result.u, result.z, result.p_value);
+            let (faster, slower) = if result.z < 0.0 { (a, b) } else { (b, a) };
+            if result.p_value < alpha {
+                println!("{} is faster than {} (significant at α = {})", faster.host_str, slower.host_str, alpha);
+            } else {
+                println!("No significant difference between {} and {} at α = {}", a.host_str, b.host_str, alpha);
+            }
+        },
+        None => println!("Not enough replies to compare {} and {}", a.host_str, b.host_str),
+    }
+}
+
+/// Pings every host `count` times, then waits up to `grace` for the last replies
+fn ping_count(mut hinfos: Vec<HostInfo>, count: u32, options: PingOptions, grace: Duration) -> Result<Vec<HostInfo>, Error> {
+    let (tx, rx) = mpsc::channel::<StatusUpdate>();
+    start_pinging(&hinfos, options, tx)?;
+    
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let timeout = match deadline {
+            Some(d) => d.saturating_duration_since(Instant::now()),
+            None => Duration::from_secs(60),
+        };
+        let update = match rx.recv_timeout(timeout) {
+            Ok(u) => u,
+            Err(_) => break,
+        };
+        let wanted = match update {
+            StatusUpdate::Sent(i) => hinfos[i].pings_sent < count,
+            StatusUpdate::Received(i, _, _) => hinfos[i].successful < count,
+            _ => true,
+        };
+        if wanted {
+            update_host_info(&update, &mut hinfos);
+        }
+        
+        if deadline.is_none() && hinfos.iter().all(|h| h.pings_sent >= count) {
+            deadline = Some(Instant::now() + grace);
+        }
+        if hinfos.iter().all(|h| h.successful >= count) {
+            break;
+        }
+    }
+    Ok(hinfos)
+}
+
+/// The hosts from the command line, followed by the ones in the config file
+fn hosts_and_config_hosts<'a>(args: &'a Arguments, config: Option<&'a Config>) -> Vec<&'a String> {
+    args.hosts.iter().chain(config.into_iter().flat_map(|c| &c.hosts)).collect()
+}
+
+/// Nagios/Icinga plugin mode: pings every host (from the command line and the config file) `args.count` times,
+/// prints a status line with perfdata, and exits with the plugin exit code of the worst host
+fn check(args: &Arguments, config: Option<&Config>) -> ! {
+    let unknown = |message: String| -> ! {
+        println!("PING {} - {}", nagios::Status::Unknown, message);
+        exit(nagios::Status::Unknown.exit_code());
+    };
+    let hosts = hosts_and_config_hosts(args, config);
+    if hosts.is_empty() {
+        unknown("no hosts given".to_string());
+    }
+    let mut hinfos = Vec::new();
+    for host in hosts {
+        match HostInfo::new_group(host, HostOptions { ip_version: args.ip_version }) {
+            Ok(group) => hinfos.extend(group),
+            Err(e) => unknown(format!("failed to resolve {}: {}", host, e)),
+        }
+    }
+    // Once every ping has been sent, wait a little while for the stragglers
+    let options = ping_options(args, config);
+    let grace = options.interval.max(Duration::from_secs(1));
+    let hinfos = match ping_count(hinfos, args.count, options, grace) {
+        Ok(h) => h,
+        Err(e) => unknown(format!("failed to start pinging: {}", e)),
+    };
+    let (status, output) = nagios::report(&hinfos, args.warning, args.critical);
+    println!("{}", output);
+    exit(status.exit_code());
+}
+
+/// Pings every host `count` times, prints whether each one stayed within the thresholds as JSON, and exits with
+/// 0 if they all did, or 1 if not
+fn assert_thresholds(hinfos: Vec<HostInfo>, count: u32, max_p95: Option<f64>, max_loss: Option<f64>, options: PingOptions) -> ! {
+    let grace = options.interval.max(Duration::from_secs(1));
+    let mut hinfos = match ping_count(hinfos, count, options, grace) {
+        Ok(h) => h,
+        Err(e) => {
+            eprintln!("Failed to start pinging: {}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    
+    let mut passed = true;
+    let mut results = Vec::new();
+    for h in &mut hinfos {
+        // Requests that still haven't been answered after the grace period are lost
+        h.sequence_loss.finish();
+        let loss = h.loss_percent().unwrap_or(0.0);
+        let p95 = percentile(&h.samples_vec(), 95.0).map(|p| p as f64 / 1000.0);
+        let mut failures = Vec::new();
+        let mut failure = |check: &str, value: f64, limit: f64, reason: String| failures.push(JsonObject::new()
+            .string("check", check)
+            .number("value", value)
+            .number("limit", limit)
+            .string("reason", &reason)
+            .finish());
+        if let Some(limit) = max_p95 {
+            match p95 {
+                Some(p95) if p95 > limit => failure("p95", p95, limit, format!("95th percentile round trip time of {:.3} ms is over {} ms", p95, limit)),
+                Some(_) => {},
+                None => failure("p95", f64::NAN, limit, "no replies".to_string()),
+            }
+        }
+        if let Some(limit) = max_loss && loss > limit {
+            failure("loss", loss, limit, format!("packet loss of {:.1}% is over {}%", loss, limit));
+        }
+        passed &= failures.is_empty();
+        results.push(JsonObject::new()
+            .string("host", &h.host_str)
+            .string("address", &h.host.ip().to_string())
+            .string("probe", h.probe.name())
+            .integer("sent", h.pings_sent as i64)
+            .integer("received", h.successful as i64)
+            .number("loss_percent", loss)
+            .number("p95_ms", p95.unwrap_or(f64::NAN))
+            .boolean("passed", failures.is_empty())
+            .raw("failures", &format!("[{}]", failures.join(",")))
+            .finish());
+    }
+    
+    println!("{}", JsonObject::new()
+        .boolean("passed", passed)
+        .integer("count", count as i64)
+        .number("max_p95_ms", max_p95.unwrap_or(f64::NAN))
+        .number("max_loss_percent", max_loss.unwrap_or(f64::NAN))
+        .raw("hosts", &format!("[{}]", results.join(",")))
+        .finish());
+    exit(if passed { EXIT_OK } else { EXIT_UNANSWERED });
+}
+
+/// Single round mode: pings every host once, waits up to `args.timeout` for the replies, prints which hosts
+/// replied and exits with 0 if all of them (or with `args.any`, any of them) did
+fn once(args: &Arguments, config: Option<&Config>) -> ! {
+    let hosts = hosts_and_config_hosts(args, config);
+    if hosts.is_empty() {
+        eprintln!("You need to specify hosts on the command line.\nExample: multiping --once 127.0.0.1");
+        exit(EXIT_ERROR);
+    }
+    let mut hinfos = Vec::new();
+    // Hosts that can't be resolved count as not having replied
+    let mut failed = Vec::new();
+    for host in hosts {
+        match HostInfo::new_group(host, HostOptions { ip_version: args.ip_version }) {
+            Ok(group) => hinfos.extend(group),
+            Err(e) => failed.push(format!("{} ({})", host, e)),
+        }
+    }
+    if !hinfos.is_empty() {
+        hinfos = match ping_count(hinfos, 1, ping_options(args, config), args.timeout) {
+            Ok(h) => h,
+            Err(e) => {
+                eprintln!("Failed to start pinging: {}", e);
+                exit(EXIT_ERROR);
+            },
+        };
+    }
+    
+    let replied: Vec<String> = hinfos.iter().filter(|h| h.successful > 0).map(|h| match h.latest_time {
+        Some(t) => format!("{} {:.2} ms", h.host_str, t as f32 / 1000.0),
+        None => h.host_str.clone(),
+    }).collect();
+    failed.extend(hinfos.iter().filter(|h| h.successful == 0).map(|h| h.host_str.clone()));
+    let total = replied.len() + failed.len();
+    let mut line = format!("{}/{} hosts replied", replied.len(), total);
+    if !replied.is_empty() {
+        line += &format!(": {}", replied.join(", "));
+    }
+    if !failed.is_empty() {
+        line += &format!("; no reply from {}", failed.join(", "));
+    }
+    println!("{}", line);
+    let ok = if args.any { !replied.is_empty() } else { failed.is_empty() };
+    exit(if ok { EXIT_OK } else { EXIT_UNANSWERED });
+}
+
+/// The system allocator, counting how many allocations are made for `multiping bench`. Only with the
+/// count-allocations feature, as every allocation pays for the count
+#[cfg(feature = "count-allocations")]
+struct CountingAllocator;
+
+#[cfg(feature = "count-allocations")]
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Implementing GlobalAlloc can't be done without unsafe code, and this only passes each call on to System
+#[cfg(feature = "count-allocations")]
+#[allow(unsafe_code)]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+    
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc_zeroed(layout) }
+    }
+    
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+    
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+/// A probe that answers every request straight away, for benchmarking everything apart from the network
+struct MockProbe;
+
+impl Probe for MockProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+    
+    fn match_reply(&mut self, _timeout: Duration) -> Result<ProbeReply, Error> {
+        Ok(ProbeReply::new(1))
+    }
+    
+    fn describe(&self) -> String {
+        "mock probe".to_string()
+    }
+}
+
+/// CPU time used by this process so far, in user and kernel mode
+fn cpu_time() -> Duration {
+    getrusage(UsageWho::RUSAGE_SELF).map_or(Duration::ZERO, |usage| {
+        let micros = usage.user_time().num_microseconds() + usage.system_time().num_microseconds();
+        Duration::from_micros(micros.max(0) as u64)
+    })
+}
+
+/// Pings `count` addresses on this machine back to back for `duration`, then prints the throughput
+/// and how much CPU time and (with the count-allocations feature) how many allocations each request took
+fn bench(args: &Arguments, count: u32, duration: Duration, mock: bool) {
+    let (tx, rx) = mpsc::channel::<StatusUpdate>();
+    // Back to back: each round is already late by the time the last one finishes
+    let options = PingOptions { interval: Duration::from_nanos(1), retries: 0, burst: 1, ..ping_options(args, None) };
+    let first = u32::from(std::net::Ipv4Addr::LOCALHOST);
+    let addresses: Vec<HostInfo> = (0..count).map(|i| {
+        let address = std::net::Ipv4Addr::from(first + i);
+        HostInfo::from_address(&address.to_string(), address.into())
+    }).collect();
+    
+    let start = Instant::now();
+    let cpu_before = cpu_time();
+    #[cfg(feature = "count-allocations")]
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let (mut hinfos, pinger) = if mock {
+        (Vec::new(), start_pinging(&[], options, tx))
+    } else {
+        (addresses.clone(), start_pinging(&addresses, options, tx))
+    };
+    let pinger = match pinger {
+        Ok(pinger) => pinger,
+        Err(e) => {
+            eprintln!("Failed to start pinging: {}", e);
+            exit(EXIT_ERROR);
+        }
+    };
+    if mock {
+        for h in addresses {
+            pinger.add_probe(h, Box::new(MockProbe));
+        }
+    }
+    
+    let (mut sent, mut received, mut errors) = (0u64, 0u64, 0u64);
+    while let Some(remaining) = duration.checked_sub(start.elapsed()) {
+        let Ok(update) = rx.recv_timeout(remaining) else { continue };
+        match update {
+            StatusUpdate::Sent(_) => sent += 1,
+            StatusUpdate::Received(..) => received += 1,
+            StatusUpdate::Error(..) => errors += 1,
+            _ => {},
+        }
+        update_host_info(&update, &mut hinfos);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let cpu = cpu_time().saturating_sub(cpu_before);
+    #[cfg(feature = "count-allocations")]
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    // Measured first, as stopping waits for the threads
+    pinger.shutdown();
+    
+    let per = |n: f64| if sent > 0 { n / sent as f64 } else { 0.0 };
+    println!("Pinged {} {} for {:.1} s{}", count, if count == 1 { "address" } else { "addresses" }, elapsed,
+        if mock { " with mock probes" } else { "" });
+    println!("Requests sent:     {:>10} ({:.0}/s)", sent, sent as f64 / elapsed);
+    println!("Replies received:  {:>10} ({:.0}/s, {:.1}% lost)", received, received as f64 / elapsed,
+        100.0 - per(received as f64) * 100.0);
+    if errors > 0 {
+        println!("Errors:            {:>10}", errors);
+    }
+    println!("CPU time:          {:>10.2} s ({:.1} µs per request)", cpu.as_secs_f64(), per(cpu.as_micros() as f64));
+    #[cfg(feature = "count-allocations")]
+    println!("Allocations:       {:>10} ({:.1} per request)", allocations, per(allocations as f64));
+}
+
+/// Pings each host `count` times with each payload size, printing the statistics for every size as it finishes.
+/// Each host has its own connected socket, so the hosts can be pinged at the same time
+fn sweep(hinfos: Vec<HostInfo>, sizes: &[usize], count: u32, interval: Duration) {
+    let mut probes = Vec::new();
+    for h in &hinfos {
+        let probe = match h.probe {
+            ProbeType::Icmp => probe::ConnectedIcmpProbe::new(h.clone()),
+            _ => Err(Error::new(std::io::ErrorKind::Unsupported, "only ICMP can be sent with different sizes")),
+        };
+        match probe {
+            Ok(p) => probes.push(p),
+            Err(e) => {
+                eprintln!("Can't sweep {}: {}", h.host_str, e);
+                exit(EXIT_ERROR);
+            }
+        }
+    }
+    
+    let host_spaces = max(12, hinfos.iter().map(|h| h.host_str.len()).max().unwrap_or(0));
+    println!("{:<host_spaces$}   {:>5}   {:>7}   {:>5}   {:>9}", "Host", "Size", "Replies", "Loss", "Average");
+    for &size in sizes {
+        let results: Vec<(Vec<u64>, Option<Error>)> = thread::scope(|scope| {
+            let threads: Vec<_> = probes.iter_mut().map(|probe| scope.spawn(move || {
+                probe.set_payload_size(size);
+                let mut samples = Vec::new();
+                let mut error = None;
+                for _ in 0..count {
+                    let start = Instant::now();
+                    match probe.send().and_then(|_| probe.match_reply(interval)) {
+                        Ok(reply) => samples.push(reply.latency),
+                        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {},
+                        Err(e) => error = Some(e),
+                    }
+                    thread::sleep(interval.saturating_sub(start.elapsed()));
+                }
+                (samples, error)
+            })).collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        
+        for (h, (samples, error)) in hinfos.iter().zip(results) {
+            let replies = format!("{}/{}", samples.len(), count);
+            let loss = format!("{:.0}%", (count as usize - samples.len()) as f64 * 100.0 / count as f64);
+            let note = error.map(|e| format!("   {}", e)).unwrap_or_default();
+            println!("{:<host_spaces$}   {:>5}   {:>7}   {:>5}   {:>9}{}", h.host_str, size, replies, loss, format_ms(mean(&samples)), note);
+        }
+    }
+}
+
+/// Prints the path MTU to each host, and each size that was tried on the way
+#[cfg(target_os = "linux")]
+fn path_mtu(hinfos: Vec<HostInfo>, attempts: u32, timeout: Duration) {
+    let mut failed = false;
+    for h in &hinfos {
+        if h.probe != ProbeType::Icmp {
+            eprintln!("Can't find the path MTU to {}: only ICMP can be sent with different sizes", h.host_str);
+            exit(EXIT_ERROR);
+        }
+        let result = mtu::discover(h, timeout, attempts.max(1), |size, outcome| {
+            let what = match outcome {
+                mtu::Outcome::Fits => "reply".to_string(),
+                mtu::Outcome::TooBig(Some(mtu)) => format!("too big (path MTU {})", mtu),
+                mtu::Outcome::TooBig(None) => "too big".to_string(),
+                mtu::Outcome::Lost => "no reply".to_string(),
+            };
+            println!("{} with {} bytes: {}", h.host.ip(), size, what);
+        });
+        match result {
+            Ok(found) => println!("Path MTU to {} ({}): {} bytes (largest payload {} bytes)", h.host_str, h.host.ip(), found.mtu, found.payload),
+            Err(e) => {
+                eprintln!("Couldn't find the path MTU to {}: {}", h.host_str, e);
+                failed = true;
+            },
+        }
+    }
+    if failed {
+        exit(EXIT_ERROR);
+    }
+}
+
+/// The default gateways of the IP version asked for, if --with-gateway is given. Exits if there aren't any
+fn default_gateways(args: &Arguments) -> Vec<HostInfo> {
+    if !args.with_gateway {
+        return Vec::new();
+    }
+    #[cfg(target_os = "linux")]
+    let gateways = multiping::gateway::default_gateways().map(|gateways| gateways.into_iter()
+        .filter(|g| args.ip_version.is_none_or(|v| (v == 4) == g.address.is_ipv4()))
+        .map(|g| g.host_info())
+        .collect::<Vec<HostInfo>>());
+    #[cfg(not(target_os = "linux"))]
+    let gateways: Result<Vec<HostInfo>, Error> = Err(std::io::ErrorKind::Unsupported.into());
+    match gateways {
+        Ok(gateways) if !gateways.is_empty() => gateways,
+        Ok(_) => {
+            eprintln!("There's no default gateway for IPv{}", args.ip_version.unwrap_or(4));
+            exit(EXIT_ERROR);
+        },
+        Err(e) => {
+            eprintln!("Failed to find the default gateway: {}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// This machine's addresses, for probing from each of them. Exits if they can't be listed
+fn local_addresses_or_exit() -> Vec<sources::LocalAddress> {
+    match sources::local_addresses() {
+        Ok(local) => local,
+        Err(e) => {
+            eprintln!("Failed to list this machine's addresses: {}", e);
+            exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// Prints an ICMP message read as hex from stdin, field by field
+fn decode(ipv6: bool) {
+    let mut input = String::new();
+    if let Err(e) = stdin().read_to_string(&mut input) {
+        eprintln!("Couldn't read the message: {}", e);
+        exit(EXIT_ERROR);
+    }
+    let Some(bytes) = parse_hex(&input) else {
+        eprintln!("The message has to be hex bytes, e.g. \"08 00 f7 fc 00 01 00 02\"");
+        exit(EXIT_ERROR);
+    };
+    // IPv4 headers say how long they are, and IPv6 ones are followed by any extension headers
+    let (message, ipv6) = match bytes.first().map(|b| b >> 4) {
+        Some(4) if let Ok((_, payload)) = Ipv4Header::split(&bytes) => (payload, false),
+        Some(6) if let Ok((header, payload)) = Ipv6Header::split(&bytes) && header.protocol == 58 => (payload, true),
+        _ => (&bytes[..], ipv6),
+    };
+    let family = if ipv6 { icmp::Family::V6 } else { icmp::Family::V4 };
+    let decoded = icmp::ICMPMessage::try_from((message, family)).map(|m| m.dump());
+    match decoded {
+        Ok(dump) => print!("{}", dump),
+        Err(e) => {
+            eprintln!("Couldn't decode the {} message: {}", if ipv6 { "ICMPv6" } else { "ICMP" }, e);
+            eprint!("{}", icmp::hex_dump(message));
+            exit(EXIT_ERROR);
+        },
+    }
+}
+
+/// A number of milliseconds more than 0, as a Duration (so that it can't be too big for one)
+fn parse_milliseconds(s: &str) -> Result<Duration, String> {
+    s.trim().parse::<f64>().ok()
+        .and_then(|ms| Duration::try_from_secs_f64(ms / 1000.0).ok())
+        .filter(|d| !d.is_zero())
+        .ok_or_else(|| format!("{:?} should be a number of milliseconds more than 0", s))
+}
+
+/// Bytes written in hex, which can be separated by spaces, colons or commas, and can start with "0x"
+fn parse_hex(input: &str) -> Option<Vec<u8>> {
+    let digits: String = input.split(|c: char| c.is_whitespace() || c == ':' || c == ',')
+        .map(|word| word.strip_prefix("0x").unwrap_or(word))
+        .collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok()).collect()
+}
+
+fn mean(samples: &[u64]) -> Option<f64> {
+    if samples.is_empty() {
+        None
+    } else {
+        Some(samples.iter().sum::<u64>() as f64 / samples.len() as f64)
+    }
+}
+
+/// Formats a time in microseconds as milliseconds
+fn format_ms(micros: Option<f64>) -> String {
+    match micros {
+        Some(m) => format!("{:.2} ms", m / 1000.0),
+        None => "-".to_string(),
+    }
+}
+
+/// Shows the table until probing stops (or a host goes over the --fail-fast threshold), then returns the exit code
+fn display_loop(rx: Receiver<StatusUpdate>, mut hinfos: Vec<HostInfo>, args: Arguments, sla: Arc<Mutex<Sla>>,
+        colours: Arc<Mutex<LatencyColours>>, pinger: Pinger, snapshots: Snapshots) -> Result<i32, Error> {
+    let mut term = Term::buffered_stdout();
+    let mut table = Table {
+        colour: console::colors_enabled() && args.colour.unwrap_or(true),
+        columns: Columns {
+            address: args.show_address,
+            notes: args.notes.then_some(0),
+            retries: pinger.options().retries > 0,
+            averages: args.averages,
+            trend: args.trend,
+            health: args.health.then(|| args.health_weights.unwrap_or_default()),
+            last_reply: args.last_reply,
+            icmp_errors: args.icmp_errors,
+            bursts: args.burst > 1,
+        },
+        latency_colours: LatencyColours::default(),
+        highlight_worst: args.highlight_worst,
+        pin_worst: args.pin_worst,
+        summary: args.summary,
+        diagnosis: None,
+        incident: None,
+        notice: None,
+    };
+
+    // Key presses are ignored if there's no terminal to read them from
+    let keyboard = read_keys(pinger.clone()).ok();
+    // Puts the terminal back however this returns, or as soon as anything panics
+    let guard = DisplayGuard::new(keyboard);
+    term.hide_cursor()?;
+    // Enable the alternate screen buffer
+    term.write_all(b"\x1b[?1049h")?;
+    
+    let restore = guard.restore.clone();
+    handle_exit_signals(&args, pinger.clone(), move || {
+        let _ = restore.restore();
+    })?;
+    
+    // Listen for updates
+    let mut failed = None;
+    let mut packets: VecDeque<String> = VecDeque::new();
+    let mut reset_at = None;
+    // The local checks take a while, so they're run on another thread, at most every DIAGNOSE_EVERY while every host is failing
+    let diagnosis: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let mut diagnosed_at: Option<Instant> = None;
+    // Lining up the failures goes through every sample, so it's only done once an interval, rather than every update
+    let mut correlated_at: Option<Instant> = None;
+    for update in rx {
+        match update {
+            StatusUpdate::Stopped => break,
+            StatusUpdate::Reset => reset_at = Some(SystemTime::now()),
+            _ => {},
+        }
+        if let StatusUpdate::Packet(i, event) = &update {
+            if packets.len() == PACKET_LOG_LINES {
+                packets.pop_front();
+            }
+            packets.push_back(format_packet(*i, event, &hinfos, args.timestamps));
+        }
+        update_host_info(&update, &mut hinfos);
+        snapshots.save_if_requested(&hinfos);
+        table.latency_colours = colours.lock().unwrap().clone();
+        // Reloading the config file can turn retries on or off
+        table.columns.retries = pinger.options().retries > 0;
+        if !all_failing(&hinfos) {
+            diagnosed_at = None;
+            *diagnosis.lock().unwrap() = None;
+        } else if diagnosed_at.is_none_or(|t| t.elapsed() >= DIAGNOSE_EVERY) {
+            diagnosed_at = Some(Instant::now());
+            let diagnosis = diagnosis.clone();
+            thread::spawn(move || {
+                let summary = diagnose::summary(&diagnose::diagnose());
+                *diagnosis.lock().unwrap() = Some(summary);
+            });
+        }
+        table.diagnosis = diagnosis.lock().unwrap().clone();
+        table.notice = snapshots.notices.latest();
+        if !hinfos.iter().any(|h| !h.removed && h.is_down()) {
+            // Nothing's going on, which is cheap to tell
+            table.incident = None;
+            correlated_at = None;
+        } else if correlated_at.is_none_or(|t| t.elapsed() >= args.interval) {
+            correlated_at = Some(Instant::now());
+            table.incident = correlation::ongoing(&correlation::incidents(&hinfos, args.interval)).cloned();
+        }
+        update_display(&term, &hinfos, &table, reset_at, args.verbose.then_some(&packets))?;
+        failed = args.fail_fast.and_then(|threshold| over_loss_threshold(&hinfos, threshold));
+        if failed.is_some() {
+            break;
+        }
+    }
+    
+    term.flush()?;
+    guard.restore.restore()?;
+    Ok(finish(&hinfos, &sla, true, args.histogram, args.interval, failed))
+}
+
+/// Whether every host (that hasn't been removed) is down or getting errors
+fn all_failing(hinfos: &[HostInfo]) -> bool {
+    let mut hosts = hinfos.iter().filter(|h| !h.removed).peekable();
+    hosts.peek().is_some() && hosts.all(|h| h.is_down() || h.last_error.is_some())
+}
+
+/// Prints the reports (if `report` is set, with the histograms if `histogram` is too), and works out the exit code.
+/// `failed` is the message about the host that went over the --fail-fast threshold, if one did
+fn finish(hinfos: &[HostInfo], sla: &Mutex<Sla>, report: bool, histogram: bool, interval: Duration, failed: Option<String>) -> i32 {
+    if report {
+        print_reports(hinfos, &sla.lock().unwrap(), histogram, interval);
+    }
+    match failed {
+        Some(message) => {
+            eprintln!("{}", message);
+            EXIT_UNANSWERED
+        },
+        None => exit_code(hinfos),
+    }
+}
+
+fn exit_code(hinfos: &[HostInfo]) -> i32 {
+    if hinfos.iter().any(|h| !h.removed && h.successful == 0) {
+        EXIT_UNANSWERED
+    } else {
+        EXIT_OK
+    }
+}
+
+/// Describes the first host with packet loss over `threshold` percent, if there is one
+fn over_loss_threshold(hinfos: &[HostInfo], threshold: f64) -> Option<String> {
+    hinfos.iter().filter(|h| !h.removed).find_map(|h| {
+        let loss = h.loss_percent()?;
+        (loss > threshold).then(|| format!("{} has {:.0} % packet loss, which is over the --fail-fast threshold of {} %", h.host_str, loss, threshold))
+    })
+}
+
+/// Shuts the pinger down on Ctrl-C or when asked to terminate (or on hangup, unless it's being used to reload the
+/// config file), so that the output loop gets the last updates and finishes as it would on its own. If it's taking
+/// too long, a second signal runs `cleanup` and exits straight away, without the reports
+fn handle_exit_signals<F>(args: &Arguments, pinger: Pinger, mut cleanup: F) -> Result<(), Error>
+where F: FnMut() + Send + 'static {
+    let mut exit_signals = vec![SIGINT, SIGTERM];
+    if args.config.is_none() {
+        exit_signals.push(SIGHUP);
+    }
+    let mut signals = Signals::new(exit_signals)?;
+    thread::spawn(move || {
+        let mut signals = signals.forever();
+        if signals.next().is_some() {
+            thread::spawn(move || pinger.shutdown());
+        }
+        if signals.next().is_some() {
+            cleanup();
+            exit(EXIT_ERROR);
+        }
+    });
+    Ok(())
+}
+
+/// Logs events (hosts going down and coming back up, errors, and hosts being added or removed)
+/// instead of showing the table. With --systemd they're sent to the journal if possible
+fn log_loop(rx: Receiver<StatusUpdate>, mut hinfos: Vec<HostInfo>, args: Arguments, sla: Arc<Mutex<Sla>>, pinger: Pinger,
+        snapshots: Snapshots) -> Result<i32, Error> {
+    let journal = args.systemd && systemd::journal_available();
+    handle_exit_signals(&args, pinger, || {})?;
+    // The journal has its own timestamps, and packets already start with the time
+    let stamp = |message: &str| match args.timestamps {
+        Some(zone) => format!("{} {}", zone.format(SystemTime::now()), message),
+        None => message.to_string(),
+    };
+    
+    let mut failed = None;
+    for update in rx {
+        // Saved with the statistics from before this update, as there are a few ways through the loop
+        snapshots.save_if_requested(&hinfos);
+        let before = match update {
+            StatusUpdate::Stopped => break,
+            StatusUpdate::Packet(i, event) => {
+                let message = format_packet(i, &event, &hinfos, args.timestamps);
+                let host = i.map(|i| hinfos[i].host_str.as_str()).unwrap_or_default();
+                if !journal || systemd::journal_send(systemd::PRIORITY_DEBUG, &message, &[("MULTIPING_HOST", host), ("MULTIPING_EVENT", "packet")]).is_err() {
+                    println!("{}", message);
+                }
+                continue;
+            },
+            StatusUpdate::Reset => {
+                // The statistics are logged before they're cleared
+                for h in hinfos.iter().filter(|h| !h.removed) {
+                    let message = format_reset(h);
+                    if !journal || systemd::journal_send(systemd::PRIORITY_INFO, &message, &[("MULTIPING_HOST", &h.host_str), ("MULTIPING_EVENT", "reset")]).is_err() {
+                        println!("{}", stamp(&message));
+                    }
+                }
+                update_host_info(&update, &mut hinfos);
+                continue;
+            },
+            StatusUpdate::Added(_) | StatusUpdate::Resolved(..) => None,
+            StatusUpdate::Sent(i) | StatusUpdate::Received(i, _, _) | StatusUpdate::Corrupted(i, _) | StatusUpdate::Error(i, _)
+                | StatusUpdate::Removed(i) => {
+                Some((i, hinfos[i].is_down(), hinfos[i].last_error))
+            },
+            _ => {
+                update_host_info(&update, &mut hinfos);
+                continue;
+            },
+        };
+        update_host_info(&update, &mut hinfos);
+        failed = args.fail_fast.and_then(|threshold| over_loss_threshold(&hinfos, threshold));
+        if failed.is_some() {
+            break;
+        }
+        
+        let (host, event, priority, message) = match (update, before) {
+            (StatusUpdate::Added(h), _) => (h.host_str.clone(), "added", systemd::PRIORITY_INFO, format!("Started probing {}", h.host_str)),
+            (StatusUpdate::Removed(i), _) => (hinfos[i].host_str.clone(), "removed", systemd::PRIORITY_INFO, format!("Stopped probing {}", hinfos[i].host_str)),
+            (StatusUpdate::Resolved(i, address), _) => {
+                let previous = hinfos[i].previous_address.map(|a| a.ip().to_string()).unwrap_or_default();
+                (hinfos[i].host_str.clone(), "resolved", systemd::PRIORITY_NOTICE,
+                    format!("{} now resolves to {} (was {})", hinfos[i].host_str, address.ip(), previous))
+            },
+            (update, Some((i, was_down, last_error))) => {
+                let h = &hinfos[i];
+                if !was_down && h.is_down() {
+                    (h.host_str.clone(), "down", systemd::PRIORITY_WARNING, format!("{} is down", h.host_str))
+                } else if was_down && !h.is_down() {
+                    let duration = h.outages.last().map(|o| time::format_duration(o.duration())).unwrap_or_default();
+                    (h.host_str.clone(), "up", systemd::PRIORITY_NOTICE, format!("{} is back up after {}", h.host_str, duration))
+                } else if let Some(error) = h.last_error && last_error != Some(error) {
+                    (h.host_str.clone(), "error", systemd::PRIORITY_ERROR, format!("{}: {}", h.host_str, error))
+                } else if let StatusUpdate::Received(_, None, _) = update {
+                    (h.host_str.clone(), "clock", systemd::PRIORITY_WARNING,
+                        format!("{} replied, but the clock changed while the request was on its way, so the time isn't counted", h.host_str))
+                } else if let StatusUpdate::Corrupted(..) = update {
+                    (h.host_str.clone(), "corrupted", systemd::PRIORITY_WARNING,
+                        format!("{} replied, but the data in the reply wasn't what was sent, so it isn't counted", h.host_str))
+                } else {
+                    continue;
+                }
+            },
+            _ => continue,
+        };
+        
+        if !journal || systemd::journal_send(priority, &message, &[("MULTIPING_HOST", &host), ("MULTIPING_EVENT", event)]).is_err() {
+            println!("{}", stamp(&message));
+        }
+    }
+    
+    Ok(finish(&hinfos, &sla, true, args.histogram, args.interval, failed))
+}
+
+/// Writes a JSON object to stdout for every probe event, one per line. The reports aren't
+/// printed on exit, so that everything written to stdout is JSON
+fn ndjson_loop(rx: Receiver<StatusUpdate>, mut hinfos: Vec<HostInfo>, args: Arguments, sla: Arc<Mutex<Sla>>, pinger: Pinger,
+        snapshots: Snapshots) -> Result<i32, Error> {
+    handle_exit_signals(&args, pinger, || {})?;
+    let mut out = stdout().lock();
+    let mut events = NdjsonEvents::new(args.timestamps.unwrap_or_default(), hinfos.len());
+    let mut failed = None;
+    for update in rx {
+        if let StatusUpdate::Stopped = update {
+            break;
+        }
+        for event in events.apply(&update, &mut hinfos) {
+            writeln!(out, "{}", event.finish())?;
+        }
+        out.flush()?;
+        snapshots.save_if_requested(&hinfos);
+        
+        failed = args.fail_fast.and_then(|threshold| over_loss_threshold(&hinfos, threshold));
+        if failed.is_some() {
+            break;
+        }
+    }
+    
+    Ok(finish(&hinfos, &sla, false, false, args.interval, failed))
+}
+
+/// Turns updates into NDJSON events, keeping track of which requests are still waiting for a reply so that
+/// timeouts can be reported
+struct NdjsonEvents {
+    zone: TimeZone,
+    // Which hosts have a request that hasn't been answered (or failed) yet
+    waiting: Vec<bool>,
+}
+
+impl NdjsonEvents {
+    fn new(zone: TimeZone, hosts: usize) -> NdjsonEvents {
+        NdjsonEvents { zone, waiting: vec![false; hosts] }
+    }
+    
+    /// Applies the update to the statistics, and returns the events it makes, in order
+    fn apply(&mut self, update: &StatusUpdate, hinfos: &mut Vec<HostInfo>) -> Vec<JsonObject> {
+        let zone = self.zone;
+        let mut events = Vec::new();
+        // If a request is sent before the last one was answered, the last one timed out (unless it's in the same burst)
+        if let StatusUpdate::Sent(i) = *update && !hinfos[i].burst.is_some_and(|b| b.sending()) && std::mem::replace(&mut self.waiting[i], true) {
+            events.push(event_json(zone, "timeout", &hinfos[i]));
+        }
+        match *update {
+            StatusUpdate::Received(i, _, _) | StatusUpdate::Corrupted(i, _) | StatusUpdate::Error(i, _) | StatusUpdate::IcmpError(i, _) => self.waiting[i] = false,
+            StatusUpdate::Added(_) => self.waiting.push(false),
+            // The statistics are logged before they're cleared
+            StatusUpdate::Reset => for (i, h) in hinfos.iter().enumerate().filter(|(_, h)| !h.removed) {
+                events.push(event_json(zone, "reset", h).raw("stats", &json::host_stats(i, h).finish()));
+            },
+            _ => {},
+        }
+        // The burst before is over once the next one starts
+        let settled = if let StatusUpdate::Burst(i, _) = *update { hinfos[i].burst.filter(|b| b.sent > 0) } else { None };
+        // State changes follow the event that caused them
+        let change = update_host_info(update, hinfos);
+        
+        let event = match update {
+            StatusUpdate::Sent(i) => Some(event_json(zone, "sent", &hinfos[*i])),
+            StatusUpdate::Received(i, latency, _) => Some(event_json(zone, "received", &hinfos[*i]).number("rtt_ms", latency.map_or(f64::NAN, |l| l as f64 / 1000.0))),
+            StatusUpdate::Corrupted(i, _) => Some(event_json(zone, "corrupted", &hinfos[*i])),
+            StatusUpdate::Burst(i, _) => settled.map(|burst| event_json(zone, "burst", &hinfos[*i])
+                .integer("sent", burst.sent as i64)
+                .integer("received", burst.answered as i64)),
+            StatusUpdate::Malformed(Some(i), kind) => Some(event_json(zone, "malformed", &hinfos[*i]).string("kind", kind.name())),
+            StatusUpdate::Error(i, error) => Some(event_json(zone, "error", &hinfos[*i]).string("error", &error.to_string())),
+            StatusUpdate::Retried(i) => Some(event_json(zone, "retried", &hinfos[*i])),
+            StatusUpdate::Resolved(i, _) => Some(match hinfos[*i].previous_address {
+                Some(previous) => event_json(zone, "resolved", &hinfos[*i]).string("previous_address", &previous.ip().to_string()),
+                None => event_json(zone, "resolved", &hinfos[*i]),
+            }),
+            StatusUpdate::IcmpError(i, report) => Some(icmp_error_json(event_json(zone, "icmp_error", &hinfos[*i]), report)),
+            StatusUpdate::Added(h) => Some(event_json(zone, "added", h)),
+            StatusUpdate::Removed(i) => Some(event_json(zone, "removed", &hinfos[*i])),
+            StatusUpdate::Stale(i, stale) => Some(event_json(zone, if *stale { "stale" } else { "fresh" }, &hinfos[*i])),
+            StatusUpdate::Details(i, ProbeDetails::Ntp { offset, stratum }) => Some(event_json(zone, "details", &hinfos[*i])
+                .number("offset_ms", *offset as f64 / 1000.0)
+                .integer("stratum", *stratum as i64)),
+            StatusUpdate::Details(i, ProbeDetails::ClockOffset { offset }) => Some(event_json(zone, "details", &hinfos[*i])
+                .number("offset_ms", *offset as f64 / 1000.0)),
+            StatusUpdate::Details(i, ProbeDetails::OneWay { forward, backward }) => Some(event_json(zone, "details", &hinfos[*i])
+                .number("forward_ms", *forward as f64 / 1000.0)
+                .number("backward_ms", *backward as f64 / 1000.0)),
+            StatusUpdate::Details(i, ProbeDetails::Tls { connect, expires }) => {
+                let json = event_json(zone, "details", &hinfos[*i]).number("connect_ms", *connect as f64 / 1000.0);
+                Some(match expires {
+                    Some(expires) => json.string("certificate_expires", &time::format_rfc3339(SystemTime::UNIX_EPOCH + Duration::from_secs((*expires).max(0) as u64))),
+                    None => json,
+                })
+            },
+            StatusUpdate::Packet(i, event) => Some(packet_json(zone, i.map(|i| &hinfos[i]), event)),
+            StatusUpdate::Paused(_) | StatusUpdate::Malformed(None, _) | StatusUpdate::Reset | StatusUpdate::Note(..)
+                | StatusUpdate::Stopped => None,
+        };
+        let state = change.map(|c| event_json(zone, "state", &hinfos[c.host]).string("from", c.from.name()).string("to", c.to.name()));
+        events.extend([event, state].into_iter().flatten());
+        events
+    }
+}
+
+/// The fields that every NDJSON event has
+fn event_json(zone: TimeZone, event: &str, host: &HostInfo) -> JsonObject {
+    JsonObject::new()
+        .string("time", &zone.format(SystemTime::now()))
+        .string("event", event)
+        .string("host", &host.host_str)
+        .string("address", &host.host.ip().to_string())
+        .string("probe", host.probe.name())
+        .raw("source", &host.source.map_or("null".to_string(), |s| json::quote(&s.to_string())))
+}
+
+/// A host's statistics from before they're reset, for the log
+fn format_reset(h: &HostInfo) -> String {
+    format!("{} (resetting the statistics)", format_stats(h))
+}
+
+/// A host's statistics on one line, e.g. "example.com since ...: 10 sent, 9 received, 10 % loss, average 1.23 ms, ..."
+fn format_stats(h: &HostInfo) -> String {
+    let since = h.first_sent.map(|t| format!(" since {}", time::format_utc(t))).unwrap_or_default();
+    let loss = h.loss_percent().unwrap_or(0.0);
+    let average = (h.successful > 0).then(|| h.sum_times as f64 / h.successful as f64);
+    format!("{}{}: {} sent, {} received, {:.0} % loss, average {}, minimum {}, maximum {}",
+        h.host_str, since, h.pings_sent, h.successful, loss, format_ms(average),
+        format_ms(h.min_time.map(|t| t as f64)), format_ms(h.max_time.map(|t| t as f64)))
 }
 
-fn main() {
-    // Parse arguments
-    let args = Arguments::parse();
+/// The NDJSON event for a packet in the verbose log. Replies from unknown addresses and receive errors
+/// aren't about a host, so they don't have the host's fields
+fn packet_json(zone: TimeZone, host: Option<&HostInfo>, event: &PacketEvent) -> JsonObject {
+    let json = match host {
+        Some(h) => event_json(zone, "packet", h),
+        None => JsonObject::new().string("time", &zone.format(SystemTime::now())).string("event", "packet"),
+    };
+    match event {
+        PacketEvent::Sent { sequence } => {
+            let json = json.string("kind", "request");
+            match sequence {
+                Some(sequence) => json.integer("sequence", *sequence as i64),
+                None => json,
+            }
+        },
+        PacketEvent::Reply { from, sequence, icmp_type, latency, description } => {
+            let mut json = json.string("kind", "reply").string("from", &from.to_string());
+            if let Some(sequence) = sequence {
+                json = json.integer("sequence", *sequence as i64);
+            }
+            if let Some(icmp_type) = icmp_type {
+                json = json.integer("icmp_type", *icmp_type as i64);
+            }
+            if let Some(description) = description {
+                json = json.string("message", description);
+            }
+            json.number("rtt_ms", latency.map_or(f64::NAN, |l| l as f64 / 1000.0))
+        },
+        PacketEvent::IcmpError(report) => icmp_error_json(json.string("kind", "icmp_error"), report),
+        PacketEvent::Error(error) => json.string("kind", "error").string("error", error),
+    }
+}
 
-    if args.hosts.is_empty() {
-        eprintln!("You need to specify hosts on the command line.\nExample: multiping 127.0.0.1");
-        exit(1);
+/// Adds what's known about an ICMP error to an event
+fn icmp_error_json(mut json: JsonObject, report: &IcmpErrorReport) -> JsonObject {
+    if let Some(from) = report.from {
+        json = json.string("from", &from.to_string());
     }
-    
-    let (send_tx, rx) = mpsc::channel::<StatusUpdate>();
-    let recv_tx4 = send_tx.clone();
-    let recv_tx6 = send_tx.clone();
-    let mut hinfos: Vec<HostInfo> = Vec::new();
-    let mut max_host_width = 0;
-    let mut uses_ipv4 = false;
-    let mut uses_ipv6 = false;
-    let term = Term::stdout();
-    
-    // Parse the provided hosts into a vector of HostInfos
-    for (i, h) in args.hosts.iter().enumerate() {
-        let _ = term.clear_line();
-        print!("Resolving host {} ({}/{}).\r", h, i+1, args.hosts.len());
-        let _ = stdout().flush();
-        
-        let maybe_hinfo = HostInfo::new(h, HostOptions { ip_version: args.ip_version });
-        if let Ok(hinfo) = maybe_hinfo {
-            uses_ipv4 |= hinfo.host.is_ipv4();
-            uses_ipv6 |= hinfo.host.is_ipv6();
-            hinfos.push(hinfo);
-            max_host_width = max(max_host_width, console::measure_text_width(h));
-        } else {
-            eprintln!("\nFailed to parse/resolve {}", h);
-            exit(1);
+    if let Some(sequence) = report.sequence {
+        json = json.integer("sequence", sequence as i64);
+    }
+    json.integer("icmp_type", report.icmp_type as i64)
+        .integer("icmp_code", report.code as i64)
+        .string("error", icmp::type_name(report.icmp_type, report.to.is_ipv4()).unwrap_or("unknown"))
+}
+
+/// One line of the verbose log, e.g. "12:34:56.789 example.com seq=3 reply from 192.0.2.1, echo reply (type 0, code 0),
+/// identifier 0x1234, sequence 3, 56 bytes of data, 12.34 ms"
+fn format_packet(host: Option<usize>, event: &PacketEvent, hinfos: &[HostInfo], timestamps: Option<TimeZone>) -> String {
+    let mut s = match timestamps {
+        Some(zone) => zone.format(SystemTime::now()),
+        None => time::format_time_of_day(SystemTime::now()),
+    };
+    match host {
+        Some(i) => s.push_str(format!(" {}", hinfos[i].host_str).as_str()),
+        None if matches!(event, PacketEvent::Reply { .. }) => s.push_str(" (not a host being pinged)"),
+        None => {},
+    }
+    let sequence = |sequence: &Option<u16>| sequence.map(|n| format!(" seq={}", n)).unwrap_or_default();
+    match event {
+        PacketEvent::Sent { sequence: n } => s.push_str(format!("{} request sent", sequence(n)).as_str()),
+        PacketEvent::Reply { from, sequence: n, icmp_type, latency, description } => {
+            s.push_str(format!("{} reply from {}", sequence(n), from).as_str());
+            if let Some(description) = description {
+                s.push_str(format!(", {}", description).as_str());
+            } else if let Some(icmp_type) = icmp_type {
+                let name = icmp::type_name(*icmp_type, from.is_ipv4()).unwrap_or("unknown");
+                s.push_str(format!(", {} (type {})", name, icmp_type).as_str());
+            }
+            match latency {
+                Some(latency) => s.push_str(format!(", {:.2} ms", *latency as f64 / 1000.0).as_str()),
+                None => s.push_str(", time unknown as the clock changed"),
+            }
+        },
+        PacketEvent::IcmpError(report) => {
+            let name = icmp::type_name(report.icmp_type, report.to.is_ipv4()).unwrap_or("unknown error");
+            s.push_str(format!("{} {} (type {}, code {})", sequence(&report.sequence), name, report.icmp_type, report.code).as_str());
+            if let Some(from) = report.from {
+                s.push_str(format!(" from {}", from).as_str());
+            }
+        },
+        PacketEvent::Error(error) => s.push_str(format!(" error receiving: {}", error).as_str()),
+    }
+    s
+}
+
+/// Prints the reports shown on exit, once the display has been cleaned up. `interval` is how far apart failures
+/// of different hosts can be to count as happening at the same time
+fn print_reports(hinfos: &[HostInfo], sla: &Sla, histogram: bool, interval: Duration) {
+    print_outage_report(hinfos);
+    print_incident_report(hinfos, interval);
+    if !sla.is_empty() {
+        print_sla_report(hinfos, sla);
+    }
+    if histogram {
+        print_histograms(hinfos);
+    }
+}
+
+/// Prints a histogram of the round trip times of every host that replied
+fn print_histograms(hinfos: &[HostInfo]) {
+    for h in hinfos.iter().filter(|h| !h.samples.is_empty()) {
+        let buckets = log_histogram(&h.samples_vec());
+        let most = buckets.iter().map(|b| b.count).max().unwrap_or(0);
+        let labels: Vec<String> = buckets.iter().map(|b| format!("{} - {} ms", b.low as f64 / 1000.0, b.high as f64 / 1000.0)).collect();
+        let label_spaces = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+        // Older ones may have been dropped to keep within the history limit
+        let latest = if h.samples.len() < h.successful as usize { "latest " } else { "" };
+        println!("\nRound trip times for {} ({}{} replies):", name_with_note(h), latest, h.samples.len());
+        for (bucket, label) in buckets.iter().zip(&labels) {
+            // Any bucket with samples in it gets at least one #, so it isn't mistaken for an empty one
+            let bar = (bucket.count * HISTOGRAM_WIDTH).div_ceil(most.max(1));
+            println!("{:>label_spaces$} | {:<HISTOGRAM_WIDTH$} {}", label, "#".repeat(bar), bucket.count);
         }
     }
+}
+
+/// Prints whether each host met every SLA threshold
+fn print_sla_report(hinfos: &[HostInfo], sla: &Sla) {
+    let host_spaces = max(12, hinfos.iter().map(|h| name_with_note(h).len()).max().unwrap_or(0));
+    let hinfos: Vec<&HostInfo> = hinfos.iter().filter(|h| !h.removed).collect();
+    let results: Vec<Vec<SlaCheck>> = hinfos.iter().map(|h| sla.evaluate(h)).collect();
     
-    let _ = term.clear_line();
-    println!("All hosts resolved");
-    
-    let recv_enum_host_infos4 = hinfos.clone().into_iter().enumerate();
-    let recv_enum_host_infos6 = hinfos.clone().into_iter().enumerate();
-    let send_enum_host_infos = hinfos.clone().into_iter().enumerate();
-    let txsocket4 = mkv4socket().unwrap();
-    let rxsocket4 = txsocket4.try_clone().unwrap();
-    let txsocket6 = mkv6socket().unwrap();
-    let rxsocket6 = txsocket6.try_clone().unwrap();
+    let mut header = format!("{:<host_spaces$}", "Host");
+    for check in results.first().into_iter().flatten() {
+        let unit = if check.name == "Average" { "ms" } else { "%" };
+        let heading = format!("{} ({} {} {})", check.name, if check.name == "Availability" { ">" } else { "<" }, check.threshold, unit);
+        header.push_str(format!("   {:>22}", heading).as_str());
+    }
+    header.push_str("   Result");
+    println!("\nSLA report:\n{}", header);
     
-    // Spawn threads
-    // Sending thread (both IPv4 and IPv6)
-    thread::spawn(move || {
-        loop {
-            for (i, h) in send_enum_host_infos.clone() {
-                let send_result;
-                if h.host.is_ipv4() {
-                    send_result = send_ping(&h, &txsocket4);
-                } else if h.host.is_ipv6() {
-                    send_result = send_ping(&h, &txsocket6);
-                } else {
-                    eprintln!("{} is neither IPv4 nor IPv6", h.host_str);
-                    continue;
-                }
-                match send_result {
-                    Err(e) => send_tx.send(StatusUpdate::Error(i, e.kind())).unwrap(),
-                    Ok(_) => send_tx.send(StatusUpdate::Sent(i)).unwrap()
-                }
+    let mut group = None;
+    for (h, checks) in hinfos.iter().zip(&results) {
+        if h.group != group {
+            group = h.group.clone();
+            if let Some(name) = &group {
+                println!("{}", format_group_heading(name, false));
             }
-            thread::sleep(Duration::from_secs_f32(args.interval));
         }
-    });
+        let mut line = format!("{:<host_spaces$}", name_with_note(h));
+        for check in checks {
+            let cell = match check.actual {
+                Some(actual) => format!("{:.3} {}", actual, if check.passed { "pass" } else { "FAIL" }),
+                None => "- FAIL".to_string(),
+            };
+            line.push_str(format!("   {:>22}", cell).as_str());
+        }
+        line.push_str(if checks.iter().all(|c| c.passed) { "   PASS" } else { "   FAIL" });
+        println!("{}", line);
+    }
+}
 
-    // IPv4 listening thread
-    if uses_ipv4 {
-        thread::spawn(move || {
-            loop {
-                match receive_ping(&rxsocket4) {
-                    Ok((addr, latency)) => {
-                        // Figure out which host the address was from
-                        let mut found = false;
-                        for (i, h) in recv_enum_host_infos4.clone() {
-                            if h.host == addr {
-                                recv_tx4.send(StatusUpdate::Received(i, latency)).unwrap();
-                                found = true;
-                                break;
-                            }
-                        }
-                        if !found {
-                            eprintln!("Host not found: addr = {}", addr)
-                        }
-                    },
-                    Err(e) => {
-                        eprintln!("Error listening to socket: {}", e);
-                    }
-                }
-            }
-        });
+/// The heading above the hosts in a group, in the table and the reports
+fn format_group_heading(name: &str, colour: bool) -> String {
+    let heading = format!("[{}]", name);
+    if colour { style(heading).bold().to_string() } else { heading }
+}
+
+/// The host's name for the reports, followed by its note if it has one
+fn name_with_note(host: &HostInfo) -> String {
+    match &host.note {
+        Some(note) => format!("{} ({})", host.host_str, note),
+        None => host.host_str.clone(),
     }
-    
-    if uses_ipv6 {
-        // IPv6 listening thread
-        thread::spawn(move || {
-            loop {
-                match receive_ping(&rxsocket6) {
-                    Ok((addr, latency)) => {
-                        // Figure out which host the address was from
-                        let mut found = false;
-                        for (i, h) in recv_enum_host_infos6.clone() {
-                            if h.host == addr {
-                                recv_tx6.send(StatusUpdate::Received(i, latency)).unwrap();
-                                found = true;
-                                break;
-                            }
-                        }
-                        if !found {
-                            eprintln!("Host not found: addr = {}", addr)
-                        }
-                    },
-                    Err(e) => {
-                        eprintln!("Error listening to socket: {}", e);
-                    }
-                }
+}
+
+/// Prints every outage of every host, once the display has been cleaned up
+fn print_outage_report(hinfos: &[HostInfo]) {
+    if hinfos.iter().all(|h| h.outages.is_empty()) {
+        return;
+    }
+    println!("Outages:");
+    let mut group = None;
+    for h in hinfos.iter().filter(|h| !h.outages.is_empty()) {
+        if h.group != group {
+            group = h.group.clone();
+            if let Some(name) = &group {
+                println!("{}", format_group_heading(name, false));
             }
-        });
+        }
+        for outage in &h.outages {
+            println!("{}", format_outage(h, outage));
+        }
     }
-    
-    if let Err(e) = display_loop(rx, hinfos, max_host_width, args) {
-        eprintln!("Error in display loop {}", e);
+}
+
+/// Prints when hosts failed at the same time, and whether it looked local or remote
+fn print_incident_report(hinfos: &[HostInfo], interval: Duration) {
+    let incidents = correlation::incidents(hinfos, interval);
+    if incidents.is_empty() {
+        return;
+    }
+    println!("\nIncidents:");
+    for incident in &incidents {
+        let when = match incident.end {
+            Some(end) => format!("At {} for {}", time::format_utc(incident.start),
+                time::format_duration(end.duration_since(incident.start).unwrap_or_default())),
+            None => format!("Since {}", time::format_utc(incident.start)),
+        };
+        let names: Vec<String> = incident.hosts.iter().map(|i| hinfos[*i].host_str.clone()).collect();
+        println!("{}: {} ({})", when, describe_incident(incident), names.join(", "));
     }
 }
 
-fn display_loop(rx: Receiver<StatusUpdate>, mut hinfos: Vec<HostInfo>, max_host_width: usize, args: Arguments) -> Result<(), Error> {
-    let mut term = Term::buffered_stdout();
-    let colour = console::colors_enabled() && args.colour.unwrap_or(true);
+/// What an incident affected, and where the problem seems to be
+fn describe_incident(incident: &correlation::Incident) -> String {
+    let what = if incident.outage { "down" } else { "slow" };
+    match incident.scope {
+        correlation::Scope::Local => format!("every host {} at once, so the problem looks local (this machine, its network or uplink)", what),
+        correlation::Scope::Shared => format!("{} addresses {} at once, so the problem looks to be on a path they share", incident.addresses, what),
+        correlation::Scope::Remote => format!("only one address {}, so the problem looks remote (that host or the path to it)", what),
+    }
+}
 
-    term.hide_cursor()?;
-    // Enable the alternate screen buffer
-    term.write(b"\x1b[?1049h")?;
-    
-    let mut handler_term = term.clone();
-    ctrlc::set_handler(move || {
-        let _ = cleanup_display(&mut handler_term);
-        exit(0);
-    }).expect("Couldn't set Ctrl-C handler");
-    
-    // Listen for updates
-    for update in rx {
-        update_host_info(&update, &mut hinfos);
-        update_display(&term, &hinfos, max_host_width, colour)?;
+fn format_outage(host: &HostInfo, outage: &Outage) -> String {
+    match outage.end {
+        Some(_) => format!("{}: down at {} for {}", name_with_note(host),
+            time::format_utc(outage.start), time::format_duration(outage.duration())),
+        None => format!("{}: down since {} ({} so far)", name_with_note(host),
+            time::format_utc(outage.start), time::format_duration(outage.duration())),
     }
-    
-    cleanup_display(&mut term)?;
-    Ok(())
 }
 
-fn cleanup_display(term: &mut Term) -> Result<(), Error> {
+/// Reads key presses from the terminal in another thread: r resets the statistics. The terminal's echo
+/// and line buffering are turned off to do that, so the returned settings need to be put back afterwards
+fn read_keys(pinger: Pinger) -> Result<(Arc<File>, Termios), Error> {
+    let tty = File::open("/dev/tty")?;
+    let original = tcgetattr(&tty)?;
+    let mut settings = original.clone();
+    settings.local_flags.remove(LocalFlags::ICANON | LocalFlags::ECHO);
+    tcsetattr(&tty, SetArg::TCSANOW, &settings)?;
+    let tty = Arc::new(tty);
+    let reader = tty.clone();
+    thread::spawn(move || {
+        let mut key = [0u8];
+        while (&*reader).read(&mut key).is_ok_and(|n| n == 1) {
+            if key[0] == b'r' {
+                pinger.reset_stats();
+            } else if key[0] == b's' {
+                // Handled like SIGUSR2, by whatever keeps the statistics
+                let _ = signal_hook::low_level::raise(SIGUSR2);
+            }
+        }
+    });
+    Ok((tty, original))
+}
+
+/// Puts the terminal back to how it was before the table was shown
+fn cleanup_display(term: &mut Term, keyboard: Option<&(Arc<File>, Termios)>) -> Result<(), Error> {
+    if let Some((tty, settings)) = keyboard {
+        tcsetattr(tty.as_ref(), SetArg::TCSANOW, settings)?;
+    }
     // Disable the alternate screen buffer
-    term.write(b"\x1b[?1049l")?;
+    term.write_all(b"\x1b[?1049l")?;
     term.show_cursor()?;
     term.flush()?;
     Ok(())
 }
 
-fn update_display(term: &Term, hinfos: &Vec<HostInfo>, max_host_width: usize, colour: bool) -> Result<(), Error> {
+/// Puts the terminal back (only once, however many times it's asked to)
+#[derive(Clone)]
+struct RestoreDisplay {
+    keyboard: Option<(Arc<File>, Termios)>,
+    done: Arc<AtomicBool>,
+}
+
+impl RestoreDisplay {
+    fn restore(&self) -> Result<(), Error> {
+        if self.done.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        // Written straight to stdout, as the table's buffered terminal may be halfway through a frame
+        cleanup_display(&mut Term::stdout(), self.keyboard.as_ref())
+    }
+}
+
+/// Puts the terminal back when it's dropped, so that returning early with an error doesn't leave the cursor hidden
+/// on the alternate screen. A panic on the thread showing the table puts it back before the panic message is
+/// printed, so the message ends up on the normal screen, where it can be read after exiting. Panics on other
+/// threads are left alone, as the table carries on (or finishes normally, if they were passing it updates)
+struct DisplayGuard {
+    restore: RestoreDisplay,
+}
+
+impl DisplayGuard {
+    fn new(keyboard: Option<(Arc<File>, Termios)>) -> DisplayGuard {
+        let restore = RestoreDisplay { keyboard, done: Arc::new(AtomicBool::new(false)) };
+        // Termios isn't Sync, which the hook needs to be
+        let (hook_restore, display_thread) = (Mutex::new(restore.clone()), thread::current().id());
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if thread::current().id() == display_thread && let Ok(restore) = hook_restore.lock() {
+                let _ = restore.restore();
+            }
+            default_hook(info);
+        }));
+        DisplayGuard { restore }
+    }
+}
+
+impl Drop for DisplayGuard {
+    fn drop(&mut self) {
+        let _ = self.restore.restore();
+    }
+}
+
+/// Shows the table, then when the statistics were last reset (if they were), the recent outages,
+/// and as much of the verbose log as fits (if it's given)
+fn update_display(term: &Term, hinfos: &[HostInfo], table: &Table, reset_at: Option<SystemTime>, packets: Option<&VecDeque<String>>) -> Result<(), Error> {
     term.clear_screen()?;
+    let mut lines = 0;
     
+    // Hosts can be added while running, so the width is worked out every time
+    let max_host_width = hinfos.iter().filter(|h| !h.removed).map(|h| console::measure_text_width(&host_label(h, table.columns.address))).max().unwrap_or(0);
     let host_spaces = max(12, max_host_width);
     let stat_spaces = 7;
+    // As for the hosts, the notes column is as wide as the widest note
+    let columns = Columns {
+        notes: table.columns.notes.map(|_| hinfos.iter().filter(|h| !h.removed)
+            .filter_map(|h| h.note.as_deref().map(console::measure_text_width)).max().unwrap_or(0).max(4)),
+        ..table.columns
+    };
+    
+    // When every host is failing, the local checks are shown above the table, along with the error if every host
+    // has the same one (in which case the rows show their statistics instead of a wall of identical errors)
+    let shared_error = table.diagnosis.as_ref().and_then(|_| {
+        let mut errors = hinfos.iter().filter(|h| !h.removed).map(|h| h.last_error);
+        errors.next().flatten().filter(|first| errors.all(|e| e == Some(*first)))
+    });
+    // Otherwise, a banner says where the problem seems to be while hosts are down
+    if let Some(diagnosis) = &table.diagnosis {
+        let error = shared_error.map(|e| format!(" with \"{}\"", e)).unwrap_or_default();
+        let banner = format!("Every host is failing{}: {}", error, diagnosis);
+        term.write_line(if table.colour { style(banner).red().bold().to_string() } else { banner }.as_str())?;
+        lines += 1;
+    } else if let Some(incident) = &table.incident {
+        let banner = format!("Since {}: {}", time::format_utc(incident.start), describe_incident(incident));
+        term.write_line(if table.colour { style(banner).red().bold().to_string() } else { banner }.as_str())?;
+        lines += 1;
+    }
     
-    let header_line = format_header(host_spaces, stat_spaces);
+    let header_line = format_header(host_spaces, stat_spaces, columns);
     term.write_line(header_line.as_str())?;
+    lines += 1;
     
-    for host in hinfos {
-        let line = format_host_info(host, colour, host_spaces, stat_spaces);
+    let worst = table.highlight_worst.and_then(|by| worst_host(hinfos, by));
+    let mut rows: Vec<usize> = (0..hinfos.len()).filter(|i| !hinfos[*i].removed).collect();
+    // Hosts added to a group later (e.g. the rows from a --from machine) are moved up to join the rest of it
+    let mut group_starts: HashMap<&str, usize> = HashMap::new();
+    for i in &rows {
+        if let Some(name) = &hinfos[*i].group {
+            group_starts.entry(name.as_str()).or_insert(*i);
+        }
+    }
+    rows.sort_by_key(|i| hinfos[*i].group.as_deref().map_or(*i, |name| group_starts[name]));
+    if let Some(worst) = worst && table.pin_worst {
+        // The worst host is moved up along with the rest of its group, so that its sub-rows stay underneath it
+        let start = (0..=worst).rev().find(|i| !hinfos[*i].sub_row).unwrap_or(worst);
+        let end = (worst + 1..hinfos.len()).find(|i| !hinfos[*i].sub_row).unwrap_or(hinfos.len());
+        rows.sort_by_key(|i| !(start..end).contains(i));
+    }
+    // Pinned hosts stay above everything else, even the worst one
+    rows.sort_by_key(|i| !hinfos[*i].pinned);
+    let mut group = None;
+    for i in rows {
+        let host = &hinfos[i];
+        // Hosts in a group are under a heading for it
+        if !host.sub_row && host.group != group {
+            group = host.group.clone();
+            if let Some(name) = &group {
+                term.write_line(format_group_heading(name, table.colour).as_str())?;
+                lines += 1;
+            }
+        }
+        let mut line = match shared_error {
+            Some(_) => format_host_info(&HostInfo { last_error: None, ..host.clone() }, table.colour, host_spaces, stat_spaces,
+                columns, table.latency_colours.for_host(host)),
+            None => format_host_info(host, table.colour, host_spaces, stat_spaces, columns, table.latency_colours.for_host(host)),
+        };
+        if worst == Some(i) {
+            line = highlight_row(line, table.colour, host_spaces);
+        }
         term.write_line(line.as_str())?;
+        lines += 1;
+    }
+    
+    if table.summary {
+        term.write_line("-".repeat(console::measure_text_width(&header_line)).as_str())?;
+        term.write_line(format_summary(hinfos, table.colour, host_spaces, stat_spaces, columns).as_str())?;
+        lines += 2;
+    }
+    
+    if let Some(time) = reset_at {
+        term.write_line("")?;
+        term.write_line(format!("Statistics since {} (press r to reset them again)", time::format_utc(time)).as_str())?;
+        lines += 2;
+    }
+    
+    if let Some((time, message)) = &table.notice {
+        let line = format!("{}: {}", time::format_utc(*time), message);
+        term.write_line("")?;
+        term.write_line(if table.colour { style(line).yellow().to_string() } else { line }.as_str())?;
+        lines += 2;
+    }
+    
+    // The most recent outages (including ongoing ones) are listed underneath the table
+    let mut outages: Vec<(&HostInfo, &Outage)> = hinfos.iter()
+        .flat_map(|h| h.outages.iter().map(move |o| (h, o)))
+        .collect();
+    if !outages.is_empty() {
+        outages.sort_by_key(|(_, o)| Reverse(o.start));
+        term.write_line("")?;
+        term.write_line("Recent outages:")?;
+        for (host, outage) in outages.iter().take(RECENT_OUTAGES) {
+            term.write_line(format_outage(host, outage).as_str())?;
+        }
+        lines += 2 + outages.len().min(RECENT_OUTAGES);
+    }
+    
+    // The newest packets, in the rows left at the bottom of the screen
+    if let Some(packets) = packets {
+        let rows = term.size().0 as usize;
+        let room = rows.saturating_sub(lines + 2).max(MIN_PACKET_LINES);
+        term.write_line("")?;
+        term.write_line("Packets (UTC):")?;
+        for packet in packets.iter().skip(packets.len().saturating_sub(room)) {
+            term.write_line(console::truncate_str(packet, term.size().1 as usize, "").as_ref())?;
+        }
     }
     
     term.flush()?;
@@ -211,25 +2970,122 @@ fn update_display(term: &Term, hinfos: &Vec<HostInfo>, max_host_width: usize, co
 }
 
 const SEPARATOR: &str = " | ";
+/// Width of the 1/5/15 minute average columns
+const AVERAGES_SPACES: usize = 15;
+/// Width of the last reply column, which fits "1h 02m 03s ago"
+const LAST_REPLY_SPACES: usize = 14;
+/// Width of the ICMP errors column
+const ICMP_ERRORS_SPACES: usize = 17;
+const BURSTS_SPACES: usize = 19;
+/// How long the longest bar of a histogram is
+const HISTOGRAM_WIDTH: usize = 40;
+/// How many outages are listed under the table
+const RECENT_OUTAGES: usize = 5;
+/// How long a message from the background is shown under the table
+const NOTICE_SHOWN_FOR: Duration = Duration::from_secs(60);
+/// How often the local checks are run again while every host is failing
+const DIAGNOSE_EVERY: Duration = Duration::from_secs(10);
+/// How many lines of the verbose log are kept for showing under the table
+const PACKET_LOG_LINES: usize = 200;
+/// How many lines of the verbose log are shown, even if the screen's already full
+const MIN_PACKET_LINES: usize = 5;
+
+/// How the table is shown
+#[derive(Clone, Debug)]
+struct Table {
+    colour: bool,
+    columns: Columns,
+    latency_colours: LatencyColours,
+    highlight_worst: Option<Worst>,
+    pin_worst: bool,
+    summary: bool,
+    /// What the local checks found, while every host is failing
+    diagnosis: Option<String>,
+    /// The incident going on now, if a host is down
+    incident: Option<correlation::Incident>,
+    /// The latest message from the background
+    notice: Option<Notice>,
+}
 
-pub fn format_header(host_spaces: usize, stat_spaces: usize) -> String {
+/// The optional columns of the table
+#[derive(Clone, Copy, Debug)]
+pub struct Columns {
+    /// The address being probed, next to the host's name
+    pub address: bool,
+    /// Each host's note, padded to this width
+    pub notes: Option<usize>,
+    pub retries: bool,
+    /// Average latency and loss over the last 1, 5 and 15 minutes
+    pub averages: bool,
+    pub trend: bool,
+    /// Health score, worked out with these weights
+    pub health: Option<HealthWeights>,
+    /// When the host last replied, shown like this
+    pub last_reply: Option<LastReply>,
+    /// ICMP errors of each kind
+    pub icmp_errors: bool,
+    /// How many bursts were answered completely, partly or not at all
+    pub bursts: bool,
+}
+
+pub fn format_header(host_spaces: usize, stat_spaces: usize, columns: Columns) -> String {
     let mut s = String::new();
     
     s.push_str(format!("{:<host_spaces$}", "Host").as_str());
     s.push_str(SEPARATOR);
-    for heading in ["Time", "Minimum", "Average", "Maximum", "Jitter", "Loss"] {
+    if let Some(note_spaces) = columns.notes {
+        s.push_str(format!("{:<note_spaces$}", "Note").as_str());
+        s.push_str(SEPARATOR);
+    }
+    for heading in ["Time", "Minimum", "Average", "Maximum", "Jitter", "Loss", "MOS"] {
         s.push_str(format!("{:<stat_spaces$}", heading).as_str());
         s.push_str(SEPARATOR);
     }
+    if columns.retries {
+        s.push_str(format!("{:<stat_spaces$}", "Retries").as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.averages {
+        for heading in ["Average 1/5/15m", "Loss 1/5/15m"] {
+            s.push_str(format!("{:<AVERAGES_SPACES$}", heading).as_str());
+            s.push_str(SEPARATOR);
+        }
+    }
+    if columns.trend {
+        s.push_str("Trend");
+        s.push_str(SEPARATOR);
+    }
+    if columns.health.is_some() {
+        s.push_str(format!("{:<stat_spaces$}", "Health").as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.last_reply.is_some() {
+        s.push_str(format!("{:<LAST_REPLY_SPACES$}", "Last reply").as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.icmp_errors {
+        s.push_str(format!("{:<ICMP_ERRORS_SPACES$}", "Unreach/TTL/Other").as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.bursts {
+        s.push_str(format!("{:<BURSTS_SPACES$}", "Bursts OK/Part/Lost").as_str());
+        s.push_str(SEPARATOR);
+    }
     
     s
 }
 
-pub fn format_host_info(host: &HostInfo, colour: bool, host_spaces: usize, stat_spaces: usize) -> String {
+/// `thresholds` are the host's warning and critical latencies, which the latencies are coloured by
+pub fn format_host_info(host: &HostInfo, colour: bool, host_spaces: usize, stat_spaces: usize, columns: Columns,
+        thresholds: (Option<f64>, Option<f64>)) -> String {
     let mut s = String::new();
     
-    s.push_str(format!("{:<host_spaces$}", host.host_str).as_str());
+    s.push_str(format!("{:<host_spaces$}", host_label(host, columns.address)).as_str());
     s.push_str(SEPARATOR);
+    if let Some(note_spaces) = columns.notes {
+        s.push_str(format!("{:<note_spaces$}", host.note.as_deref().unwrap_or("")).as_str());
+        s.push_str(SEPARATOR);
+    }
     
     if let Some(error) = host.last_error {
         for _x in 0..=stat_spaces - 6 {
@@ -242,16 +3098,234 @@ pub fn format_host_info(host: &HostInfo, colour: bool, host_spaces: usize, stat_
         return s;
     }
     
-    for stat in [to_sec(host.latest_time), to_sec(host.min_time), not_nan(host.average()), to_sec(host.max_time), not_nan(host.jitter())] {
-        s.push_str(format_time_cell(colour, stat_spaces, stat).as_str());
+    for stat in [to_sec(host.latest_time), to_sec(host.min_time), not_nan(host.average()), to_sec(host.max_time)] {
+        s.push_str(format_time_cell(colour, stat_spaces, stat, thresholds).as_str());
+        s.push_str(SEPARATOR);
+    }
+    // Jitter isn't a latency, so it isn't compared with the thresholds
+    s.push_str(format_time_cell(colour, stat_spaces, not_nan(host.jitter()), (None, None)).as_str());
+    s.push_str(SEPARATOR);
+    // Loss from rate limiting isn't a problem with the network, so it isn't shown in red
+    let loss_cell = format_colour_percent(colour && !host.rate_limited(), stat_spaces, host.sequence_loss.answered(), host.sequence_loss.settled());
+    s.push_str(if colour && host.rate_limited() { style(loss_cell).yellow().to_string() } else { loss_cell }.as_str());
+    s.push_str(SEPARATOR);
+    s.push_str(format_mos_cell(colour, stat_spaces, host.call_quality()).as_str());
+    s.push_str(SEPARATOR);
+    if columns.retries {
+        s.push_str(format!("{:>stat_spaces$}", host.retries).as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.averages {
+        s.push_str(format_averages_cell(colour, host.latency_averages.averages(), "ms").as_str());
+        s.push_str(SEPARATOR);
+        s.push_str(format_averages_cell(colour, host.loss_averages.averages(), "%").as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.trend {
+        s.push_str(format_trend_cell(colour, host.latency_trend()).as_str());
+        s.push_str(SEPARATOR);
+    }
+    if let Some(weights) = columns.health {
+        s.push_str(format_health_cell(colour, stat_spaces, host.health_score(weights)).as_str());
+        s.push_str(SEPARATOR);
+    }
+    if let Some(format) = columns.last_reply {
+        s.push_str(format_last_reply_cell(colour, host, format).as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.icmp_errors {
+        s.push_str(format_icmp_errors_cell(colour, host.icmp_errors).as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.bursts {
+        s.push_str(format_bursts_cell(colour, host.bursts).as_str());
+        s.push_str(SEPARATOR);
+    }
+    
+    if host.paused {
+        let note = "Paused ";
+        if colour { s.push_str(style(note).yellow().to_string().as_str()) } else { s.push_str(note); }
+    }
+    if host.rate_limited() {
+        let note = "Rate-limited? ";
+        if colour { s.push_str(style(note).yellow().to_string().as_str()) } else { s.push_str(note); }
+    }
+    if host.stale {
+        let note = "Stale ";
+        if colour { s.push_str(style(note).yellow().to_string().as_str()) } else { s.push_str(note); }
+    }
+    if host.corrupted > 0 {
+        let note = format!("{} corrupted ", host.corrupted);
+        if colour { s.push_str(style(note).red().to_string().as_str()) } else { s.push_str(&note); }
+    }
+    if host.malformed.total() > 0 {
+        let note = format!("{} malformed ", host.malformed.total());
+        if colour { s.push_str(style(note).red().to_string().as_str()) } else { s.push_str(&note); }
+    }
+    if let Some(details) = &host.details {
+        s.push_str(format_details(details).as_str());
+    }
+    
+    s
+}
+
+/// The host with the highest latest latency or packet loss, not counting removed or paused hosts. Hosts that are down
+/// come before any others, and hosts without any loss (or only loss that looks like rate limiting) aren't the worst by loss
+fn worst_host(hinfos: &[HostInfo], by: Worst) -> Option<usize> {
+    let badness = |h: &HostInfo| match by {
+        Worst::Latency => h.latest_time.map(|t| t as f64),
+        Worst::Loss => h.loss_percent().filter(|loss| *loss > 0.0 && !h.rate_limited()),
+    };
+    hinfos.iter().enumerate()
+        .filter(|(_, h)| !h.removed && !h.paused)
+        .filter_map(|(i, h)| {
+            let down = h.is_down() || h.last_error.is_some();
+            (down || badness(h).is_some()).then(|| (i, down, badness(h).unwrap_or(0.0)))
+        })
+        .max_by(|a, b| (a.1, a.2).partial_cmp(&(b.1, b.2)).unwrap_or(std::cmp::Ordering::Equal).then(b.0.cmp(&a.0)))
+        .map(|(i, _, _)| i)
+}
+
+/// Makes a row stand out: the host's name is shown in reverse video, or with an arrow if there's no colour
+fn highlight_row(line: String, colour: bool, host_spaces: usize) -> String {
+    let split = line.char_indices().nth(host_spaces).map(|(i, _)| i).unwrap_or(line.len());
+    let (name, rest) = line.split_at(split);
+    if colour {
+        format!("{}{}", style(name).reverse().bold(), rest)
+    } else {
+        format!("{}{}<- worst", name, rest)
+    }
+}
+
+/// The row under the table that adds up every host (apart from removed ones), with the same columns as the hosts
+fn format_summary(hinfos: &[HostInfo], colour: bool, host_spaces: usize, stat_spaces: usize, columns: Columns) -> String {
+    let hosts: Vec<&HostInfo> = hinfos.iter().filter(|h| !h.removed).collect();
+    let sent: u32 = hosts.iter().map(|h| h.pings_sent).sum();
+    let received: u32 = hosts.iter().map(|h| h.successful).sum();
+    let sum_times: u64 = hosts.iter().map(|h| h.sum_times).sum();
+    let failing = hosts.iter().filter(|h| h.is_down() || h.last_error.is_some()).count();
+    let blank = format!("{:stat_spaces$}", "");
+    let mut s = String::new();
+    
+    s.push_str(format!("{:<host_spaces$}", "All hosts").as_str());
+    s.push_str(SEPARATOR);
+    if let Some(note_spaces) = columns.notes {
+        s.push_str(format!("{:note_spaces$}", "").as_str());
         s.push_str(SEPARATOR);
     }
-    s.push_str(format_colour_percent(colour, stat_spaces, host.successful, host.pings_sent).as_str());
+    let min = hosts.iter().filter_map(|h| h.min_time).min();
+    let average = (received > 0).then(|| sum_times / received as u64);
+    let max = hosts.iter().filter_map(|h| h.max_time).max();
+    s.push_str(&blank);
     s.push_str(SEPARATOR);
+    for stat in [to_sec(min), to_sec(average), to_sec(max)] {
+        s.push_str(format_time_cell(colour, stat_spaces, stat, (None, None)).as_str());
+        s.push_str(SEPARATOR);
+    }
+    s.push_str(&blank);
+    s.push_str(SEPARATOR);
+    let answered: u32 = hosts.iter().map(|h| h.sequence_loss.answered()).sum();
+    let settled: u32 = hosts.iter().map(|h| h.sequence_loss.settled()).sum();
+    s.push_str(format_colour_percent(colour, stat_spaces, answered, settled).as_str());
+    s.push_str(SEPARATOR);
+    s.push_str(&blank);
+    s.push_str(SEPARATOR);
+    if columns.retries {
+        s.push_str(format!("{:>stat_spaces$}", hosts.iter().map(|h| h.retries).sum::<u32>()).as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.averages {
+        for _ in 0..2 {
+            s.push_str(format!("{:AVERAGES_SPACES$}", "").as_str());
+            s.push_str(SEPARATOR);
+        }
+    }
+    if columns.trend {
+        s.push_str(format!("{:5}", "").as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.health.is_some() {
+        s.push_str(&blank);
+        s.push_str(SEPARATOR);
+    }
+    if columns.last_reply.is_some() {
+        s.push_str(format!("{:LAST_REPLY_SPACES$}", "").as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.icmp_errors {
+        let total = hosts.iter().fold(IcmpErrorCounts::default(), |total, h| IcmpErrorCounts {
+            unreachable: total.unreachable + h.icmp_errors.unreachable,
+            time_exceeded: total.time_exceeded + h.icmp_errors.time_exceeded,
+            other: total.other + h.icmp_errors.other,
+        });
+        s.push_str(format_icmp_errors_cell(colour, total).as_str());
+        s.push_str(SEPARATOR);
+    }
+    if columns.bursts {
+        let total = hosts.iter().fold(BurstCounts::default(), |total, h| BurstCounts {
+            complete: total.complete + h.bursts.complete,
+            partial: total.partial + h.bursts.partial,
+            lost: total.lost + h.bursts.lost,
+        });
+        s.push_str(format_bursts_cell(colour, total).as_str());
+        s.push_str(SEPARATOR);
+    }
     
+    s.push_str(format!("{} sent, {} received, ", sent, received).as_str());
+    let note = format!("{} of {} down or with errors", failing, hosts.len());
+    if colour && failing > 0 {
+        s.push_str(style(note).red().to_string().as_str());
+    } else {
+        s.push_str(note.as_str());
+    }
     s
 }
 
+/// What's shown in the host column. Other probes of the same target are indented
+/// underneath it, and only show the probe type. With `show_address`, names are followed
+/// by the address being probed (and the one before it, if it's changed)
+fn host_label(host: &HostInfo, show_address: bool) -> String {
+    if host.sub_row {
+        let from = host.source.map(|source| format!(" from {}", source)).unwrap_or_default();
+        match host.probe.default_port() {
+            Some(_) => format!("  └ {}:{}{}", host.probe.name(), host.host.port(), from),
+            None => format!("  └ {}{}", host.probe.name(), from),
+        }
+    } else if show_address && let Some(previous) = host.previous_address {
+        format!("{} ({}, was {})", host.host_str, host.host.ip(), previous.ip())
+    } else if show_address && !host.host_str.contains(&host.host.ip().to_string()) {
+        format!("{} ({})", host.host_str, host.host.ip())
+    } else {
+        host.host_str.clone()
+    }
+}
+
+/// Extra information from probes that report more than the latency
+fn format_details(details: &ProbeDetails) -> String {
+    match details {
+        ProbeDetails::Ntp { offset, stratum } => {
+            format!("offset {:+.2} ms, stratum {}", *offset as f64 / 1000.0, stratum)
+        },
+        ProbeDetails::Tls { connect, expires } => {
+            let mut s = format!("connect {:.2} ms", *connect as f64 / 1000.0);
+            if let Some(expires) = expires {
+                let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as i64;
+                let days = (expires - now).div_euclid(86400);
+                if days >= 0 {
+                    s.push_str(format!(", certificate expires in {} days", days).as_str());
+                } else {
+                    s.push_str(format!(", certificate expired {} days ago", -days).as_str());
+                }
+            }
+            s
+        },
+        ProbeDetails::ClockOffset { offset } => format!("clock offset {:+} ms", offset / 1000),
+        ProbeDetails::OneWay { forward, backward } => {
+            format!("there {:.2} ms, back {:.2} ms", *forward as f64 / 1000.0, *backward as f64 / 1000.0)
+        },
+    }
+}
+
 fn to_sec(microseconds: Option<u64>) -> Option<u64> {
     Some(microseconds? / 1000)
 }
@@ -284,12 +3358,122 @@ fn format_colour_percent(colour: bool, stat_spaces: usize, suc: u32, total: u32)
     }
 }
 
-fn format_time_cell(colour: bool, stat_spaces: usize, stat: Option<u64>) -> String {
+/// The estimated MOS, coloured like the loss: good calls are green, and poor ones are red
+fn format_mos_cell(colour: bool, stat_spaces: usize, quality: Option<CallQuality>) -> String {
+    let Some(quality) = quality else {
+        let cell = format!("{:>stat_spaces$}", "- ");
+        return if colour { style(cell).red().to_string() } else { cell };
+    };
+    let cell = format!("{:>stat_spaces$.1}", quality.mos);
+    if !colour {
+        cell
+    } else if quality.mos >= 4.0 {
+        style(cell).green().to_string()
+    } else if quality.mos >= 3.6 {
+        style(cell).yellow().to_string()
+    } else {
+        style(cell).red().to_string()
+    }
+}
+
+/// The 1, 5 and 15 minute averages, e.g. "12/15/20 ms". Loss is coloured like the loss column (using the 1 minute average)
+fn format_averages_cell(colour: bool, averages: Option<[f64; 3]>, unit: &str) -> String {
+    let Some(averages) = averages else {
+        let cell = format!("{:>AVERAGES_SPACES$}", "- ");
+        return if colour { style(cell).red().to_string() } else { cell };
+    };
+    let cell = format!("{:>AVERAGES_SPACES$}", format!("{:.0}/{:.0}/{:.0} {}", averages[0], averages[1], averages[2], unit));
+    if !colour {
+        cell
+    } else if unit != "%" {
+        style(cell).cyan().to_string()
+    } else if averages[0] < 10.0 {
+        style(cell).green().to_string()
+    } else if averages[0] <= 50.0 {
+        style(cell).yellow().to_string()
+    } else {
+        style(cell).red().to_string()
+    }
+}
+
+/// An arrow for which way the latency is going: up is red (as it's getting worse), and down is green
+fn format_trend_cell(colour: bool, trend: Option<Trend>) -> String {
+    let arrow = match trend {
+        Some(Trend::Rising) => "↑",
+        Some(Trend::Falling) => "↓",
+        Some(Trend::Steady) => "→",
+        None => "-",
+    };
+    let cell = format!("{:^5}", arrow);
+    match trend {
+        Some(Trend::Rising) if colour => style(cell).red().to_string(),
+        Some(Trend::Falling) if colour => style(cell).green().to_string(),
+        _ => cell,
+    }
+}
+
+/// The number of each kind of ICMP error, in red if there are any
+fn format_icmp_errors_cell(colour: bool, errors: IcmpErrorCounts) -> String {
+    let cell = format!("{:>ICMP_ERRORS_SPACES$}", format!("{}/{}/{}", errors.unreachable, errors.time_exceeded, errors.other));
+    if colour && errors.total() > 0 { style(cell).red().to_string() } else { cell }
+}
+
+/// The number of bursts answered completely, partly and not at all. Partly answered ones are yellow, and lost ones red
+fn format_bursts_cell(colour: bool, bursts: BurstCounts) -> String {
+    let cell = format!("{:>BURSTS_SPACES$}", format!("{}/{}/{}", bursts.complete, bursts.partial, bursts.lost));
+    match (colour, bursts) {
+        (true, BurstCounts { lost: 1.., .. }) => style(cell).red().to_string(),
+        (true, BurstCounts { partial: 1.., .. }) => style(cell).yellow().to_string(),
+        _ => cell,
+    }
+}
+
+/// When the host last replied, which is red while it's down
+fn format_last_reply_cell(colour: bool, host: &HostInfo, format: LastReply) -> String {
+    let Some(last_reply) = host.last_reply else {
+        let cell = format!("{:>LAST_REPLY_SPACES$}", "never ");
+        return if colour && host.pings_sent > 1 { style(cell).red().to_string() } else { cell };
+    };
+    let text = match format {
+        LastReply::Ago => format!("{} ago", time::format_duration(last_reply.elapsed().unwrap_or_default())),
+        LastReply::Time => time::format_time_of_day(last_reply)[..8].to_string(),
+    };
+    let cell = format!("{:>LAST_REPLY_SPACES$}", text);
+    if colour && host.is_down() { style(cell).red().to_string() } else { cell }
+}
+
+/// Scores of 80 or more are green, 50 or more yellow and anything lower red
+fn format_health_cell(colour: bool, stat_spaces: usize, score: Option<f64>) -> String {
+    let Some(score) = score else {
+        let cell = format!("{:>stat_spaces$}", "- ");
+        return if colour { style(cell).red().to_string() } else { cell };
+    };
+    let cell = format!("{:>stat_spaces$.0}", score);
+    if !colour {
+        cell
+    } else if score >= 80.0 {
+        style(cell).green().to_string()
+    } else if score >= 50.0 {
+        style(cell).yellow().to_string()
+    } else {
+        style(cell).red().to_string()
+    }
+}
+
+/// Times are green under the warning threshold, yellow at or over it and red at or over the critical one.
+/// Without any thresholds they're cyan
+fn format_time_cell(colour: bool, stat_spaces: usize, stat: Option<u64>, thresholds: (Option<f64>, Option<f64>)) -> String {
     let united_spaces = stat_spaces -  3;
     if let Some(s) = stat {
         let cell = format!("{:>united_spaces$} ms", s);
         if colour {
-            return style(cell).cyan().to_string();
+            let ms = s as f64;
+            return match thresholds {
+                (None, None) => style(cell).cyan(),
+                (_, Some(critical)) if ms >= critical => style(cell).red(),
+                (Some(warning), _) if ms >= warning => style(cell).yellow(),
+                _ => style(cell).green(),
+            }.to_string();
         }
         cell
     } else {
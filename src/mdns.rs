@@ -0,0 +1,103 @@
+//! Discovery of devices on the local network using DNS-SD over multicast DNS
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use crate::dns::*;
+
+const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+/// Asking for this lists every type of service being advertised
+const SERVICES_QUERY: &str = "_services._dns-sd._udp.local";
+
+/// Browse for devices advertising services over mDNS for `duration`.
+/// `found` is called with the friendly name and address of each device as it is found
+pub fn discover(duration: Duration, mut found: impl FnMut(&str, IpAddr)) -> Result<(), Error> {
+    // One-shot queries from an ephemeral port get unicast replies, so there's no need
+    // to share port 5353 with any mDNS responder already running on this machine
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_read_timeout(Some(Duration::from_millis(250)))?;
+
+    let deadline = Instant::now() + duration;
+    let mut next_browse = Instant::now();
+    let mut service_types: HashSet<String> = HashSet::new();
+    // Instance name -> service type it was advertised under
+    let mut instances: HashMap<String, String> = HashMap::new();
+    // Instance name -> host name
+    let mut targets: HashMap<String, String> = HashMap::new();
+    // Host name -> address
+    let mut addresses: HashMap<String, IpAddr> = HashMap::new();
+    let mut reported: HashSet<IpAddr> = HashSet::new();
+    let mut buf = [0u8; 9000];
+
+    while Instant::now() < deadline {
+        // Ask again every so often, as multicast packets are easily lost
+        if Instant::now() >= next_browse {
+            socket.send_to(&build_query(0, &[(SERVICES_QUERY, TYPE_PTR)]), MDNS_ADDR)?;
+            for service in &service_types {
+                socket.send_to(&build_query(0, &[(service, TYPE_PTR)]), MDNS_ADDR)?;
+            }
+            next_browse = Instant::now() + Duration::from_secs(1);
+        }
+
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+        let Some((_, records)) = parse_response(&buf[..len]) else { continue };
+
+        let mut questions: Vec<(String, u16)> = Vec::new();
+        for record in records {
+            match record.data {
+                RecordData::Ptr(target) => {
+                    if record.name == SERVICES_QUERY {
+                        if service_types.insert(target.clone()) {
+                            questions.push((target, TYPE_PTR));
+                        }
+                    } else if !instances.contains_key(&target) {
+                        // The target is an instance of the service
+                        instances.insert(target.clone(), record.name);
+                        questions.push((target, TYPE_SRV));
+                    }
+                },
+                RecordData::Srv { target, .. } => {
+                    if !addresses.contains_key(&target) {
+                        questions.push((target.clone(), TYPE_A));
+                    }
+                    targets.insert(record.name, target);
+                },
+                RecordData::A(addr) => {
+                    addresses.insert(record.name, IpAddr::V4(addr));
+                },
+                // Link-local addresses are no good without knowing the interface
+                RecordData::Aaaa(addr) if !addr.is_unicast_link_local() => {
+                    addresses.entry(record.name).or_insert(IpAddr::V6(addr));
+                },
+                _ => {},
+            }
+        }
+
+        for (question, qtype) in questions {
+            socket.send_to(&build_query(0, &[(&question, qtype)]), MDNS_ADDR)?;
+        }
+
+        for (instance, service) in &instances {
+            let Some(addr) = targets.get(instance).and_then(|t| addresses.get(t)) else { continue };
+            if reported.insert(*addr) {
+                found(friendly_name(instance, service), *addr);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Instance names look like "Living Room TV._airplay._tcp.local", and the first part is the friendly name
+fn friendly_name<'a>(instance: &'a str, service: &str) -> &'a str {
+    instance.strip_suffix(service)
+        .and_then(|name| name.strip_suffix('.'))
+        .unwrap_or(instance)
+}
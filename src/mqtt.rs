@@ -0,0 +1,154 @@
+//! Just enough of MQTT 3.1.1 to publish messages to a broker (at QoS 0), so reachability
+//! can be fed into home automation systems such as Home Assistant
+
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The port brokers listen on for unencrypted connections
+pub const DEFAULT_PORT: u16 = 1883;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+const DISCONNECT: u8 = 0xe0;
+
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    /// Whether the broker keeps the message for clients that subscribe later
+    pub retain: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct MqttOptions {
+    pub server: String, // e.g. "broker.example.com" or "192.0.2.1:1883"
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub keep_alive: Duration,
+    /// Published by the broker if the connection is lost without disconnecting
+    pub will: Option<Message>,
+}
+
+/// A connection to a broker
+pub struct MqttClient {
+    stream: TcpStream,
+    keep_alive: Duration,
+    last_write: Instant,
+}
+
+impl MqttClient {
+    pub fn connect(options: &MqttOptions) -> Result<MqttClient, Error> {
+        // Without a port, the address can't be parsed, so the default port is added
+        let addr = match options.server.to_socket_addrs() {
+            Ok(mut addrs) => addrs.next(),
+            Err(_) => (options.server.trim_matches(['[', ']']), DEFAULT_PORT).to_socket_addrs()?.next(),
+        }.ok_or(Error::from(ErrorKind::AddrNotAvailable))?;
+        let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))?;
+        
+        let mut flags = 0x02; // Clean session
+        let mut payload = string(&options.client_id);
+        if let Some(will) = &options.will {
+            flags |= 0x04 | if will.retain { 0x20 } else { 0 };
+            payload.extend(string(&will.topic));
+            payload.extend(string(&will.payload));
+        }
+        if let Some(username) = &options.username {
+            flags |= 0x80;
+            payload.extend(string(username));
+        }
+        if let Some(password) = &options.password {
+            flags |= 0x40;
+            payload.extend(string(password));
+        }
+        let mut body = string("MQTT");
+        body.push(4); // Protocol level 4 is MQTT 3.1.1
+        body.push(flags);
+        body.extend_from_slice(&(options.keep_alive.as_secs().min(u16::MAX as u64) as u16).to_be_bytes());
+        body.extend(payload);
+        stream.write_all(&packet(CONNECT, &body))?;
+        
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != CONNACK {
+            return Err(Error::new(ErrorKind::InvalidData, "not an MQTT broker"));
+        }
+        match connack[3] {
+            0 => {},
+            4 | 5 => return Err(Error::new(ErrorKind::PermissionDenied, "the broker refused the username or password")),
+            code => return Err(Error::new(ErrorKind::ConnectionRefused, format!("the broker refused the connection (code {})", code))),
+        }
+        
+        // Nothing is subscribed to, so all that comes back are ping responses, which are thrown away
+        stream.set_read_timeout(None)?;
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || {
+            let mut buf = [0u8; 256];
+            while matches!(reader.read(&mut buf), Ok(len) if len > 0) {}
+        });
+        Ok(MqttClient { stream, keep_alive: options.keep_alive, last_write: Instant::now() })
+    }
+
+    pub fn publish(&mut self, message: &Message) -> Result<(), Error> {
+        let mut body = string(&message.topic);
+        body.extend_from_slice(message.payload.as_bytes());
+        let flags = if message.retain { 0x01 } else { 0 };
+        self.write(&packet(PUBLISH | flags, &body))
+    }
+
+    /// Pings the broker if nothing's been sent for a while, so it doesn't close the connection
+    pub fn keep_alive(&mut self) -> Result<(), Error> {
+        if self.last_write.elapsed() >= self.keep_alive / 2 {
+            self.write(&packet(PINGREQ, &[]))?;
+        }
+        Ok(())
+    }
+
+    /// Disconnects cleanly, so the broker doesn't publish the will
+    pub fn disconnect(mut self) {
+        let _ = self.stream.write_all(&packet(DISCONNECT, &[]));
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+
+    fn write(&mut self, packet: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(packet)?;
+        self.last_write = Instant::now();
+        Ok(())
+    }
+}
+
+/// Makes a host name safe to use as one level of a topic, as '/' separates levels and '+' and '#' are wildcards
+pub fn topic_level(name: &str) -> String {
+    name.replace(['/', '+', '#'], "_")
+}
+
+/// A fixed header (with the remaining length encoded 7 bits at a time), followed by the body
+fn packet(packet_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![packet_type];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// A UTF-8 string with its length in front
+fn string(s: &str) -> Vec<u8> {
+    let mut encoded = (s.len() as u16).to_be_bytes().to_vec();
+    encoded.extend_from_slice(s.as_bytes());
+    encoded
+}
@@ -0,0 +1,126 @@
+//! Path MTU discovery: binary-searches the largest echo request that gets to a host without being
+//! fragmented, using the "fragmentation needed"/"packet too big" errors the kernel learns about
+
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use nix::sys::socket::sockopt::IpMtu;
+use nix::sys::socket::{getsockopt, setsockopt};
+use nix::{getsockopt_impl, setsockopt_impl, sockopt_impl};
+use socket2::Socket;
+
+use crate::probe::{ConnectedIcmpProbe, Probe};
+use crate::{HostInfo, MIN_PAYLOAD_SIZE};
+
+/// Bytes of IPv4 and ICMP headers in front of the payload
+const IPV4_OVERHEAD: usize = 20 + 8;
+/// Bytes of IPv6 and ICMPv6 headers in front of the payload
+const IPV6_OVERHEAD: usize = 40 + 8;
+
+// nix only has IpMtu, so the others are made the same way it makes its own
+sockopt_impl!(
+    /// How path MTU discovery is done on an IPv4 socket (one of the IP_PMTUDISC_ values)
+    IpMtuDiscover, SetOnly, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, libc::c_int
+);
+sockopt_impl!(
+    /// How path MTU discovery is done on an IPv6 socket (one of the IPV6_PMTUDISC_ values)
+    Ipv6MtuDiscover, SetOnly, libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, libc::c_int
+);
+sockopt_impl!(
+    /// The path MTU of a connected IPv6 socket
+    Ipv6Mtu, GetOnly, libc::IPPROTO_IPV6, libc::IPV6_MTU, libc::c_int
+);
+
+/// What happened to one echo request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// A reply came back
+    Fits,
+    /// The kernel (or a router, through it) says the packet's too big. Has the path MTU if it's known
+    TooBig(Option<usize>),
+    /// No reply and no error, even after retrying. Counted as too big, as it's probably being dropped
+    Lost,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathMtu {
+    /// The largest payload that got a reply
+    pub payload: usize,
+    /// The largest packet (including the IP and ICMP headers) that got a reply
+    pub mtu: usize,
+}
+
+/// Finds the path MTU to an ICMP host. Each size is tried up to `attempts` times, waiting up to
+/// `timeout` each time, and `progress` is told what happened to every size that's tried
+pub fn discover<F>(hinfo: &HostInfo, timeout: Duration, attempts: u32, mut progress: F) -> Result<PathMtu, Error>
+where F: FnMut(usize, Outcome) {
+    let overhead = if hinfo.host.is_ipv4() { IPV4_OVERHEAD } else { IPV6_OVERHEAD };
+    let mut probe = ConnectedIcmpProbe::new(hinfo.clone())?;
+    set_dont_fragment(probe.socket(), hinfo.host.is_ipv4())?;
+    
+    let mut try_size = |probe: &mut ConnectedIcmpProbe, size: usize| -> Result<Outcome, Error> {
+        probe.set_payload_size(size);
+        let mut outcome = Outcome::Lost;
+        for _ in 0..attempts {
+            let result = probe.send().and_then(|_| probe.match_reply(timeout));
+            outcome = match result {
+                Ok(_) => Outcome::Fits,
+                Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => Outcome::TooBig(path_mtu(probe.socket(), hinfo.host.is_ipv4())),
+                // A "fragmentation needed" error may have lowered the path MTU without an error being returned
+                Err(e) if e.kind() == ErrorKind::TimedOut => match path_mtu(probe.socket(), hinfo.host.is_ipv4()) {
+                    Some(mtu) if mtu < size + overhead => Outcome::TooBig(Some(mtu)),
+                    _ => Outcome::Lost,
+                },
+                // Dropped on the way out, e.g. by a link with a smaller MTU than ours
+                Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => Outcome::Lost,
+                Err(e) => return Err(e),
+            };
+            if outcome != Outcome::Lost {
+                break;
+            }
+        }
+        progress(size, outcome);
+        Ok(outcome)
+    };
+    
+    // The smallest payload has to get through, or nothing will
+    let mut good = MIN_PAYLOAD_SIZE;
+    if try_size(&mut probe, good)? != Outcome::Fits {
+        return Err(Error::new(ErrorKind::TimedOut, "no reply, even to the smallest packets"));
+    }
+    // The MTU of the route is as big as it can be. One more than the biggest payload that could fit is known not to
+    let route_mtu = path_mtu(probe.socket(), hinfo.host.is_ipv4()).unwrap_or(65535);
+    let mut bad = route_mtu.saturating_sub(overhead) + 1;
+    let mut next = bad - 1;
+    while bad - good > 1 {
+        match try_size(&mut probe, next)? {
+            Outcome::Fits => good = next,
+            Outcome::TooBig(mtu) => {
+                bad = next;
+                // Jump straight to the size the MTU says should fit, if it's useful
+                if let Some(payload) = mtu.map(|m| m.saturating_sub(overhead)) && payload > good && payload < bad {
+                    next = payload;
+                    continue;
+                }
+            },
+            Outcome::Lost => bad = next,
+        }
+        next = good + (bad - good) / 2;
+    }
+    Ok(PathMtu { payload: good, mtu: good + overhead })
+}
+
+/// Sets the "don't fragment" bit, and makes sending anything bigger than the known path MTU fail with EMSGSIZE
+fn set_dont_fragment(socket: &Socket, ipv4: bool) -> Result<(), Error> {
+    let result = if ipv4 {
+        setsockopt(socket, IpMtuDiscover, &libc::IP_PMTUDISC_DO)
+    } else {
+        setsockopt(socket, Ipv6MtuDiscover, &libc::IPV6_PMTUDISC_DO)
+    };
+    result.map_err(Error::from)
+}
+
+/// The kernel's idea of the path MTU to the host the socket is connected to
+fn path_mtu(socket: &Socket, ipv4: bool) -> Option<usize> {
+    let mtu = if ipv4 { getsockopt(socket, IpMtu) } else { getsockopt(socket, Ipv6Mtu) };
+    mtu.ok().filter(|mtu| *mtu > 0).map(|mtu| mtu as usize)
+}
@@ -0,0 +1,107 @@
+//! Nagios/Icinga plugin output: a status line with performance data, and the exit code that goes with it
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::str::FromStr;
+
+use crate::HostInfo;
+
+/// Round trip average and packet loss limits, written like check_ping's, e.g. "100.0,20%"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Threshold {
+    pub rta_ms: f64,
+    pub loss_percent: f64,
+}
+
+impl FromStr for Threshold {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Threshold, Error> {
+        let invalid = || Error::new(ErrorKind::InvalidInput, format!("{:?} should look like 100.0,20%", s));
+        let (rta, loss) = s.split_once(',').ok_or_else(invalid)?;
+        let rta_ms = rta.trim().parse().map_err(|_| invalid())?;
+        let loss_percent = loss.trim().trim_end_matches('%').parse().map_err(|_| invalid())?;
+        Ok(Threshold { rta_ms, loss_percent })
+    }
+}
+
+/// Plugin states, in order of severity
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl Status {
+    /// The exit code Nagios expects for this state
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Status::Ok => 0,
+            Status::Warning => 1,
+            Status::Critical => 2,
+            Status::Unknown => 3,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown => "UNKNOWN",
+        })
+    }
+}
+
+/// Percentage of requests that weren't answered
+fn loss(host: &HostInfo) -> f64 {
+    if host.pings_sent == 0 {
+        return 100.0;
+    }
+    host.pings_sent.saturating_sub(host.successful) as f64 * 100.0 / host.pings_sent as f64
+}
+
+/// The state of one host. A host that never replied is critical, whatever the thresholds are
+pub fn host_status(host: &HostInfo, warning: Threshold, critical: Threshold) -> Status {
+    if host.successful == 0 {
+        return Status::Critical;
+    }
+    let (rta, loss) = (host.average() as f64, loss(host));
+    if rta >= critical.rta_ms || loss >= critical.loss_percent {
+        Status::Critical
+    } else if rta >= warning.rta_ms || loss >= warning.loss_percent {
+        Status::Warning
+    } else {
+        Status::Ok
+    }
+}
+
+/// Builds the plugin output for every host, e.g.
+/// "PING OK - example.com: rta 12.345ms, lost 0% | 'example.com rta'=12.345ms;100.000;500.000;0; 'example.com pl'=0%;20;60;0",
+/// and returns it with the worst host's state
+pub fn report(hosts: &[HostInfo], warning: Threshold, critical: Threshold) -> (Status, String) {
+    let mut worst = Status::Ok;
+    let mut summaries = Vec::new();
+    let mut perfdata = Vec::new();
+    for host in hosts {
+        let status = host_status(host, warning, critical);
+        worst = worst.max(status);
+        let rta = if host.successful > 0 { format!("{:.3}ms", host.average()) } else { "-".to_string() };
+        let label = perfdata_label(&host.host_str);
+        summaries.push(format!("{}: rta {}, lost {:.0}%", host.host_str, rta, loss(host)));
+        // Hosts that never replied have no round trip average, which is written as "U" (undetermined)
+        let rta_value = if host.successful > 0 { format!("{:.3}ms", host.average()) } else { "U".to_string() };
+        perfdata.push(format!("'{} rta'={};{:.3};{:.3};0;", label, rta_value, warning.rta_ms, critical.rta_ms));
+        perfdata.push(format!("'{} pl'={:.0}%;{};{};0", label, loss(host), warning.loss_percent, critical.loss_percent));
+    }
+    (worst, format!("PING {} - {} | {}", worst, summaries.join("; "), perfdata.join(" ")))
+}
+
+/// Perfdata labels are quoted, so they can't contain quotes or equals signs
+fn perfdata_label(host: &str) -> String {
+    host.replace(['\'', '='], "_")
+}
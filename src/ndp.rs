@@ -0,0 +1,100 @@
+//! NDP ping: the IPv6 equivalent of ARP ping. Sends a Neighbor Solicitation for the target and
+//! times how long the Neighbor Advertisement takes, which only works for on-link neighbours
+
+use std::io::{Error, ErrorKind, Read};
+use std::net::{Ipv6Addr, SocketAddrV6};
+use std::time::{Duration, Instant};
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::{if_indextoname, if_nametoindex};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::arp::interface_mac;
+use crate::icmp::*;
+use crate::helper::{self, HelperSocket};
+use crate::privileges::{self, SocketKind};
+use crate::probe::{Probe, ProbeReply};
+
+/// Finds the index and MAC address of the interface the target is on.
+/// Link-local targets need a scope ID (e.g. fe80::1%eth0), as they could be on any interface
+pub fn find_interface(target: Ipv6Addr, scope_id: u32) -> Result<(u32, [u8; 6]), Error> {
+    let addrs: Vec<_> = getifaddrs().map_err(Error::from)?.collect();
+    if scope_id != 0 {
+        let name = if_indextoname(scope_id).map_err(Error::from)?;
+        return Ok((scope_id, interface_mac(&addrs, &name.to_string_lossy())?));
+    }
+    for ifaddr in &addrs {
+        let Some(ip) = ifaddr.address.as_ref().and_then(|a| a.as_sockaddr_in6()).map(|a| a.ip()) else { continue };
+        let Some(mask) = ifaddr.netmask.as_ref().and_then(|a| a.as_sockaddr_in6()).map(|a| a.ip()) else { continue };
+        if ip.is_loopback() || ip.to_bits() & mask.to_bits() != target.to_bits() & mask.to_bits() {
+            continue;
+        }
+        let mac = interface_mac(&addrs, &ifaddr.interface_name)?;
+        let index = if_nametoindex(ifaddr.interface_name.as_str()).map_err(Error::from)?;
+        return Ok((index, mac));
+    }
+    Err(Error::new(ErrorKind::AddrNotAvailable, format!("{} is not on a local subnet", target)))
+}
+
+/// Sends Neighbor Solicitations for a target, and waits for the advertisements
+pub struct NdpProbe {
+    target: Ipv6Addr,
+    index: u32,
+    mac: [u8; 6],
+    socket: Socket,
+    sent_at: Instant,
+}
+
+impl NdpProbe {
+    pub fn new(target: Ipv6Addr, scope_id: u32) -> Result<NdpProbe, Error> {
+        let (index, mac) = find_interface(target, scope_id)?;
+        let socket = Socket::new(Domain::IPV6, Type::RAW, Some(Protocol::ICMPV6))
+            .or_else(|e| helper::fallback(e, HelperSocket::RawIcmp6))
+            .map_err(|e| privileges::explain(e, SocketKind::Raw))?;
+        // Neighbours ignore NDP messages that might have come through a router
+        socket.set_multicast_hops_v6(255)?;
+        socket.set_unicast_hops_v6(255)?;
+        socket.set_multicast_if_v6(index)?;
+        Ok(NdpProbe { target, index, mac, socket, sent_at: Instant::now() })
+    }
+}
+
+impl Probe for NdpProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        let destination = SocketAddrV6::new(solicited_node_address(self.target), 0, 0, self.index);
+        self.sent_at = Instant::now();
+        self.socket.send_to(&construct_neighbor_solicitation(self.target, self.mac), &destination.into())?;
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let mut buf = [0u8; 1500];
+        loop {
+            let remaining = timeout.saturating_sub(self.sent_at.elapsed());
+            if remaining.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+            let len = match (&self.socket).read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::TimedOut.into()),
+                Err(e) => return Err(e),
+            };
+            // Every ICMPv6 message arrives on a raw socket, so look for the right one
+            let maybe_type = ICMPv6Packet::new(&buf[..len]).and_then(|packet| packet.message_type());
+            if let Ok(ICMPv6Type::NeighborAdvertisement { target, .. }) = maybe_type
+                && target == self.target {
+                return Ok(ProbeReply::new(self.sent_at.elapsed().as_micros() as u64));
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("NDP neighbor solicitation for {}", self.target)
+    }
+}
+
+/// Neighbor Solicitations are sent to ff02::1:ffXX:XXXX, where the Xs are the last 24 bits of the target
+fn solicited_node_address(target: Ipv6Addr) -> Ipv6Addr {
+    let octets = target.octets();
+    Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00 | octets[13] as u16, u16::from_be_bytes([octets[14], octets[15]]))
+}
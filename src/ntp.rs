@@ -0,0 +1,111 @@
+//! NTP client-mode probe, which reports the server's clock offset and stratum as well as the delay
+
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::probe::{Probe, ProbeDetails, ProbeReply};
+
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+#[derive(Clone, Copy, Debug)]
+pub struct NtpReply {
+    /// Round-trip delay in microseconds, not counting the time the server spent replying
+    pub delay: u64,
+    /// How far ahead of our clock the server's clock is, in microseconds
+    pub offset: i64,
+    pub stratum: u8,
+}
+
+/// Sends client requests to an NTP server, and waits for its replies
+pub struct NtpProbe {
+    server: SocketAddr,
+    socket: UdpSocket,
+    /// Our transmit time from the last request, which the server copies into its reply
+    transmit: [u8; 8],
+    sent_at: Instant,
+}
+
+impl NtpProbe {
+    pub fn new(server: SocketAddr) -> Result<NtpProbe, Error> {
+        let bind_addr = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(server)?;
+        Ok(NtpProbe { server, socket, transmit: [0; 8], sent_at: Instant::now() })
+    }
+
+    /// Waits up to `timeout` for the server's reply to the last request
+    pub fn receive(&mut self, timeout: Duration) -> Result<NtpReply, Error> {
+        let mut reply = [0u8; 68];
+        loop {
+            let remaining = timeout.saturating_sub(self.sent_at.elapsed());
+            if remaining.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+            let len = match self.socket.recv(&mut reply) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::TimedOut.into()),
+                Err(e) => return Err(e),
+            };
+            let t4 = ntp_now();
+            // The server copies our transmit time into the originate field, so stale replies can be ignored
+            if len < 48 || reply[24..32] != self.transmit {
+                continue;
+            }
+            let stratum = reply[1];
+            if stratum == 0 {
+                // "Kiss-o'-Death", meaning the server doesn't want to talk to us
+                return Err(ErrorKind::ConnectionRefused.into());
+            }
+
+            let t1 = u64::from_be_bytes(self.transmit);
+            let t2 = u64::from_be_bytes(reply[32..40].try_into().unwrap());
+            let t3 = u64::from_be_bytes(reply[40..48].try_into().unwrap());
+            let (t1, t2, t3, t4) = (to_micros(t1), to_micros(t2), to_micros(t3), to_micros(t4));
+            let delay = (t4 - t1) - (t3 - t2);
+            let offset = ((t2 - t1) + (t3 - t4)) / 2;
+            return Ok(NtpReply { delay: delay.max(0) as u64, offset, stratum });
+        }
+    }
+}
+
+impl Probe for NtpProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        let mut request = [0u8; 48];
+        request[0] = 0x23; // Leap indicator 0, version 4, mode 3 (client)
+        self.transmit = ntp_now().to_be_bytes();
+        request[40..48].copy_from_slice(&self.transmit);
+        self.sent_at = Instant::now();
+        self.socket.send(&request)?;
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let reply = self.receive(timeout)?;
+        Ok(ProbeReply {
+            latency: reply.delay,
+            details: Some(ProbeDetails::Ntp { offset: reply.offset, stratum: reply.stratum }),
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!("NTP request to {}", self.server)
+    }
+}
+
+/// The current time as an NTP timestamp (32 bits of seconds, 32 bits of fraction)
+fn ntp_now() -> u64 {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    let secs = now.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((now.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (secs << 32) | fraction
+}
+
+/// Converts an NTP timestamp into microseconds since the NTP epoch
+fn to_micros(timestamp: u64) -> i64 {
+    let secs = (timestamp >> 32) as i64;
+    let fraction = ((timestamp & 0xffff_ffff) * 1_000_000) >> 32;
+    secs * 1_000_000 + fraction as i64
+}
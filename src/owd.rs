@@ -0,0 +1,107 @@
+//! One-way delay probe, which times UDP packets to and from `multiping reflect` running on the other machine.
+//! The reflector adds when it received each packet and when it sent it back, so if both clocks are synchronised
+//! (with NTP or PTP), the delay each way can be worked out, to tell which direction is congested
+
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::probe::{Probe, ProbeDetails, ProbeReply};
+
+/// The port the reflector listens on, unless it's told otherwise
+pub const DEFAULT_PORT: u16 = 8623;
+
+/// Start of every packet, so that anything else sent to the port is ignored
+const MAGIC: &[u8; 4] = b"MPOW";
+
+/// The magic, then our transmit time, the reflector's receive time and its transmit time
+/// (each in microseconds since the Unix epoch, big-endian)
+const PACKET_LEN: usize = 28;
+
+/// Sends timestamped packets to a reflector, and waits for them to come back
+pub struct OneWayProbe {
+    reflector: SocketAddr,
+    socket: UdpSocket,
+    /// Our transmit time from the last request, which the reflector sends back
+    transmit: u64,
+    sent_at: Instant,
+}
+
+impl OneWayProbe {
+    pub fn new(reflector: SocketAddr) -> Result<OneWayProbe, Error> {
+        let bind_addr = if reflector.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(reflector)?;
+        Ok(OneWayProbe { reflector, socket, transmit: 0, sent_at: Instant::now() })
+    }
+}
+
+impl Probe for OneWayProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        let mut request = [0u8; PACKET_LEN];
+        request[0..4].copy_from_slice(MAGIC);
+        self.transmit = now_micros();
+        request[4..12].copy_from_slice(&self.transmit.to_be_bytes());
+        self.sent_at = Instant::now();
+        self.socket.send(&request)?;
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let mut reply = [0u8; PACKET_LEN];
+        loop {
+            let remaining = timeout.saturating_sub(self.sent_at.elapsed());
+            if remaining.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+            let len = match self.socket.recv(&mut reply) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::TimedOut.into()),
+                Err(e) => return Err(e),
+            };
+            let t4 = now_micros();
+            let round_trip = self.sent_at.elapsed();
+            // Stale replies have an older transmit time in them
+            if len < PACKET_LEN || &reply[0..4] != MAGIC || reply[4..12] != self.transmit.to_be_bytes() {
+                continue;
+            }
+            let t1 = self.transmit as i64;
+            let t2 = u64::from_be_bytes(reply[12..20].try_into().unwrap()) as i64;
+            let t3 = u64::from_be_bytes(reply[20..28].try_into().unwrap()) as i64;
+            let t4 = t4 as i64;
+            // The round trip is timed with our monotonic clock, so it's right even if the clocks aren't
+            let latency = (round_trip.as_micros() as i64 - (t3 - t2)).max(0) as u64;
+            return Ok(ProbeReply {
+                latency,
+                details: Some(ProbeDetails::OneWay { forward: t2 - t1, backward: t4 - t3 }),
+            });
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("UDP to the reflector at {}", self.reflector)
+    }
+}
+
+/// Answers one-way delay probes sent to `address`, until there's an error
+pub fn reflect(address: SocketAddr) -> Result<(), Error> {
+    let socket = UdpSocket::bind(address)?;
+    let mut packet = [0u8; PACKET_LEN];
+    loop {
+        let (len, from) = socket.recv_from(&mut packet)?;
+        let receive = now_micros();
+        if len < PACKET_LEN || &packet[0..4] != MAGIC {
+            continue;
+        }
+        packet[12..20].copy_from_slice(&receive.to_be_bytes());
+        packet[20..28].copy_from_slice(&now_micros().to_be_bytes());
+        // The sender may have gone away, which shouldn't stop the others from being answered
+        let _ = socket.send_to(&packet, from);
+    }
+}
+
+/// The current time in microseconds since the Unix epoch
+fn now_micros() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
@@ -0,0 +1,123 @@
+//! Working out why a socket couldn't be created, so the error can say how to fix it rather than
+//! just "permission denied"
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+/// Linux's capability number for CAP_NET_RAW
+#[cfg(target_os = "linux")]
+const CAP_NET_RAW: u32 = 13;
+
+/// What sort of socket was being created, as they need different permissions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketKind {
+    /// Unprivileged ICMP ("ping") sockets, allowed for the groups in net.ipv4.ping_group_range
+    Ping,
+    /// Raw and packet sockets (used for ARP and NDP), which need CAP_NET_RAW
+    Raw,
+}
+
+/// Why creating a socket wasn't allowed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cause {
+    /// None of our groups are in net.ipv4.ping_group_range (which covers IPv6 too)
+    PingGroupRange { low: u32, high: u32, groups: Vec<u32> },
+    MissingCapNetRaw,
+    /// Everything else looks fine, but SELinux is enforcing, so a policy is probably denying it
+    SeLinux,
+    Unknown,
+}
+
+/// A socket couldn't be created because of missing permissions. This is what's inside the
+/// `std::io::Error` returned by `mkv4socket` and friends, and can be got with `get_ref` and `downcast_ref`
+#[derive(Debug)]
+pub struct PermissionError {
+    pub kind: SocketKind,
+    pub cause: Cause,
+    pub source: Error,
+}
+
+impl fmt::Display for PermissionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let socket = match self.kind {
+            SocketKind::Ping => "an ICMP socket",
+            SocketKind::Raw => "a raw socket",
+        };
+        write!(f, "not allowed to create {} ({}). ", socket, self.source)?;
+        let exe = std::env::current_exe().map_or("multiping".to_string(), |p| p.display().to_string());
+        match &self.cause {
+            Cause::PingGroupRange { low, high, groups } => write!(f,
+                "None of your groups ({}) are in net.ipv4.ping_group_range ({} {}). To allow every group, run: \
+                sudo sysctl -w net.ipv4.ping_group_range=\"0 2147483647\"",
+                groups.iter().map(|g| g.to_string()).collect::<Vec<_>>().join(", "), low, high),
+            Cause::MissingCapNetRaw => write!(f,
+                "This needs CAP_NET_RAW. Run as root, or give the binary the capability with: sudo setcap cap_net_raw+ep {}", exe),
+            Cause::SeLinux => write!(f,
+                "SELinux is enforcing and is probably denying it. Check the audit log with: sudo ausearch -m avc -ts recent"),
+            Cause::Unknown => write!(f, "Try running as root"),
+        }
+    }
+}
+
+impl std::error::Error for PermissionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// If creating a socket failed with EACCES or EPERM, works out why and returns an error explaining it.
+/// Other errors are returned as they are
+pub fn explain(error: Error, kind: SocketKind) -> Error {
+    let denied = error.kind() == ErrorKind::PermissionDenied
+        || matches!(error.raw_os_error(), Some(libc::EACCES) | Some(libc::EPERM));
+    if !denied {
+        return error;
+    }
+    let cause = diagnose(kind);
+    Error::new(ErrorKind::PermissionDenied, PermissionError { kind, cause, source: error })
+}
+
+/// Looks for the reason this process can't create the kind of socket
+#[cfg(target_os = "linux")]
+pub fn diagnose(kind: SocketKind) -> Cause {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    let field = |name: &str| status.lines().find_map(|l| l.strip_prefix(name)).map(str::trim);
+    match kind {
+        SocketKind::Ping => {
+            if let Some((low, high)) = ping_group_range() {
+                // The effective GID is the second one on the Gid line
+                let mut groups: Vec<u32> = field("Gid:").and_then(|g| g.split_whitespace().nth(1)?.parse().ok())
+                    .into_iter().collect();
+                groups.extend(field("Groups:").unwrap_or_default().split_whitespace().filter_map(|g| g.parse::<u32>().ok()));
+                groups.dedup();
+                if !groups.iter().any(|g| (low..=high).contains(g)) {
+                    return Cause::PingGroupRange { low, high, groups };
+                }
+            }
+        },
+        SocketKind::Raw => {
+            let effective = field("CapEff:").and_then(|c| u64::from_str_radix(c, 16).ok()).unwrap_or(0);
+            if effective & (1 << CAP_NET_RAW) == 0 {
+                return Cause::MissingCapNetRaw;
+            }
+        },
+    }
+    if std::fs::read_to_string("/sys/fs/selinux/enforce").is_ok_and(|e| e.trim() == "1") {
+        return Cause::SeLinux;
+    }
+    Cause::Unknown
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn diagnose(_kind: SocketKind) -> Cause {
+    Cause::Unknown
+}
+
+/// The range of group IDs allowed to create ping sockets. If the low end is bigger than the
+/// high end (the default, "1 0"), nobody is
+#[cfg(target_os = "linux")]
+pub(crate) fn ping_group_range() -> Option<(u32, u32)> {
+    let range = std::fs::read_to_string("/proc/sys/net/ipv4/ping_group_range").ok()?;
+    let mut parts = range.split_whitespace().map(|p| p.parse().ok());
+    Some((parts.next()??, parts.next()??))
+}
@@ -0,0 +1,402 @@
+//! The different ways of probing a host, and the `Probe` trait they implement so that the same
+//! scheduler, statistics and display can be used for every one of them. The exception is ICMP on the
+//! shared sockets, which the scheduler sends itself so that every host can use the same few sockets,
+//! with the replies matched up by a listening thread. `ConnectedIcmpProbe` is ICMP as a `Probe`, with
+//! a socket of its own (--connected-sockets)
+
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{CorruptedReply, DEFAULT_PAYLOAD_SIZE, HostInfo, MalformedReply, mkv4socket, mkv6socket, next_sequence, receive_ping, reserve_receive_buffer, send_ping};
+
+/// A way of checking whether a host is reachable, and how long it takes to answer.
+/// Each probe is used by one thread, which calls `send` then `match_reply` every interval
+pub trait Probe: Send {
+    /// Sends a request to the host
+    fn send(&mut self) -> Result<(), Error>;
+
+    /// Waits up to `timeout` for the reply to the last request, ignoring anything that isn't it.
+    /// Returns an error of kind `TimedOut` if no reply came (which counts as a lost ping).
+    /// Probes that use connections (like TCP) can do all of their work here
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error>;
+
+    /// Short description of what's being probed, e.g. "TCP connect to 192.0.2.1:443"
+    fn describe(&self) -> String;
+
+    /// The sequence number of the last request, for probes that number them (used by the verbose log)
+    fn sequence(&self) -> Option<u16> {
+        None
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProbeReply {
+    /// Time between sending the request and getting the reply, in microseconds
+    pub latency: u64,
+    pub details: Option<ProbeDetails>,
+}
+
+impl ProbeReply {
+    pub fn new(latency: u64) -> ProbeReply {
+        ProbeReply { latency, details: None }
+    }
+}
+
+/// Every kind of error that probing can report, so that they can be read back from their names or descriptions
+pub(crate) const ERROR_KINDS: [ErrorKind; 23] = [
+    ErrorKind::NotFound, ErrorKind::PermissionDenied, ErrorKind::ConnectionRefused, ErrorKind::ConnectionReset,
+    ErrorKind::HostUnreachable, ErrorKind::NetworkUnreachable, ErrorKind::ConnectionAborted, ErrorKind::NotConnected,
+    ErrorKind::AddrInUse, ErrorKind::AddrNotAvailable, ErrorKind::NetworkDown, ErrorKind::BrokenPipe,
+    ErrorKind::AlreadyExists, ErrorKind::WouldBlock, ErrorKind::InvalidInput, ErrorKind::InvalidData,
+    ErrorKind::TimedOut, ErrorKind::WriteZero, ErrorKind::Interrupted, ErrorKind::Unsupported,
+    ErrorKind::UnexpectedEof, ErrorKind::OutOfMemory, ErrorKind::Other,
+];
+
+/// How a host is pinged. Chosen by prefixing the host with the probe's name, e.g. "arp:192.168.1.1"
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProbeType {
+    Icmp,
+    Arp, // Only for IPv4 hosts on a local subnet
+    Ndp, // Only for IPv6 hosts on a local subnet
+    Ntp, // Asks an NTP server for the time
+    OneWay, // Sends UDP packets to `multiping reflect` on the host, to time each direction (e.g. "owd:example.com")
+    Timestamp, // Asks for the time with ICMP timestamp requests. Only for IPv4 hosts, and needs a raw socket
+    Tcp, // Times a TCP connection (e.g. "tcp:example.com:22")
+    Udp, // Sends a UDP datagram, and waits for a reply or a port unreachable error (e.g. "udp:example.com:53")
+    Http, // Times an HTTP GET request, up to the status line (e.g. "http:example.com:8080/health")
+    Tls, // Times a TCP connection and TLS handshake (e.g. "tls:example.com:443")
+    Quic, // Times a QUIC handshake (e.g. "quic:example.com:443"). Needs the "quic" feature
+    Custom, // A probe supplied by a library user, with `Pinger::add_probe`
+}
+
+/// Extra information reported by some types of probe
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProbeDetails {
+    Ntp {
+        offset: i64, // Microseconds the server's clock is ahead of ours
+        stratum: u8,
+    },
+    Tls {
+        connect: u64, // Microseconds taken by the TCP connection, before the handshake started
+        expires: Option<i64>, // When the certificate expires, in seconds since the Unix epoch
+    },
+    ClockOffset {
+        offset: i64, // Microseconds the host's clock is ahead of ours (to the nearest millisecond)
+    },
+    /// Only meaningful if both clocks are synchronised. Otherwise the difference between them is added to one and taken from the other
+    OneWay {
+        forward: i64, // Microseconds from us to the host
+        backward: i64, // Microseconds from the host back to us
+    },
+}
+
+impl ProbeType {
+    /// Splits the probe prefix (if there is one) from the host
+    pub fn parse(host: &str) -> (ProbeType, &str) {
+        match host.split_once(':') {
+            Some((name, rest)) => match ProbeType::from_name(name) {
+                Some(probe) => (probe, rest),
+                None => (ProbeType::Icmp, host),
+            },
+            None => (ProbeType::Icmp, host),
+        }
+    }
+
+    /// Splits a prefix naming several probe types (e.g. "icmp+tcp:example.com") from the host.
+    /// Returns `None` if the prefix doesn't name more than one
+    pub fn parse_multiple(host: &str) -> Option<(Vec<ProbeType>, &str)> {
+        let (names, rest) = host.split_once(':')?;
+        if !names.contains('+') {
+            return None;
+        }
+        let probes: Option<Vec<ProbeType>> = names.split('+').map(ProbeType::from_name).collect();
+        Some((probes?, rest))
+    }
+
+    /// The probe type with the given prefix, e.g. "tcp". Custom probes can't be chosen by name
+    pub fn from_name(name: &str) -> Option<ProbeType> {
+        match name {
+            "icmp" => Some(ProbeType::Icmp),
+            "arp" => Some(ProbeType::Arp),
+            "ndp" => Some(ProbeType::Ndp),
+            "ntp" => Some(ProbeType::Ntp),
+            "owd" => Some(ProbeType::OneWay),
+            "timestamp" => Some(ProbeType::Timestamp),
+            "tcp" => Some(ProbeType::Tcp),
+            "udp" => Some(ProbeType::Udp),
+            "http" => Some(ProbeType::Http),
+            "tls" => Some(ProbeType::Tls),
+            "quic" => Some(ProbeType::Quic),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProbeType::Icmp => "icmp",
+            ProbeType::Arp => "arp",
+            ProbeType::Ndp => "ndp",
+            ProbeType::Ntp => "ntp",
+            ProbeType::OneWay => "owd",
+            ProbeType::Timestamp => "timestamp",
+            ProbeType::Tcp => "tcp",
+            ProbeType::Udp => "udp",
+            ProbeType::Http => "http",
+            ProbeType::Tls => "tls",
+            ProbeType::Quic => "quic",
+            ProbeType::Custom => "custom",
+        }
+    }
+
+    /// The IP version a probe has to use, if it only works with one
+    pub fn ip_version(&self) -> Option<u8> {
+        match self {
+            ProbeType::Arp | ProbeType::Timestamp => Some(4),
+            ProbeType::Ndp => Some(6),
+            _ => None,
+        }
+    }
+
+    /// The port used if the host doesn't specify one, for probes that use ports
+    pub fn default_port(&self) -> Option<u16> {
+        match self {
+            ProbeType::Ntp => Some(123),
+            ProbeType::OneWay => Some(crate::owd::DEFAULT_PORT),
+            ProbeType::Tcp | ProbeType::Tls | ProbeType::Quic => Some(443),
+            ProbeType::Udp => Some(UDP_DEFAULT_PORT),
+            ProbeType::Http => Some(80),
+            _ => None,
+        }
+    }
+}
+
+/// Splits a port (if there is one) from the end of an address. IPv6 addresses
+/// need to be in brackets to have a port, e.g. "[::1]:443". Anything after a slash
+/// is a path, which only HTTP probes use, so it's left out. A port that isn't a
+/// number from 0 to 65535 is an error of kind `InvalidInput`
+pub(crate) fn split_port(address: &str) -> Result<(&str, Option<u16>), Error> {
+    let address = address.split_once('/').map_or(address, |(address, _)| address);
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("invalid port in {}", address));
+    let (host, port) = if let Some(rest) = address.strip_prefix('[') && let Some((ip, after)) = rest.split_once(']') {
+        match after.strip_prefix(':') {
+            Some(port) => (ip, Some(port)),
+            None if after.is_empty() => (ip, None),
+            None => return Err(invalid()),
+        }
+    } else {
+        match address.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (address, None),
+        }
+    };
+    Ok((host, port.map(|port| port.parse().map_err(|_| invalid())).transpose()?))
+}
+
+/// Creates the probe for a host, for every type except ICMP (which shares sockets between hosts,
+/// unless `ConnectedIcmpProbe` is used) and custom probes (which are supplied already made)
+pub fn new_probe(hinfo: &HostInfo) -> Result<Box<dyn Probe>, Error> {
+    // The host name (without the probe type or port), which TLS and QUIC send to the server
+    #[cfg(feature = "tls")]
+    let server_name = split_port(ProbeType::parse(&hinfo.host_str).1)?.0;
+    match (hinfo.probe, hinfo.host) {
+        #[cfg(target_os = "linux")]
+        (ProbeType::Arp, SocketAddr::V4(addr)) => Ok(Box::new(crate::arp::ArpProbe::new(*addr.ip())?)),
+        #[cfg(target_os = "linux")]
+        (ProbeType::Ndp, SocketAddr::V6(addr)) => Ok(Box::new(crate::ndp::NdpProbe::new(*addr.ip(), addr.scope_id())?)),
+        (ProbeType::Ntp, addr) => Ok(Box::new(crate::ntp::NtpProbe::new(addr)?)),
+        (ProbeType::OneWay, addr) => Ok(Box::new(crate::owd::OneWayProbe::new(addr)?)),
+        (ProbeType::Timestamp, SocketAddr::V4(addr)) => Ok(Box::new(crate::timestamp::TimestampProbe::new(addr)?)),
+        (ProbeType::Tcp, addr) => Ok(Box::new(TcpProbe { source: hinfo.source, ..TcpProbe::new(addr) })),
+        (ProbeType::Udp, addr) => Ok(Box::new(UdpProbe::new(addr, hinfo.source)?)),
+        (ProbeType::Http, addr) => {
+            let mut probe = crate::http::HttpProbe::new(addr, ProbeType::parse(&hinfo.host_str).1);
+            probe.source = hinfo.source;
+            Ok(Box::new(probe))
+        },
+        #[cfg(feature = "tls")]
+        (ProbeType::Tls, addr) => Ok(Box::new(crate::tls::TlsProbe::new(addr, server_name))),
+        #[cfg(feature = "quic")]
+        (ProbeType::Quic, addr) => Ok(Box::new(crate::quic::QuicProbe::new(addr, server_name))),
+        _ => Err(ErrorKind::Unsupported.into()),
+    }
+}
+
+/// Times how long it takes to open a TCP connection, which is closed straight away
+pub struct TcpProbe {
+    addr: SocketAddr,
+    /// The local address to connect from, or None to let the kernel choose
+    pub source: Option<IpAddr>,
+}
+
+impl TcpProbe {
+    pub fn new(addr: SocketAddr) -> TcpProbe {
+        TcpProbe { addr, source: None }
+    }
+}
+
+impl Probe for TcpProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let start = Instant::now();
+        connect_tcp(self.addr, self.source, timeout)?;
+        Ok(ProbeReply::new(start.elapsed().as_micros() as u64))
+    }
+
+    fn describe(&self) -> String {
+        match self.source {
+            Some(source) => format!("TCP connect to {} from {}", self.addr, source),
+            None => format!("TCP connect to {}", self.addr),
+        }
+    }
+}
+
+/// Opens a TCP connection to `addr`, from `source` if it's given
+pub(crate) fn connect_tcp(addr: SocketAddr, source: Option<IpAddr>, timeout: Duration) -> Result<TcpStream, Error> {
+    let Some(source) = source else {
+        return TcpStream::connect_timeout(&addr, timeout);
+    };
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.bind(&SocketAddr::new(source, 0).into())?;
+    socket.connect_timeout(&addr.into(), timeout)?;
+    Ok(socket.into())
+}
+
+/// The port UDP probes use if the host doesn't give one: the first one traceroute uses, as nothing's meant to
+/// listen on it, so the host answers with a port unreachable error
+pub const UDP_DEFAULT_PORT: u16 = 33434;
+
+/// Sends a UDP datagram, and times how long the host takes to answer it. Either a reply or an ICMP port unreachable
+/// error (which the kernel reports as the connection being refused) counts, as both mean the datagram got there.
+/// Datagrams don't have sequence numbers, so a late answer to the last one can be taken for the answer to this one
+pub struct UdpProbe {
+    addr: SocketAddr,
+    socket: UdpSocket,
+    sent_at: Instant,
+}
+
+impl UdpProbe {
+    pub fn new(addr: SocketAddr, source: Option<IpAddr>) -> Result<UdpProbe, Error> {
+        let unspecified = if addr.is_ipv4() { IpAddr::from(Ipv4Addr::UNSPECIFIED) } else { IpAddr::from(Ipv6Addr::UNSPECIFIED) };
+        let socket = UdpSocket::bind(SocketAddr::new(source.unwrap_or(unspecified), 0))?;
+        socket.connect(addr)?;
+        Ok(UdpProbe { addr, socket, sent_at: Instant::now() })
+    }
+}
+
+impl Probe for UdpProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        self.sent_at = Instant::now();
+        match self.socket.send(b"multiping") {
+            // The host refusing an earlier datagram after it had timed out, which is reported by the next send
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => self.socket.send(b"multiping").map(|_| ()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let remaining = timeout.saturating_sub(self.sent_at.elapsed());
+        if remaining.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        self.socket.set_read_timeout(Some(remaining))?;
+        let mut reply = [0u8; 1500];
+        match self.socket.recv(&mut reply) {
+            Ok(_) => Ok(ProbeReply::new(self.sent_at.elapsed().as_micros() as u64)),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => Ok(ProbeReply::new(self.sent_at.elapsed().as_micros() as u64)),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Err(ErrorKind::TimedOut.into()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("UDP datagram to {}", self.addr)
+    }
+}
+
+/// Pings one host using its own ICMP socket, which is connected to the host so that
+/// the kernel only gives it that host's replies
+pub struct ConnectedIcmpProbe {
+    hinfo: HostInfo,
+    socket: Socket,
+    payload_size: usize,
+    sent_at: Instant,
+    /// The sequence number of the next request
+    next_sequence: u16,
+    /// The first sequence number after the latest one answered. Replies to earlier ones are duplicates or stale
+    unanswered_from: u16,
+}
+
+impl ConnectedIcmpProbe {
+    pub fn new(hinfo: HostInfo) -> Result<ConnectedIcmpProbe, Error> {
+        let socket = if hinfo.host.is_ipv4() { mkv4socket()? } else { mkv6socket()? };
+        if let Some(source) = hinfo.source {
+            socket.bind(&SocketAddr::new(source, 0).into())?;
+        }
+        socket.connect(&hinfo.host.into())?;
+        Ok(ConnectedIcmpProbe { hinfo, socket, payload_size: DEFAULT_PAYLOAD_SIZE, sent_at: Instant::now(), next_sequence: 0, unanswered_from: 0 })
+    }
+
+    /// Changes how many bytes of data are sent in each request, from the next one on
+    pub fn set_payload_size(&mut self, size: usize) {
+        self.payload_size = size;
+    }
+
+    pub fn socket(&self) -> &Socket {
+        &self.socket
+    }
+}
+
+impl Probe for ConnectedIcmpProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        self.sent_at = Instant::now();
+        send_ping(&self.hinfo, &self.socket, next_sequence(&mut self.next_sequence), self.payload_size)
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        reserve_receive_buffer(self.payload_size);
+        loop {
+            let remaining = timeout.saturating_sub(self.sent_at.elapsed());
+            if remaining.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+            match receive_ping(&self.socket) {
+                // Sequence numbers wrap around, so they're compared by how far they are from the oldest one that could be answered
+                Ok(reply) if reply.sequence.wrapping_sub(self.unanswered_from) < self.next_sequence.wrapping_sub(self.unanswered_from) => {
+                    self.unanswered_from = reply.sequence.wrapping_add(1);
+                    // If the clock changed, the time since the latest request was sent is the best there is
+                    if !reply.is_intact(self.payload_size) {
+                        return Err(Error::new(ErrorKind::InvalidData, CorruptedReply { sequence: Some(reply.sequence) }));
+                    }
+                    let latency = reply.latency.unwrap_or_else(|| self.sent_at.elapsed().as_micros() as u64);
+                    return Ok(ProbeReply::new(latency));
+                },
+                Ok(_) => continue,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::TimedOut.into()),
+                // Only counted on the shared sockets, as a probe's errors count as lost pings
+                Err(e) if e.get_ref().is_some_and(|e| e.is::<MalformedReply>()) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.hinfo.source {
+            Some(source) => format!("ICMP echo to {} from {} (connected socket)", self.hinfo.host, source),
+            None => format!("ICMP echo to {} (connected socket)", self.hinfo.host),
+        }
+    }
+
+    fn sequence(&self) -> Option<u16> {
+        Some(self.next_sequence.wrapping_sub(1))
+    }
+}
@@ -0,0 +1,198 @@
+//! Python module, built with the `python` feature (e.g. with `maturin develop`), for scripting the probing engine
+//! from notebooks and test harnesses:
+//!
+//! ```python
+//! import multiping
+//! pinger = multiping.Pinger(interval=0.5)
+//! pinger.add_host("example.com")
+//! while True:
+//!     pinger.poll(timeout=1.0)
+//!     print(pinger.stats())
+//! ```
+
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::schedule::Schedule;
+use crate::{HistoryLimit, HostInfo, HostOptions, HostState, PingOptions, StateThresholds, StatusUpdate, start_pinging, update_host_info};
+
+/// Pings hosts in the background. The statistics are only updated by `poll`
+#[pyclass(module = "multiping")]
+struct Pinger {
+    pinger: crate::Pinger,
+    state: Mutex<State>,
+}
+
+/// What's only touched by `poll` and the statistics
+struct State {
+    rx: Receiver<StatusUpdate>,
+    hinfos: Vec<HostInfo>,
+}
+
+/// A host's statistics when they were read. Times are in milliseconds, and are None until the host has replied
+#[pyclass(module = "multiping", get_all, frozen)]
+#[derive(Clone)]
+struct HostStats {
+    index: usize,
+    host: String,
+    address: String,
+    sent: u32,
+    received: u32,
+    /// None until a request has been answered or given up on
+    loss_percent: Option<f64>,
+    latest_ms: Option<f64>,
+    min_ms: Option<f64>,
+    average_ms: Option<f64>,
+    max_ms: Option<f64>,
+    jitter_ms: Option<f64>,
+    /// "up", "degraded" or "down"
+    state: &'static str,
+    /// Whether the state is down
+    down: bool,
+    removed: bool,
+}
+
+#[pymethods]
+impl Pinger {
+    /// Starts pinging nothing every `interval` seconds, sending each request again up to `retries` times
+    /// within the interval if there's no reply
+    #[new]
+    #[pyo3(signature = (interval = 1.0, retries = 0))]
+    fn new(interval: f64, retries: u32) -> PyResult<Pinger> {
+        let interval = Duration::try_from_secs_f64(interval).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        if interval.is_zero() {
+            return Err(PyValueError::new_err("the interval needs to be more than 0 seconds"));
+        }
+        let options = PingOptions {
+            interval,
+            retries,
+            schedule: Schedule::default(),
+            connected_sockets: false,
+            raw_sockets: false,
+            packet_log: false,
+            jitter: 0.0,
+            burst: 1,
+            shards: 1,
+            history: HistoryLimit::default(),
+            state_thresholds: StateThresholds::default(),
+        };
+        let (tx, rx) = mpsc::channel();
+        let pinger = start_pinging(&[], options, tx).map_err(|e| PyOSError::new_err(e.to_string()))?;
+        Ok(Pinger { pinger, state: Mutex::new(State { rx, hinfos: Vec::new() }) })
+    }
+
+    /// Starts pinging a host, given as on the command line (e.g. "example.com" or "tcp:example.com:443"), and
+    /// returns its index. A host with several probe types (e.g. "icmp+tcp:example.com:443") gets an index for each,
+    /// one after the other, and the first is returned. It's in the statistics after the next poll
+    fn add_host(&self, host: &str) -> PyResult<usize> {
+        let group = HostInfo::new_group(host, HostOptions::default())
+            .map_err(|e| PyOSError::new_err(format!("couldn't resolve {}: {}", host, e)))?;
+        group.into_iter().map(|h| self.pinger.add_host(h)).min()
+            .ok_or_else(|| PyValueError::new_err(format!("{} has no probes", host)))
+    }
+
+    /// Stops pinging a host. Its index isn't reused
+    fn remove_host(&self, index: usize) {
+        self.pinger.remove_host(index);
+    }
+
+    /// Pauses (or resumes) probing every host
+    fn set_paused(&self, paused: bool) {
+        self.pinger.set_paused(paused);
+    }
+
+    /// Stops probing for good, waiting (up to an interval) until every thread has stopped and closed its sockets.
+    /// The statistics can still be polled for the last updates
+    fn shutdown(&self, py: Python<'_>) {
+        py.detach(|| self.pinger.shutdown());
+    }
+
+    /// Waits up to `timeout` seconds for something to happen, then updates the statistics with everything that has.
+    /// Returns how many updates there were
+    #[pyo3(signature = (timeout = 0.0))]
+    fn poll(&self, py: Python<'_>, timeout: f64) -> PyResult<usize> {
+        let timeout = Duration::try_from_secs_f64(timeout).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        // Other Python threads carry on while waiting
+        py.detach(|| {
+            let mut state = self.state.lock().unwrap();
+            let deadline = Instant::now() + timeout;
+            let mut handled = 0;
+            loop {
+                let update = if handled == 0 {
+                    state.rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                } else {
+                    state.rx.try_recv().map_err(|_| RecvTimeoutError::Timeout)
+                };
+                match update {
+                    Ok(update) => {
+                        update_host_info(&update, &mut state.hinfos);
+                        handled += 1;
+                    },
+                    Err(RecvTimeoutError::Timeout) => return Ok(handled),
+                    Err(RecvTimeoutError::Disconnected) => return Err(PyOSError::new_err("the pinger has stopped")),
+                }
+            }
+        })
+    }
+
+    /// The statistics of every host (apart from removed ones), or of the host at `index`
+    #[pyo3(signature = (index = None))]
+    fn stats(&self, py: Python<'_>, index: Option<usize>) -> PyResult<Py<PyAny>> {
+        let state = self.state.lock().unwrap();
+        match index {
+            Some(i) => {
+                let h = state.hinfos.get(i).ok_or_else(|| PyValueError::new_err(format!("there's no host {}", i)))?;
+                Ok(Py::new(py, HostStats::from_host(i, h))?.into_any())
+            },
+            None => {
+                let all: Vec<HostStats> = state.hinfos.iter().enumerate().filter(|(_, h)| !h.removed)
+                    .map(|(i, h)| HostStats::from_host(i, h)).collect();
+                Ok(all.into_pyobject(py)?.into_any().unbind())
+            },
+        }
+    }
+}
+
+impl HostStats {
+    fn from_host(index: usize, h: &HostInfo) -> HostStats {
+        let ms = |time: Option<u64>| time.map(|t| t as f64 / 1000.0);
+        let replied = h.successful > 0;
+        HostStats {
+            index,
+            host: h.host_str.clone(),
+            address: h.host.ip().to_string(),
+            sent: h.pings_sent,
+            received: h.successful,
+            loss_percent: h.loss_percent(),
+            latest_ms: ms(h.latest_time),
+            min_ms: ms(h.min_time),
+            average_ms: replied.then(|| h.average() as f64),
+            max_ms: ms(h.max_time),
+            jitter_ms: replied.then(|| h.jitter() as f64),
+            state: h.state.name(),
+            down: h.state == HostState::Down,
+            removed: h.removed,
+        }
+    }
+}
+
+#[pymethods]
+impl HostStats {
+    fn __repr__(&self) -> String {
+        let ms = |time: Option<f64>| time.map_or("None".to_string(), |t| format!("{:.3}", t));
+        format!("HostStats(host={:?}, sent={}, received={}, loss_percent={}, average_ms={}, state={:?})",
+            self.host, self.sent, self.received, ms(self.loss_percent), ms(self.average_ms), self.state)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "multiping")]
+fn multiping_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Pinger>()?;
+    m.add_class::<HostStats>()?;
+    Ok(())
+}
@@ -0,0 +1,75 @@
+//! QUIC handshake probe: times how long it takes to establish a QUIC connection (with 1-RTT keys).
+//! Useful for services where ICMP is deprioritised, but whose UDP traffic isn't
+
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{ClientConfig, Endpoint};
+use rustls::crypto::ring;
+use rustls::version::TLS13;
+
+use crate::probe::{Probe, ProbeReply};
+use crate::tls::AcceptAnyCertificate;
+
+/// The application protocol asked for during the handshake. Servers refuse connections without one they support
+const ALPN: &[u8] = b"h3";
+
+/// Establishes a new QUIC connection every time
+pub struct QuicProbe {
+    addr: SocketAddr,
+    server_name: String,
+}
+
+impl QuicProbe {
+    pub fn new(addr: SocketAddr, server_name: &str) -> QuicProbe {
+        QuicProbe { addr, server_name: server_name.to_string() }
+    }
+}
+
+impl Probe for QuicProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        Ok(ProbeReply::new(quic_handshake(self.addr, &self.server_name, timeout)?))
+    }
+
+    fn describe(&self) -> String {
+        format!("QUIC handshake with {} ({})", self.server_name, self.addr)
+    }
+}
+
+/// Connects to the QUIC server at `addr`, giving up after `timeout`. Returns the time taken in microseconds
+pub fn quic_handshake(addr: SocketAddr, server_name: &str, timeout: Duration) -> Result<u64, Error> {
+    let provider = Arc::new(ring::default_provider());
+    let mut tls_config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&TLS13])
+        .map_err(Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth();
+    tls_config.alpn_protocols = vec![ALPN.to_vec()];
+    let config = ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls_config).map_err(Error::other)?));
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    runtime.block_on(async {
+        let bind: SocketAddr = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+        let mut endpoint = Endpoint::client(bind)?;
+        endpoint.set_default_client_config(config);
+
+        let start = Instant::now();
+        let connecting = endpoint.connect(addr, server_name).map_err(Error::other)?;
+        let connection = tokio::time::timeout(timeout, connecting).await
+            .map_err(|_| Error::from(ErrorKind::TimedOut))?
+            .map_err(|e| Error::new(ErrorKind::ConnectionRefused, e))?;
+        let elapsed = start.elapsed().as_micros() as u64;
+
+        // Give the close a moment to be sent, so the server isn't left waiting for the connection to time out
+        connection.close(0u32.into(), b"");
+        let _ = tokio::time::timeout(Duration::from_millis(100), endpoint.wait_idle()).await;
+        Ok(elapsed)
+    })
+}
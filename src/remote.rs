@@ -0,0 +1,254 @@
+//! Probing from other machines (vantage points): multiping is run on each of them over SSH with `--output ndjson`,
+//! and their results are fed into local probes, so that they show up as rows alongside the local ones. This makes
+//! it easy to compare how a host looks from several places at once
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::net::IpAddr;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender, TrySendError, sync_channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::probe::ERROR_KINDS;
+use crate::{CorruptedReply, HostInfo, Pinger, Probe, ProbeReply, json};
+
+/// How many results from a vantage point are kept for a host if they're coming in faster than they're used.
+/// Any more are dropped, so that the rows don't fall further and further behind
+pub const REMOTE_BACKLOG: usize = 3;
+
+/// What happened to a request sent from a vantage point
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Replied(u64), // The round trip time, in microseconds
+    Corrupted,
+    Failed(ErrorKind),
+    Lost, // The request wasn't answered, which is only reported once the next one is sent
+    Other, // Any other event, e.g. a request being sent, which isn't the result of one
+}
+
+/// An NDJSON event from multiping on a vantage point
+#[derive(Clone, Debug, PartialEq)]
+pub struct RemoteEvent {
+    pub host: String, // As it was given to the vantage point's multiping, e.g. "tcp:example.com:443"
+    pub address: IpAddr, // What the host resolved to there
+    pub outcome: Outcome,
+}
+
+/// Reads an NDJSON event written by `multiping --output ndjson`. None for events that aren't about a host, or
+/// replies without a round trip time (as the clock changed while the request was on its way)
+pub fn parse_event(line: &str) -> Option<RemoteEvent> {
+    let fields = json::read_object(line)?;
+    let outcome = match fields.get("event")?.as_str() {
+        "received" => Outcome::Replied((fields.get("rtt_ms")?.parse::<f64>().ok()? * 1000.0) as u64),
+        "corrupted" => Outcome::Corrupted,
+        "timeout" => Outcome::Lost,
+        // The error is written as its kind's description, e.g. "connection refused"
+        "error" => Outcome::Failed(fields.get("error")
+            .and_then(|error| ERROR_KINDS.into_iter().find(|kind| kind.to_string() == *error))
+            .unwrap_or(ErrorKind::Other)),
+        _ => Outcome::Other,
+    };
+    Some(RemoteEvent { host: fields.get("host")?.clone(), address: fields.get("address")?.parse().ok()?, outcome })
+}
+
+/// Quotes a word for the remote shell that SSH runs commands with
+pub fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+/// The command that runs `program` (multiping) on a vantage point, probing `hosts` every `interval`. The program is
+/// a shell command, so it can have options of its own (e.g. "sudo multiping --raw-sockets")
+pub fn agent_command(program: &str, hosts: &[String], interval: Duration) -> String {
+    let hosts: Vec<String> = hosts.iter().map(|h| shell_quote(h)).collect();
+    format!("{} --output ndjson --interval {}s -- {}", program, interval.as_secs_f64(), hosts.join(" "))
+}
+
+/// The SSH command for running `command` on `destination` ([user@]host), without asking for passwords, which
+/// couldn't be typed in while the table is shown
+fn ssh(destination: &str, command: &str) -> Command {
+    let mut ssh = Command::new("ssh");
+    ssh.args(["-T", "-o", "BatchMode=yes", "--", destination, command]).stdin(Stdio::null());
+    ssh
+}
+
+/// Checks that `program` can be run on `destination`, so that problems (like SSH not being able to log in, or
+/// multiping not being installed there) are found before probing starts. The error has what SSH or the shell said
+pub fn check(destination: &str, program: &str) -> Result<(), Error> {
+    let output = ssh(destination, &format!("{} --version", program)).output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("it exited with an error");
+    Err(Error::other(message.trim().to_string()))
+}
+
+/// Starts multiping on `destination`, and adds a row for each host it reports on (the first time it does), in a
+/// group named after the vantage point. The rows' probes are fed the results as they come in. If the connection
+/// ends, they report errors from then on
+pub fn start(destination: &str, program: &str, hosts: &[String], interval: Duration, pinger: Pinger) -> Result<(), Error> {
+    let mut child = ssh(destination, &agent_command(program, hosts, interval))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let stdout = child.stdout.take().ok_or(ErrorKind::BrokenPipe)?;
+    let destination = destination.to_string();
+    thread::spawn(move || {
+        let mut rows: HashMap<String, SyncSender<Outcome>> = HashMap::new();
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else { break };
+            let Some(event) = parse_event(&line) else { continue };
+            let row = rows.entry(event.host.clone()).or_insert_with(|| {
+                let (tx, rx) = sync_channel(REMOTE_BACKLOG);
+                let hinfo = HostInfo {
+                    group: Some(format!("From {}", destination)),
+                    ..HostInfo::from_address(&event.host, event.address)
+                };
+                let probe = RemoteProbe { destination: destination.clone(), host: event.host.clone(), interval, results: rx };
+                pinger.add_probe(hinfo, Box::new(probe));
+                tx
+            });
+            if event.outcome != Outcome::Other && let Err(TrySendError::Disconnected(_)) = row.try_send(event.outcome) {
+                // The row was removed
+                rows.remove(&event.host);
+            }
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+/// Reports the results of a host's requests from a vantage point, as they come in. Each one is the result of one of
+/// the vantage point's requests, so the probe doesn't time out on its own unless the vantage point has gone quiet
+struct RemoteProbe {
+    destination: String,
+    host: String,
+    interval: Duration, // How often the vantage point sends requests
+    results: Receiver<Outcome>,
+}
+
+impl Probe for RemoteProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        // A lost request is only reported when the vantage point sends its next one, an interval later
+        let deadline = Instant::now() + timeout + self.interval;
+        loop {
+            return match self.results.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(Outcome::Replied(latency)) => Ok(ProbeReply { latency, details: None }),
+                Ok(Outcome::Corrupted) => Err(Error::new(ErrorKind::InvalidData, CorruptedReply { sequence: None })),
+                Ok(Outcome::Failed(kind)) => Err(kind.into()),
+                Ok(Outcome::Lost) | Err(RecvTimeoutError::Timeout) => Err(ErrorKind::TimedOut.into()),
+                Ok(Outcome::Other) => continue,
+                Err(RecvTimeoutError::Disconnected) => Err(Error::new(ErrorKind::ConnectionAborted, format!("lost the connection to {}", self.destination))),
+            };
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("{} from {}", self.host, self.destination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::sync_channel;
+    use crate::json::JsonObject;
+    use crate::time::parse_interval;
+
+    /// An event as `multiping --output ndjson` writes it
+    fn event(kind: &str) -> JsonObject {
+        JsonObject::new()
+            .string("time", "2025-06-01T12:34:56.789Z")
+            .string("event", kind)
+            .string("host", "tcp:example.com:443")
+            .string("address", "2001:db8::1")
+            .string("probe", "tcp")
+            .raw("source", "null")
+    }
+
+    fn outcome(line: &str) -> Option<Outcome> {
+        parse_event(line).map(|e| e.outcome)
+    }
+
+    #[test]
+    fn events_parse() {
+        let received = parse_event(&event("received").number("rtt_ms", 1.5).finish()).unwrap();
+        assert_eq!(received, RemoteEvent {
+            host: "tcp:example.com:443".to_string(),
+            address: "2001:db8::1".parse().unwrap(),
+            outcome: Outcome::Replied(1500),
+        });
+        assert_eq!(outcome(&event("corrupted").finish()), Some(Outcome::Corrupted));
+        assert_eq!(outcome(&event("timeout").finish()), Some(Outcome::Lost));
+        assert_eq!(outcome(&event("sent").finish()), Some(Outcome::Other));
+        for kind in ERROR_KINDS {
+            let line = event("error").string("error", &kind.to_string()).finish();
+            assert_eq!(outcome(&line), Some(Outcome::Failed(kind)), "{}", line);
+        }
+        assert_eq!(outcome(&event("error").string("error", "something else").finish()), Some(Outcome::Failed(ErrorKind::Other)));
+    }
+
+    #[test]
+    fn events_without_a_round_trip_time_or_host_are_skipped() {
+        // The clock changed while the request was on its way
+        assert_eq!(outcome(&event("received").number("rtt_ms", f64::NAN).finish()), None);
+        assert_eq!(outcome(r#"{"event":"received","host":"a","address":"192.0.2.1","rtt_ms":null}"#), None);
+        assert_eq!(outcome(r#"{"event":"state","from":"up","to":"down"}"#), None);
+        assert_eq!(outcome(r#"{"event":"sent","host":"a","address":"not an address"}"#), None);
+        assert_eq!(outcome("Usage: multiping [OPTIONS]"), None);
+    }
+
+    #[test]
+    fn words_are_quoted_for_the_shell() {
+        assert_eq!(shell_quote("example.com"), "'example.com'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        for word in ["it's", "''", "$(reboot) `id` \"x\"", "a b\tc"] {
+            let output = Command::new("sh").args(["-c", &format!("printf %s {}", shell_quote(word))]).output().unwrap();
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), word);
+        }
+    }
+
+    #[test]
+    fn agent_intervals_can_be_read_back() {
+        let hosts = ["example.com".to_string(), "tcp:it's:443".to_string()];
+        for interval in [Duration::from_secs(1), Duration::from_millis(250), Duration::from_millis(10), Duration::from_secs(90)] {
+            let command = agent_command("sudo multiping", &hosts, interval);
+            assert!(command.starts_with("sudo multiping --output ndjson --interval "), "{}", command);
+            assert!(command.ends_with(r" -- 'example.com' 'tcp:it'\''s:443'"), "{}", command);
+            let text = command.split(' ').skip_while(|word| *word != "--interval").nth(1).unwrap();
+            assert_eq!(parse_interval(text).unwrap(), interval, "{}", text);
+        }
+    }
+
+    #[test]
+    fn results_are_reported_by_the_probe() {
+        let (tx, rx) = sync_channel(REMOTE_BACKLOG);
+        let mut probe = RemoteProbe {
+            destination: "vantage".to_string(),
+            host: "example.com".to_string(),
+            interval: Duration::from_millis(10),
+            results: rx,
+        };
+        let timeout = Duration::from_millis(10);
+        tx.send(Outcome::Replied(1500)).unwrap();
+        assert_eq!(probe.match_reply(timeout).unwrap().latency, 1500);
+        // Other events are skipped
+        tx.send(Outcome::Other).unwrap();
+        tx.send(Outcome::Lost).unwrap();
+        assert_eq!(probe.match_reply(timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+        tx.send(Outcome::Corrupted).unwrap();
+        assert_eq!(probe.match_reply(timeout).unwrap_err().kind(), ErrorKind::InvalidData);
+        tx.send(Outcome::Failed(ErrorKind::ConnectionRefused)).unwrap();
+        assert_eq!(probe.match_reply(timeout).unwrap_err().kind(), ErrorKind::ConnectionRefused);
+        // Nothing from the vantage point
+        assert_eq!(probe.match_reply(timeout).unwrap_err().kind(), ErrorKind::TimedOut);
+        drop(tx);
+        assert_eq!(probe.match_reply(timeout).unwrap_err().kind(), ErrorKind::ConnectionAborted);
+    }
+}
@@ -0,0 +1,116 @@
+//! Restricting probing to certain times of day, e.g. only during working hours,
+//! or not during a maintenance window
+
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::time::local_minute_of_day;
+
+/// A period of each day, in local time. If the end is before the start, it wraps over midnight
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: u32, // Minutes since midnight
+    pub end: u32,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = String;
+
+    /// Parses a window like "09:00-17:00"
+    fn from_str(s: &str) -> Result<TimeWindow, String> {
+        let (start, end) = s.split_once('-').ok_or(format!("{} should look like 09:00-17:00", s))?;
+        Ok(TimeWindow { start: parse_time_of_day(start)?, end: parse_time_of_day(end)? })
+    }
+}
+
+/// Parses a time like "09:30" into minutes since midnight
+fn parse_time_of_day(s: &str) -> Result<u32, String> {
+    let invalid = || format!("{} isn't a valid time (like 09:30)", s);
+    let (hours, minutes) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    // 24:00 is allowed, so that a window can end at midnight
+    if minutes >= 60 || hours * 60 + minutes > 24 * 60 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// When hosts should be probed
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    /// Probing only happens during these windows. If there aren't any, it happens all day
+    pub windows: Vec<TimeWindow>,
+    /// Probing never happens during these windows, even if it's in one of `windows`
+    pub pauses: Vec<TimeWindow>,
+}
+
+impl Schedule {
+    /// Whether hosts should be probed at the given time
+    pub fn is_active(&self, time: SystemTime) -> bool {
+        if self.windows.is_empty() && self.pauses.is_empty() {
+            return true;
+        }
+        let minute = local_minute_of_day(time);
+        (self.windows.is_empty() || self.windows.iter().any(|w| w.contains(minute)))
+            && !self.pauses.iter().any(|w| w.contains(minute))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(s: &str) -> TimeWindow {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn windows_parse() {
+        assert_eq!(window("09:00-17:30"), TimeWindow { start: 9 * 60, end: 17 * 60 + 30 });
+        assert_eq!(window(" 22:00 - 24:00 "), TimeWindow { start: 22 * 60, end: 24 * 60 });
+        for invalid in ["09:00", "9-17", "09:60-10:00", "24:01-01:00", "-01:00-02:00", "09:00-17:00:00"] {
+            assert!(invalid.parse::<TimeWindow>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn windows_include_the_start_but_not_the_end() {
+        let working_hours = window("09:00-17:00");
+        assert!(!working_hours.contains(9 * 60 - 1));
+        assert!(working_hours.contains(9 * 60));
+        assert!(working_hours.contains(17 * 60 - 1));
+        assert!(!working_hours.contains(17 * 60));
+        assert!(window("22:00-24:00").contains(24 * 60 - 1));
+    }
+
+    #[test]
+    fn windows_can_wrap_over_midnight() {
+        let night = window("22:00-06:00");
+        assert!(!night.contains(22 * 60 - 1));
+        assert!(night.contains(22 * 60));
+        assert!(night.contains(0));
+        assert!(night.contains(6 * 60 - 1));
+        assert!(!night.contains(6 * 60));
+        assert!(!night.contains(12 * 60));
+    }
+
+    #[test]
+    fn pauses_override_windows() {
+        let schedule = Schedule { windows: vec![], pauses: vec![] };
+        assert!(schedule.is_active(SystemTime::now()));
+        let always = window("00:00-24:00");
+        let schedule = Schedule { windows: vec![always], pauses: vec![always] };
+        assert!(!schedule.is_active(SystemTime::now()));
+    }
+}
@@ -0,0 +1,39 @@
+//! Serde support for the fields that can't derive it, used with `#[serde(with = ...)]` when the `serde` feature is on
+
+/// `std::io::ErrorKind`, as its name (e.g. "TimedOut"). Names that aren't known come back as `Other`
+pub(crate) mod error_kind {
+    use std::io::ErrorKind;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::probe::ERROR_KINDS;
+
+    pub(crate) fn serialize<S: Serializer>(kind: &ErrorKind, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", kind))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ErrorKind, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(from_name(&name))
+    }
+
+    pub(super) fn from_name(name: &str) -> ErrorKind {
+        ERROR_KINDS.into_iter().find(|kind| format!("{:?}", kind) == name).unwrap_or(ErrorKind::Other)
+    }
+}
+
+/// `Option<std::io::ErrorKind>`, as the error's name or null
+pub(crate) mod option_error_kind {
+    use std::io::ErrorKind;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(kind: &Option<ErrorKind>, serializer: S) -> Result<S::Ok, S::Error> {
+        match kind {
+            Some(kind) => super::error_kind::serialize(kind, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<ErrorKind>, D::Error> {
+        Ok(Option::<String>::deserialize(deserializer)?.map(|name| super::error_kind::from_name(&name)))
+    }
+}
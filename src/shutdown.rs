@@ -0,0 +1,52 @@
+//! Stopping the probing threads, so that their sockets are closed and every update they were going to send has been
+//! sent before the program (or the library's user) moves on, rather than leaving them to be killed on exit
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::time;
+
+/// How long before a deadline waiting stops being interruptible, so that `time::sleep_until` can still be precise
+const PRECISE_FOR: Duration = Duration::from_millis(1);
+
+/// Tells threads to stop, and wakes them up if they're waiting. Clones share the same token
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    /// Tells everything using the token to stop, waking up anything waiting in `sleep_until`
+    pub fn cancel(&self) {
+        let (cancelled, wake) = &*self.inner;
+        *cancelled.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        wake.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.inner.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits until the deadline, or until the token is cancelled. Returns false if it was cancelled
+    pub fn sleep_until(&self, deadline: Instant) -> bool {
+        let (cancelled, wake) = &*self.inner;
+        let mut guard = cancelled.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if *guard {
+                return false;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now()).saturating_sub(PRECISE_FOR);
+            if remaining.is_zero() {
+                break;
+            }
+            guard = wake.wait_timeout(guard, remaining).unwrap_or_else(|e| e.into_inner()).0;
+        }
+        drop(guard);
+        time::sleep_until(deadline);
+        true
+    }
+}
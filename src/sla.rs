@@ -0,0 +1,60 @@
+//! Checking hosts against service level thresholds, e.g. an average latency under 50 ms
+
+use std::time::SystemTime;
+
+use crate::HostInfo;
+
+/// The thresholds a host has to meet. Thresholds that are None aren't checked
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sla {
+    pub max_average_ms: Option<f64>,
+    pub max_loss_percent: Option<f64>,
+    pub min_availability_percent: Option<f64>,
+}
+
+/// The result of checking one threshold
+#[derive(Clone, Copy, Debug)]
+pub struct SlaCheck {
+    pub name: &'static str,
+    pub threshold: f64,
+    pub actual: Option<f64>, // None if there isn't enough data, which counts as failing
+    pub passed: bool,
+}
+
+impl Sla {
+    pub fn is_empty(&self) -> bool {
+        self.max_average_ms.is_none() && self.max_loss_percent.is_none() && self.min_availability_percent.is_none()
+    }
+
+    /// Checks a host against every threshold that's set
+    pub fn evaluate(&self, host: &HostInfo) -> Vec<SlaCheck> {
+        let mut checks = Vec::new();
+        if let Some(threshold) = self.max_average_ms {
+            let actual = (host.successful > 0).then(|| host.average() as f64);
+            checks.push(SlaCheck { name: "Average", threshold, actual, passed: actual.is_some_and(|a| a < threshold) });
+        }
+        if let Some(threshold) = self.max_loss_percent {
+            let actual = host.loss_percent();
+            checks.push(SlaCheck { name: "Loss", threshold, actual, passed: actual.is_some_and(|a| a < threshold) });
+        }
+        if let Some(threshold) = self.min_availability_percent {
+            let actual = availability(host);
+            checks.push(SlaCheck { name: "Availability", threshold, actual, passed: actual.is_some_and(|a| a > threshold) });
+        }
+        checks
+    }
+}
+
+/// Percentage of the time since the first request was sent that the host wasn't in an outage.
+/// Outages from before the statistics were reset only count from the reset onwards
+pub fn availability(host: &HostInfo) -> Option<f64> {
+    let first_sent = host.first_sent?;
+    let monitored = SystemTime::now().duration_since(first_sent).ok()?.as_secs_f64();
+    if monitored <= 0.0 {
+        return None;
+    }
+    let down: f64 = host.outages.iter()
+        .map(|o| o.end.unwrap_or_else(SystemTime::now).duration_since(o.start.max(first_sent)).unwrap_or_default().as_secs_f64())
+        .sum();
+    Some((100.0 * (1.0 - down / monitored)).max(0.0))
+}
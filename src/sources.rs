@@ -0,0 +1,55 @@
+//! The machine's own addresses, so that hosts can be probed from each of them in turn (e.g. from every uplink or
+//! tunnel of a multihomed machine), to find out which one a problem is on
+
+use std::io::Error;
+use std::net::IpAddr;
+
+use nix::ifaddrs::getifaddrs;
+
+use crate::{HostInfo, ProbeType};
+
+/// One of the machine's addresses, and the interface it's on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LocalAddress {
+    pub interface: String,
+    pub address: IpAddr,
+}
+
+/// The addresses of every interface that's up, apart from loopback and IPv6 link-local ones (which would need
+/// a scope ID to be used, and can't reach anything past the link anyway)
+pub fn local_addresses() -> Result<Vec<LocalAddress>, Error> {
+    let mut addresses = Vec::new();
+    for ifaddr in getifaddrs()? {
+        let Some(address) = ifaddr.address else { continue };
+        let address = match (address.as_sockaddr_in(), address.as_sockaddr_in6()) {
+            (Some(v4), _) => IpAddr::V4(v4.ip()),
+            (_, Some(v6)) => IpAddr::V6(v6.ip()),
+            _ => continue,
+        };
+        let link_local = matches!(address, IpAddr::V6(v6) if v6.is_unicast_link_local());
+        let up = ifaddr.flags.contains(nix::net::if_::InterfaceFlags::IFF_UP);
+        if up && !address.is_loopback() && !link_local {
+            addresses.push(LocalAddress { interface: ifaddr.interface_name, address });
+        }
+    }
+    Ok(addresses)
+}
+
+/// Whether a host can be probed from a chosen address. Only ICMP, TCP, UDP and HTTP probes can be
+pub fn can_choose_source(hinfo: &HostInfo) -> bool {
+    matches!(hinfo.probe, ProbeType::Icmp | ProbeType::Tcp | ProbeType::Udp | ProbeType::Http)
+}
+
+/// The host followed by a copy of it for each local address of the same IP version, which are sub-rows
+/// probed from that address. Hosts whose probe can't choose where it's sent from are left as they are
+pub fn with_each_source(hinfo: HostInfo, local: &[LocalAddress]) -> Vec<HostInfo> {
+    let copies: Vec<HostInfo> = if can_choose_source(&hinfo) {
+        local.iter()
+            .filter(|l| l.address.is_ipv4() == hinfo.host.is_ipv4())
+            .map(|l| HostInfo { source: Some(l.address), sub_row: true, ..hinfo.clone() })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    [vec![hinfo], copies].concat()
+}
@@ -0,0 +1,114 @@
+//! Expanding DNS SRV records (e.g. "srv:_xmpp-server._tcp.example.com") into the targets and ports they point to,
+//! by asking the nameservers in /etc/resolv.conf directly, as the system resolver can't look up SRV records
+
+use std::fs;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::dns::*;
+
+/// Hosts starting with this are SRV record names
+pub const PREFIX: &str = "srv:";
+/// How long to wait for each nameserver to answer
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Somewhere an SRV record says the service can be found
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub target: String,
+    pub port: u16,
+    pub priority: u16,
+    pub weight: u16,
+}
+
+/// The SRV record name in a host, if it has the "srv:" prefix
+pub fn record_name(host: &str) -> Option<&str> {
+    host.strip_prefix(PREFIX)
+}
+
+/// Looks up the SRV records for `name`, in order of priority (then weight, highest first).
+/// Errors with `NotFound` if there are none, or the record says the service isn't available
+pub fn lookup(name: &str) -> Result<Vec<SrvTarget>, Error> {
+    let mut last_error = Error::new(ErrorKind::NotFound, "no nameservers in /etc/resolv.conf");
+    for server in nameservers() {
+        match query(name, server) {
+            Ok(mut targets) => {
+                // A single target of "." means the service is deliberately not available
+                targets.retain(|t| !t.target.is_empty());
+                if targets.is_empty() {
+                    return Err(Error::new(ErrorKind::NotFound, format!("no SRV records for {}", name)));
+                }
+                targets.sort_by_key(|t| (t.priority, u16::MAX - t.weight));
+                return Ok(targets);
+            },
+            // There's no point asking the other nameservers if the name doesn't exist
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(e),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// The nameservers from /etc/resolv.conf, or the local one if there aren't any (like the system resolver)
+pub fn nameservers() -> Vec<SocketAddr> {
+    let servers: Vec<SocketAddr> = fs::read_to_string("/etc/resolv.conf").unwrap_or_default().lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .map(|addr| SocketAddr::new(addr, 53))
+        .collect();
+    if servers.is_empty() {
+        vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53)]
+    } else {
+        servers
+    }
+}
+
+/// Asks one nameserver for the SRV records, over UDP and then TCP if the answer didn't fit
+fn query(name: &str, server: SocketAddr) -> Result<Vec<SrvTarget>, Error> {
+    let id = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos() as u16;
+    let request = build_query(id, &[(name, TYPE_SRV)]);
+
+    let socket = UdpSocket::bind(if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(server)?;
+    socket.send(&request)?;
+    let mut buf = [0u8; 4096];
+    let response = loop {
+        let len = socket.recv(&mut buf)?;
+        // Anything else is a late answer to an earlier question
+        if buf[..len].starts_with(&id.to_be_bytes()) {
+            break buf[..len].to_vec();
+        }
+    };
+    // The truncation bit
+    let response = if response.get(2).is_some_and(|flags| flags & 0x02 != 0) {
+        query_tcp(&request, server)?
+    } else {
+        response
+    };
+
+    match response.get(3).map(|flags| flags & 0x0f) {
+        Some(0) => {},
+        Some(3) => return Err(Error::new(ErrorKind::NotFound, format!("{} doesn't exist", name))),
+        _ => return Err(Error::new(ErrorKind::InvalidData, format!("{} couldn't answer", server.ip()))),
+    }
+    let (_, records) = parse_response(&response)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("malformed answer from {}", server.ip())))?;
+    Ok(records.into_iter().filter_map(|record| match record.data {
+        RecordData::Srv { priority, weight, port, target } => Some(SrvTarget { target, port, priority, weight }),
+        _ => None,
+    }).collect())
+}
+
+/// Asks over TCP, where each message starts with its length
+fn query_tcp(request: &[u8], server: SocketAddr) -> Result<Vec<u8>, Error> {
+    let mut stream = TcpStream::connect_timeout(&server, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.write_all(&[(request.len() as u16).to_be_bytes().as_slice(), request].concat())?;
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len)?;
+    let mut response = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
@@ -0,0 +1,176 @@
+//! Whether each host is up, degraded or down, with hysteresis so that a host on the edge of a threshold doesn't flap
+//! between states. `update_host_info` returns a `StateChange` whenever a host moves from one to another, so that
+//! alerts and exporters can react to the edges without keeping track of the counters themselves
+
+use std::time::Duration;
+
+use crate::HostInfo;
+
+/// How far back under a degraded threshold a host has to get to stop being degraded, as a fraction of it
+pub const RECOVERY_FRACTION: f64 = 0.8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HostState {
+    #[default]
+    Up,
+    Degraded, // Answering, but slowly or losing requests (see `StateThresholds`)
+    Down,
+}
+
+impl HostState {
+    /// The name used in the output, e.g. "degraded"
+    pub fn name(&self) -> &'static str {
+        match self {
+            HostState::Up => "up",
+            HostState::Degraded => "degraded",
+            HostState::Down => "down",
+        }
+    }
+}
+
+/// When hosts change state. A host is down once more than `down_after` requests in a row have gone unanswered, and
+/// only comes back after `up_after` replies in a row. It's degraded while its average latency or loss over the last
+/// minute is over a threshold, until it's back under `RECOVERY_FRACTION` of it
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateThresholds {
+    pub down_after: u32,
+    pub up_after: u32,
+    pub degraded_latency: Option<Duration>,
+    pub degraded_loss: Option<f64>, // Percent
+}
+
+impl Default for StateThresholds {
+    fn default() -> StateThresholds {
+        StateThresholds { down_after: 3, up_after: 2, degraded_latency: None, degraded_loss: None }
+    }
+}
+
+/// A host moved from one state to another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateChange {
+    pub host: usize,
+    pub from: HostState,
+    pub to: HostState,
+}
+
+impl HostInfo {
+    /// Moves the host (at index `i`) to the state it should be in now, returning the change if there was one
+    pub(crate) fn update_state(&mut self, i: usize) -> Option<StateChange> {
+        let to = self.next_state();
+        let from = std::mem::replace(&mut self.state, to);
+        (from != to).then_some(StateChange { host: i, from, to })
+    }
+
+    fn next_state(&self) -> HostState {
+        let t = self.state_thresholds;
+        match self.state {
+            HostState::Down if self.replied_in_a_row < t.up_after.max(1) => HostState::Down,
+            _ if self.unanswered > t.down_after => HostState::Down,
+            state => {
+                // Once degraded, it has to get back under the lower limit to stop being degraded
+                let limit = if state == HostState::Degraded { RECOVERY_FRACTION } else { 1.0 };
+                let over = |average: Option<f64>, threshold: Option<f64>| {
+                    average.zip(threshold).is_some_and(|(average, threshold)| average > threshold * limit)
+                };
+                let averages = |a: &crate::stats::WindowedAverage| a.averages().map(|[minute, _, _]| minute);
+                let latency = over(averages(&self.latency_averages), t.degraded_latency.map(|d| d.as_secs_f64() * 1000.0));
+                let loss = over(averages(&self.loss_averages), t.degraded_loss);
+                if latency || loss { HostState::Degraded } else { HostState::Up }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use crate::stats::WindowedAverage;
+    use crate::{StatusUpdate, update_host_info};
+
+    fn host(state_thresholds: StateThresholds) -> Vec<HostInfo> {
+        vec![HostInfo { state_thresholds, ..HostInfo::from_address("example.com", "192.0.2.1".parse().unwrap()) }]
+    }
+
+    /// The state the host moves to after each update, if it changes
+    fn states(hinfos: &mut Vec<HostInfo>, updates: Vec<StatusUpdate>) -> Vec<Option<HostState>> {
+        updates.into_iter().map(|update| update_host_info(&update, hinfos).map(|change| change.to)).collect()
+    }
+
+    fn sent() -> StatusUpdate {
+        StatusUpdate::Sent(0)
+    }
+
+    fn received() -> StatusUpdate {
+        StatusUpdate::Received(0, Some(1000), None)
+    }
+
+    /// Moves the host to the state it should be in with this average latency (or loss) over the last minute
+    fn with_average(h: &mut HostInfo, average: fn(&mut HostInfo) -> &mut WindowedAverage, value: f64) -> Option<HostState> {
+        *average(h) = WindowedAverage::default();
+        average(h).add(value, SystemTime::UNIX_EPOCH);
+        h.update_state(0).map(|change| change.to)
+    }
+
+    fn latency(h: &mut HostInfo) -> &mut WindowedAverage {
+        &mut h.latency_averages
+    }
+
+    fn loss(h: &mut HostInfo) -> &mut WindowedAverage {
+        &mut h.loss_averages
+    }
+
+    #[test]
+    fn hosts_go_down_and_come_back_up() {
+        let mut hinfos = host(StateThresholds::default());
+        // The request that was just sent can't have been answered yet, so it takes one more than down_after
+        assert_eq!(states(&mut hinfos, vec![sent(), sent(), sent(), sent()]), [None, None, None, Some(HostState::Down)]);
+        assert!(hinfos[0].is_down());
+        assert!(hinfos[0].outages.last().is_some_and(|o| o.end.is_none()));
+        // Losing another request starts counting the replies again
+        assert_eq!(states(&mut hinfos, vec![received(), sent(), sent(), received(), sent()]), [None; 5]);
+        assert_eq!(states(&mut hinfos, vec![received()]), [Some(HostState::Up)]);
+        assert!(!hinfos[0].is_down());
+        assert_eq!(hinfos[0].outages.len(), 1);
+        assert!(hinfos[0].outages[0].end.is_some());
+    }
+
+    #[test]
+    fn the_lowest_thresholds_go_down_on_the_first_loss() {
+        let mut hinfos = host(StateThresholds { down_after: 1, up_after: 1, ..StateThresholds::default() });
+        assert_eq!(states(&mut hinfos, vec![sent(), sent()]), [None, Some(HostState::Down)]);
+        assert_eq!(states(&mut hinfos, vec![received()]), [Some(HostState::Up)]);
+    }
+
+    #[test]
+    fn slow_hosts_are_degraded_until_well_under_the_threshold() {
+        let mut hinfos = host(StateThresholds { degraded_latency: Some(Duration::from_millis(100)), ..StateThresholds::default() });
+        let h = &mut hinfos[0];
+        assert_eq!(with_average(h, latency, 90.0), None);
+        assert_eq!(with_average(h, latency, 130.0), Some(HostState::Degraded));
+        assert_eq!(with_average(h, latency, 90.0), None);
+        assert_eq!(with_average(h, latency, 79.0), Some(HostState::Up));
+    }
+
+    #[test]
+    fn lossy_hosts_are_degraded_until_well_under_the_threshold() {
+        let mut hinfos = host(StateThresholds { degraded_loss: Some(10.0), ..StateThresholds::default() });
+        let h = &mut hinfos[0];
+        assert_eq!(with_average(h, loss, 5.0), None);
+        assert_eq!(with_average(h, loss, 20.0), Some(HostState::Degraded));
+        assert_eq!(with_average(h, loss, 9.0), None);
+        assert_eq!(with_average(h, loss, 7.0), Some(HostState::Up));
+    }
+
+    #[test]
+    fn slow_hosts_come_back_degraded() {
+        let thresholds = StateThresholds { up_after: 1, degraded_latency: Some(Duration::from_millis(100)), ..StateThresholds::default() };
+        let mut hinfos = host(thresholds);
+        assert_eq!(states(&mut hinfos, vec![sent(), sent(), sent(), sent()]).last(), Some(&Some(HostState::Down)));
+        assert_eq!(states(&mut hinfos, vec![StatusUpdate::Received(0, Some(300_000), None)]), [Some(HostState::Degraded)]);
+        assert!(hinfos[0].outages[0].end.is_some());
+    }
+}
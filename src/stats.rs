@@ -0,0 +1,589 @@
+//! Statistics on collections of latency samples
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Result of a Mann-Whitney U test between two sets of samples
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MannWhitney {
+    /// U statistic for the first set of samples
+    pub u: f64,
+    /// Normal approximation of U (negative when the first set tends to be smaller)
+    pub z: f64,
+    /// Two-sided p-value
+    pub p_value: f64,
+}
+
+/// Performs a Mann-Whitney U test on two sets of samples, using the normal approximation
+/// (with a correction for ties). Returns None if either set is empty or every sample is the same
+pub fn mann_whitney_u(a: &[u64], b: &[u64]) -> Option<MannWhitney> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let n1 = a.len() as f64;
+    let n2 = b.len() as f64;
+    let n = n1 + n2;
+
+    // Rank every sample together, remembering which set it came from
+    let mut combined: Vec<(u64, bool)> = a.iter().map(|&x| (x, true))
+        .chain(b.iter().map(|&x| (x, false)))
+        .collect();
+    combined.sort_by_key(|&(x, _)| x);
+
+    let mut rank_sum_a = 0.0;
+    let mut tie_correction = 0.0;
+    let mut start = 0;
+    while start < combined.len() {
+        let mut end = start;
+        while end < combined.len() && combined[end].0 == combined[start].0 {
+            end += 1;
+        }
+        // Tied samples all get the average of the ranks they span (ranks start at 1)
+        let tied = (end - start) as f64;
+        let rank = (start + end + 1) as f64 / 2.0;
+        rank_sum_a += rank * combined[start..end].iter().filter(|(_, from_a)| *from_a).count() as f64;
+        tie_correction += tied * tied * tied - tied;
+        start = end;
+    }
+
+    let u = rank_sum_a - n1 * (n1 + 1.0) / 2.0;
+    let mean = n1 * n2 / 2.0;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - tie_correction / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return None;
+    }
+
+    // Continuity correction
+    let diff = u - mean;
+    let corrected = if diff > 0.0 { (diff - 0.5).max(0.0) } else { (diff + 0.5).min(0.0) };
+    let z = corrected / variance.sqrt();
+    let p_value = (2.0 * (1.0 - normal_cdf(z.abs()))).clamp(0.0, 1.0);
+
+    Some(MannWhitney { u, z, p_value })
+}
+
+/// Median of the samples, or None if there aren't any
+pub fn median(samples: &[u64]) -> Option<f64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        Some((sorted[mid - 1] + sorted[mid]) as f64 / 2.0)
+    } else {
+        Some(sorted[mid] as f64)
+    }
+}
+
+/// The sample that `percent` percent of the samples are at or below (the nearest rank), or None if there aren't any
+pub fn percentile(samples: &[u64], percent: f64) -> Option<u64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let rank = (percent.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+/// One bar of a latency histogram, counting the samples from `low` up to (but not including) `high` microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bucket {
+    pub low: u64,
+    pub high: u64,
+    pub count: usize,
+}
+
+/// Sorts the samples into buckets that get wider as the latency goes up (1, 2, 5, 10, 20, 50... microseconds),
+/// so that both short and long times are visible. Only the buckets from the fastest sample to the slowest are returned
+pub fn log_histogram(samples: &[u64]) -> Vec<Bucket> {
+    let (Some(&fastest), Some(&slowest)) = (samples.iter().min(), samples.iter().max()) else {
+        return Vec::new();
+    };
+    let mut edges = vec![0];
+    let mut decade = 1;
+    while *edges.last().unwrap() <= slowest {
+        for step in [1, 2, 5] {
+            edges.push(decade * step);
+        }
+        decade *= 10;
+    }
+    let mut buckets: Vec<Bucket> = edges.windows(2)
+        .map(|w| Bucket { low: w[0], high: w[1], count: 0 })
+        .filter(|b| b.high > fastest && b.low <= slowest)
+        .collect();
+    for sample in samples {
+        if let Some(bucket) = buckets.iter_mut().find(|b| *sample < b.high) {
+            bucket.count += 1;
+        }
+    }
+    buckets
+}
+
+/// The windows averaged over by `WindowedAverage`: 1, 5 and 15 minutes, like the load average
+pub const WINDOWS: [Duration; 3] = [Duration::from_secs(60), Duration::from_secs(300), Duration::from_secs(900)];
+
+/// Averages over the last 1, 5 and 15 minutes. Like the load average, they're exponentially weighted:
+/// a value from one window ago counts for about a third as much as one from now
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WindowedAverage {
+    averages: Option<[f64; 3]>,
+    updated: Option<SystemTime>,
+}
+
+impl WindowedAverage {
+    pub fn add(&mut self, value: f64, now: SystemTime) {
+        let elapsed = self.updated.and_then(|t| now.duration_since(t).ok()).unwrap_or_default();
+        self.updated = Some(now);
+        let Some(averages) = &mut self.averages else {
+            self.averages = Some([value; 3]);
+            return;
+        };
+        for (average, window) in averages.iter_mut().zip(WINDOWS) {
+            let weight = 1.0 - (-elapsed.as_secs_f64() / window.as_secs_f64()).exp();
+            *average += weight * (value - *average);
+        }
+    }
+
+    /// The 1, 5 and 15 minute averages, or None if nothing's been added yet
+    pub fn averages(&self) -> Option<[f64; 3]> {
+        self.averages
+    }
+}
+
+/// How many newer requests have to be sent before an unanswered one counts as lost.
+/// Until then, its reply could still turn up late, or after the reply to a later one
+pub const REORDER_WINDOW: usize = 2;
+
+/// A request (and any retries of it) that's in the reordering window
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct PendingRequest {
+    first_sequence: u16, // Of the first attempt. Retries use the sequence numbers after it
+    attempts: u16,
+    answered: bool,
+    gap: Option<Duration>, // How long after the request before it this one was sent, if that's known
+}
+
+/// The latest requests to a host, so that replies can be matched up with them by sequence number.
+/// Sequence numbers wrap around, so they're compared by how far apart they are rather than which is bigger
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PendingRequests {
+    next_sequence: u16, // Numbered the same way the shared sockets number the requests to each host
+    requests: VecDeque<PendingRequest>, // The requests in the window, oldest first
+    window: usize, // REORDER_WINDOW, unless requests are sent in bursts
+}
+
+impl Default for PendingRequests {
+    fn default() -> Self {
+        PendingRequests { next_sequence: 0, requests: VecDeque::new(), window: REORDER_WINDOW }
+    }
+}
+
+impl PendingRequests {
+    /// Requests sent in bursts of `burst` are all sent before any of them can be answered, so the window has to
+    /// hold the whole burst as well as the newer requests that late replies can come after
+    pub fn set_burst(&mut self, burst: u32) {
+        self.window = REORDER_WINDOW + burst.max(1) as usize - 1;
+    }
+
+
+    /// A new request was sent. If that pushed the oldest request out of the window, returns whether it was answered
+    pub fn sent(&mut self) -> Option<bool> {
+        self.push(None).map(|request| request.answered)
+    }
+
+    fn push(&mut self, gap: Option<Duration>) -> Option<PendingRequest> {
+        self.requests.push_back(PendingRequest { first_sequence: self.next_sequence, attempts: 1, answered: false, gap });
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        if self.requests.len() > self.window {
+            self.requests.pop_front()
+        } else {
+            None
+        }
+    }
+
+    /// The sequence number of the next request (or retry)
+    pub fn next_sequence(&self) -> u16 {
+        self.next_sequence
+    }
+
+    /// The latest request was sent again
+    pub fn retried(&mut self) {
+        if let Some(request) = self.requests.back_mut() {
+            request.attempts = request.attempts.saturating_add(1);
+        }
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+    }
+
+    /// Matches up a reply with its request. Without a sequence number, it's taken to be for the latest request
+    /// (probes that don't have sequence numbers only have one request out at a time). Returns false for duplicates,
+    /// and for replies to requests that have fallen out of the window (or were never sent)
+    pub fn answer(&mut self, sequence: Option<u16>) -> bool {
+        self.answer_request(sequence).is_some()
+    }
+
+    fn answer_request(&mut self, sequence: Option<u16>) -> Option<PendingRequest> {
+        let request = match sequence {
+            Some(sequence) => self.requests.iter_mut().find(|r| sequence.wrapping_sub(r.first_sequence) < r.attempts),
+            None => self.requests.back_mut(),
+        }?;
+        (!std::mem::replace(&mut request.answered, true)).then_some(*request)
+    }
+
+    /// Whether the latest request is still waiting for a reply
+    pub fn waiting(&self) -> bool {
+        self.requests.back().is_some_and(|request| !request.answered)
+    }
+}
+
+/// Works out packet loss from which sequence numbers were answered. Requests only count once they've been
+/// answered or fallen out of the reordering window, so ones that are still on their way aren't counted as lost
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SequenceLoss {
+    pending: PendingRequests,
+    answered: u32,
+    lost: u32,
+    last_sent: Option<SystemTime>,
+    // Whether each of the latest settled requests was answered, and how long after the one before it it was sent,
+    // oldest first
+    recent: VecDeque<(bool, Option<Duration>)>,
+}
+
+/// How many settled requests `SequenceLoss` keeps track of, to look for the pattern of loss from rate limiting
+pub const RATE_LIMIT_WINDOW: usize = 200;
+
+impl SequenceLoss {
+    /// A new request was sent at `at`
+    pub fn sent(&mut self, at: SystemTime) {
+        let gap = self.last_sent.and_then(|last| at.duration_since(last).ok());
+        self.last_sent = Some(at);
+        if let Some(request) = self.pending.push(gap) && !request.answered {
+            self.lost += 1;
+            self.settle(request);
+        }
+    }
+
+    fn settle(&mut self, request: PendingRequest) {
+        if self.recent.len() >= RATE_LIMIT_WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((request.answered, request.gap));
+    }
+
+    /// The latest request was sent again
+    pub fn retried(&mut self) {
+        self.pending.retried();
+    }
+
+    /// Requests are sent in bursts of this many (see `PendingRequests::set_burst`)
+    pub fn set_burst(&mut self, burst: u32) {
+        self.pending.set_burst(burst);
+    }
+
+    /// The sequence number of the next request (or retry)
+    pub fn next_sequence(&self) -> u16 {
+        self.pending.next_sequence()
+    }
+
+    /// A reply arrived (see `PendingRequests::answer`)
+    pub fn received(&mut self, sequence: Option<u16>) {
+        if let Some(request) = self.pending.answer_request(sequence) {
+            self.answered += 1;
+            self.settle(request);
+        }
+    }
+
+    /// Whether the latest requests were lost the way a rate limiter loses them: in a regular pattern (see
+    /// `periodic_loss`), or only when they were sent close together (see `loss_vanishes_when_slower`)
+    pub fn looks_rate_limited(&self) -> bool {
+        periodic_loss(self.recent.iter().map(|(answered, _)| *answered))
+            || loss_vanishes_when_slower(self.recent.iter().copied())
+    }
+
+    /// No more replies are expected (e.g. probing has stopped and the stragglers have been waited for), so the
+    /// unanswered requests still in the window count as lost
+    pub fn finish(&mut self) {
+        for request in std::mem::take(&mut self.pending.requests) {
+            if !request.answered {
+                self.lost += 1;
+                self.settle(request);
+            }
+        }
+    }
+
+    /// Requests that were answered
+    pub fn answered(&self) -> u32 {
+        self.answered
+    }
+
+    /// Requests that were answered or lost, leaving out the unanswered ones in the window
+    pub fn settled(&self) -> u32 {
+        self.answered + self.lost
+    }
+
+    /// Percentage of the settled requests that were lost, or None if none have settled yet
+    pub fn loss_percent(&self) -> Option<f64> {
+        (self.settled() > 0).then(|| self.lost as f64 * 100.0 / self.settled() as f64)
+    }
+
+    /// Starts counting again. Unanswered requests in the window still count if they're answered (or lost) later
+    pub fn reset(&mut self) {
+        self.answered = 0;
+        self.lost = 0;
+        self.recent.clear();
+    }
+}
+
+/// Whether requests were lost the way a rate limiter at the host loses them, rather than at random. A limiter lets
+/// through a fixed share of requests sent faster than its rate, so the replies and the losses come in runs that are
+/// always (give or take one) the same length, e.g. every third request lost. Random loss gives runs of every length,
+/// and an outage gives one long run, so neither looks like this. Needs at least 40 requests with 5 losses in them
+pub fn periodic_loss(answered: impl Iterator<Item = bool>) -> bool {
+    // The lengths of the runs of replies and of losses, in order
+    let mut runs: Vec<(bool, usize)> = Vec::new();
+    for a in answered {
+        match runs.last_mut() {
+            Some((kind, len)) if *kind == a => *len += 1,
+            _ => runs.push((a, 1)),
+        }
+    }
+    let total: usize = runs.iter().map(|(_, len)| len).sum();
+    let lost: usize = runs.iter().filter(|(a, _)| !a).map(|(_, len)| len).sum();
+    // The first and last runs could have started before the window or still be going, so they aren't counted
+    let inner = runs.get(1..runs.len().saturating_sub(1)).unwrap_or_default();
+    let steady = |kind: bool| {
+        let lens: Vec<usize> = inner.iter().filter(|(a, _)| *a == kind).map(|(_, len)| *len).collect();
+        let (min, max) = (lens.iter().min(), lens.iter().max());
+        lens.len() >= 4 && min.zip(max).is_some_and(|(min, max)| max - min <= 1)
+    };
+    total >= 40 && lost >= 5 && lost < total && steady(true) && steady(false)
+}
+
+/// Whether requests stopped being lost when they were sent further apart, which a rate limiter does but a lossy
+/// link doesn't. Takes whether each request was answered and how long after the one before it it was sent. The
+/// slower ones are those sent over twice the usual (median) gap after the one before, e.g. the first of each burst,
+/// or the ones after the interval was made longer. Needs at least 20 of them, all answered, while at least a fifth
+/// (and 5) of the others were lost
+pub fn loss_vanishes_when_slower(requests: impl Iterator<Item = (bool, Option<Duration>)>) -> bool {
+    let requests: Vec<(bool, Duration)> = requests.filter_map(|(answered, gap)| Some((answered, gap?))).collect();
+    let mut gaps: Vec<Duration> = requests.iter().map(|(_, gap)| *gap).collect();
+    gaps.sort();
+    let Some(usual) = gaps.get(gaps.len() / 2) else { return false };
+    let (slower, faster): (Vec<&(bool, Duration)>, Vec<_>) = requests.iter().partition(|(_, gap)| *gap > *usual * 2);
+    let lost = faster.iter().filter(|(answered, _)| !answered).count();
+    slower.len() >= 20 && slower.iter().all(|(answered, _)| *answered) && lost >= 5 && lost * 5 >= faster.len()
+}
+
+/// Which way the latency is going
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Trend {
+    Rising,
+    Steady,
+    Falling,
+}
+
+/// Compares a recent average with one over a longer time. It's only rising or falling if they differ
+/// by more than 10 % and 1 ms, so that tiny changes in short times don't count
+pub fn trend(recent_ms: f64, longer_ms: f64) -> Trend {
+    let threshold = (longer_ms * 0.1).max(1.0);
+    if recent_ms > longer_ms + threshold {
+        Trend::Rising
+    } else if recent_ms < longer_ms - threshold {
+        Trend::Falling
+    } else {
+        Trend::Steady
+    }
+}
+
+/// Voice call quality estimated from the network conditions
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CallQuality {
+    /// Transmission rating factor, from 0 (unusable) to 93.2 (the best possible without a wideband codec)
+    pub r_factor: f64,
+    /// Mean opinion score, from 1 (bad) to 4.5 (excellent)
+    pub mos: f64,
+}
+
+/// Estimates the quality of a VoIP call using a simplified version of the ITU-T G.107 E-model.
+/// Latency and jitter are one-way times in milliseconds, and loss is a percentage
+pub fn call_quality(latency_ms: f64, jitter_ms: f64, loss_percent: f64) -> CallQuality {
+    // Jitter buffers add delay, so jitter is counted twice, plus 10 ms for the codec
+    let effective_latency = latency_ms + 2.0 * jitter_ms + 10.0;
+    let delay_impairment = if effective_latency < 160.0 {
+        effective_latency / 40.0
+    } else {
+        (effective_latency - 120.0) / 10.0
+    };
+    let r_factor = (93.2 - delay_impairment - 2.5 * loss_percent).clamp(0.0, 93.2);
+    let mos = 1.0 + 0.035 * r_factor + 0.000007 * r_factor * (r_factor - 60.0) * (100.0 - r_factor);
+    CallQuality { r_factor, mos: mos.clamp(1.0, 4.5) }
+}
+
+/// How much latency, jitter and loss count towards the health score, relative to each other
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HealthWeights {
+    pub latency: f64,
+    pub jitter: f64,
+    pub loss: f64,
+}
+
+impl Default for HealthWeights {
+    /// Loss counts twice as much as latency or jitter
+    fn default() -> HealthWeights {
+        HealthWeights { latency: 1.0, jitter: 1.0, loss: 2.0 }
+    }
+}
+
+impl FromStr for HealthWeights {
+    type Err = String;
+
+    /// Parses weights like "1,1,2" (latency, jitter, loss)
+    fn from_str(s: &str) -> Result<HealthWeights, String> {
+        let invalid = || format!("{} should be three weights for latency, jitter and loss, like 1,1,2", s);
+        let weights: Vec<f64> = s.split(',').map(|w| w.trim().parse().map_err(|_| invalid())).collect::<Result<_, _>>()?;
+        let [latency, jitter, loss] = weights[..] else { return Err(invalid()) };
+        if weights.iter().any(|w| *w < 0.0 || !w.is_finite()) || weights.iter().sum::<f64>() == 0.0 {
+            return Err(invalid());
+        }
+        Ok(HealthWeights { latency, jitter, loss })
+    }
+}
+
+/// A score from 0 (unusable) to 100 (perfect), as the weighted average of a score for each of latency,
+/// jitter and loss. Those drop from 100 to 0 as the latency goes up to 500 ms, the jitter to 50 ms and the loss to 10 %
+pub fn health_score(latency_ms: f64, jitter_ms: f64, loss_percent: f64, weights: HealthWeights) -> f64 {
+    let latency = (100.0 - latency_ms / 5.0).clamp(0.0, 100.0);
+    let jitter = (100.0 - jitter_ms * 2.0).clamp(0.0, 100.0);
+    let loss = (100.0 - loss_percent * 10.0).clamp(0.0, 100.0);
+    let total = weights.latency + weights.jitter + weights.loss;
+    (latency * weights.latency + jitter * weights.jitter + loss * weights.loss) / total
+}
+
+/// Cumulative distribution function of the standard normal distribution
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2))
+}
+
+/// Approximation of the error function (Abramowitz and Stegun 7.1.26, error < 1.5e-7)
+fn erf(x: f64) -> f64 {
+    let sign = if x < 0.0 { -1.0 } else { 1.0 };
+    let x = x.abs();
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    sign * (1.0 - poly * (-x * x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether each of `count` requests is answered, losing about `loss_percent` of them at random (always the same
+    /// ones, from a fixed seed)
+    fn random_loss(count: usize, loss_percent: u64) -> Vec<bool> {
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        (0..count).map(|_| {
+            // xorshift64
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state % 100 >= loss_percent
+        }).collect()
+    }
+
+    /// An outage: 80 replies, then 40 losses, then 80 replies
+    fn outage() -> Vec<bool> {
+        [true; 80].into_iter().chain([false; 40]).chain([true; 80]).collect()
+    }
+
+    /// Requests sent in bursts of three a second apart, with the requests in a burst sent 1 ms after each other
+    fn burst_gaps(answered: &[bool]) -> Vec<(bool, Option<Duration>)> {
+        answered.iter().enumerate().map(|(i, answered)| {
+            let gap = if i % 3 == 0 { Duration::from_secs(1) } else { Duration::from_millis(1) };
+            (*answered, Some(gap))
+        }).collect()
+    }
+
+    #[test]
+    fn random_loss_isnt_rate_limiting() {
+        for loss_percent in [5, 20, 50] {
+            let answered = random_loss(RATE_LIMIT_WINDOW, loss_percent);
+            assert!(!periodic_loss(answered.iter().copied()), "{} % loss", loss_percent);
+            assert!(!loss_vanishes_when_slower(burst_gaps(&answered).into_iter()), "{} % loss", loss_percent);
+        }
+    }
+
+    #[test]
+    fn outage_isnt_rate_limiting() {
+        assert!(!periodic_loss(outage().into_iter()));
+        assert!(!loss_vanishes_when_slower(burst_gaps(&outage()).into_iter()));
+    }
+
+    #[test]
+    fn periodic_loss_is_rate_limiting() {
+        // Every third request lost
+        assert!(periodic_loss((0..RATE_LIMIT_WINDOW).map(|i| i % 3 != 2)));
+        // Two out of every five lost, which takes runs of two and three replies
+        assert!(periodic_loss((0..RATE_LIMIT_WINDOW).map(|i| [true, false, true, true, false][i % 5])));
+        // Too few requests to tell
+        assert!(!periodic_loss((0..30).map(|i| i % 3 != 2)));
+    }
+
+    #[test]
+    fn loss_that_vanishes_at_lower_rates_is_rate_limiting() {
+        // The first request of each burst is always answered, and the rest are lost at random
+        let mut answered = random_loss(RATE_LIMIT_WINDOW, 50);
+        for first in answered.iter_mut().step_by(3) {
+            *first = true;
+        }
+        assert!(!periodic_loss(answered.iter().copied()));
+        assert!(loss_vanishes_when_slower(burst_gaps(&answered).into_iter()));
+        // Without anything sent more slowly, it's the same as random loss
+        assert!(!loss_vanishes_when_slower(answered.iter().map(|a| (*a, Some(Duration::from_secs(1))))));
+    }
+
+    #[test]
+    fn sequence_loss_keeps_track_of_gaps() {
+        let mut loss = SequenceLoss::default();
+        let start = SystemTime::UNIX_EPOCH;
+        for burst in 0..40 {
+            for i in 0..3 {
+                let sequence = loss.next_sequence();
+                loss.sent(start + Duration::from_secs(burst) + Duration::from_millis(i));
+                if i == 0 {
+                    loss.received(Some(sequence));
+                }
+            }
+        }
+        assert!(loss.looks_rate_limited());
+        assert_eq!(loss.answered(), 40);
+    }
+
+    #[test]
+    fn finishing_counts_the_window_as_lost() {
+        let mut loss = SequenceLoss::default();
+        for i in 0..4 {
+            let sequence = loss.next_sequence();
+            loss.sent(SystemTime::UNIX_EPOCH + Duration::from_secs(i));
+            if i % 2 == 0 {
+                loss.received(Some(sequence));
+            }
+        }
+        // The last one is still in the reordering window
+        assert_eq!(loss.settled(), 3);
+        loss.finish();
+        assert_eq!(loss.settled(), 4);
+        assert_eq!(loss.loss_percent(), Some(50.0));
+    }
+}
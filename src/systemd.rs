@@ -0,0 +1,83 @@
+//! Running as a systemd service: telling systemd when we're ready (or reloading) with sd_notify,
+//! keeping the watchdog happy, and logging to the journal with structured fields
+
+use std::env;
+use std::io::{Error, ErrorKind};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::Duration;
+use nix::time::{ClockId, clock_gettime};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Log priorities, as used by syslog and the journal
+pub const PRIORITY_ERROR: u8 = 3;
+pub const PRIORITY_WARNING: u8 = 4;
+pub const PRIORITY_NOTICE: u8 = 5;
+pub const PRIORITY_INFO: u8 = 6;
+pub const PRIORITY_DEBUG: u8 = 7;
+
+/// Sends a state change (e.g. "READY=1") to systemd. Returns false if we weren't started by
+/// systemd (or it isn't expecting notifications), in which case nothing is sent
+pub fn notify(state: &str) -> Result<bool, Error> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let path = path.to_string_lossy();
+    // Names starting with @ are in the abstract namespace
+    let addr = match path.strip_prefix('@') {
+        Some(name) => abstract_address(name)?,
+        None => SocketAddr::from_pathname(path.as_ref())?,
+    };
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(true)
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_address(name: &str) -> Result<SocketAddr, Error> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_address(_name: &str) -> Result<SocketAddr, Error> {
+    Err(ErrorKind::Unsupported.into())
+}
+
+/// Tells systemd that the configuration is being reloaded. `notify("READY=1")` should be sent once it's done
+pub fn notify_reloading() -> Result<bool, Error> {
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC).map_err(Error::from)?;
+    let usec = now.tv_sec() as u64 * 1_000_000 + now.tv_nsec() as u64 / 1000;
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", usec))
+}
+
+/// How often to send "WATCHDOG=1", if systemd's watchdog is enabled for this process.
+/// This is half of the watchdog timeout, as recommended by sd_watchdog_enabled(3)
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") && pid.parse() != Ok(process::id()) {
+        return None;
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Whether the journal is available to log to
+pub fn journal_available() -> bool {
+    std::path::Path::new(JOURNAL_SOCKET).exists()
+}
+
+/// Logs a message to the journal, with extra fields (e.g. ("MULTIPING_HOST", "example.com")).
+/// Field names have to be upper case, and values can't contain newlines
+pub fn journal_send(priority: u8, message: &str, fields: &[(&str, &str)]) -> Result<(), Error> {
+    let mut entry = format!("PRIORITY={}\nSYSLOG_IDENTIFIER=multiping\nMESSAGE={}\n", priority, message.replace('\n', " "));
+    for (name, value) in fields {
+        if value.contains('\n') {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        entry.push_str(&format!("{}={}\n", name, value));
+    }
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(entry.as_bytes(), JOURNAL_SOCKET)?;
+    Ok(())
+}
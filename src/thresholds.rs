@@ -0,0 +1,59 @@
+//! Latency thresholds for colouring the table, which can be set for every host or just some of them
+
+use std::str::FromStr;
+
+use crate::HostInfo;
+
+/// A latency in milliseconds for every host (e.g. "100"), or for one host (e.g. "example.com=300")
+#[derive(Clone, Debug, PartialEq)]
+pub struct HostThreshold {
+    pub host: Option<String>,
+    pub ms: f64,
+}
+
+impl FromStr for HostThreshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<HostThreshold, String> {
+        let (host, ms) = match s.rsplit_once('=') {
+            Some((host, ms)) => (Some(host.trim().to_string()), ms),
+            None => (None, s),
+        };
+        let ms: f64 = ms.trim().parse().map_err(|_| format!("{} isn't a number of milliseconds", ms.trim()))?;
+        if ms < 0.0 || ms.is_nan() {
+            return Err(format!("{} isn't a number of milliseconds", ms));
+        }
+        Ok(HostThreshold { host, ms })
+    }
+}
+
+impl HostThreshold {
+    /// Whether the threshold is for this host
+    fn applies_to(&self, host: &HostInfo) -> bool {
+        self.host.as_ref().is_none_or(|name| host.is_named(name))
+    }
+}
+
+/// Warning and critical latencies. Times over them are shown in yellow and red, and ones under them in green
+#[derive(Clone, Debug, Default)]
+pub struct LatencyColours {
+    pub warning: Vec<HostThreshold>,
+    pub critical: Vec<HostThreshold>,
+}
+
+impl LatencyColours {
+    pub fn is_empty(&self) -> bool {
+        self.warning.is_empty() && self.critical.is_empty()
+    }
+
+    /// The warning and critical latencies for a host. A threshold for the host itself wins over one for every host,
+    /// and later ones win over earlier ones
+    pub fn for_host(&self, host: &HostInfo) -> (Option<f64>, Option<f64>) {
+        let find = |thresholds: &[HostThreshold]| {
+            thresholds.iter().rev().find(|t| t.host.is_some() && t.applies_to(host))
+                .or_else(|| thresholds.iter().rev().find(|t| t.host.is_none()))
+                .map(|t| t.ms)
+        };
+        (find(&self.warning), find(&self.critical))
+    }
+}
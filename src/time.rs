@@ -0,0 +1,186 @@
+//! Formatting of times and dates, without depending on a date/time crate
+//!
+//! The local time zone's offset comes from the C library's `localtime_r`, which is the only thing that knows the
+//! system's time zone rules (TZ, /etc/localtime and the tz database, with their DST rules for future times) short of
+//! a time zone crate. Calling it is unsafe, so `local_offset` is one of the few places in the crate that allows unsafe
+//! code. localtime_r only writes to the `tm` it's given, so it's safe to call from any thread
+
+use std::io::{Error, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Formats a time as a UTC date and time, e.g. "2025-06-01 12:34:56 UTC"
+pub fn format_utc(time: SystemTime) -> String {
+    let secs = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time_of_day = secs.rem_euclid(86400);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day,
+        time_of_day / 3600, time_of_day % 3600 / 60, time_of_day % 60)
+}
+
+/// Formats a time as RFC 3339 in UTC, with milliseconds, e.g. "2025-06-01T12:34:56.789Z"
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time_of_day = secs.rem_euclid(86400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+        time_of_day / 3600, time_of_day % 3600 / 60, time_of_day % 60, since_epoch.subsec_millis())
+}
+
+/// Formats the UTC time of day with milliseconds, e.g. "12:34:56.789"
+pub fn format_time_of_day(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let time_of_day = since_epoch.as_secs() % 86400;
+    format!("{:02}:{:02}:{:02}.{:03}", time_of_day / 3600, time_of_day % 3600 / 60, time_of_day % 60, since_epoch.subsec_millis())
+}
+
+/// Formats a duration to the nearest second, e.g. "1h 02m 03s" or "45s"
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {:02}m {:02}s", secs / 3600, secs % 3600 / 60, secs % 60)
+    } else if secs >= 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Parses an interval in seconds, which can instead end with ms or us for milliseconds or microseconds
+/// (e.g. "0.5", "2s", "10ms" or "250us"). It has to be more than 0
+pub fn parse_interval(s: &str) -> Result<Duration, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("{:?} should be a time more than 0, e.g. 2, 0.5s, 10ms or 250us", s));
+    let s = s.trim();
+    let (number, unit) = if let Some(number) = s.strip_suffix("ms") {
+        (number, 1e-3)
+    } else if let Some(number) = s.strip_suffix("us").or_else(|| s.strip_suffix("µs")) {
+        (number, 1e-6)
+    } else {
+        (s.strip_suffix('s').unwrap_or(s), 1.0)
+    };
+    let seconds = number.trim().parse::<f64>().map_err(|_| invalid())? * unit;
+    Duration::try_from_secs_f64(seconds).ok().filter(|d| !d.is_zero()).ok_or_else(invalid)
+}
+
+/// Sleeps until `deadline`. Sleeping tends to overshoot by tens of microseconds, which matters with short
+/// intervals, so the last bit is spent spinning instead
+pub fn sleep_until(deadline: Instant) {
+    const SPIN: Duration = Duration::from_micros(200);
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining > SPIN {
+        thread::sleep(remaining - SPIN);
+    }
+    while Instant::now() < deadline {
+        thread::yield_now();
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The date (year, month, day) that's the given number of days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+/// Minutes since midnight in the local time zone
+pub fn local_minute_of_day(time: SystemTime) -> u32 {
+    let secs = time.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+    // Fall back to UTC if the local time can't be worked out
+    let offset = local_offset(time).unwrap_or(0);
+    ((secs + offset).rem_euclid(86400) / 60) as u32
+}
+
+/// Formats a time as RFC 3339 in the local time zone, with milliseconds and the offset from UTC,
+/// e.g. "2025-06-01T14:34:56.789+02:00". If the local time can't be worked out, it's in UTC
+pub fn format_rfc3339_local(time: SystemTime) -> String {
+    let Some(offset) = local_offset(time) else { return format_rfc3339(time) };
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64 + offset;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time_of_day = secs.rem_euclid(86400);
+    let sign = if offset < 0 { '-' } else { '+' };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}{}{:02}:{:02}", year, month, day,
+        time_of_day / 3600, time_of_day % 3600 / 60, time_of_day % 60, since_epoch.subsec_millis(),
+        sign, offset.abs() / 3600, offset.abs() % 3600 / 60)
+}
+
+/// How far ahead of UTC the local time zone is at the given time, in seconds
+#[allow(unsafe_code)]
+fn local_offset(time: SystemTime) -> Option<i64> {
+    let secs = match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as libc::time_t,
+        Err(_) => 0,
+    };
+    // SAFETY: tm is plain data, so all zeroes is valid
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call, and localtime_r is thread-safe
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(tm.tm_gmtoff as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervals_parse() {
+        assert_eq!(parse_interval("2").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_interval("0.5s").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_interval(" 10ms ").unwrap(), Duration::from_millis(10));
+        assert_eq!(parse_interval("250us").unwrap(), Duration::from_micros(250));
+        assert_eq!(parse_interval("250µs").unwrap(), Duration::from_micros(250));
+        assert_eq!(parse_interval("1.5 ms").unwrap(), Duration::from_micros(1500));
+    }
+
+    #[test]
+    fn intervals_have_to_be_more_than_0() {
+        for invalid in ["0", "0ms", "-1", "nan", "inf", "1e30", "", "s", "ten", "10m", "1h"] {
+            let e = parse_interval(invalid).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::InvalidInput, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn days_convert_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(24855), (2038, 1, 19));
+    }
+
+    #[test]
+    fn dates_convert_back_to_the_same_days() {
+        // Includes 1900 and 2100, which aren't leap years, and 2000, which is
+        for days in -30000..100000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(civil_from_days(days_from_civil(2100, 2, 28) + 1), (2100, 3, 1));
+        assert_eq!(civil_from_days(days_from_civil(1900, 2, 28) + 1), (1900, 3, 1));
+    }
+}
@@ -0,0 +1,105 @@
+//! ICMP timestamp probe, which asks an IPv4 host for the time (in milliseconds since midnight UTC) like the classic
+//! icmp timestamp tools, and works out how far its clock is from ours. This needs a raw socket (or the helper)
+
+use std::io::{Error, ErrorKind, Read};
+use std::net::SocketAddrV4;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use socket2::Socket;
+
+use crate::icmp::{ICMPv4Packet, ICMPv4Type, construct_timestamp_request};
+use crate::ipv4::Ipv4Header;
+use crate::probe::{Probe, ProbeDetails, ProbeReply};
+use crate::{mkv4rawsocket, next_sequence};
+
+const MILLIS_PER_DAY: i64 = 86_400_000;
+
+/// Set in a timestamp if it isn't in milliseconds since midnight UTC, in which case it can't be compared with ours
+const NON_STANDARD: u32 = 0x8000_0000;
+
+/// Raw sockets get every ICMP message, so each probe has its own identifier to pick out its replies
+static NEXT_IDENTIFIER: AtomicU16 = AtomicU16::new(0);
+
+/// Sends timestamp requests to a host, and waits for its replies
+pub struct TimestampProbe {
+    addr: SocketAddrV4,
+    socket: Socket,
+    identifier: u16,
+    next_sequence: u16,
+    /// Our time in the last request
+    originate: u32,
+    sent_at: Instant,
+}
+
+impl TimestampProbe {
+    pub fn new(addr: SocketAddrV4) -> Result<TimestampProbe, Error> {
+        let socket = mkv4rawsocket()?;
+        // Only the host's replies are wanted
+        socket.connect(&SocketAddrV4::new(*addr.ip(), 0).into())?;
+        let identifier = (std::process::id() as u16) ^ NEXT_IDENTIFIER.fetch_add(1, Ordering::Relaxed).rotate_left(8);
+        Ok(TimestampProbe { addr, socket, identifier, next_sequence: 0, originate: 0, sent_at: Instant::now() })
+    }
+}
+
+impl Probe for TimestampProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        self.originate = millis_since_midnight();
+        let request = construct_timestamp_request(self.identifier, next_sequence(&mut self.next_sequence), self.originate);
+        self.sent_at = Instant::now();
+        self.socket.send(&request)?;
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let mut buf = [0u8; 128];
+        loop {
+            let remaining = timeout.saturating_sub(self.sent_at.elapsed());
+            if remaining.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            self.socket.set_read_timeout(Some(remaining))?;
+            let len = match (&self.socket).read(&mut buf) {
+                Ok(len) => len,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::TimedOut.into()),
+                Err(e) => return Err(e),
+            };
+            let t4 = millis_since_midnight();
+            let latency = self.sent_at.elapsed().as_micros() as u64;
+            let packet = &buf[..len];
+            // Raw sockets give the IP header too
+            let Some(message) = Ipv4Header::try_from(packet).ok()
+                .and_then(|header| packet.get(header.header_len..)).filter(|m| m.len() >= 20) else { continue };
+            let Ok(ICMPv4Type::TimestampReply { identifier, sequence_num, ts_originate, ts_receive, ts_transmit })
+                = ICMPv4Packet::new(message).and_then(|packet| packet.message_type()) else { continue };
+            if identifier != self.identifier || sequence_num != self.next_sequence.wrapping_sub(1) || ts_originate != self.originate {
+                continue;
+            }
+            if ts_receive & NON_STANDARD != 0 || ts_transmit & NON_STANDARD != 0 {
+                return Ok(ProbeReply::new(latency));
+            }
+            let (t1, t2, t3, t4) = (self.originate as i64, ts_receive as i64, ts_transmit as i64, t4 as i64);
+            let offset_ms = (wrap_day(t2 - t1) + wrap_day(t3 - t4)) / 2;
+            return Ok(ProbeReply { latency, details: Some(ProbeDetails::ClockOffset { offset: offset_ms * 1000 }) });
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("ICMP timestamp request to {}", self.addr.ip())
+    }
+
+    fn sequence(&self) -> Option<u16> {
+        Some(self.next_sequence.wrapping_sub(1))
+    }
+}
+
+/// The current time in milliseconds since midnight UTC, as ICMP timestamps are
+fn millis_since_midnight() -> u32 {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    (now.as_millis() % MILLIS_PER_DAY as u128) as u32
+}
+
+/// A difference between two times of day, taking the shortest way around midnight
+fn wrap_day(difference: i64) -> i64 {
+    (difference + MILLIS_PER_DAY / 2).rem_euclid(MILLIS_PER_DAY) - MILLIS_PER_DAY / 2
+}
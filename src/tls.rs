@@ -0,0 +1,172 @@
+//! TLS handshake probe: connects over TCP, completes a TLS handshake, and reads the certificate's expiry date.
+//! The certificate isn't verified, as the point is to measure the endpoint (and warn about expiry), not to trust it
+
+use std::io::{Error, ErrorKind};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, ring, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme};
+
+use crate::probe::{Probe, ProbeDetails, ProbeReply};
+use crate::time::days_from_civil;
+
+#[derive(Clone, Copy, Debug)]
+pub struct TlsReply {
+    /// Time taken to connect over TCP, in microseconds
+    pub connect: u64,
+    /// Time taken to connect and complete the handshake, in microseconds
+    pub total: u64,
+    /// When the server's certificate expires, in seconds since the Unix epoch
+    pub expires: Option<i64>,
+}
+
+/// Connects to a TLS server and completes a handshake every time
+pub struct TlsProbe {
+    addr: SocketAddr,
+    server_name: String,
+}
+
+impl TlsProbe {
+    pub fn new(addr: SocketAddr, server_name: &str) -> TlsProbe {
+        TlsProbe { addr, server_name: server_name.to_string() }
+    }
+}
+
+impl Probe for TlsProbe {
+    fn send(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn match_reply(&mut self, timeout: Duration) -> Result<ProbeReply, Error> {
+        let reply = tls_handshake(self.addr, &self.server_name, timeout)?;
+        Ok(ProbeReply {
+            latency: reply.total,
+            details: Some(ProbeDetails::Tls { connect: reply.connect, expires: reply.expires }),
+        })
+    }
+
+    fn describe(&self) -> String {
+        format!("TLS handshake with {} ({})", self.server_name, self.addr)
+    }
+}
+
+/// Connects to `addr` and performs a TLS handshake, giving up after `timeout`.
+/// `server_name` is sent with SNI, so it should be the host name rather than the IP address if possible
+pub fn tls_handshake(addr: SocketAddr, server_name: &str, timeout: Duration) -> Result<TlsReply, Error> {
+    let provider = Arc::new(ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth();
+    let name = ServerName::try_from(server_name.to_string())
+        .unwrap_or(ServerName::IpAddress(addr.ip().into()));
+    let mut connection = ClientConnection::new(Arc::new(config), name).map_err(Error::other)?;
+
+    let start = Instant::now();
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    let connect = start.elapsed().as_micros() as u64;
+
+    while connection.is_handshaking() {
+        let remaining = timeout.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        stream.set_write_timeout(Some(remaining))?;
+        match connection.complete_io(&mut stream) {
+            Ok(_) => {},
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Err(ErrorKind::TimedOut.into()),
+            Err(e) => return Err(e),
+        }
+    }
+    let total = start.elapsed().as_micros() as u64;
+
+    let expires = connection.peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(|cert| certificate_expiry(cert));
+    Ok(TlsReply { connect, total, expires })
+}
+
+/// Skips verifying the certificate, but still checks the handshake signatures
+#[derive(Debug)]
+pub(crate) struct AcceptAnyCertificate(pub(crate) Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(&self, _end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct)
+            -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer<'_>, dss: &DigitallySignedStruct)
+            -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Reads the notAfter time out of a DER-encoded X.509 certificate
+fn certificate_expiry(cert: &[u8]) -> Option<i64> {
+    // Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { [0] version OPTIONAL, serialNumber,
+    //     signature, issuer, validity SEQUENCE { notBefore, notAfter }, ... } ... }
+    let (_, certificate, _) = der_element(cert, 0)?;
+    let (_, tbs, _) = der_element(cert, certificate)?;
+    let mut pos = tbs;
+    let (tag, _, end) = der_element(cert, pos)?;
+    if tag == 0xa0 {
+        pos = end; // Skip the version
+    }
+    for _ in 0..3 {
+        pos = der_element(cert, pos)?.2; // Skip the serial number, signature algorithm and issuer
+    }
+    let (_, validity, _) = der_element(cert, pos)?;
+    let not_before_end = der_element(cert, validity)?.2;
+    let (tag, start, end) = der_element(cert, not_before_end)?;
+    parse_der_time(tag, cert.get(start..end)?)
+}
+
+/// Reads the header of the DER element at `pos`, returning its tag and where its contents start and end
+fn der_element(buf: &[u8], pos: usize) -> Option<(u8, usize, usize)> {
+    let tag = *buf.get(pos)?;
+    let first = *buf.get(pos + 1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let len_bytes = first & 0x7f;
+        if len_bytes > 4 {
+            return None;
+        }
+        let len = buf.get(pos + 2..pos + 2 + len_bytes)?.iter().fold(0, |acc, b| (acc << 8) | *b as usize);
+        (len, 2 + len_bytes)
+    };
+    let start = pos + header;
+    Some((tag, start, start.checked_add(len)?))
+}
+
+/// Parses a UTCTime (YYMMDDHHMMSSZ) or GeneralizedTime (YYYYMMDDHHMMSSZ) into seconds since the Unix epoch
+fn parse_der_time(tag: u8, time: &[u8]) -> Option<i64> {
+    let text = std::str::from_utf8(time).ok()?;
+    let (year, rest) = match tag {
+        0x17 => {
+            let yy: i64 = text.get(0..2)?.parse().ok()?;
+            (if yy >= 50 { 1900 + yy } else { 2000 + yy }, text.get(2..)?)
+        },
+        0x18 => (text.get(0..4)?.parse().ok()?, text.get(4..)?),
+        _ => return None,
+    };
+    let field = |i: usize| -> Option<i64> { rest.get(i..i + 2)?.parse().ok() };
+    let days = days_from_civil(year, field(0)?, field(2)?);
+    Some(days * 86400 + field(4)? * 3600 + field(6)? * 60 + field(8)?)
+}
@@ -0,0 +1,40 @@
+//! A small web server with a built-in dashboard, so the statistics can be watched from a browser.
+//! The page gets its data from the same WebSocket feed as `--ws`, at /ws, and a snapshot is at /api/hosts
+
+use std::io::{BufReader, Error, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use crate::websocket::{Feed, read_request};
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Starts the web server on its own thread
+pub fn serve(addr: SocketAddr, feed: Feed) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let feed = feed.clone();
+            thread::spawn(move || handle_connection(stream, &feed));
+        }
+    });
+    Ok(())
+}
+
+/// Answers one request. Connections aren't kept alive, apart from WebSockets
+fn handle_connection(mut stream: TcpStream, feed: &Feed) -> Result<(), Error> {
+    let request = read_request(&mut BufReader::new(stream.try_clone()?))?;
+    let path = request.path.split('?').next().unwrap_or("/");
+    match (request.method.as_str(), path) {
+        ("GET", "/ws") if request.is_websocket() => feed.accept(stream, &request),
+        ("GET", "/") => respond(&mut stream, "200 OK", "text/html; charset=utf-8", DASHBOARD),
+        ("GET", "/api/hosts") => respond(&mut stream, "200 OK", "application/json", &feed.snapshot()),
+        ("GET", _) => respond(&mut stream, "404 Not Found", "text/plain", "Not found\n"),
+        _ => respond(&mut stream, "405 Method Not Allowed", "text/plain", "Method not allowed\n"),
+    }
+}
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> Result<(), Error> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body)
+}
@@ -0,0 +1,331 @@
+//! A WebSocket server which streams the statistics of every host as JSON, so that a browser
+//! or dashboard can show them live. Only what's needed for sending text messages is implemented
+
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::HostInfo;
+use crate::json;
+
+/// Added to the client's key to make the handshake's accept key (RFC 6455 section 1.3)
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// How many messages can be waiting to be sent to a client. A client that falls this far behind is dropped, rather
+/// than holding up the updates
+const CLIENT_BACKLOG: usize = 256;
+/// How long writing to a client can take before it's given up on
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The start of an HTTP request
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>, // Names are lower case
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    /// Whether the request wants to switch to the WebSocket protocol
+    pub fn is_websocket(&self) -> bool {
+        self.header("upgrade").is_some_and(|u| u.eq_ignore_ascii_case("websocket"))
+    }
+}
+
+/// Reads the request line and headers of an HTTP request (but not the body)
+pub fn read_request(reader: &mut impl BufRead) -> Result<Request, Error> {
+    let invalid = || Error::from(ErrorKind::InvalidData);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(invalid)?.to_string();
+    let path = parts.next().ok_or_else(invalid)?.to_string();
+    
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(invalid)?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    Ok(Request { method, path, headers })
+}
+
+/// A frame that's ready to send, shared between the clients it's queued for
+type Frame = Arc<Vec<u8>>;
+
+/// Sends the latest statistics to every WebSocket client
+#[derive(Clone, Default)]
+pub struct Feed {
+    clients: Arc<Mutex<Vec<SyncSender<Frame>>>>, // Each client's frames are written by its own thread
+    latest: Arc<Mutex<Vec<Option<String>>>>, // Sent to clients when they connect
+}
+
+impl Feed {
+    pub fn new() -> Feed {
+        Feed::default()
+    }
+
+    /// Sends a host's latest statistics to every client
+    pub fn publish(&self, index: usize, host: &HostInfo) {
+        let message = json::host_stats(index, host).finish();
+        {
+            let mut latest = self.latest.lock().unwrap();
+            if latest.len() <= index {
+                latest.resize(index + 1, None);
+            }
+            latest[index] = Some(message.clone());
+        }
+        let frame = Arc::new(frame(OPCODE_TEXT, message.as_bytes()));
+        // Clients whose writer has stopped have gone away, and ones with a full queue aren't keeping up
+        self.clients.lock().unwrap().retain(|client| client.try_send(frame.clone()).is_ok());
+    }
+
+    /// The latest statistics of every host, as a JSON array
+    pub fn snapshot(&self) -> String {
+        let latest = self.latest.lock().unwrap();
+        let hosts: Vec<&str> = latest.iter().flatten().map(|s| s.as_str()).collect();
+        format!("[{}]", hosts.join(","))
+    }
+
+    /// Completes the WebSocket handshake for a request that's already been read,
+    /// then sends the client every update from now on
+    pub fn accept(&self, mut stream: TcpStream, request: &Request) -> Result<(), Error> {
+        let Some(key) = request.header("sec-websocket-key") else {
+            stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+            return Err(ErrorKind::InvalidData.into());
+        };
+        stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+        write!(stream, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept_key(key))?;
+        
+        // Hold the clients lock while taking the snapshot, so that no update can come in between
+        let mut clients = self.clients.lock().unwrap();
+        let snapshot: Vec<Vec<u8>> = self.latest.lock().unwrap().iter().flatten()
+            .map(|message| frame(OPCODE_TEXT, message.as_bytes()))
+            .collect();
+        let (tx, rx) = sync_channel(CLIENT_BACKLOG);
+        let reader = stream.try_clone()?;
+        clients.push(tx.clone());
+        thread::spawn(move || write_frames(stream, snapshot, rx));
+        thread::spawn(move || read_frames(reader, tx));
+        Ok(())
+    }
+}
+
+/// Starts a WebSocket server for the feed on its own thread, which accepts connections on any path
+pub fn serve(addr: SocketAddr, feed: Feed) -> Result<(), Error> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let feed = feed.clone();
+            // Each handshake has its own thread, so a slow client can't hold up the others
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone()?);
+                let request = read_request(&mut reader)?;
+                feed.accept(stream, &request)
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Writes the snapshot, then every frame queued for a client, until the client goes away or closes the connection
+fn write_frames(mut stream: TcpStream, snapshot: Vec<Vec<u8>>, queue: Receiver<Frame>) {
+    for frame in snapshot {
+        if stream.write_all(&frame).is_err() {
+            return;
+        }
+    }
+    for frame in queue {
+        if stream.write_all(&frame).is_err() {
+            break;
+        }
+        if frame[0] & 0x0f == OPCODE_CLOSE {
+            break;
+        }
+    }
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+}
+
+/// Reads (and ignores) what the client sends, apart from answering pings and closing when asked to. Replies are
+/// queued for the writer, so that they can't end up in the middle of another frame
+fn read_frames(mut stream: TcpStream, replies: SyncSender<Frame>) {
+    loop {
+        let mut header = [0u8; 2];
+        if stream.read_exact(&mut header).is_err() {
+            return;
+        }
+        let opcode = header[0] & 0x0f;
+        let masked = header[1] & 0x80 != 0;
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0u8; 2];
+                if stream.read_exact(&mut len).is_err() { return; }
+                u16::from_be_bytes(len) as u64
+            },
+            127 => {
+                let mut len = [0u8; 8];
+                if stream.read_exact(&mut len).is_err() { return; }
+                u64::from_be_bytes(len)
+            },
+            len => len as u64,
+        };
+        let mut mask = [0u8; 4];
+        if masked && stream.read_exact(&mut mask).is_err() {
+            return;
+        }
+        // Nothing big is expected from clients
+        if len > 4096 {
+            return;
+        }
+        let mut payload = vec![0u8; len as usize];
+        if stream.read_exact(&mut payload).is_err() {
+            return;
+        }
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+        match opcode {
+            OPCODE_PING => { let _ = replies.try_send(Arc::new(frame(OPCODE_PONG, &payload))); },
+            OPCODE_CLOSE => {
+                if replies.try_send(Arc::new(frame(OPCODE_CLOSE, &payload))).is_err() {
+                    let _ = stream.shutdown(std::net::Shutdown::Both);
+                }
+                return;
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Builds an unmasked frame (servers don't mask what they send)
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode]; // FIN set, as messages aren't split up
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        },
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// The Sec-WebSocket-Accept value for a client's Sec-WebSocket-Key
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// SHA-1, which the WebSocket handshake needs (and which isn't used for anything security related)
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for i in 0..16 {
+            w[i] = u32::from_be_bytes(chunk[i * 4..i * 4 + 4].try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..20 => ((b & c) | (!b & d), 0x5a827999),
+                20..40 => (b ^ c ^ d, 0x6ed9eba1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*wi);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (hi, x) in h.iter_mut().zip([a, b, c, d, e]) {
+            *hi = hi.wrapping_add(x);
+        }
+    }
+    
+    let mut digest = [0u8; 20];
+    for (i, hi) in h.iter().enumerate() {
+        digest[i * 4..i * 4 + 4].copy_from_slice(&hi.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handshake_example() {
+        // From RFC 6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn sha1_vectors() {
+        // From FIPS 180-2, plus the empty message and one that needs a second block for the length
+        let hex = |digest: [u8; 20]| digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(hex(sha1(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+}
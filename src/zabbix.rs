@@ -0,0 +1,121 @@
+//! Sends statistics to a Zabbix server or proxy with the sender (trapper) protocol, so hosts show up
+//! as trapper items without running zabbix_sender
+
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime};
+
+use crate::HostInfo;
+use crate::json::JsonObject;
+
+/// The port Zabbix servers and proxies listen for trapper data on
+pub const DEFAULT_PORT: u16 = 10051;
+
+/// The default item key, where {metric} is e.g. "rtt" and {host} is the pinged host
+pub const DEFAULT_KEY: &str = "multiping.{metric}[{host}]";
+
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+
+/// One value for a trapper item
+#[derive(Clone, Debug)]
+pub struct Item {
+    pub host: String, // The host name in Zabbix, which the item belongs to
+    pub key: String,
+    pub value: String,
+    pub clock: u64, // Seconds since the Unix epoch
+}
+
+/// Fills in an item key template, e.g. "multiping.{metric}[{host}]" becomes "multiping.rtt[example.com]".
+/// Hosts are quoted if they have characters that would end the key's parameter early
+pub fn item_key(template: &str, metric: &str, host: &str) -> String {
+    let host = if host.contains([',', ']', '"', ' ']) {
+        format!("\"{}\"", host.replace('"', "\\\""))
+    } else {
+        host.to_string()
+    };
+    template.replace("{metric}", metric).replace("{host}", &host)
+}
+
+/// The items for a host's statistics: rtt, min, max and jitter (in milliseconds, only once it's replied),
+/// loss (as a percentage), sent, received, and up (1, or 0 while it's down)
+pub fn host_items(zabbix_host: &str, template: &str, host: &HostInfo) -> Vec<Item> {
+    let clock = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let mut metrics = vec![
+        ("sent", host.pings_sent.to_string()),
+        ("received", host.successful.to_string()),
+        ("up", if host.is_down() { "0" } else { "1" }.to_string()),
+    ];
+    if let Some(loss) = host.loss_percent() {
+        metrics.push(("loss", format!("{:.2}", loss)));
+    }
+    if host.successful > 0 {
+        metrics.push(("rtt", format!("{:.3}", host.average())));
+        metrics.push(("jitter", format!("{:.3}", host.jitter())));
+    }
+    if let Some(min) = host.min_time {
+        metrics.push(("min", format!("{:.3}", min as f64 / 1000.0)));
+    }
+    if let Some(max) = host.max_time {
+        metrics.push(("max", format!("{:.3}", max as f64 / 1000.0)));
+    }
+    metrics.into_iter().map(|(metric, value)| Item {
+        host: zabbix_host.to_string(),
+        key: item_key(template, metric, &host.host_str),
+        value,
+        clock,
+    }).collect()
+}
+
+/// Sends items to a Zabbix server, e.g. "zabbix.example.com" or "192.0.2.1:10051",
+/// and returns the server's summary (e.g. "processed: 8; failed: 0; total: 8; ...")
+pub fn send(server: &str, items: &[Item]) -> Result<String, Error> {
+    // Without a port, the address can't be parsed, so the default port is added
+    let addr = match server.to_socket_addrs() {
+        Ok(mut addrs) => addrs.next(),
+        Err(_) => (server.trim_matches(['[', ']']), DEFAULT_PORT).to_socket_addrs()?.next(),
+    }.ok_or(Error::from(ErrorKind::AddrNotAvailable))?;
+    
+    let data: Vec<String> = items.iter().map(|item| JsonObject::new()
+        .string("host", &item.host)
+        .string("key", &item.key)
+        .string("value", &item.value)
+        .integer("clock", item.clock as i64)
+        .finish()).collect();
+    let request = JsonObject::new()
+        .string("request", "sender data")
+        .raw("data", &format!("[{}]", data.join(",")))
+        .finish();
+    
+    let mut stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut packet = HEADER.to_vec();
+    packet.extend_from_slice(&(request.len() as u64).to_le_bytes());
+    packet.extend_from_slice(request.as_bytes());
+    stream.write_all(&packet)?;
+    
+    let mut header = [0u8; 13];
+    stream.read_exact(&mut header)?;
+    if &header[..5] != HEADER {
+        return Err(Error::new(ErrorKind::InvalidData, "not a Zabbix response"));
+    }
+    let len = u64::from_le_bytes(header[5..13].try_into().unwrap());
+    if len > 1 << 20 {
+        return Err(Error::new(ErrorKind::InvalidData, "Zabbix response is too big"));
+    }
+    let mut response = vec![0u8; len as usize];
+    stream.read_exact(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    
+    // The response looks like {"response":"success","info":"processed: 1; failed: 0; ..."}
+    if !response.contains("\"success\"") {
+        return Err(Error::other(format!("Zabbix rejected the data: {}", response)));
+    }
+    Ok(response_field(&response, "info").unwrap_or_default())
+}
+
+/// Pulls a string field out of the server's (simple, flat) JSON response
+fn response_field(response: &str, name: &str) -> Option<String> {
+    let start = response.find(&format!("\"{}\"", name))? + name.len() + 2;
+    let rest = response[start..].trim_start().strip_prefix(':')?.trim_start().strip_prefix('"')?;
+    Some(rest[..rest.find('"')?].to_string())
+}